@@ -9,7 +9,7 @@ update-alternatives --remove-all clang
 update-alternatives --remove-all clang++
 
 apt-get install --no-install-recommends -y tar cmake python3 gpg curl libpulse-dev libxcb-render0 libxcb-render0 libxcb-xinerama0 libgtk-3-dev \
-                                           libxcb-render0-dev libxcb-shape0-dev libxcb-xfixes0-dev libspeechd-dev libxkbcommon-dev libssl-dev ca-certificates;
+                                           libxcb-render0-dev libxcb-shape0-dev libxcb-xfixes0-dev libspeechd-dev libxkbcommon-dev libssl-dev ca-certificates dbus;
 update-ca-certificates
 
 # mold linker
@@ -13,9 +13,9 @@ DEST=/home/deck/.local/bin
 test ! -d $DEST && mkdir -p $DEST && chown -R deck: $DEST
 
 echo "Stopping shortcut_daemon service (if exists)"
-systemctl stop shortcut_daemon 2> /dev/null
+systemctl stop shortcut_daemon.socket shortcut_daemon 2> /dev/null
 echo "Disabling shortcut_daemon service (if exists)"
-systemctl disable shortcut_daemon 2> /dev/null
+systemctl disable shortcut_daemon.socket shortcut_daemon 2> /dev/null
 
 echo "Installing files"
 for bin in shortcut-gui shortcut-daemon; do
@@ -30,23 +30,36 @@ done
 
 
 echo "Installing shortcut_daemon service"
+cat > /etc/systemd/system/shortcut_daemon.socket <<- EOM
+[Unit]
+Description=Steam Deck Shortcut Deamon Socket
+[Socket]
+ListenStream=/run/shortcutd/shortcutd.sock
+SocketMode=0666
+FileDescriptorName=shortcutd
+[Install]
+WantedBy=sockets.target
+EOM
+
 cat > /etc/systemd/system/shortcut_daemon.service <<- EOM
 [Unit]
 Description=Steam Deck Shortcut Deamon
+Requires=shortcut_daemon.socket
+After=shortcut_daemon.socket
 [Service]
 Type=simple
 User=root
-Restart=always
+Restart=on-failure
 ExecStart=/home/deck/.local/bin/shortcut-daemon
 WorkingDirectory=/home/deck/
 [Install]
 WantedBy=multi-user.target
 EOM
 
-echo "Enabling shortcut_daemon service"
+echo "Enabling shortcut_daemon socket and service"
 systemctl daemon-reload
-systemctl enable shortcut_daemon
-systemctl start shortcut_daemon
+systemctl enable shortcut_daemon.socket shortcut_daemon
+systemctl start shortcut_daemon.socket shortcut_daemon
 
 echo "Installing Shortcut desktop entry"
 cat > /home/deck/.local/share/applications/Shortcut.desktop <<- EOM
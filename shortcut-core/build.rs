@@ -1,5 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/wifi.proto")?;
-    tonic_build::compile_protos("proto/ssh.proto")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("shortcut_descriptor.bin"))
+        .compile(
+            &[
+                "proto/wifi.proto",
+                "proto/ssh.proto",
+                "proto/bluetooth.proto",
+                "proto/cpu.proto",
+                "proto/sensors.proto",
+                "proto/display.proto",
+                "proto/battery.proto",
+                "proto/error.proto",
+            ],
+            &["proto"],
+        )?;
     Ok(())
 }
@@ -0,0 +1,143 @@
+use prost::bytes::Bytes;
+use prost::Message;
+use tonic::{Code, Status};
+
+tonic::include_proto!("shortcut.error");
+
+/// Errors shared between the daemon and its clients.
+///
+/// Converts into a [`Status`] carrying an [`ErrorDetails`] payload so clients can recover the
+/// variant with `Error::from(status)` instead of matching on message strings.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("{resource} not found")]
+    NotFound { resource: String },
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    pub fn not_found(resource: impl Into<String>) -> Self {
+        Error::NotFound {
+            resource: resource.into(),
+        }
+    }
+
+    pub fn code(&self) -> Code {
+        match self {
+            Error::NotFound { .. } => Code::NotFound,
+            Error::Unavailable(_) => Code::Unavailable,
+            Error::PermissionDenied(_) => Code::PermissionDenied,
+            Error::InvalidArgument(_) => Code::InvalidArgument,
+            Error::Internal(_) => Code::Internal,
+        }
+    }
+
+    fn details(&self) -> ErrorDetails {
+        let (reason, resource) = match self {
+            Error::NotFound { resource } => (Reason::NotFound, resource.clone()),
+            Error::Unavailable(_) => (Reason::Unavailable, String::new()),
+            Error::PermissionDenied(_) => (Reason::PermissionDenied, String::new()),
+            Error::InvalidArgument(_) => (Reason::InvalidArgument, String::new()),
+            Error::Internal(_) => (Reason::Internal, String::new()),
+        };
+
+        ErrorDetails {
+            reason: reason as i32,
+            resource,
+        }
+    }
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        let details = Bytes::from(err.details().encode_to_vec());
+        let message = match &err {
+            Error::NotFound { .. } => err.to_string(),
+            Error::Unavailable(message)
+            | Error::PermissionDenied(message)
+            | Error::InvalidArgument(message)
+            | Error::Internal(message) => message.clone(),
+        };
+
+        Status::with_details(err.code(), message, details)
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+
+        let details = ErrorDetails::decode(status.details()).ok();
+        let reason = details
+            .as_ref()
+            .and_then(|details| Reason::from_i32(details.reason))
+            .unwrap_or(Reason::Unspecified);
+
+        match (reason, status.code()) {
+            (Reason::NotFound, _) => Error::NotFound {
+                resource: details.map(|d| d.resource).unwrap_or(message),
+            },
+            (Reason::Unspecified, Code::NotFound) => Error::NotFound { resource: message },
+            (Reason::Unavailable, _) | (Reason::Unspecified, Code::Unavailable) => {
+                Error::Unavailable(message)
+            }
+            (Reason::PermissionDenied, _)
+            | (Reason::Unspecified, Code::PermissionDenied | Code::Unauthenticated) => {
+                Error::PermissionDenied(message)
+            }
+            (Reason::InvalidArgument, _) | (Reason::Unspecified, Code::InvalidArgument) => {
+                Error::InvalidArgument(message)
+            }
+            _ => Error::Internal(message),
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Self {
+        Error::Unavailable(format!("unable to connect to shortcut-daemon: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_status() {
+        for err in [
+            Error::not_found("wifi device wlan9"),
+            Error::Unavailable("nl80211 is not available".to_string()),
+            Error::PermissionDenied("uid 1001 is not allowed".to_string()),
+            Error::InvalidArgument("bad key".to_string()),
+            Error::Internal("boom".to_string()),
+        ] {
+            let status = Status::from(err.clone());
+            assert_eq!(status.code(), err.code());
+            assert_eq!(Error::from(status), err);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_status_code_without_details() {
+        assert_eq!(
+            Error::from(Status::not_found("sshd.service")),
+            Error::not_found("sshd.service")
+        );
+        assert_eq!(
+            Error::from(Status::unauthenticated("nope")),
+            Error::PermissionDenied("nope".to_string())
+        );
+        assert_eq!(
+            Error::from(Status::unknown("huh")),
+            Error::Internal("huh".to_string())
+        );
+    }
+}
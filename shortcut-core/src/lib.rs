@@ -4,12 +4,74 @@ pub use tokio_stream;
 pub use tonic;
 pub use tower;
 
-pub const SOCKET_PATH: &str = "/tmp/shortcutd.sock";
+use std::path::PathBuf;
+
+pub mod error;
+
+pub use error::Error;
+
+/// Where the daemon listens unless told otherwise.
+pub const SOCKET_PATH: &str = "/run/shortcutd/shortcutd.sock";
+
+/// Environment variable that overrides [`SOCKET_PATH`] for both the daemon and the GUI.
+pub const SOCKET_PATH_ENV: &str = "SHORTCUT_SOCKET";
+
+/// The socket path from [`SOCKET_PATH_ENV`], falling back to [`SOCKET_PATH`].
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_PATH_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(SOCKET_PATH))
+}
+
+/// Encoded descriptors of every shortcut proto, served through gRPC server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("shortcut_descriptor");
 
 pub mod wifi {
     tonic::include_proto!("shortcut.wifi");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.wifi.WifiService";
 }
 
 pub mod ssh {
     tonic::include_proto!("shortcut.ssh");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.ssh.SshService";
+}
+
+pub mod bluetooth {
+    tonic::include_proto!("shortcut.bluetooth");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.bluetooth.BluetoothService";
+}
+
+pub mod cpu {
+    tonic::include_proto!("shortcut.cpu");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.cpu.CpuService";
+}
+
+pub mod sensors {
+    tonic::include_proto!("shortcut.sensors");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.sensors.SensorsService";
+}
+
+pub mod display {
+    tonic::include_proto!("shortcut.display");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.display.DisplayService";
+}
+
+pub mod battery {
+    tonic::include_proto!("shortcut.battery");
+
+    /// Name of the service in health checks.
+    pub const SERVICE_NAME: &str = "shortcut.battery.BatteryService";
 }
@@ -0,0 +1,329 @@
+use std::ffi::CString;
+use std::fmt;
+use std::sync::Arc;
+
+use nix::unistd::{Gid, Group, Uid, User};
+use shortcut_core::tokio::sync::watch;
+use shortcut_core::tonic::service::Interceptor;
+use shortcut_core::tonic::transport::server::UdsConnectInfo;
+use shortcut_core::tonic::{Request, Status};
+use shortcut_core::Error;
+
+/// Users allowed to talk to the daemon unless overridden.
+pub const DEFAULT_ALLOWED_USERS: &[&str] = &["root", "deck"];
+
+/// The process on the other end of the socket, as reported by `SO_PEERCRED`.
+///
+/// Inserted into the request extensions of every authenticated call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl Caller {
+    /// The authenticated caller of `request`, if it went through [`Authenticator`].
+    pub fn of<T>(request: &Request<T>) -> Option<Caller> {
+        request.extensions().get::<Caller>().copied()
+    }
+
+    /// Describes the caller of `request` for log messages.
+    pub fn describe<T>(request: &Request<T>) -> String {
+        Self::of(request).map_or_else(|| "unknown caller".to_string(), |caller| caller.to_string())
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid={} gid={}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid={pid}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl Allowlist {
+    pub fn new(uids: Vec<u32>, gids: Vec<u32>) -> Self {
+        Self { uids, gids }
+    }
+
+    /// Builds an allowlist from user and group names or numeric ids. Groups match the primary
+    /// group of the caller and every group its user is a member of.
+    ///
+    /// Unknown names are skipped with a warning so a missing `deck` user doesn't lock out root.
+    pub fn from_names<S: AsRef<str>>(users: &[S], groups: &[S]) -> Self {
+        let uids = resolve(users, "user", |name| {
+            Ok(User::from_name(name)?.map(|user| user.uid.as_raw()))
+        });
+        let gids = resolve(groups, "group", |name| {
+            Ok(Group::from_name(name)?.map(|group| group.gid.as_raw()))
+        });
+
+        Self::new(uids, gids)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty()
+    }
+
+    pub fn allows(&self, caller: &Caller) -> bool {
+        self.allows_with(caller, groups_of)
+    }
+
+    /// Like [`Allowlist::allows`], looking up the groups of a uid through `groups`. They are only
+    /// looked up when neither the uid nor the primary group is listed.
+    fn allows_with(&self, caller: &Caller, groups: impl FnOnce(u32) -> Vec<u32>) -> bool {
+        if self.uids.contains(&caller.uid) || self.gids.contains(&caller.gid) {
+            return true;
+        }
+        !self.gids.is_empty() && groups(caller.uid).iter().any(|gid| self.gids.contains(gid))
+    }
+}
+
+impl fmt::Display for Allowlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let users: Vec<String> = self
+            .uids
+            .iter()
+            .map(|uid| match User::from_uid(Uid::from_raw(*uid)) {
+                Ok(Some(user)) => format!("{}({uid})", user.name),
+                _ => uid.to_string(),
+            })
+            .collect();
+        let groups: Vec<String> = self
+            .gids
+            .iter()
+            .map(|gid| match Group::from_gid(Gid::from_raw(*gid)) {
+                Ok(Some(group)) => format!("{}({gid})", group.name),
+                _ => gid.to_string(),
+            })
+            .collect();
+        write!(
+            f,
+            "uids=[{}] gids=[{}]",
+            users.join(", "),
+            groups.join(", ")
+        )
+    }
+}
+
+/// Resolves names of `kind` through `lookup`, ids are taken as they are.
+fn resolve<S: AsRef<str>>(
+    names: &[S],
+    kind: &str,
+    lookup: impl Fn(&str) -> nix::Result<Option<u32>>,
+) -> Vec<u32> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let name = name.as_ref().trim();
+            if let Ok(id) = name.parse::<u32>() {
+                return Some(id);
+            }
+            match lookup(name) {
+                Ok(Some(id)) => Some(id),
+                Ok(None) => {
+                    tracing::warn!("Ignoring unknown {kind} {name} in allowlist");
+                    None
+                }
+                Err(err) => {
+                    tracing::warn!("Unable to look up {kind} {name}: {err}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The supplementary groups of the user with `uid`, none for unknown users.
+fn groups_of(uid: u32) -> Vec<u32> {
+    let user = match User::from_uid(Uid::from_raw(uid)) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Vec::new(),
+        Err(err) => {
+            tracing::warn!("Unable to look up uid {uid}: {err}");
+            return Vec::new();
+        }
+    };
+    let name = match CString::new(user.name.as_str()) {
+        Ok(name) => name,
+        Err(_) => return Vec::new(),
+    };
+    match nix::unistd::getgrouplist(&name, user.gid) {
+        Ok(groups) => groups.into_iter().map(Gid::as_raw).collect(),
+        Err(err) => {
+            tracing::warn!("Unable to look up the groups of {}: {err}", user.name);
+            Vec::new()
+        }
+    }
+}
+
+/// Rejects calls from peers that are not on the [`Allowlist`].
+///
+/// The allowlist is watched so a config reload applies to connections that are already open.
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    allowlist: watch::Receiver<Arc<Allowlist>>,
+}
+
+impl Authenticator {
+    pub fn new(allowlist: watch::Receiver<Arc<Allowlist>>) -> Self {
+        Self { allowlist }
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let caller = request
+            .extensions()
+            .get::<UdsConnectInfo>()
+            .and_then(|info| info.peer_cred)
+            .map(|cred| Caller {
+                uid: cred.uid(),
+                gid: cred.gid(),
+                pid: cred.pid(),
+            });
+
+        match caller {
+            Some(caller) if self.allowlist.borrow().allows(&caller) => {
+                request.extensions_mut().insert(caller);
+                Ok(request)
+            }
+            Some(caller) => {
+                tracing::warn!("Rejecting call from {caller}");
+                Err(Error::PermissionDenied(format!("uid {} is not allowed", caller.uid)).into())
+            }
+            None => {
+                tracing::warn!("Rejecting call without peer credentials");
+                Err(Error::PermissionDenied("unable to identify caller".to_string()).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shortcut_core::tokio;
+    use shortcut_core::tokio::net::UnixStream;
+    use shortcut_core::tonic::transport::server::Connected;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+
+    fn authenticator(allowlist: Allowlist) -> Authenticator {
+        Authenticator::new(watch::channel(Arc::new(allowlist)).1)
+    }
+
+    /// A request as tonic hands it to interceptors for a connection from this process.
+    async fn request_from_self() -> Request<()> {
+        let (_client, server) = UnixStream::pair().unwrap();
+        let mut request = Request::new(());
+        request.extensions_mut().insert(server.connect_info());
+        request
+    }
+
+    #[tokio::test]
+    async fn allows_listed_uid_and_exposes_caller() {
+        let uid = nix::unistd::getuid().as_raw();
+        let mut auth = authenticator(Allowlist::new(vec![uid], vec![]));
+
+        let request = auth.call(request_from_self().await).unwrap();
+
+        let caller = Caller::of(&request).unwrap();
+        assert_eq!(caller.uid, uid);
+        assert_eq!(caller.pid, Some(std::process::id() as i32));
+    }
+
+    #[tokio::test]
+    async fn allows_listed_gid() {
+        let gid = nix::unistd::getgid().as_raw();
+        let mut auth = authenticator(Allowlist::new(vec![], vec![gid]));
+
+        assert!(auth.call(request_from_self().await).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_unlisted_uid() {
+        let uid = nix::unistd::getuid().as_raw();
+        let mut auth = authenticator(Allowlist::new(vec![uid + 1], vec![]));
+
+        let status = auth.call(request_from_self().await).unwrap_err();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn applies_reloaded_allowlist() {
+        let uid = nix::unistd::getuid().as_raw();
+        let (tx, rx) = watch::channel(Arc::new(Allowlist::new(vec![uid], vec![])));
+        let mut auth = Authenticator::new(rx);
+        assert!(auth.call(request_from_self().await).is_ok());
+
+        tx.send(Arc::new(Allowlist::new(vec![uid + 1], vec![])))
+            .unwrap();
+
+        assert!(auth.call(request_from_self().await).is_err());
+    }
+
+    #[test]
+    fn rejects_requests_without_credentials() {
+        let mut auth = authenticator(Allowlist::new(vec![0], vec![]));
+
+        let status = auth.call(Request::new(())).unwrap_err();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[test]
+    fn resolves_user_names_and_uids() {
+        let allowlist = Allowlist::from_names(&["root", "1234", "no-such-user-here"], &[]);
+
+        assert_eq!(allowlist, Allowlist::new(vec![0, 1234], vec![]));
+    }
+
+    #[test]
+    fn resolves_group_names_and_gids() {
+        let allowlist = Allowlist::from_names(&[], &["root", "4321", "no-such-group-here"]);
+
+        assert_eq!(allowlist, Allowlist::new(vec![], vec![0, 4321]));
+        assert!(allowlist.allows(&Caller {
+            uid: 1000,
+            gid: 4321,
+            pid: None,
+        }));
+    }
+
+    #[test]
+    fn allows_members_of_listed_groups() {
+        let allowlist = Allowlist::new(vec![], vec![10]);
+        let caller = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: None,
+        };
+
+        assert!(allowlist.allows_with(&caller, |uid| {
+            assert_eq!(uid, 1000);
+            vec![1000, 10, 998]
+        }));
+        assert!(!allowlist.allows_with(&caller, |_| vec![1000, 998]));
+        assert!(!Allowlist::new(vec![0], vec![])
+            .allows_with(&caller, |_| panic!("nothing to look up without groups")));
+    }
+
+    #[test]
+    fn looks_up_supplementary_groups() {
+        let root = groups_of(0);
+
+        assert!(root.contains(&0), "{root:?}");
+        assert!(groups_of(u32::MAX - 1).is_empty());
+    }
+}
@@ -0,0 +1,1724 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use shortcut_core::futures::stream::{self, BoxStream, StreamExt};
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::mpsc;
+use shortcut_core::tokio_stream::wrappers::UnboundedReceiverStream;
+use shortcut_core::tonic;
+use shortcut_core::Error;
+
+use crate::battery::power_supply::{Battery, PowerSupply};
+use crate::bluetooth::bluez::{self, Bluez};
+use crate::cpu::sysfs::{self as cpu_sysfs, CpuState};
+use crate::display::backlight::{Backlight, Backlights};
+use crate::logind::Logind;
+use crate::sensors::hwmon::{self, Hwmon};
+use crate::ssh::authorized_keys::{AuthorizedKey, AuthorizedKeys};
+use crate::ssh::sshd_config::{self, SshdConfig};
+use crate::systemd::{Systemd, UnitState};
+use crate::wifi::icmp;
+use crate::wifi::network_manager::{AccessPoint, NetworkManager, SavedNetwork};
+use crate::wifi::nl80211::{Interface, Link, Nl80211};
+use crate::wifi::nm_config::NmConfig;
+
+/// Changes on the host that can undo settings the daemon applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// The system woke up from suspend or hibernation.
+    Resumed,
+    /// A wireless interface with the given name was created.
+    InterfaceAdded(String),
+}
+
+/// Everything the services need from the host system.
+///
+/// The daemon runs against [`LinuxBackend`]; tests inject [`fake::FakeBackend`].
+#[tonic::async_trait]
+pub trait SystemBackend: Send + Sync + 'static {
+    async fn wifi_interfaces(&self) -> Result<Vec<Interface>, Error>;
+
+    async fn power_save(&self, device: &str) -> Result<bool, Error>;
+
+    /// Applies the power save setting and returns the value read back from the device.
+    async fn set_power_save(&self, device: &str, enabled: bool) -> Result<bool, Error>;
+
+    /// The power save setting of the connection the device is connected with, `None` when it
+    /// is left to the global default or the device isn't connected.
+    async fn connection_power_save(&self, device: &str) -> Result<Option<bool>, Error>;
+
+    /// Saves power save in the connection the device is connected with, it applies from the
+    /// next time the connection is activated.
+    async fn set_connection_power_save(&self, device: &str, enabled: bool) -> Result<(), Error>;
+
+    /// The power save default of connections that leave it unset, `None` without one.
+    async fn global_power_save(&self) -> Result<Option<bool>, Error>;
+
+    /// Saves the power save default, it applies from the next time a connection is activated.
+    async fn set_global_power_save(&self, enabled: bool) -> Result<(), Error>;
+
+    /// The access points the device saw last, strongest first.
+    async fn access_points(&self, device: &str) -> Result<Vec<AccessPoint>, Error>;
+
+    /// Scans on the device and returns the access points found, strongest first.
+    async fn scan_wifi(&self, device: &str) -> Result<Vec<AccessPoint>, Error>;
+
+    /// Saved networks, highest priority first.
+    async fn saved_networks(&self) -> Result<Vec<SavedNetwork>, Error>;
+
+    /// Deletes the saved network with `uuid` and returns the ones left.
+    async fn forget_network(&self, uuid: &str) -> Result<Vec<SavedNetwork>, Error>;
+
+    async fn set_network_autoconnect(
+        &self,
+        uuid: &str,
+        enabled: bool,
+    ) -> Result<Vec<SavedNetwork>, Error>;
+
+    async fn set_network_priority(
+        &self,
+        uuid: &str,
+        priority: i32,
+    ) -> Result<Vec<SavedNetwork>, Error>;
+
+    /// Connects the device to `ssid`, using the saved passphrase when `passphrase` is `None`,
+    /// and returns the access points once connected.
+    async fn connect_wifi(
+        &self,
+        device: &str,
+        ssid: &str,
+        passphrase: Option<&str>,
+    ) -> Result<Vec<AccessPoint>, Error>;
+
+    async fn disconnect_wifi(&self, device: &str) -> Result<Vec<AccessPoint>, Error>;
+
+    /// The connection of the device, `None` while it isn't connected.
+    async fn wifi_link(&self, device: &str) -> Result<Option<Link>, Error>;
+
+    /// The IPv4 gateway of the default route through the device, `None` without one.
+    async fn gateway(&self, device: &str) -> Result<Option<Ipv4Addr>, Error>;
+
+    /// Sends an ICMP echo request and returns the round trip, `None` when no reply came within
+    /// `timeout`.
+    async fn ping(&self, addr: Ipv4Addr, timeout: Duration) -> Result<Option<Duration>, Error>;
+
+    async fn unit_state(&self, unit: &str) -> Result<UnitState, Error>;
+
+    async fn start_unit(&self, unit: &str) -> Result<UnitState, Error>;
+
+    async fn stop_unit(&self, unit: &str) -> Result<UnitState, Error>;
+
+    /// Enables or disables the unit at boot without starting or stopping it.
+    async fn set_unit_enabled(&self, unit: &str, enabled: bool) -> Result<UnitState, Error>;
+
+    /// Makes a running unit pick up its configuration, a stopped one stays stopped.
+    async fn reload_unit(&self, unit: &str) -> Result<UnitState, Error>;
+
+    async fn sshd_options(&self) -> Result<sshd_config::Options, Error>;
+
+    /// Applies the options once sshd accepts them and returns the options read back.
+    async fn set_sshd_options(
+        &self,
+        options: sshd_config::Options,
+    ) -> Result<sshd_config::Options, Error>;
+
+    async fn authorized_keys(&self, user: &str) -> Result<Vec<AuthorizedKey>, Error>;
+
+    /// Authorizes the key for `user` and returns the keys read back.
+    async fn add_authorized_key(
+        &self,
+        user: &str,
+        key: AuthorizedKey,
+    ) -> Result<Vec<AuthorizedKey>, Error>;
+
+    async fn remove_authorized_key(
+        &self,
+        user: &str,
+        fingerprint: &str,
+    ) -> Result<Vec<AuthorizedKey>, Error>;
+
+    async fn events(&self) -> Result<BoxStream<'static, SystemEvent>, Error>;
+
+    async fn bluetooth_powered(&self) -> Result<bool, Error>;
+
+    /// Powers the adapter on or off and returns the state read back from it.
+    async fn set_bluetooth_powered(&self, powered: bool) -> Result<bool, Error>;
+
+    async fn bluetooth_devices(&self) -> Result<Vec<bluez::Device>, Error>;
+
+    async fn connect_bluetooth(&self, address: &str) -> Result<bluez::Device, Error>;
+
+    async fn disconnect_bluetooth(&self, address: &str) -> Result<bluez::Device, Error>;
+
+    async fn remove_bluetooth(&self, address: &str) -> Result<(), Error>;
+
+    /// Starts discovering devices and answering pairing prompts until the session is dropped.
+    async fn start_bluetooth_pairing(&self) -> Result<bluez::PairingSession, Error>;
+
+    /// Pairs with a discovered device while a pairing session runs.
+    async fn pair_bluetooth(&self, address: &str) -> Result<bluez::Device, Error>;
+
+    async fn cpu_state(&self) -> Result<CpuState, Error>;
+
+    /// Applies the governor to every core and returns the state read back.
+    async fn set_cpu_governor(&self, governor: &str) -> Result<CpuState, Error>;
+
+    async fn set_cpu_smt(&self, enabled: bool) -> Result<CpuState, Error>;
+
+    async fn set_cpu_boost(&self, enabled: bool) -> Result<CpuState, Error>;
+
+    /// Caps the frequency of every core, in kHz.
+    async fn set_cpu_max_frequency(&self, khz: u32) -> Result<CpuState, Error>;
+
+    async fn sensors(&self) -> Result<Vec<hwmon::Reading>, Error>;
+
+    /// The `pwm1_enable` mode of the fan on the hwmon device named `device`.
+    async fn fan_mode(&self, device: &str) -> Result<u8, Error>;
+
+    async fn set_fan_mode(&self, device: &str, mode: u8) -> Result<(), Error>;
+
+    /// Sets the mode without awaiting, for handing the fan back from a drop guard when the
+    /// runtime may already be gone. It is a single sysfs write.
+    fn restore_fan_mode(&self, device: &str, mode: u8) -> Result<(), Error>;
+
+    async fn set_fan_pwm(&self, device: &str, pwm: u8) -> Result<(), Error>;
+
+    async fn backlights(&self) -> Result<Vec<Backlight>, Error>;
+
+    /// Sets the raw brightness of the backlight `name`, the preferred one when empty.
+    async fn set_backlight(&self, name: &str, brightness: u32) -> Result<Backlight, Error>;
+
+    async fn battery(&self) -> Result<Battery, Error>;
+
+    /// Stops charging at `percent` and returns the battery read back.
+    async fn set_charge_limit(&self, percent: u32) -> Result<Battery, Error>;
+}
+
+/// Runs `f` on the blocking thread pool, for netlink and ICMP sockets and the sysfs and config
+/// files behind the backend. Writes can take a while, turning SMT on or off brings whole cores
+/// up or down.
+async fn blocking<T, E, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    E: Into<Error> + Send + 'static,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| Error::Internal(err.to_string()))?
+        .map_err(Into::into)
+}
+
+/// The `authorized_keys` file of `user`.
+fn authorized_keys_of(user: &str) -> Result<AuthorizedKeys, Error> {
+    let account = nix::unistd::User::from_name(user)
+        .map_err(|err| Error::Internal(format!("unable to look up user {user}: {err}")))?
+        .ok_or_else(|| Error::not_found(format!("user {user}")))?;
+    Ok(AuthorizedKeys::new(
+        account.dir.join(".ssh/authorized_keys"),
+        account.uid.as_raw(),
+        account.gid.as_raw(),
+    ))
+}
+
+/// nl80211 and NetworkManager for WiFi, systemd over D-Bus for units, logind for suspend, BlueZ
+/// for bluetooth, sysfs for the CPU, sensors, backlights and battery and plain files for SSH
+/// keys, sshd and NetworkManager drop-ins.
+#[derive(Debug)]
+pub struct LinuxBackend {
+    systemd: Systemd,
+    logind: Logind,
+    network_manager: NetworkManager,
+    nm_config: NmConfig,
+    bluez: Bluez,
+    cpu: cpu_sysfs::Sysfs,
+    hwmon: Hwmon,
+    backlights: Backlights,
+    power_supply: PowerSupply,
+    sshd_config: SshdConfig,
+}
+
+impl LinuxBackend {
+    // One argument per part of the host, main is the only caller
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        systemd: Systemd,
+        logind: Logind,
+        network_manager: NetworkManager,
+        nm_config: NmConfig,
+        bluez: Bluez,
+        cpu: cpu_sysfs::Sysfs,
+        hwmon: Hwmon,
+        backlights: Backlights,
+        power_supply: PowerSupply,
+        sshd_config: SshdConfig,
+    ) -> Self {
+        Self {
+            systemd,
+            logind,
+            network_manager,
+            nm_config,
+            bluez,
+            cpu,
+            hwmon,
+            backlights,
+            power_supply,
+            sshd_config,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SystemBackend for LinuxBackend {
+    async fn wifi_interfaces(&self) -> Result<Vec<Interface>, Error> {
+        blocking(|| Nl80211::connect()?.interfaces()).await
+    }
+
+    async fn power_save(&self, device: &str) -> Result<bool, Error> {
+        let device = device.to_string();
+        blocking(move || {
+            let mut nl = Nl80211::connect()?;
+            let iface = nl.interface(&device)?;
+            nl.power_save(iface.ifindex)
+        })
+        .await
+    }
+
+    async fn set_power_save(&self, device: &str, enabled: bool) -> Result<bool, Error> {
+        let device = device.to_string();
+        blocking(move || {
+            let mut nl = Nl80211::connect()?;
+            let iface = nl.interface(&device)?;
+            nl.set_power_save(iface.ifindex, enabled)?;
+            nl.power_save(iface.ifindex)
+        })
+        .await
+    }
+
+    async fn connection_power_save(&self, device: &str) -> Result<Option<bool>, Error> {
+        Ok(self.network_manager.power_save(device).await?)
+    }
+
+    async fn set_connection_power_save(&self, device: &str, enabled: bool) -> Result<(), Error> {
+        Ok(self.network_manager.set_power_save(device, enabled).await?)
+    }
+
+    async fn global_power_save(&self) -> Result<Option<bool>, Error> {
+        let nm_config = self.nm_config.clone();
+        blocking(move || nm_config.power_save()).await
+    }
+
+    async fn set_global_power_save(&self, enabled: bool) -> Result<(), Error> {
+        let nm_config = self.nm_config.clone();
+        blocking(move || nm_config.set_power_save(enabled)).await?;
+        Ok(self.network_manager.reload_config().await?)
+    }
+
+    async fn access_points(&self, device: &str) -> Result<Vec<AccessPoint>, Error> {
+        Ok(self.network_manager.access_points(device).await?)
+    }
+
+    async fn scan_wifi(&self, device: &str) -> Result<Vec<AccessPoint>, Error> {
+        Ok(self.network_manager.scan(device).await?)
+    }
+
+    async fn saved_networks(&self) -> Result<Vec<SavedNetwork>, Error> {
+        Ok(self.network_manager.saved_networks().await?)
+    }
+
+    async fn forget_network(&self, uuid: &str) -> Result<Vec<SavedNetwork>, Error> {
+        Ok(self.network_manager.forget(uuid).await?)
+    }
+
+    async fn set_network_autoconnect(
+        &self,
+        uuid: &str,
+        enabled: bool,
+    ) -> Result<Vec<SavedNetwork>, Error> {
+        Ok(self.network_manager.set_autoconnect(uuid, enabled).await?)
+    }
+
+    async fn set_network_priority(
+        &self,
+        uuid: &str,
+        priority: i32,
+    ) -> Result<Vec<SavedNetwork>, Error> {
+        Ok(self.network_manager.set_priority(uuid, priority).await?)
+    }
+
+    async fn connect_wifi(
+        &self,
+        device: &str,
+        ssid: &str,
+        passphrase: Option<&str>,
+    ) -> Result<Vec<AccessPoint>, Error> {
+        Ok(self
+            .network_manager
+            .connect(device, ssid, passphrase)
+            .await?)
+    }
+
+    async fn disconnect_wifi(&self, device: &str) -> Result<Vec<AccessPoint>, Error> {
+        Ok(self.network_manager.disconnect(device).await?)
+    }
+
+    async fn wifi_link(&self, device: &str) -> Result<Option<Link>, Error> {
+        let device = device.to_string();
+        blocking(move || {
+            let mut nl = Nl80211::connect()?;
+            let iface = nl.interface(&device)?;
+            nl.link(iface.ifindex)
+        })
+        .await
+    }
+
+    async fn gateway(&self, device: &str) -> Result<Option<Ipv4Addr>, Error> {
+        let device = device.to_string();
+        blocking(move || icmp::gateway(&device)).await
+    }
+
+    async fn ping(&self, addr: Ipv4Addr, timeout: Duration) -> Result<Option<Duration>, Error> {
+        blocking(move || icmp::ping(addr, timeout)).await
+    }
+
+    async fn unit_state(&self, unit: &str) -> Result<UnitState, Error> {
+        Ok(self.systemd.unit_state(unit).await?)
+    }
+
+    async fn start_unit(&self, unit: &str) -> Result<UnitState, Error> {
+        Ok(self.systemd.start_unit(unit).await?)
+    }
+
+    async fn stop_unit(&self, unit: &str) -> Result<UnitState, Error> {
+        Ok(self.systemd.stop_unit(unit).await?)
+    }
+
+    async fn set_unit_enabled(&self, unit: &str, enabled: bool) -> Result<UnitState, Error> {
+        Ok(self.systemd.set_unit_enabled(unit, enabled).await?)
+    }
+
+    async fn reload_unit(&self, unit: &str) -> Result<UnitState, Error> {
+        Ok(self.systemd.reload_unit(unit).await?)
+    }
+
+    async fn sshd_options(&self) -> Result<sshd_config::Options, Error> {
+        let sshd_config = self.sshd_config.clone();
+        blocking(move || sshd_config.options()).await
+    }
+
+    async fn set_sshd_options(
+        &self,
+        options: sshd_config::Options,
+    ) -> Result<sshd_config::Options, Error> {
+        let sshd_config = self.sshd_config.clone();
+        blocking(move || sshd_config.set_options(&options)).await
+    }
+
+    async fn authorized_keys(&self, user: &str) -> Result<Vec<AuthorizedKey>, Error> {
+        let user = user.to_string();
+        blocking(move || Ok::<_, Error>(authorized_keys_of(&user)?.list()?)).await
+    }
+
+    async fn add_authorized_key(
+        &self,
+        user: &str,
+        key: AuthorizedKey,
+    ) -> Result<Vec<AuthorizedKey>, Error> {
+        let user = user.to_string();
+        blocking(move || Ok::<_, Error>(authorized_keys_of(&user)?.add(&key)?)).await
+    }
+
+    async fn remove_authorized_key(
+        &self,
+        user: &str,
+        fingerprint: &str,
+    ) -> Result<Vec<AuthorizedKey>, Error> {
+        let (user, fingerprint) = (user.to_string(), fingerprint.to_string());
+        blocking(move || Ok::<_, Error>(authorized_keys_of(&user)?.remove(&fingerprint)?)).await
+    }
+
+    async fn events(&self) -> Result<BoxStream<'static, SystemEvent>, Error> {
+        let resumed = self
+            .logind
+            .resumed()
+            .await
+            .map_err(|err| Error::Unavailable(format!("D-Bus error: {err}")))?
+            .map(|_| SystemEvent::Resumed);
+
+        // The multicast socket blocks forever, so it gets its own thread instead of the pool
+        let (tx, rx) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let result = Nl80211::watch_new_interfaces(|iface| {
+                tx.send(SystemEvent::InterfaceAdded(iface.name)).is_ok()
+            });
+            if let Err(err) = result {
+                tracing::warn!("unable to watch for new wifi interfaces: {err}");
+            }
+        });
+
+        Ok(stream::select(resumed, UnboundedReceiverStream::new(rx)).boxed())
+    }
+
+    async fn bluetooth_powered(&self) -> Result<bool, Error> {
+        Ok(self.bluez.powered().await?)
+    }
+
+    async fn set_bluetooth_powered(&self, powered: bool) -> Result<bool, Error> {
+        Ok(self.bluez.set_powered(powered).await?)
+    }
+
+    async fn bluetooth_devices(&self) -> Result<Vec<bluez::Device>, Error> {
+        Ok(self.bluez.devices().await?)
+    }
+
+    async fn connect_bluetooth(&self, address: &str) -> Result<bluez::Device, Error> {
+        Ok(self.bluez.connect(address).await?)
+    }
+
+    async fn disconnect_bluetooth(&self, address: &str) -> Result<bluez::Device, Error> {
+        Ok(self.bluez.disconnect(address).await?)
+    }
+
+    async fn remove_bluetooth(&self, address: &str) -> Result<(), Error> {
+        Ok(self.bluez.remove(address).await?)
+    }
+
+    async fn start_bluetooth_pairing(&self) -> Result<bluez::PairingSession, Error> {
+        Ok(self.bluez.start_pairing().await?)
+    }
+
+    async fn pair_bluetooth(&self, address: &str) -> Result<bluez::Device, Error> {
+        Ok(self.bluez.pair(address).await?)
+    }
+
+    async fn cpu_state(&self) -> Result<CpuState, Error> {
+        let cpu = self.cpu.clone();
+        blocking(move || cpu.state()).await
+    }
+
+    async fn set_cpu_governor(&self, governor: &str) -> Result<CpuState, Error> {
+        let governor = governor.to_string();
+        let cpu = self.cpu.clone();
+        blocking(move || {
+            cpu.set_governor(&governor)?;
+            cpu.state()
+        })
+        .await
+    }
+
+    async fn set_cpu_smt(&self, enabled: bool) -> Result<CpuState, Error> {
+        let cpu = self.cpu.clone();
+        blocking(move || {
+            cpu.set_smt(enabled)?;
+            cpu.state()
+        })
+        .await
+    }
+
+    async fn set_cpu_boost(&self, enabled: bool) -> Result<CpuState, Error> {
+        let cpu = self.cpu.clone();
+        blocking(move || {
+            cpu.set_boost(enabled)?;
+            cpu.state()
+        })
+        .await
+    }
+
+    async fn set_cpu_max_frequency(&self, khz: u32) -> Result<CpuState, Error> {
+        let cpu = self.cpu.clone();
+        blocking(move || {
+            cpu.set_max_frequency(khz)?;
+            cpu.state()
+        })
+        .await
+    }
+
+    async fn sensors(&self) -> Result<Vec<hwmon::Reading>, Error> {
+        let hwmon = self.hwmon.clone();
+        blocking(move || hwmon.readings()).await
+    }
+
+    async fn fan_mode(&self, device: &str) -> Result<u8, Error> {
+        let (hwmon, device) = (self.hwmon.clone(), device.to_string());
+        blocking(move || hwmon.fan_mode(&device)).await
+    }
+
+    async fn set_fan_mode(&self, device: &str, mode: u8) -> Result<(), Error> {
+        let (hwmon, device) = (self.hwmon.clone(), device.to_string());
+        blocking(move || hwmon.set_fan_mode(&device, mode)).await
+    }
+
+    fn restore_fan_mode(&self, device: &str, mode: u8) -> Result<(), Error> {
+        Ok(self.hwmon.set_fan_mode(device, mode)?)
+    }
+
+    async fn set_fan_pwm(&self, device: &str, pwm: u8) -> Result<(), Error> {
+        let (hwmon, device) = (self.hwmon.clone(), device.to_string());
+        blocking(move || hwmon.set_fan_pwm(&device, pwm)).await
+    }
+
+    async fn backlights(&self) -> Result<Vec<Backlight>, Error> {
+        let backlights = self.backlights.clone();
+        blocking(move || backlights.list()).await
+    }
+
+    async fn set_backlight(&self, name: &str, brightness: u32) -> Result<Backlight, Error> {
+        let (backlights, name) = (self.backlights.clone(), name.to_string());
+        blocking(move || backlights.set(&name, brightness)).await
+    }
+
+    async fn battery(&self) -> Result<Battery, Error> {
+        let power_supply = self.power_supply.clone();
+        blocking(move || power_supply.battery()).await
+    }
+
+    async fn set_charge_limit(&self, percent: u32) -> Result<Battery, Error> {
+        let power_supply = self.power_supply.clone();
+        blocking(move || power_supply.set_charge_limit(percent)).await
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use shortcut_core::tokio::sync::oneshot;
+    use shortcut_core::wifi::InterfaceType;
+
+    use super::*;
+    use crate::battery::power_supply;
+    use crate::bluetooth::agent::{AgentEvent, Answer, Prompt, PromptKind};
+    use crate::wifi::network_manager::Security;
+
+    /// The methods of [`SystemBackend`] that [`FakeBackend::fail`] can make fail.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Method {
+        AccessPoints,
+        AddAuthorizedKey,
+        AuthorizedKeys,
+        Backlights,
+        Battery,
+        BluetoothDevices,
+        BluetoothPowered,
+        ConnectBluetooth,
+        ConnectWifi,
+        ConnectionPowerSave,
+        CpuState,
+        DisconnectBluetooth,
+        DisconnectWifi,
+        Events,
+        FanMode,
+        ForgetNetwork,
+        Gateway,
+        GlobalPowerSave,
+        PairBluetooth,
+        Ping,
+        PowerSave,
+        ReloadUnit,
+        RemoveAuthorizedKey,
+        RemoveBluetooth,
+        RestoreFanMode,
+        SavedNetworks,
+        ScanWifi,
+        Sensors,
+        SetBacklight,
+        SetBluetoothPowered,
+        SetChargeLimit,
+        SetConnectionPowerSave,
+        SetCpuBoost,
+        SetCpuGovernor,
+        SetCpuMaxFrequency,
+        SetCpuSmt,
+        SetFanMode,
+        SetFanPwm,
+        SetGlobalPowerSave,
+        SetNetworkAutoconnect,
+        SetNetworkPriority,
+        SetPowerSave,
+        SetSshdOptions,
+        SetUnitEnabled,
+        SshdOptions,
+        StartBluetoothPairing,
+        StartUnit,
+        StopUnit,
+        UnitState,
+        WifiInterfaces,
+        WifiLink,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Call {
+        WifiInterfaces,
+        PowerSave(String),
+        SetPowerSave(String, bool),
+        ConnectionPowerSave(String),
+        SetConnectionPowerSave(String, bool),
+        GlobalPowerSave,
+        SetGlobalPowerSave(bool),
+        AccessPoints(String),
+        ScanWifi(String),
+        SavedNetworks,
+        ForgetNetwork(String),
+        SetNetworkAutoconnect(String, bool),
+        SetNetworkPriority(String, i32),
+        /// The device, the SSID and the passphrase.
+        ConnectWifi(String, String, Option<String>),
+        DisconnectWifi(String),
+        WifiLink(String),
+        Gateway(String),
+        Ping(Ipv4Addr),
+        UnitState(String),
+        StartUnit(String),
+        StopUnit(String),
+        SetUnitEnabled(String, bool),
+        ReloadUnit(String),
+        SshdOptions,
+        SetSshdOptions(sshd_config::Options),
+        AuthorizedKeys(String),
+        /// The user and the fingerprint of the key.
+        AddAuthorizedKey(String, String),
+        RemoveAuthorizedKey(String, String),
+        Events,
+        BluetoothPowered,
+        SetBluetoothPowered(bool),
+        BluetoothDevices,
+        ConnectBluetooth(String),
+        DisconnectBluetooth(String),
+        RemoveBluetooth(String),
+        StartBluetoothPairing,
+        PairBluetooth(String),
+        CpuState,
+        SetCpuGovernor(String),
+        SetCpuSmt(bool),
+        SetCpuBoost(bool),
+        SetCpuMaxFrequency(u32),
+        Sensors,
+        FanMode,
+        SetFanMode(u8),
+        RestoreFanMode(u8),
+        SetFanPwm(u8),
+        Backlights,
+        SetBacklight(String, u32),
+        Battery,
+        SetChargeLimit(u32),
+    }
+
+    #[derive(Default)]
+    struct State {
+        calls: Vec<Call>,
+        interfaces: Vec<Interface>,
+        power_save: HashMap<String, bool>,
+        /// What the connection of every device keeps, whether connected or not.
+        connection_power_save: HashMap<String, bool>,
+        global_power_save: Option<bool>,
+        access_points: Vec<AccessPoint>,
+        saved_networks: Vec<SavedNetwork>,
+        /// The connection of every connected device.
+        links: HashMap<String, Link>,
+        gateways: HashMap<String, Ipv4Addr>,
+        /// Round trips the pings take in turn, `None` for a lost one.
+        latencies: Vec<Option<Duration>>,
+        pings: usize,
+        /// Bytes every link carries between two reads.
+        traffic: u64,
+        units: HashMap<String, UnitState>,
+        sshd_options: sshd_config::Options,
+        /// The keys of every user.
+        authorized_keys: HashMap<String, Vec<AuthorizedKey>>,
+        /// Errors returned by the next call to the named method.
+        failures: HashMap<Method, Error>,
+        /// Overrides the state the device reports after `set_power_save`.
+        sticky_power_save: Option<bool>,
+        events: Option<mpsc::UnboundedSender<SystemEvent>>,
+        /// `None` until an adapter is added.
+        bluetooth_powered: Option<bool>,
+        bluetooth_devices: Vec<bluez::Device>,
+        /// Unpaired devices `pair_bluetooth` can pair with.
+        discoverable: Vec<bluez::Device>,
+        pairing: Option<(
+            mpsc::UnboundedSender<bluez::Device>,
+            mpsc::UnboundedSender<AgentEvent>,
+        )>,
+        /// `None` without cpufreq.
+        cpu: Option<CpuState>,
+        sensors: Vec<hwmon::Reading>,
+        /// `pwm1_enable` and `pwm1`, `None` without a fan.
+        fan: Option<(u8, u8)>,
+        /// Sensors and the fan come from these files instead when set.
+        hwmon: Option<Hwmon>,
+        backlights: Vec<Backlight>,
+        /// `None` without a battery.
+        battery: Option<Battery>,
+    }
+
+    /// Scriptable [`SystemBackend`] that records every call.
+    #[derive(Default)]
+    pub struct FakeBackend {
+        state: Mutex<State>,
+    }
+
+    pub fn interface(name: &str, ifindex: u32) -> Interface {
+        Interface {
+            ifindex,
+            name: name.to_string(),
+            mac: [0x02, 0, 0, 0, 0, ifindex as u8],
+            iftype: InterfaceType::Station,
+            phy: 0,
+        }
+    }
+
+    pub fn access_point(ssid: &str, strength: u8, security: Security) -> AccessPoint {
+        AccessPoint {
+            ssid: ssid.to_string(),
+            bssid: format!("02:00:00:00:00:{strength:02x}"),
+            strength,
+            frequency: 5180,
+            security,
+            active: false,
+            saved: false,
+        }
+    }
+
+    pub fn saved_network(id: &str, ssid: &str) -> SavedNetwork {
+        SavedNetwork {
+            id: id.to_string(),
+            uuid: format!("uuid-{id}"),
+            ssid: ssid.to_string(),
+            autoconnect: true,
+            priority: 0,
+        }
+    }
+
+    /// A connection on channel 36 with a strong signal.
+    pub fn link(ssid: &str) -> Link {
+        Link {
+            ssid: ssid.to_string(),
+            bssid: [0x02, 0, 0, 0, 0, 0x46],
+            frequency: 5180,
+            signal: -52,
+            tx_bitrate: 866_700,
+            rx_bitrate: 780_000,
+            tx_retries: 12,
+            tx_failed: 0,
+            rx_bytes: 52_428_800,
+            tx_bytes: 1_048_576,
+        }
+    }
+
+    pub fn unit_state(active_state: &str, sub_state: &str) -> UnitState {
+        UnitState {
+            active_state: active_state.to_string(),
+            sub_state: sub_state.to_string(),
+            unit_file_state: "disabled".to_string(),
+        }
+    }
+
+    pub fn bluetooth_device(address: &str, name: &str) -> bluez::Device {
+        bluez::Device {
+            address: address.to_string(),
+            name: name.to_string(),
+            connected: false,
+            battery: None,
+        }
+    }
+
+    /// A CPU with SMT and boost on, running at up to 3.5 GHz.
+    pub fn cpu_state() -> CpuState {
+        CpuState {
+            governor: "schedutil".to_string(),
+            available_governors: vec![
+                "powersave".to_string(),
+                "performance".to_string(),
+                "schedutil".to_string(),
+            ],
+            smt: Some(true),
+            boost: Some(true),
+            max_frequency: 3500000,
+            hardware_min_frequency: 1400000,
+            hardware_max_frequency: 3500000,
+        }
+    }
+
+    pub fn sensor(device: &str, label: &str, kind: hwmon::Kind, value: f64) -> hwmon::Reading {
+        hwmon::Reading {
+            device: device.to_string(),
+            label: label.to_string(),
+            kind,
+            value: Ok(value),
+        }
+    }
+
+    pub fn backlight(name: &str, brightness: u32, max_brightness: u32) -> Backlight {
+        Backlight {
+            name: name.to_string(),
+            brightness,
+            max_brightness,
+        }
+    }
+
+    /// A discharging battery at 80 % that can be limited.
+    pub fn battery_state() -> Battery {
+        Battery {
+            name: "BAT1".to_string(),
+            capacity: 80,
+            status: power_supply::Status::Discharging,
+            health: Some(94.0),
+            cycle_count: Some(153),
+            power: Some(9.5),
+            time_to_empty: Some(10800),
+            time_to_full: None,
+            charge_limit: Some(100),
+        }
+    }
+
+    impl FakeBackend {
+        pub fn with_interface(self, iface: Interface, power_save: bool) -> Self {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.power_save.insert(iface.name.clone(), power_save);
+                state.interfaces.push(iface);
+            }
+            self
+        }
+
+        pub fn with_access_point(self, ap: AccessPoint) -> Self {
+            self.state.lock().unwrap().access_points.push(ap);
+            self
+        }
+
+        pub fn with_link(self, device: &str, link: Link) -> Self {
+            self.state
+                .lock()
+                .unwrap()
+                .links
+                .insert(device.to_string(), link);
+            self
+        }
+
+        /// Changes the connection of the device behind the daemon's back.
+        pub fn set_link(&self, device: &str, link: Option<Link>) {
+            let mut state = self.state.lock().unwrap();
+            match link {
+                Some(link) => state.links.insert(device.to_string(), link),
+                None => state.links.remove(device),
+            };
+        }
+
+        pub fn with_gateway(self, device: &str, gateway: Ipv4Addr) -> Self {
+            self.state
+                .lock()
+                .unwrap()
+                .gateways
+                .insert(device.to_string(), gateway);
+            self
+        }
+
+        /// Makes the pings take `latencies` milliseconds in turn, `None` loses one.
+        pub fn set_latencies(&self, latencies: &[Option<u64>]) {
+            let mut state = self.state.lock().unwrap();
+            state.latencies = latencies
+                .iter()
+                .map(|ms| ms.map(Duration::from_millis))
+                .collect();
+            state.pings = 0;
+        }
+
+        pub fn set_traffic(&self, bytes: u64) {
+            self.state.lock().unwrap().traffic = bytes;
+        }
+
+        pub fn with_saved_network(self, network: SavedNetwork) -> Self {
+            {
+                let mut state = self.state.lock().unwrap();
+                for ap in state.access_points.iter_mut() {
+                    ap.saved |= ap.ssid == network.ssid;
+                }
+                state.saved_networks.push(network);
+            }
+            self
+        }
+
+        pub fn with_unit(self, unit: &str, unit_state: UnitState) -> Self {
+            self.state
+                .lock()
+                .unwrap()
+                .units
+                .insert(unit.to_string(), unit_state);
+            self
+        }
+
+        pub fn with_authorized_key(self, user: &str, key: AuthorizedKey) -> Self {
+            self.state
+                .lock()
+                .unwrap()
+                .authorized_keys
+                .entry(user.to_string())
+                .or_default()
+                .push(key);
+            self
+        }
+
+        pub fn with_bluetooth_adapter(self, powered: bool) -> Self {
+            self.state.lock().unwrap().bluetooth_powered = Some(powered);
+            self
+        }
+
+        pub fn with_bluetooth_device(self, device: bluez::Device) -> Self {
+            self.state.lock().unwrap().bluetooth_devices.push(device);
+            self
+        }
+
+        pub fn with_cpu(self, cpu: CpuState) -> Self {
+            self.state.lock().unwrap().cpu = Some(cpu);
+            self
+        }
+
+        pub fn with_sensor(self, reading: hwmon::Reading) -> Self {
+            self.state.lock().unwrap().sensors.push(reading);
+            self
+        }
+
+        pub fn with_fan(self, mode: u8) -> Self {
+            self.state.lock().unwrap().fan = Some((mode, 0));
+            self
+        }
+
+        /// Reads sensors and drives the fan through a hwmon tree, like [`LinuxBackend`].
+        pub fn with_hwmon(self, hwmon: Hwmon) -> Self {
+            self.state.lock().unwrap().hwmon = Some(hwmon);
+            self
+        }
+
+        pub fn with_backlight(self, backlight: Backlight) -> Self {
+            self.state.lock().unwrap().backlights.push(backlight);
+            self
+        }
+
+        pub fn with_battery(self, battery: Battery) -> Self {
+            self.state.lock().unwrap().battery = Some(battery);
+            self
+        }
+
+        /// The `pwm1_enable` mode and `pwm1` duty cycle the fan was left at.
+        pub fn fan(&self) -> (u8, u8) {
+            self.state.lock().unwrap().fan.expect("no fan")
+        }
+
+        /// Changes the value of the sensor with `label`, like the hardware would.
+        pub fn set_sensor_value(&self, label: &str, value: f64) {
+            let mut state = self.state.lock().unwrap();
+            for reading in state.sensors.iter_mut().filter(|r| r.label == label) {
+                reading.value = Ok(value);
+            }
+        }
+
+        /// Lets a pairing session discover `device`.
+        pub fn with_discoverable_device(self, device: bluez::Device) -> Self {
+            self.state.lock().unwrap().discoverable.push(device);
+            self
+        }
+
+        /// Whether the last pairing session was dropped.
+        pub fn pairing_ended(&self) -> bool {
+            let state = self.state.lock().unwrap();
+            let (discovered, _) = state.pairing.as_ref().expect("pairing never started");
+            discovered.is_closed()
+        }
+
+        /// Cancels the open prompts like BlueZ does when the device gives up.
+        pub fn cancel_bluetooth_prompts(&self) {
+            let state = self.state.lock().unwrap();
+            let (_, prompts) = state.pairing.as_ref().expect("pairing never started");
+            prompts.send(AgentEvent::Cancelled).unwrap();
+        }
+
+        /// Makes the device ignore `set_power_save` and keep reporting `enabled`.
+        pub fn with_sticky_power_save(self, enabled: bool) -> Self {
+            self.state.lock().unwrap().sticky_power_save = Some(enabled);
+            self
+        }
+
+        pub fn with_connection_power_save(self, device: &str, enabled: bool) -> Self {
+            self.state
+                .lock()
+                .unwrap()
+                .connection_power_save
+                .insert(device.to_string(), enabled);
+            self
+        }
+
+        pub fn with_global_power_save(self, enabled: bool) -> Self {
+            self.state.lock().unwrap().global_power_save = Some(enabled);
+            self
+        }
+
+        /// Changes the power save state behind the daemon's back, e.g. after a resume.
+        pub fn reset_power_save(&self, device: &str, enabled: bool) {
+            self.state
+                .lock()
+                .unwrap()
+                .power_save
+                .insert(device.to_string(), enabled);
+        }
+
+        /// Delivers `event` to the stream returned by `events`.
+        pub fn emit(&self, event: SystemEvent) {
+            let state = self.state.lock().unwrap();
+            let events = state.events.as_ref().expect("nobody is watching events");
+            events.send(event).unwrap();
+        }
+
+        pub fn fail(&self, method: Method, err: Error) {
+            self.state.lock().unwrap().failures.insert(method, err);
+        }
+
+        pub fn calls(&self) -> Vec<Call> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        fn record(&self, method: Method, call: Call) -> Result<(), Error> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(call);
+            match state.failures.remove(&method) {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        }
+
+        fn wifi_device(state: &State, device: &str) -> Result<(), Error> {
+            if !state.interfaces.iter().any(|iface| iface.name == device) {
+                return Err(Error::not_found(format!("wifi device {device}")));
+            }
+            Ok(())
+        }
+
+        /// Applies `f` to the saved network with `uuid` and returns them all by priority.
+        fn update_saved(
+            &self,
+            uuid: &str,
+            f: impl FnOnce(&mut SavedNetwork),
+        ) -> Result<Vec<SavedNetwork>, Error> {
+            let mut state = self.state.lock().unwrap();
+            let network = state
+                .saved_networks
+                .iter_mut()
+                .find(|network| network.uuid == uuid)
+                .ok_or_else(|| Error::not_found(format!("saved network {uuid}")))?;
+            f(network);
+            state
+                .saved_networks
+                .sort_by_key(|network| std::cmp::Reverse(network.priority));
+            Ok(state.saved_networks.clone())
+        }
+
+        fn set_unit(&self, unit: &str, active: bool) -> Result<UnitState, Error> {
+            let mut state = self.state.lock().unwrap();
+            let unit_state = state
+                .units
+                .get_mut(unit)
+                .ok_or_else(|| Error::not_found(format!("unit {unit}")))?;
+            unit_state.active_state = if active { "active" } else { "inactive" }.to_string();
+            unit_state.sub_state = if active { "running" } else { "dead" }.to_string();
+            Ok(unit_state.clone())
+        }
+
+        /// Applies `f` to the CPU state and returns the result.
+        fn update_cpu(&self, f: impl FnOnce(&mut CpuState)) -> Result<CpuState, Error> {
+            let mut state = self.state.lock().unwrap();
+            let cpu = state
+                .cpu
+                .as_mut()
+                .ok_or_else(|| Error::Unavailable("cpufreq is not available".to_string()))?;
+            f(cpu);
+            Ok(cpu.clone())
+        }
+
+        fn hwmon(&self) -> Option<Hwmon> {
+            self.state.lock().unwrap().hwmon.clone()
+        }
+
+        fn update_fan(&self, f: impl FnOnce(&mut (u8, u8))) -> Result<(u8, u8), Error> {
+            let mut state = self.state.lock().unwrap();
+            let fan = state
+                .fan
+                .as_mut()
+                .ok_or_else(|| Error::Unavailable("no controllable fan found".to_string()))?;
+            f(fan);
+            Ok(*fan)
+        }
+
+        fn bluetooth_adapter(state: &State) -> Result<bool, Error> {
+            state
+                .bluetooth_powered
+                .ok_or_else(|| Error::Unavailable("no bluetooth adapter found".to_string()))
+        }
+
+        fn set_connected(&self, address: &str, connected: bool) -> Result<bluez::Device, Error> {
+            let mut state = self.state.lock().unwrap();
+            Self::bluetooth_adapter(&state)?;
+            let device = state
+                .bluetooth_devices
+                .iter_mut()
+                .find(|device| device.address == address)
+                .ok_or_else(|| Error::not_found(format!("bluetooth device {address}")))?;
+            device.connected = connected;
+            Ok(device.clone())
+        }
+    }
+
+    #[tonic::async_trait]
+    impl SystemBackend for FakeBackend {
+        async fn wifi_interfaces(&self) -> Result<Vec<Interface>, Error> {
+            self.record(Method::WifiInterfaces, Call::WifiInterfaces)?;
+            Ok(self.state.lock().unwrap().interfaces.clone())
+        }
+
+        async fn power_save(&self, device: &str) -> Result<bool, Error> {
+            self.record(Method::PowerSave, Call::PowerSave(device.to_string()))?;
+            self.state
+                .lock()
+                .unwrap()
+                .power_save
+                .get(device)
+                .copied()
+                .ok_or_else(|| Error::not_found(format!("wifi device {device}")))
+        }
+
+        async fn set_power_save(&self, device: &str, enabled: bool) -> Result<bool, Error> {
+            self.record(
+                Method::SetPowerSave,
+                Call::SetPowerSave(device.to_string(), enabled),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            let enabled = state.sticky_power_save.unwrap_or(enabled);
+            let value = state
+                .power_save
+                .get_mut(device)
+                .ok_or_else(|| Error::not_found(format!("wifi device {device}")))?;
+            *value = enabled;
+            Ok(enabled)
+        }
+
+        async fn connection_power_save(&self, device: &str) -> Result<Option<bool>, Error> {
+            self.record(
+                Method::ConnectionPowerSave,
+                Call::ConnectionPowerSave(device.to_string()),
+            )?;
+            let state = self.state.lock().unwrap();
+            if !state.links.contains_key(device) {
+                return Ok(None);
+            }
+            Ok(state.connection_power_save.get(device).copied())
+        }
+
+        async fn set_connection_power_save(
+            &self,
+            device: &str,
+            enabled: bool,
+        ) -> Result<(), Error> {
+            self.record(
+                Method::SetConnectionPowerSave,
+                Call::SetConnectionPowerSave(device.to_string(), enabled),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            Self::wifi_device(&state, device)?;
+            if !state.links.contains_key(device) {
+                return Err(Error::Unavailable(format!("{device} is not connected")));
+            }
+            state
+                .connection_power_save
+                .insert(device.to_string(), enabled);
+            Ok(())
+        }
+
+        async fn global_power_save(&self) -> Result<Option<bool>, Error> {
+            self.record(Method::GlobalPowerSave, Call::GlobalPowerSave)?;
+            Ok(self.state.lock().unwrap().global_power_save)
+        }
+
+        async fn set_global_power_save(&self, enabled: bool) -> Result<(), Error> {
+            self.record(
+                Method::SetGlobalPowerSave,
+                Call::SetGlobalPowerSave(enabled),
+            )?;
+            self.state.lock().unwrap().global_power_save = Some(enabled);
+            Ok(())
+        }
+
+        async fn access_points(&self, device: &str) -> Result<Vec<AccessPoint>, Error> {
+            self.record(Method::AccessPoints, Call::AccessPoints(device.to_string()))?;
+            let state = self.state.lock().unwrap();
+            Self::wifi_device(&state, device)?;
+            Ok(state.access_points.clone())
+        }
+
+        async fn scan_wifi(&self, device: &str) -> Result<Vec<AccessPoint>, Error> {
+            self.record(Method::ScanWifi, Call::ScanWifi(device.to_string()))?;
+            let state = self.state.lock().unwrap();
+            Self::wifi_device(&state, device)?;
+            Ok(state.access_points.clone())
+        }
+
+        async fn saved_networks(&self) -> Result<Vec<SavedNetwork>, Error> {
+            self.record(Method::SavedNetworks, Call::SavedNetworks)?;
+            Ok(self.state.lock().unwrap().saved_networks.clone())
+        }
+
+        async fn forget_network(&self, uuid: &str) -> Result<Vec<SavedNetwork>, Error> {
+            self.record(Method::ForgetNetwork, Call::ForgetNetwork(uuid.to_string()))?;
+            let mut state = self.state.lock().unwrap();
+            let before = state.saved_networks.len();
+            state.saved_networks.retain(|network| network.uuid != uuid);
+            if state.saved_networks.len() == before {
+                return Err(Error::not_found(format!("saved network {uuid}")));
+            }
+            Ok(state.saved_networks.clone())
+        }
+
+        async fn set_network_autoconnect(
+            &self,
+            uuid: &str,
+            enabled: bool,
+        ) -> Result<Vec<SavedNetwork>, Error> {
+            self.record(
+                Method::SetNetworkAutoconnect,
+                Call::SetNetworkAutoconnect(uuid.to_string(), enabled),
+            )?;
+            self.update_saved(uuid, |network| network.autoconnect = enabled)
+        }
+
+        async fn set_network_priority(
+            &self,
+            uuid: &str,
+            priority: i32,
+        ) -> Result<Vec<SavedNetwork>, Error> {
+            self.record(
+                Method::SetNetworkPriority,
+                Call::SetNetworkPriority(uuid.to_string(), priority),
+            )?;
+            self.update_saved(uuid, |network| network.priority = priority)
+        }
+
+        /// Connects to the strongest access point with the SSID, saving the network.
+        async fn connect_wifi(
+            &self,
+            device: &str,
+            ssid: &str,
+            passphrase: Option<&str>,
+        ) -> Result<Vec<AccessPoint>, Error> {
+            self.record(
+                Method::ConnectWifi,
+                Call::ConnectWifi(
+                    device.to_string(),
+                    ssid.to_string(),
+                    passphrase.map(str::to_string),
+                ),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            Self::wifi_device(&state, device)?;
+            let index = state
+                .access_points
+                .iter()
+                .position(|ap| ap.ssid == ssid)
+                .ok_or_else(|| Error::not_found(format!("wifi network {ssid}")))?;
+            let ap = &state.access_points[index];
+            if !ap.saved && ap.security != Security::Open && passphrase.is_none() {
+                return Err(Error::InvalidArgument(format!("{ssid} needs a passphrase")));
+            }
+
+            for (i, ap) in state.access_points.iter_mut().enumerate() {
+                ap.active = i == index;
+                ap.saved |= ap.ssid == ssid;
+            }
+            if !state.saved_networks.iter().any(|n| n.ssid == ssid) {
+                state.saved_networks.push(saved_network(ssid, ssid));
+            }
+            Ok(state.access_points.clone())
+        }
+
+        async fn disconnect_wifi(&self, device: &str) -> Result<Vec<AccessPoint>, Error> {
+            self.record(
+                Method::DisconnectWifi,
+                Call::DisconnectWifi(device.to_string()),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            Self::wifi_device(&state, device)?;
+            for ap in state.access_points.iter_mut() {
+                ap.active = false;
+            }
+            state.links.remove(device);
+            Ok(state.access_points.clone())
+        }
+
+        async fn wifi_link(&self, device: &str) -> Result<Option<Link>, Error> {
+            self.record(Method::WifiLink, Call::WifiLink(device.to_string()))?;
+            let mut state = self.state.lock().unwrap();
+            Self::wifi_device(&state, device)?;
+            let traffic = state.traffic;
+            Ok(state.links.get_mut(device).map(|link| {
+                link.rx_bytes += traffic;
+                link.clone()
+            }))
+        }
+
+        async fn gateway(&self, device: &str) -> Result<Option<Ipv4Addr>, Error> {
+            self.record(Method::Gateway, Call::Gateway(device.to_string()))?;
+            Ok(self.state.lock().unwrap().gateways.get(device).copied())
+        }
+
+        async fn ping(&self, addr: Ipv4Addr, timeout: Duration) -> Result<Option<Duration>, Error> {
+            self.record(Method::Ping, Call::Ping(addr))?;
+            let mut state = self.state.lock().unwrap();
+            if state.latencies.is_empty() {
+                return Ok(None);
+            }
+            let latency = state.latencies[state.pings % state.latencies.len()];
+            state.pings += 1;
+            Ok(latency.filter(|latency| *latency <= timeout))
+        }
+
+        async fn unit_state(&self, unit: &str) -> Result<UnitState, Error> {
+            self.record(Method::UnitState, Call::UnitState(unit.to_string()))?;
+            self.state
+                .lock()
+                .unwrap()
+                .units
+                .get(unit)
+                .cloned()
+                .ok_or_else(|| Error::not_found(format!("unit {unit}")))
+        }
+
+        async fn start_unit(&self, unit: &str) -> Result<UnitState, Error> {
+            self.record(Method::StartUnit, Call::StartUnit(unit.to_string()))?;
+            self.set_unit(unit, true)
+        }
+
+        async fn stop_unit(&self, unit: &str) -> Result<UnitState, Error> {
+            self.record(Method::StopUnit, Call::StopUnit(unit.to_string()))?;
+            self.set_unit(unit, false)
+        }
+
+        async fn set_unit_enabled(&self, unit: &str, enabled: bool) -> Result<UnitState, Error> {
+            self.record(
+                Method::SetUnitEnabled,
+                Call::SetUnitEnabled(unit.to_string(), enabled),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            let unit_state = state
+                .units
+                .get_mut(unit)
+                .ok_or_else(|| Error::not_found(format!("unit {unit}")))?;
+            unit_state.unit_file_state = if enabled { "enabled" } else { "disabled" }.to_string();
+            Ok(unit_state.clone())
+        }
+
+        async fn reload_unit(&self, unit: &str) -> Result<UnitState, Error> {
+            self.record(Method::ReloadUnit, Call::ReloadUnit(unit.to_string()))?;
+            self.state
+                .lock()
+                .unwrap()
+                .units
+                .get(unit)
+                .cloned()
+                .ok_or_else(|| Error::not_found(format!("unit {unit}")))
+        }
+
+        async fn sshd_options(&self) -> Result<sshd_config::Options, Error> {
+            self.record(Method::SshdOptions, Call::SshdOptions)?;
+            Ok(self.state.lock().unwrap().sshd_options.clone())
+        }
+
+        async fn set_sshd_options(
+            &self,
+            options: sshd_config::Options,
+        ) -> Result<sshd_config::Options, Error> {
+            self.record(
+                Method::SetSshdOptions,
+                Call::SetSshdOptions(options.clone()),
+            )?;
+            self.state.lock().unwrap().sshd_options = options.clone();
+            Ok(options)
+        }
+
+        async fn authorized_keys(&self, user: &str) -> Result<Vec<AuthorizedKey>, Error> {
+            self.record(
+                Method::AuthorizedKeys,
+                Call::AuthorizedKeys(user.to_string()),
+            )?;
+            let state = self.state.lock().unwrap();
+            Ok(state.authorized_keys.get(user).cloned().unwrap_or_default())
+        }
+
+        async fn add_authorized_key(
+            &self,
+            user: &str,
+            key: AuthorizedKey,
+        ) -> Result<Vec<AuthorizedKey>, Error> {
+            self.record(
+                Method::AddAuthorizedKey,
+                Call::AddAuthorizedKey(user.to_string(), key.fingerprint.clone()),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            let keys = state.authorized_keys.entry(user.to_string()).or_default();
+            if !keys.iter().any(|k| k.fingerprint == key.fingerprint) {
+                keys.push(key);
+            }
+            Ok(keys.clone())
+        }
+
+        async fn remove_authorized_key(
+            &self,
+            user: &str,
+            fingerprint: &str,
+        ) -> Result<Vec<AuthorizedKey>, Error> {
+            self.record(
+                Method::RemoveAuthorizedKey,
+                Call::RemoveAuthorizedKey(user.to_string(), fingerprint.to_string()),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            let keys = state.authorized_keys.entry(user.to_string()).or_default();
+            let before = keys.len();
+            keys.retain(|key| key.fingerprint != fingerprint);
+            if keys.len() == before {
+                return Err(Error::not_found(format!("authorized key {fingerprint}")));
+            }
+            Ok(keys.clone())
+        }
+
+        async fn events(&self) -> Result<BoxStream<'static, SystemEvent>, Error> {
+            self.record(Method::Events, Call::Events)?;
+            let (tx, rx) = mpsc::unbounded_channel();
+            self.state.lock().unwrap().events = Some(tx);
+            Ok(UnboundedReceiverStream::new(rx).boxed())
+        }
+
+        async fn bluetooth_powered(&self) -> Result<bool, Error> {
+            self.record(Method::BluetoothPowered, Call::BluetoothPowered)?;
+            Self::bluetooth_adapter(&self.state.lock().unwrap())
+        }
+
+        async fn set_bluetooth_powered(&self, powered: bool) -> Result<bool, Error> {
+            self.record(
+                Method::SetBluetoothPowered,
+                Call::SetBluetoothPowered(powered),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            Self::bluetooth_adapter(&state)?;
+            state.bluetooth_powered = Some(powered);
+            Ok(powered)
+        }
+
+        async fn bluetooth_devices(&self) -> Result<Vec<bluez::Device>, Error> {
+            self.record(Method::BluetoothDevices, Call::BluetoothDevices)?;
+            let state = self.state.lock().unwrap();
+            Self::bluetooth_adapter(&state)?;
+            Ok(state.bluetooth_devices.clone())
+        }
+
+        async fn connect_bluetooth(&self, address: &str) -> Result<bluez::Device, Error> {
+            self.record(
+                Method::ConnectBluetooth,
+                Call::ConnectBluetooth(address.to_string()),
+            )?;
+            self.set_connected(address, true)
+        }
+
+        async fn disconnect_bluetooth(&self, address: &str) -> Result<bluez::Device, Error> {
+            self.record(
+                Method::DisconnectBluetooth,
+                Call::DisconnectBluetooth(address.to_string()),
+            )?;
+            self.set_connected(address, false)
+        }
+
+        async fn remove_bluetooth(&self, address: &str) -> Result<(), Error> {
+            self.record(
+                Method::RemoveBluetooth,
+                Call::RemoveBluetooth(address.to_string()),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            Self::bluetooth_adapter(&state)?;
+            let before = state.bluetooth_devices.len();
+            state
+                .bluetooth_devices
+                .retain(|device| device.address != address);
+            if state.bluetooth_devices.len() == before {
+                return Err(Error::not_found(format!("bluetooth device {address}")));
+            }
+            Ok(())
+        }
+
+        async fn start_bluetooth_pairing(&self) -> Result<bluez::PairingSession, Error> {
+            self.record(Method::StartBluetoothPairing, Call::StartBluetoothPairing)?;
+            let mut state = self.state.lock().unwrap();
+            Self::bluetooth_adapter(&state)?;
+            let (discovered_tx, discovered) = mpsc::unbounded_channel();
+            let (prompts_tx, prompts) = mpsc::unbounded_channel();
+            for device in &state.discoverable {
+                discovered_tx.send(device.clone()).unwrap();
+            }
+            state.pairing = Some((discovered_tx, prompts_tx));
+            Ok(bluez::PairingSession::new(discovered, prompts, || {}))
+        }
+
+        /// Asks to confirm passkey 123456 and pairs on acceptance, like most phones do.
+        async fn pair_bluetooth(&self, address: &str) -> Result<bluez::Device, Error> {
+            self.record(
+                Method::PairBluetooth,
+                Call::PairBluetooth(address.to_string()),
+            )?;
+            let (tx, rx) = oneshot::channel();
+            {
+                let state = self.state.lock().unwrap();
+                let (_, prompts) = state
+                    .pairing
+                    .as_ref()
+                    .ok_or_else(|| Error::Unavailable("not pairing".to_string()))?;
+                prompts
+                    .send(AgentEvent::Prompt(Prompt {
+                        kind: PromptKind::ConfirmPasskey,
+                        address: address.to_string(),
+                        passkey: Some(123456),
+                        pin_code: None,
+                        reply: Some(tx),
+                    }))
+                    .map_err(|_| Error::Unavailable("pairing has ended".to_string()))?;
+            }
+            if rx.await != Ok(Answer::Accept) {
+                return Err(Error::Internal("authentication rejected".to_string()));
+            }
+
+            let mut state = self.state.lock().unwrap();
+            let index = state
+                .discoverable
+                .iter()
+                .position(|device| device.address == address)
+                .ok_or_else(|| Error::not_found(format!("bluetooth device {address}")))?;
+            let mut device = state.discoverable.remove(index);
+            device.connected = true;
+            state.bluetooth_devices.push(device.clone());
+            Ok(device)
+        }
+
+        async fn cpu_state(&self) -> Result<CpuState, Error> {
+            self.record(Method::CpuState, Call::CpuState)?;
+            self.update_cpu(|_| {})
+        }
+
+        async fn set_cpu_governor(&self, governor: &str) -> Result<CpuState, Error> {
+            self.record(
+                Method::SetCpuGovernor,
+                Call::SetCpuGovernor(governor.to_string()),
+            )?;
+            self.update_cpu(|cpu| cpu.governor = governor.to_string())
+        }
+
+        async fn set_cpu_smt(&self, enabled: bool) -> Result<CpuState, Error> {
+            self.record(Method::SetCpuSmt, Call::SetCpuSmt(enabled))?;
+            self.update_cpu(|cpu| cpu.smt = cpu.smt.map(|_| enabled))
+        }
+
+        async fn set_cpu_boost(&self, enabled: bool) -> Result<CpuState, Error> {
+            self.record(Method::SetCpuBoost, Call::SetCpuBoost(enabled))?;
+            self.update_cpu(|cpu| cpu.boost = cpu.boost.map(|_| enabled))
+        }
+
+        async fn set_cpu_max_frequency(&self, khz: u32) -> Result<CpuState, Error> {
+            self.record(Method::SetCpuMaxFrequency, Call::SetCpuMaxFrequency(khz))?;
+            self.update_cpu(|cpu| cpu.max_frequency = khz)
+        }
+
+        async fn sensors(&self) -> Result<Vec<hwmon::Reading>, Error> {
+            self.record(Method::Sensors, Call::Sensors)?;
+            if let Some(hwmon) = self.hwmon() {
+                return Ok(hwmon.readings()?);
+            }
+            Ok(self.state.lock().unwrap().sensors.clone())
+        }
+
+        async fn fan_mode(&self, device: &str) -> Result<u8, Error> {
+            self.record(Method::FanMode, Call::FanMode)?;
+            if let Some(hwmon) = self.hwmon() {
+                return Ok(hwmon.fan_mode(device)?);
+            }
+            Ok(self.update_fan(|_| {})?.0)
+        }
+
+        async fn set_fan_mode(&self, device: &str, mode: u8) -> Result<(), Error> {
+            self.record(Method::SetFanMode, Call::SetFanMode(mode))?;
+            if let Some(hwmon) = self.hwmon() {
+                return Ok(hwmon.set_fan_mode(device, mode)?);
+            }
+            self.update_fan(|fan| fan.0 = mode)?;
+            Ok(())
+        }
+
+        fn restore_fan_mode(&self, device: &str, mode: u8) -> Result<(), Error> {
+            self.record(Method::RestoreFanMode, Call::RestoreFanMode(mode))?;
+            if let Some(hwmon) = self.hwmon() {
+                return Ok(hwmon.set_fan_mode(device, mode)?);
+            }
+            self.update_fan(|fan| fan.0 = mode)?;
+            Ok(())
+        }
+
+        async fn set_fan_pwm(&self, device: &str, pwm: u8) -> Result<(), Error> {
+            self.record(Method::SetFanPwm, Call::SetFanPwm(pwm))?;
+            if let Some(hwmon) = self.hwmon() {
+                return Ok(hwmon.set_fan_pwm(device, pwm)?);
+            }
+            self.update_fan(|fan| fan.1 = pwm)?;
+            Ok(())
+        }
+
+        async fn backlights(&self) -> Result<Vec<Backlight>, Error> {
+            self.record(Method::Backlights, Call::Backlights)?;
+            Ok(self.state.lock().unwrap().backlights.clone())
+        }
+
+        async fn set_backlight(&self, name: &str, brightness: u32) -> Result<Backlight, Error> {
+            self.record(
+                Method::SetBacklight,
+                Call::SetBacklight(name.to_string(), brightness),
+            )?;
+            let mut state = self.state.lock().unwrap();
+            let backlight = state
+                .backlights
+                .iter_mut()
+                .find(|backlight| name.is_empty() || backlight.name == name)
+                .ok_or_else(|| match name {
+                    "" => Error::Unavailable("no backlight found".to_string()),
+                    name => Error::not_found(format!("backlight {name}")),
+                })?;
+            backlight.brightness = brightness.min(backlight.max_brightness);
+            Ok(backlight.clone())
+        }
+
+        async fn battery(&self) -> Result<Battery, Error> {
+            self.record(Method::Battery, Call::Battery)?;
+            self.state
+                .lock()
+                .unwrap()
+                .battery
+                .clone()
+                .ok_or_else(|| Error::Unavailable("no battery found".to_string()))
+        }
+
+        async fn set_charge_limit(&self, percent: u32) -> Result<Battery, Error> {
+            self.record(Method::SetChargeLimit, Call::SetChargeLimit(percent))?;
+            let mut state = self.state.lock().unwrap();
+            let battery = state
+                .battery
+                .as_mut()
+                .ok_or_else(|| Error::Unavailable("no battery found".to_string()))?;
+            if battery.charge_limit.is_none() {
+                return Err(Error::Unavailable(
+                    "the battery has no charge limit".to_string(),
+                ));
+            }
+            battery.charge_limit = Some(percent);
+            Ok(battery.clone())
+        }
+    }
+}
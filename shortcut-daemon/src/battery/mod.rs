@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::battery;
+use shortcut_core::battery::battery_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{Service, WatchStream};
+use crate::state::StateFile;
+
+pub(crate) mod power_supply;
+
+/// The lowest charge limit callers may set, lower ones leave too little for a day off the dock.
+const MIN_CHARGE_LIMIT: u32 = 50;
+
+pub struct BatteryServer {
+    backend: Arc<dyn SystemBackend>,
+    state: Arc<StateFile>,
+    service: Service,
+}
+
+impl BatteryServer {
+    pub fn new(
+        backend: Arc<dyn SystemBackend>,
+        config: SharedConfig,
+        state: Arc<StateFile>,
+    ) -> Self {
+        Self {
+            backend,
+            service: Service::new("battery", config, |config| config.battery.enabled),
+            state,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl battery_service_server::BatteryService for BatteryServer {
+    async fn get_battery(
+        &self,
+        request: Request<battery::GetBatteryRequest>,
+    ) -> Result<Response<battery::GetBatteryResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let battery = self.backend.battery().await.map_err(|err| {
+            tracing::error!("error when get_battery: {err}");
+            err
+        })?;
+
+        let reply = battery::GetBatteryResponse {
+            battery: Some(battery.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_charge_limit(
+        &self,
+        request: Request<battery::SetChargeLimitRequest>,
+    ) -> Result<Response<battery::SetChargeLimitResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        if !(MIN_CHARGE_LIMIT..=100).contains(&inner.percent) {
+            return Err(Error::InvalidArgument(format!(
+                "charge limit must be between {MIN_CHARGE_LIMIT} and 100 %, got {}",
+                inner.percent
+            ))
+            .into());
+        }
+
+        tracing::info!("Limiting charge to {} % for {caller}", inner.percent);
+        let battery = self
+            .backend
+            .set_charge_limit(inner.percent)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_charge_limit: {err}");
+                err
+            })?;
+        self.state.set_charge_limit(inner.percent);
+        self.service.notify();
+
+        let reply = battery::SetChargeLimitResponse {
+            battery: Some(battery.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchBatteryStream = WatchStream<battery::WatchBatteryResponse>;
+
+    async fn watch_battery(
+        &self,
+        request: Request<battery::WatchBatteryRequest>,
+    ) -> Result<Response<Self::WatchBatteryStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            async move {
+                let battery = backend.battery().await?;
+                Ok(battery::WatchBatteryResponse {
+                    battery: Some(battery.into()),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::battery::battery_service_server::BatteryService;
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::tokio;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::backend::fake::{battery_state, Call, FakeBackend};
+    use crate::config::{self, BatteryConfig, Config};
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, BatteryServer) {
+        let backend = Arc::new(backend);
+        let server = BatteryServer::new(
+            backend.clone(),
+            config::fixed(Config::default()),
+            Arc::new(StateFile::in_memory()),
+        );
+        (backend, server)
+    }
+
+    #[tokio::test]
+    async fn get_battery_uses_sentinels_for_unknown_values() {
+        let mut state = battery_state();
+        state.health = None;
+        state.cycle_count = None;
+        state.charge_limit = None;
+        let (_, server) = server(FakeBackend::default().with_battery(state));
+
+        let battery = server
+            .get_battery(Request::new(battery::GetBatteryRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .battery
+            .unwrap();
+
+        assert_eq!(battery.capacity_percent, 80);
+        assert_eq!(battery.status(), battery::ChargeStatus::Discharging);
+        assert_eq!(battery.health_percent, -1.0);
+        assert_eq!(battery.cycle_count, -1);
+        assert_eq!(battery.seconds_to_empty, 10800);
+        assert!(!battery.charge_limit_supported);
+    }
+
+    #[tokio::test]
+    async fn charge_limit_must_leave_a_usable_charge() {
+        let (backend, server) = server(FakeBackend::default().with_battery(battery_state()));
+
+        for percent in [0, 49, 101] {
+            let status = server
+                .set_charge_limit(Request::new(battery::SetChargeLimitRequest { percent }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{percent}");
+        }
+
+        let reply = server
+            .set_charge_limit(Request::new(battery::SetChargeLimitRequest { percent: 80 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.battery.unwrap().charge_limit_percent, 80);
+        assert_eq!(backend.calls(), vec![Call::SetChargeLimit(80)]);
+    }
+
+    #[tokio::test]
+    async fn set_charge_limit_remembers_the_limit() {
+        let state = Arc::new(StateFile::in_memory());
+        let server = BatteryServer::new(
+            Arc::new(FakeBackend::default().with_battery(battery_state())),
+            config::fixed(Config::default()),
+            state.clone(),
+        );
+
+        server
+            .set_charge_limit(Request::new(battery::SetChargeLimitRequest { percent: 80 }))
+            .await
+            .unwrap();
+
+        assert_eq!(state.desired().charge_limit, Some(80));
+    }
+
+    #[tokio::test]
+    async fn unsupported_charge_limit_is_unavailable() {
+        let mut state = battery_state();
+        state.charge_limit = None;
+        let (_, server) = server(FakeBackend::default().with_battery(state));
+
+        let status = server
+            .set_charge_limit(Request::new(battery::SetChargeLimitRequest { percent: 80 }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let backend = Arc::new(FakeBackend::default().with_battery(battery_state()));
+        let config = config::fixed(Config {
+            battery: BatteryConfig { enabled: false },
+            ..Config::default()
+        });
+        let server = BatteryServer::new(backend.clone(), config, Arc::new(StateFile::in_memory()));
+
+        let status = server
+            .get_battery(Request::new(battery::GetBatteryRequest {}))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_battery_follows_changes() {
+        let (_, mut server) = server(FakeBackend::default().with_battery(battery_state()));
+        server.service.interval = Duration::from_secs(3600);
+
+        let mut stream = server
+            .watch_battery(Request::new(battery::WatchBatteryRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = stream.next().await.unwrap().unwrap().battery.unwrap();
+        assert_eq!(first.charge_limit_percent, 100);
+
+        server
+            .set_charge_limit(Request::new(battery::SetChargeLimitRequest { percent: 80 }))
+            .await
+            .unwrap();
+
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        let next = next.unwrap().unwrap().unwrap().battery.unwrap();
+        assert_eq!(next.charge_limit_percent, 80);
+    }
+}
@@ -0,0 +1,412 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Where the kernel lists batteries and chargers.
+pub const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+#[derive(Debug)]
+pub enum Error {
+    /// There is no system battery, e.g. on a desktop.
+    NoBattery,
+    /// The driver offers no `charge_control_end_threshold`.
+    NoChargeLimit,
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        value: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoBattery => write!(f, "no battery found"),
+            Error::NoChargeLimit => write!(f, "the battery has no charge limit"),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+            Error::Parse { path, value } => {
+                write!(f, "unexpected value in {}: {value:?}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoBattery | Error::NoChargeLimit => {
+                shortcut_core::Error::Unavailable(err.to_string())
+            }
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            // The kernel answers EINVAL for thresholds the firmware refuses
+            Error::Io { err: ref io, .. } if io.raw_os_error() == Some(libc::EINVAL) => {
+                shortcut_core::Error::InvalidArgument(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// The `status` attribute of a battery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Unknown,
+    Charging,
+    Discharging,
+    /// Plugged in but held back, e.g. by the charge limit.
+    NotCharging,
+    Full,
+}
+
+impl Status {
+    fn parse(status: &str) -> Self {
+        match status {
+            "Charging" => Status::Charging,
+            "Discharging" => Status::Discharging,
+            "Not charging" => Status::NotCharging,
+            "Full" => Status::Full,
+            _ => Status::Unknown,
+        }
+    }
+}
+
+/// A battery as the kernel reports it, `None` for what the driver doesn't provide.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Battery {
+    pub name: String,
+    pub capacity: u32,
+    pub status: Status,
+    /// Full charge compared to the design capacity, in percent.
+    pub health: Option<f64>,
+    pub cycle_count: Option<u32>,
+    /// Power flowing in or out, in watts.
+    pub power: Option<f64>,
+    /// In seconds, only while discharging.
+    pub time_to_empty: Option<u64>,
+    /// In seconds, only while charging.
+    pub time_to_full: Option<u64>,
+    /// The `charge_control_end_threshold` in percent.
+    pub charge_limit: Option<u32>,
+}
+
+impl From<Battery> for shortcut_core::battery::Battery {
+    fn from(battery: Battery) -> Self {
+        use shortcut_core::battery::ChargeStatus;
+
+        let status = match battery.status {
+            Status::Unknown => ChargeStatus::Unspecified,
+            Status::Charging => ChargeStatus::Charging,
+            Status::Discharging => ChargeStatus::Discharging,
+            Status::NotCharging => ChargeStatus::NotCharging,
+            Status::Full => ChargeStatus::Full,
+        };
+        Self {
+            name: battery.name,
+            capacity_percent: battery.capacity,
+            status: status as i32,
+            health_percent: battery.health.unwrap_or(-1.0),
+            cycle_count: battery.cycle_count.map_or(-1, |count| count as i32),
+            power_watts: battery.power.unwrap_or_default(),
+            seconds_to_empty: battery.time_to_empty.unwrap_or_default(),
+            seconds_to_full: battery.time_to_full.unwrap_or_default(),
+            charge_limit_supported: battery.charge_limit.is_some(),
+            charge_limit_percent: battery.charge_limit.unwrap_or_default(),
+        }
+    }
+}
+
+/// Reads the attributes of a single power supply.
+struct Supply {
+    path: PathBuf,
+}
+
+impl Supply {
+    fn string(&self, attribute: &str) -> Result<Option<String>, Error> {
+        let path = self.path.join(attribute);
+        match fs::read_to_string(&path) {
+            Ok(value) => Ok(Some(value.trim().to_string())),
+            // Drivers answer ENODATA for values they can't tell right now
+            Err(err)
+                if err.kind() == io::ErrorKind::NotFound
+                    || err.raw_os_error() == Some(libc::ENODATA) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(Error::Io { path, err }),
+        }
+    }
+
+    /// A numeric attribute, `None` when it is missing.
+    fn number(&self, attribute: &str) -> Result<Option<f64>, Error> {
+        match self.string(attribute)? {
+            Some(value) => {
+                let number: f64 = value.parse().map_err(|_| Error::Parse {
+                    path: self.path.join(attribute),
+                    value,
+                })?;
+                Ok(Some(number))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// `power_now` or `current_now`, `None` when it is missing or zero. Drivers report zero
+    /// while they don't measure, e.g. right after plugging in.
+    fn flow(&self, attribute: &str) -> Result<Option<f64>, Error> {
+        Ok(self.number(attribute)?.filter(|flow| *flow != 0.0))
+    }
+
+    /// `energy_<attribute>`, or `charge_<attribute>` for drivers that only count charge.
+    fn energy_or_charge(&self, attribute: &str) -> Result<Option<f64>, Error> {
+        match self.number(&format!("energy_{attribute}"))? {
+            Some(energy) => Ok(Some(energy)),
+            None => self.number(&format!("charge_{attribute}")),
+        }
+    }
+
+    fn battery(&self, name: &str) -> Result<Battery, Error> {
+        let capacity = self.number("capacity")?.unwrap_or(0.0) as u32;
+        let status = Status::parse(&self.string("status")?.unwrap_or_default());
+
+        let now = self.energy_or_charge("now")?;
+        let full = self.energy_or_charge("full")?;
+        let design = self.energy_or_charge("full_design")?;
+        let health = match (full, design) {
+            (Some(full), Some(design)) if design > 0.0 => Some(full / design * 100.0),
+            _ => None,
+        };
+
+        // Energy is in µWh and power in µW, charge in µAh and current in µA
+        let uses_energy = self.number("energy_now")?.is_some();
+        let rate = if uses_energy {
+            self.flow("power_now")?
+        } else {
+            self.flow("current_now")?
+        }
+        .map(f64::abs);
+        let power = match self.flow("power_now")? {
+            Some(power) => Some(power.abs() / 1e6),
+            None => match (self.flow("current_now")?, self.number("voltage_now")?) {
+                (Some(current), Some(voltage)) => Some((current * voltage).abs() / 1e12),
+                _ => None,
+            },
+        };
+
+        let estimate = |amount: Option<f64>| match (amount, rate) {
+            (Some(amount), Some(rate)) if amount > 0.0 => Some((amount / rate * 3600.0) as u64),
+            _ => None,
+        };
+        let (time_to_empty, time_to_full) = match status {
+            Status::Discharging => {
+                let reported = self.number("time_to_empty_now")?.map(|secs| secs as u64);
+                (reported.or_else(|| estimate(now)), None)
+            }
+            Status::Charging => {
+                let reported = self.number("time_to_full_now")?.map(|secs| secs as u64);
+                let missing = match (full, now) {
+                    (Some(full), Some(now)) => Some(full - now),
+                    _ => None,
+                };
+                (None, reported.or_else(|| estimate(missing)))
+            }
+            _ => (None, None),
+        };
+
+        Ok(Battery {
+            name: name.to_string(),
+            capacity,
+            status,
+            health,
+            cycle_count: self.number("cycle_count")?.map(|count| count as u32),
+            power,
+            time_to_empty,
+            time_to_full,
+            charge_limit: self
+                .number("charge_control_end_threshold")?
+                .map(|limit| limit as u32),
+        })
+    }
+}
+
+/// The power supplies below a sysfs root, [`POWER_SUPPLY_ROOT`] outside of tests.
+#[derive(Debug, Clone)]
+pub struct PowerSupply {
+    root: PathBuf,
+}
+
+impl PowerSupply {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The first battery powering the system, skipping those of controllers and mice.
+    fn system_battery(&self) -> Result<(String, Supply), Error> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(Error::NoBattery),
+            Err(err) => {
+                return Err(Error::Io {
+                    path: self.root.clone(),
+                    err,
+                })
+            }
+        };
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| Some(entry.ok()?.file_name().to_str()?.to_string()))
+            .collect();
+        names.sort();
+        for name in names {
+            let supply = Supply {
+                path: self.root.join(&name),
+            };
+            let is_battery = supply.string("type")?.as_deref() == Some("Battery");
+            let is_peripheral = supply.string("scope")?.as_deref() == Some("Device");
+            if is_battery && !is_peripheral {
+                return Ok((name, supply));
+            }
+        }
+
+        Err(Error::NoBattery)
+    }
+
+    pub fn battery(&self) -> Result<Battery, Error> {
+        let (name, supply) = self.system_battery()?;
+        supply.battery(&name)
+    }
+
+    /// Stops charging at `percent`, the firmware may round it to the steps it supports.
+    pub fn set_charge_limit(&self, percent: u32) -> Result<Battery, Error> {
+        let (name, supply) = self.system_battery()?;
+        let path = supply.path.join("charge_control_end_threshold");
+        if !path.exists() {
+            return Err(Error::NoChargeLimit);
+        }
+
+        tracing::debug!("Writing {percent} to {}", path.display());
+        fs::write(&path, percent.to_string()).map_err(|err| Error::Io { path, err })?;
+        supply.battery(&name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    /// The Steam Deck battery, charge based, next to the AC adapter and a controller.
+    fn fake_power_supply(name: &str) -> FakeSysfs {
+        let sysfs = FakeSysfs::new(name);
+        sysfs.write("ACAD/type", "Mains");
+        sysfs.write("ACAD/online", "1");
+        sysfs.write("BAT1/type", "Battery");
+        sysfs.write("BAT1/status", "Charging");
+        sysfs.write("BAT1/capacity", "50");
+        sysfs.write("BAT1/charge_now", "2500000");
+        sysfs.write("BAT1/charge_full", "5000000");
+        sysfs.write("BAT1/charge_full_design", "5313000");
+        sysfs.write("BAT1/current_now", "2500000");
+        sysfs.write("BAT1/voltage_now", "8000000");
+        sysfs.write("BAT1/cycle_count", "153");
+        sysfs.write("hid-dualsense-battery/type", "Battery");
+        sysfs.write("hid-dualsense-battery/scope", "Device");
+        sysfs
+    }
+
+    #[test]
+    fn reads_the_system_battery() {
+        let sysfs = fake_power_supply("battery-read");
+
+        let battery = PowerSupply::new(sysfs.root()).battery().unwrap();
+
+        assert_eq!(battery.name, "BAT1");
+        assert_eq!(battery.capacity, 50);
+        assert_eq!(battery.status, Status::Charging);
+        assert_eq!(battery.cycle_count, Some(153));
+        assert_eq!(battery.power, Some(20.0));
+        assert_eq!(battery.time_to_full, Some(3600));
+        assert_eq!(battery.time_to_empty, None);
+        assert_eq!(battery.charge_limit, None);
+        assert!((battery.health.unwrap() - 94.1).abs() < 0.1);
+    }
+
+    #[test]
+    fn a_new_battery_has_zero_cycles() {
+        let sysfs = fake_power_supply("battery-new");
+        sysfs.write("BAT1/cycle_count", "0");
+        sysfs.write("BAT1/current_now", "0");
+
+        let battery = PowerSupply::new(sysfs.root()).battery().unwrap();
+
+        assert_eq!(battery.cycle_count, Some(0));
+        assert_eq!(battery.power, None);
+        assert_eq!(battery.time_to_full, None);
+    }
+
+    #[test]
+    fn prefers_energy_and_kernel_estimates() {
+        let sysfs = fake_power_supply("battery-energy");
+        let supply = Supply {
+            path: sysfs.root().join("BAT1"),
+        };
+        sysfs.write("BAT1/status", "Discharging");
+        sysfs.write("BAT1/energy_now", "20000000");
+        sysfs.write("BAT1/power_now", "-10000000");
+
+        let estimated = supply.battery("BAT1").unwrap();
+        assert_eq!(estimated.power, Some(10.0));
+        assert_eq!(estimated.time_to_empty, Some(7200));
+
+        sysfs.write("BAT1/time_to_empty_now", "5400");
+        let reported = supply.battery("BAT1").unwrap();
+        assert_eq!(reported.time_to_empty, Some(5400));
+    }
+
+    #[test]
+    fn sets_the_charge_limit() {
+        let sysfs = fake_power_supply("battery-limit");
+        sysfs.write("BAT1/charge_control_end_threshold", "100");
+        let power_supply = PowerSupply::new(sysfs.root());
+
+        let battery = power_supply.set_charge_limit(80).unwrap();
+
+        assert_eq!(battery.charge_limit, Some(80));
+        assert_eq!(sysfs.read("BAT1/charge_control_end_threshold"), "80");
+    }
+
+    #[test]
+    fn missing_charge_limit_is_unavailable() {
+        let sysfs = fake_power_supply("battery-no-limit");
+
+        let err = PowerSupply::new(sysfs.root())
+            .set_charge_limit(80)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::NoChargeLimit));
+        assert!(!sysfs
+            .root()
+            .join("BAT1/charge_control_end_threshold")
+            .exists());
+    }
+
+    #[test]
+    fn peripheral_batteries_are_not_the_system_battery() {
+        let sysfs = fake_power_supply("battery-peripheral");
+        fs::remove_dir_all(sysfs.root().join("BAT1")).unwrap();
+
+        let err = PowerSupply::new(sysfs.root()).battery().unwrap_err();
+
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::Unavailable(_)
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::{mpsc, oneshot};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{dbus_interface, DBusError};
+
+/// Where the agent is served on the daemon's bus connection.
+pub const AGENT_PATH: &str = "/org/shortcut/agent";
+
+/// Prompts nobody answers in time are cancelled so BlueZ doesn't wait forever.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Accept if the device shows the same passkey.
+    ConfirmPasskey,
+    /// Type the passkey on the device.
+    DisplayPasskey,
+    /// Answer with the passkey the device shows.
+    EnterPasskey,
+    /// Allow the device without a passkey.
+    Authorize,
+    /// Type the PIN code on the device, for devices older than Bluetooth 2.1.
+    DisplayPinCode,
+    /// Answer with the PIN code of the device.
+    EnterPinCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    Accept,
+    Passkey(u32),
+    PinCode(String),
+    Reject,
+}
+
+/// A question BlueZ asks while pairing.
+#[derive(Debug)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub address: String,
+    pub passkey: Option<u32>,
+    pub pin_code: Option<String>,
+    /// `None` for prompts that only inform, like [`PromptKind::DisplayPasskey`].
+    pub reply: Option<oneshot::Sender<Answer>>,
+}
+
+impl Prompt {
+    fn new(kind: PromptKind, device: &OwnedObjectPath) -> Self {
+        Self {
+            kind,
+            address: address_of(device),
+            passkey: None,
+            pin_code: None,
+            reply: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AgentEvent {
+    Prompt(Prompt),
+    /// BlueZ no longer waits for the prompts that weren't answered yet.
+    Cancelled,
+}
+
+#[derive(Debug, DBusError)]
+#[dbus_error(prefix = "org.bluez.Error")]
+pub enum AgentError {
+    #[dbus_error(zbus_error)]
+    ZBus(zbus::Error),
+    Rejected(String),
+    Canceled(String),
+}
+
+/// Turns `/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF` into `AA:BB:CC:DD:EE:FF`.
+pub fn address_of(device: &OwnedObjectPath) -> String {
+    let name = device.as_str().rsplit('/').next().unwrap_or_default();
+    name.trim_start_matches("dev_").replace('_', ":")
+}
+
+/// `org.bluez.Agent1` that hands every prompt to the pairing session through `prompts`.
+pub struct Agent {
+    prompts: mpsc::UnboundedSender<AgentEvent>,
+}
+
+impl Agent {
+    pub fn new(prompts: mpsc::UnboundedSender<AgentEvent>) -> Self {
+        Self { prompts }
+    }
+
+    fn notify(&self, prompt: Prompt) {
+        self.prompts.send(AgentEvent::Prompt(prompt)).ok();
+    }
+
+    async fn ask(&self, mut prompt: Prompt) -> Result<Answer, AgentError> {
+        let (tx, rx) = oneshot::channel();
+        prompt.reply = Some(tx);
+        self.prompts
+            .send(AgentEvent::Prompt(prompt))
+            .map_err(|_| AgentError::Canceled("pairing has ended".to_string()))?;
+
+        match tokio::time::timeout(PROMPT_TIMEOUT, rx).await {
+            Ok(Ok(Answer::Reject)) => Err(AgentError::Rejected("rejected by the user".to_string())),
+            Ok(Ok(answer)) => Ok(answer),
+            Ok(Err(_)) => Err(AgentError::Canceled("pairing has ended".to_string())),
+            Err(_) => Err(AgentError::Canceled(
+                "nobody answered the prompt".to_string(),
+            )),
+        }
+    }
+
+    async fn ask_passkey(&self, device: &OwnedObjectPath) -> Result<u32, AgentError> {
+        match self
+            .ask(Prompt::new(PromptKind::EnterPasskey, device))
+            .await?
+        {
+            Answer::Passkey(passkey) => Ok(passkey),
+            _ => Err(AgentError::Rejected("no passkey given".to_string())),
+        }
+    }
+
+    async fn ask_pin_code(&self, device: &OwnedObjectPath) -> Result<String, AgentError> {
+        match self
+            .ask(Prompt::new(PromptKind::EnterPinCode, device))
+            .await?
+        {
+            // BlueZ takes 1 to 16 characters
+            Answer::PinCode(pin_code) if (1..=16).contains(&pin_code.len()) => Ok(pin_code),
+            _ => Err(AgentError::Rejected("no PIN code given".to_string())),
+        }
+    }
+}
+
+#[dbus_interface(name = "org.bluez.Agent1")]
+impl Agent {
+    fn release(&self) {
+        tracing::debug!("BlueZ released the pairing agent");
+    }
+
+    async fn request_pin_code(&self, device: OwnedObjectPath) -> Result<String, AgentError> {
+        self.ask_pin_code(&device).await
+    }
+
+    fn display_pin_code(&self, device: OwnedObjectPath, pincode: &str) {
+        self.notify(Prompt {
+            pin_code: Some(pincode.to_string()),
+            ..Prompt::new(PromptKind::DisplayPinCode, &device)
+        });
+    }
+
+    async fn request_passkey(&self, device: OwnedObjectPath) -> Result<u32, AgentError> {
+        self.ask_passkey(&device).await
+    }
+
+    fn display_passkey(&self, device: OwnedObjectPath, passkey: u32, entered: u16) {
+        // Called again for every key typed on the device
+        if entered == 0 {
+            self.notify(Prompt {
+                passkey: Some(passkey),
+                ..Prompt::new(PromptKind::DisplayPasskey, &device)
+            });
+        }
+    }
+
+    async fn request_confirmation(
+        &self,
+        device: OwnedObjectPath,
+        passkey: u32,
+    ) -> Result<(), AgentError> {
+        self.ask(Prompt {
+            passkey: Some(passkey),
+            ..Prompt::new(PromptKind::ConfirmPasskey, &device)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn request_authorization(&self, device: OwnedObjectPath) -> Result<(), AgentError> {
+        self.ask(Prompt::new(PromptKind::Authorize, &device))
+            .await?;
+        Ok(())
+    }
+
+    async fn authorize_service(
+        &self,
+        device: OwnedObjectPath,
+        uuid: &str,
+    ) -> Result<(), AgentError> {
+        tracing::debug!("Asking to authorize service {uuid}");
+        self.ask(Prompt::new(PromptKind::Authorize, &device))
+            .await?;
+        Ok(())
+    }
+
+    fn cancel(&self) {
+        tracing::debug!("BlueZ cancelled the pairing prompt");
+        self.prompts.send(AgentEvent::Cancelled).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> OwnedObjectPath {
+        OwnedObjectPath::try_from("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_03").unwrap()
+    }
+
+    async fn next_prompt(prompts: &mut mpsc::UnboundedReceiver<AgentEvent>) -> Prompt {
+        match prompts.recv().await {
+            Some(AgentEvent::Prompt(prompt)) => prompt,
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn pin_codes_are_kept_as_text() {
+        let (tx, mut prompts) = mpsc::unbounded_channel();
+        let agent = Agent::new(tx);
+
+        agent.display_pin_code(device(), "0042");
+        let prompt = next_prompt(&mut prompts).await;
+        assert_eq!(prompt.kind, PromptKind::DisplayPinCode);
+        assert_eq!(prompt.pin_code.as_deref(), Some("0042"));
+
+        let asked = tokio::spawn(async move { agent.request_pin_code(device()).await });
+        let prompt = next_prompt(&mut prompts).await;
+        assert_eq!(prompt.kind, PromptKind::EnterPinCode);
+        let answer = Answer::PinCode("00a1".to_string());
+        prompt.reply.unwrap().send(answer).unwrap();
+
+        assert_eq!(asked.await.unwrap().unwrap(), "00a1");
+    }
+
+    #[tokio::test]
+    async fn empty_pin_codes_are_rejected() {
+        let (tx, mut prompts) = mpsc::unbounded_channel();
+        let agent = Agent::new(tx);
+
+        let asked = tokio::spawn(async move { agent.request_pin_code(device()).await });
+        let prompt = next_prompt(&mut prompts).await;
+        let answer = Answer::PinCode(String::new());
+        prompt.reply.unwrap().send(answer).unwrap();
+
+        assert!(matches!(asked.await.unwrap(), Err(AgentError::Rejected(_))));
+    }
+}
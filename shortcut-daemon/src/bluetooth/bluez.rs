@@ -0,0 +1,810 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::mpsc;
+use zbus::fdo::{ManagedObjects, ObjectManagerProxy};
+use zbus::names::OwnedInterfaceName;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+use zbus::{dbus_proxy, CacheProperties, Connection};
+
+use super::agent::{self, Agent, AgentEvent};
+
+/// How often the devices found by discovery are re-read.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(1);
+
+const BLUEZ: &str = "org.bluez";
+const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+const BATTERY_INTERFACE: &str = "org.bluez.Battery1";
+
+#[dbus_proxy(
+    interface = "org.bluez.AgentManager1",
+    default_service = "org.bluez",
+    default_path = "/org/bluez"
+)]
+trait AgentManager1 {
+    fn register_agent(&self, agent: &ObjectPath<'_>, capability: &str) -> zbus::Result<()>;
+
+    fn unregister_agent(&self, agent: &ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(interface = "org.bluez.Adapter1", default_service = "org.bluez")]
+trait Adapter1 {
+    fn remove_device(&self, device: &ObjectPath<'_>) -> zbus::Result<()>;
+
+    fn start_discovery(&self) -> zbus::Result<()>;
+
+    fn stop_discovery(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(property)]
+    fn powered(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn set_powered(&self, powered: bool) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(interface = "org.bluez.Device1", default_service = "org.bluez")]
+trait Device1 {
+    fn connect(&self) -> zbus::Result<()>;
+
+    fn disconnect(&self) -> zbus::Result<()>;
+
+    fn pair(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(property)]
+    fn set_trusted(&self, trusted: bool) -> zbus::Result<()>;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Bus(zbus::Error),
+    /// BlueZ knows no adapter, i.e. there is no bluetooth hardware or it is blocked.
+    NoAdapter,
+    /// No paired device with the given address exists.
+    NoSuchDevice(String),
+    /// The adapter is off, so nothing can be discovered or paired.
+    PoweredOff,
+    /// Another client is pairing right now.
+    AlreadyPairing,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bus(err) => write!(f, "D-Bus error: {err}"),
+            Error::NoAdapter => write!(f, "no bluetooth adapter found"),
+            Error::NoSuchDevice(address) => write!(f, "no such bluetooth device: {address}"),
+            Error::PoweredOff => write!(f, "bluetooth is powered off"),
+            Error::AlreadyPairing => write!(f, "another pairing is in progress"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<zbus::Error> for Error {
+    fn from(err: zbus::Error) -> Self {
+        Error::Bus(err)
+    }
+}
+
+impl From<zbus::fdo::Error> for Error {
+    fn from(err: zbus::fdo::Error) -> Self {
+        Error::Bus(err.into())
+    }
+}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoSuchDevice(address) => {
+                shortcut_core::Error::not_found(format!("bluetooth device {address}"))
+            }
+            Error::NoAdapter | Error::PoweredOff | Error::AlreadyPairing => {
+                shortcut_core::Error::Unavailable(err.to_string())
+            }
+            Error::Bus(zbus::Error::MethodError(ref name, _, _)) => match name.as_str() {
+                "org.freedesktop.DBus.Error.AccessDenied" | "org.bluez.Error.NotAuthorized" => {
+                    shortcut_core::Error::PermissionDenied(err.to_string())
+                }
+                "org.bluez.Error.NotReady" | "org.freedesktop.DBus.Error.ServiceUnknown" => {
+                    shortcut_core::Error::Unavailable(err.to_string())
+                }
+                // The adapter or device refused, e.g. a connection attempt timed out
+                _ => shortcut_core::Error::Internal(err.to_string()),
+            },
+            Error::Bus(_) => shortcut_core::Error::Unavailable(err.to_string()),
+        }
+    }
+}
+
+/// A paired device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub address: String,
+    /// The alias set by the user or the name the device announced.
+    pub name: String,
+    pub connected: bool,
+    /// Battery level in percent, for devices that report it.
+    pub battery: Option<u8>,
+}
+
+impl From<Device> for shortcut_core::bluetooth::Device {
+    fn from(device: Device) -> Self {
+        Self {
+            address: device.address,
+            name: device.name,
+            connected: device.connected,
+            battery_percentage: device.battery.map_or(-1, i32::from),
+        }
+    }
+}
+
+type Interfaces = HashMap<OwnedInterfaceName, HashMap<String, OwnedValue>>;
+
+fn property<'a, T>(interfaces: &'a Interfaces, interface: &str, name: &str) -> Option<T>
+where
+    T: TryFrom<&'a OwnedValue>,
+{
+    interfaces
+        .iter()
+        .find(|(iface, _)| iface.as_str() == interface)
+        .and_then(|(_, properties)| properties.get(name))
+        .and_then(|value| T::try_from(value).ok())
+}
+
+/// The first adapter, BlueZ names them `hci0`, `hci1`, ... in the order they appeared.
+fn adapter_path(objects: &ManagedObjects) -> Result<OwnedObjectPath, Error> {
+    objects
+        .iter()
+        .filter(|(_, interfaces)| {
+            interfaces
+                .keys()
+                .any(|iface| iface.as_str() == ADAPTER_INTERFACE)
+        })
+        .map(|(path, _)| path)
+        .min_by(|a, b| a.as_str().cmp(b.as_str()))
+        .cloned()
+        .ok_or(Error::NoAdapter)
+}
+
+/// Paired or unpaired devices of the adapter at `adapter`, with the object path of each.
+fn devices_of(
+    objects: &ManagedObjects,
+    adapter: &OwnedObjectPath,
+    paired: bool,
+) -> Vec<(OwnedObjectPath, Device)> {
+    let mut devices: Vec<_> = objects
+        .iter()
+        .filter_map(|(path, interfaces)| {
+            let owner: &ObjectPath<'_> = property(interfaces, DEVICE_INTERFACE, "Adapter")?;
+            let is_paired: bool = property(interfaces, DEVICE_INTERFACE, "Paired")?;
+            if owner.as_str() != adapter.as_str() || is_paired != paired {
+                return None;
+            }
+
+            let address: &str = property(interfaces, DEVICE_INTERFACE, "Address")?;
+            let name = property::<&str>(interfaces, DEVICE_INTERFACE, "Alias")
+                .or_else(|| property(interfaces, DEVICE_INTERFACE, "Name"))
+                .unwrap_or(address);
+            let device = Device {
+                address: address.to_string(),
+                name: name.to_string(),
+                connected: property(interfaces, DEVICE_INTERFACE, "Connected").unwrap_or(false),
+                battery: property(interfaces, BATTERY_INTERFACE, "Percentage"),
+            };
+            Some((path.clone(), device))
+        })
+        .collect();
+
+    devices.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name).then(a.address.cmp(&b.address)));
+    devices
+}
+
+/// Client for `org.bluez` on a D-Bus connection, always using the first adapter.
+#[derive(Debug, Clone)]
+pub struct Bluez {
+    conn: Connection,
+}
+
+impl Bluez {
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    async fn objects(&self) -> Result<ManagedObjects, Error> {
+        let manager = ObjectManagerProxy::builder(&self.conn)
+            .destination(BLUEZ)?
+            .path("/")?
+            .build()
+            .await?;
+        Ok(manager.get_managed_objects().await?)
+    }
+
+    async fn adapter(&self, path: OwnedObjectPath) -> Result<Adapter1Proxy<'static>, Error> {
+        Ok(Adapter1Proxy::builder(&self.conn)
+            .path(path)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?)
+    }
+
+    /// Looks up the adapter and the object path of the device with `address`.
+    async fn find_device(
+        &self,
+        address: &str,
+        paired: bool,
+    ) -> Result<(OwnedObjectPath, OwnedObjectPath), Error> {
+        let objects = self.objects().await?;
+        let adapter = adapter_path(&objects)?;
+        let device = devices_of(&objects, &adapter, paired)
+            .into_iter()
+            .find(|(_, device)| device.address.eq_ignore_ascii_case(address))
+            .map(|(path, _)| path)
+            .ok_or_else(|| Error::NoSuchDevice(address.to_string()))?;
+
+        Ok((adapter, device))
+    }
+
+    pub async fn powered(&self) -> Result<bool, Error> {
+        let path = adapter_path(&self.objects().await?)?;
+        Ok(self.adapter(path).await?.powered().await?)
+    }
+
+    /// Powers the adapter on or off and returns the state read back from it.
+    pub async fn set_powered(&self, powered: bool) -> Result<bool, Error> {
+        let path = adapter_path(&self.objects().await?)?;
+        let adapter = self.adapter(path).await?;
+        adapter.set_powered(powered).await?;
+        Ok(adapter.powered().await?)
+    }
+
+    /// Paired devices ordered by name.
+    pub async fn devices(&self) -> Result<Vec<Device>, Error> {
+        let objects = self.objects().await?;
+        let adapter = adapter_path(&objects)?;
+        Ok(devices_of(&objects, &adapter, true)
+            .into_iter()
+            .map(|(_, device)| device)
+            .collect())
+    }
+
+    /// Connects the device and returns its resulting state.
+    pub async fn connect(&self, address: &str) -> Result<Device, Error> {
+        self.device_call(address, true).await
+    }
+
+    /// Disconnects the device and returns its resulting state.
+    pub async fn disconnect(&self, address: &str) -> Result<Device, Error> {
+        self.device_call(address, false).await
+    }
+
+    async fn device_call(&self, address: &str, connect: bool) -> Result<Device, Error> {
+        let (_, path) = self.find_device(address, true).await?;
+        let proxy = Device1Proxy::builder(&self.conn)
+            .path(path)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        if connect {
+            proxy.connect().await?;
+        } else {
+            proxy.disconnect().await?;
+        }
+
+        self.devices()
+            .await?
+            .into_iter()
+            .find(|device| device.address.eq_ignore_ascii_case(address))
+            .ok_or_else(|| Error::NoSuchDevice(address.to_string()))
+    }
+
+    /// Unpairs the device and forgets about it.
+    pub async fn remove(&self, address: &str) -> Result<(), Error> {
+        let (adapter, device) = self.find_device(address, true).await?;
+        self.adapter(adapter)
+            .await?
+            .remove_device(&device.as_ref())
+            .await?;
+        Ok(())
+    }
+
+    /// Starts discovery and registers the pairing agent until the session is dropped.
+    pub async fn start_pairing(&self) -> Result<PairingSession, Error> {
+        let adapter_path = adapter_path(&self.objects().await?)?;
+        let adapter = self.adapter(adapter_path.clone()).await?;
+        if !adapter.powered().await? {
+            return Err(Error::PoweredOff);
+        }
+
+        let (prompts_tx, prompts) = mpsc::unbounded_channel();
+        let served = self
+            .conn
+            .object_server()
+            .at(agent::AGENT_PATH, Agent::new(prompts_tx))
+            .await?;
+        if !served {
+            return Err(Error::AlreadyPairing);
+        }
+
+        let started = async {
+            let manager = AgentManager1Proxy::new(&self.conn).await?;
+            manager
+                .register_agent(
+                    &ObjectPath::from_static_str_unchecked(agent::AGENT_PATH),
+                    "KeyboardDisplay",
+                )
+                .await?;
+            adapter.start_discovery().await
+        }
+        .await;
+        if let Err(err) = started {
+            self.stop_pairing(&adapter_path).await;
+            return Err(err.into());
+        }
+
+        let (discovered_tx, discovered) = mpsc::unbounded_channel();
+        tokio::spawn(self.clone().discover(adapter_path.clone(), discovered_tx));
+
+        let bluez = self.clone();
+        Ok(PairingSession::new(discovered, prompts, move || {
+            tokio::spawn(async move { bluez.stop_pairing(&adapter_path).await });
+        }))
+    }
+
+    /// Undoes [`Bluez::start_pairing`], as far as it got.
+    async fn stop_pairing(&self, adapter_path: &OwnedObjectPath) {
+        if let Ok(adapter) = self.adapter(adapter_path.clone()).await {
+            if let Err(err) = adapter.stop_discovery().await {
+                tracing::debug!("unable to stop discovery: {err}");
+            }
+        }
+        if let Ok(manager) = AgentManager1Proxy::new(&self.conn).await {
+            let path = ObjectPath::from_static_str_unchecked(agent::AGENT_PATH);
+            if let Err(err) = manager.unregister_agent(&path).await {
+                tracing::debug!("unable to unregister the pairing agent: {err}");
+            }
+        }
+        self.conn
+            .object_server()
+            .remove::<Agent, _>(agent::AGENT_PATH)
+            .await
+            .ok();
+    }
+
+    /// Sends every unpaired device once, and again when it changes, until `tx` is closed.
+    async fn discover(self, adapter_path: OwnedObjectPath, tx: mpsc::UnboundedSender<Device>) {
+        let mut seen: HashMap<String, Device> = HashMap::new();
+        loop {
+            match self.objects().await {
+                Ok(objects) => {
+                    for (_, device) in devices_of(&objects, &adapter_path, false) {
+                        if seen.get(&device.address) != Some(&device) {
+                            seen.insert(device.address.clone(), device.clone());
+                            if tx.send(device).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                Err(err) => tracing::warn!("unable to read discovered devices: {err}"),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(DISCOVERY_INTERVAL) => {}
+                _ = tx.closed() => return,
+            }
+        }
+    }
+
+    /// Pairs with a discovered device, trusts it so it can reconnect on its own and connects.
+    pub async fn pair(&self, address: &str) -> Result<Device, Error> {
+        let (_, path) = self.find_device(address, false).await?;
+        let proxy = Device1Proxy::builder(&self.conn)
+            .path(path)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        proxy.pair().await?;
+        proxy.set_trusted(true).await?;
+        if let Err(err) = proxy.connect().await {
+            tracing::warn!("paired with {address} but unable to connect: {err}");
+        }
+
+        self.devices()
+            .await?
+            .into_iter()
+            .find(|device| device.address.eq_ignore_ascii_case(address))
+            .ok_or_else(|| Error::NoSuchDevice(address.to_string()))
+    }
+}
+
+/// A running discovery with the pairing agent registered, stopped when dropped.
+pub struct PairingSession {
+    /// Unpaired devices, each sent again when it changes.
+    pub discovered: mpsc::UnboundedReceiver<Device>,
+    /// Prompts of the agent and their cancellation.
+    pub prompts: mpsc::UnboundedReceiver<AgentEvent>,
+    on_end: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl PairingSession {
+    pub fn new(
+        discovered: mpsc::UnboundedReceiver<Device>,
+        prompts: mpsc::UnboundedReceiver<AgentEvent>,
+        on_end: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        Self {
+            discovered,
+            prompts,
+            on_end: Some(Box::new(on_end)),
+        }
+    }
+}
+
+impl Drop for PairingSession {
+    fn drop(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use shortcut_core::tokio;
+    use zbus::{dbus_interface, fdo, ConnectionBuilder, ObjectServer};
+
+    use super::*;
+    use crate::test_bus::{self, require_bus, Bus};
+
+    const HCI0: &str = "/org/bluez/hci0";
+
+    fn device_path(address: &str) -> String {
+        format!("{HCI0}/dev_{}", address.replace(':', "_"))
+    }
+
+    /// Remembers where the agent was registered so devices can call it back.
+    #[derive(Default)]
+    struct StubAgentManager {
+        agent: Arc<Mutex<Option<(String, OwnedObjectPath)>>>,
+    }
+
+    #[dbus_interface(name = "org.bluez.AgentManager1")]
+    impl StubAgentManager {
+        fn register_agent(
+            &self,
+            agent: OwnedObjectPath,
+            _capability: &str,
+            #[zbus(header)] header: zbus::MessageHeader<'_>,
+        ) -> fdo::Result<()> {
+            let sender = header.sender()?.expect("no sender").to_string();
+            *self.agent.lock().unwrap() = Some((sender, agent));
+            Ok(())
+        }
+
+        fn unregister_agent(&self, _agent: OwnedObjectPath) {
+            *self.agent.lock().unwrap() = None;
+        }
+    }
+
+    struct StubAdapter {
+        powered: bool,
+    }
+
+    #[dbus_interface(name = "org.bluez.Adapter1")]
+    impl StubAdapter {
+        async fn remove_device(
+            &self,
+            device: ObjectPath<'_>,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<()> {
+            server.remove::<StubDevice, _>(&device).await?;
+            server.remove::<StubBattery, _>(&device).await.ok();
+            Ok(())
+        }
+
+        fn start_discovery(&self) {}
+
+        fn stop_discovery(&self) {}
+
+        #[dbus_interface(property)]
+        fn powered(&self) -> bool {
+            self.powered
+        }
+
+        #[dbus_interface(property)]
+        fn set_powered(&mut self, powered: bool) {
+            self.powered = powered;
+        }
+    }
+
+    struct StubDevice {
+        address: String,
+        alias: String,
+        paired: bool,
+        trusted: bool,
+        connected: Arc<Mutex<bool>>,
+        agent: Arc<Mutex<Option<(String, OwnedObjectPath)>>>,
+    }
+
+    #[dbus_interface(name = "org.bluez.Device1")]
+    impl StubDevice {
+        fn connect(&self) {
+            *self.connected.lock().unwrap() = true;
+        }
+
+        fn disconnect(&self) {
+            *self.connected.lock().unwrap() = false;
+        }
+
+        /// Asks the registered agent to confirm a passkey, like a phone would.
+        async fn pair(&mut self, #[zbus(connection)] conn: &Connection) -> fdo::Result<()> {
+            let (sender, agent) = self
+                .agent
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| fdo::Error::Failed("no agent".to_string()))?;
+            let device = OwnedObjectPath::try_from(device_path(&self.address)).unwrap();
+            conn.call_method(
+                Some(sender.as_str()),
+                &agent,
+                Some("org.bluez.Agent1"),
+                "RequestConfirmation",
+                &(device, 123456u32),
+            )
+            .await
+            .map_err(|err| fdo::Error::AuthFailed(err.to_string()))?;
+            self.paired = true;
+            Ok(())
+        }
+
+        #[dbus_interface(property)]
+        fn address(&self) -> String {
+            self.address.clone()
+        }
+
+        #[dbus_interface(property)]
+        fn alias(&self) -> String {
+            self.alias.clone()
+        }
+
+        #[dbus_interface(property)]
+        fn paired(&self) -> bool {
+            self.paired
+        }
+
+        #[dbus_interface(property)]
+        fn trusted(&self) -> bool {
+            self.trusted
+        }
+
+        #[dbus_interface(property)]
+        fn set_trusted(&mut self, trusted: bool) {
+            self.trusted = trusted;
+        }
+
+        #[dbus_interface(property)]
+        fn connected(&self) -> bool {
+            *self.connected.lock().unwrap()
+        }
+
+        #[dbus_interface(property)]
+        fn adapter(&self) -> OwnedObjectPath {
+            ObjectPath::try_from(HCI0).unwrap().into()
+        }
+    }
+
+    struct StubBattery {
+        percentage: u8,
+    }
+
+    #[dbus_interface(name = "org.bluez.Battery1")]
+    impl StubBattery {
+        #[dbus_interface(property)]
+        fn percentage(&self) -> u8 {
+            self.percentage
+        }
+    }
+
+    fn stub_device(
+        address: &str,
+        alias: &str,
+        paired: bool,
+        agent: &Arc<Mutex<Option<(String, OwnedObjectPath)>>>,
+    ) -> StubDevice {
+        StubDevice {
+            address: address.to_string(),
+            alias: alias.to_string(),
+            paired,
+            trusted: paired,
+            connected: Arc::new(Mutex::new(false)),
+            agent: agent.clone(),
+        }
+    }
+
+    /// Serves a stub BlueZ with one adapter, two paired devices and an unpaired one.
+    async fn stub_bluez(bus: &Bus) -> (Connection, Bluez) {
+        let manager = StubAgentManager::default();
+        let agent = manager.agent.clone();
+        let server = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name(BLUEZ)
+            .unwrap()
+            .serve_at("/org/bluez", manager)
+            .unwrap()
+            .serve_at(HCI0, StubAdapter { powered: false })
+            .unwrap()
+            .serve_at(
+                device_path("AA:BB:CC:DD:EE:01"),
+                stub_device("AA:BB:CC:DD:EE:01", "Keyboard", true, &agent),
+            )
+            .unwrap()
+            .serve_at(
+                device_path("AA:BB:CC:DD:EE:02"),
+                stub_device("AA:BB:CC:DD:EE:02", "Headphones", true, &agent),
+            )
+            .unwrap()
+            .serve_at(
+                device_path("AA:BB:CC:DD:EE:02"),
+                StubBattery { percentage: 80 },
+            )
+            .unwrap()
+            .serve_at(
+                device_path("AA:BB:CC:DD:EE:03"),
+                stub_device("AA:BB:CC:DD:EE:03", "Stranger", false, &agent),
+            )
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        // Added last, the manager would otherwise announce each object before the bus is up
+        server
+            .object_server()
+            .at("/", fdo::ObjectManager)
+            .await
+            .unwrap();
+
+        (server, Bluez::new(test_bus::connect(bus).await))
+    }
+
+    #[tokio::test]
+    async fn lists_paired_devices_by_name() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+
+        let devices = bluez.devices().await.unwrap();
+
+        assert_eq!(
+            devices,
+            vec![
+                Device {
+                    address: "AA:BB:CC:DD:EE:02".to_string(),
+                    name: "Headphones".to_string(),
+                    connected: false,
+                    battery: Some(80),
+                },
+                Device {
+                    address: "AA:BB:CC:DD:EE:01".to_string(),
+                    name: "Keyboard".to_string(),
+                    connected: false,
+                    battery: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_powered_reads_back_the_adapter() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+
+        assert!(!bluez.powered().await.unwrap());
+        assert!(bluez.set_powered(true).await.unwrap());
+        assert!(bluez.powered().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn connect_and_disconnect_return_the_new_state() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+
+        let device = bluez.connect("aa:bb:cc:dd:ee:01").await.unwrap();
+        assert!(device.connected);
+
+        let device = bluez.disconnect("AA:BB:CC:DD:EE:01").await.unwrap();
+        assert!(!device.connected);
+    }
+
+    #[tokio::test]
+    async fn remove_forgets_the_device() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+
+        bluez.remove("AA:BB:CC:DD:EE:02").await.unwrap();
+
+        let devices = bluez.devices().await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].address, "AA:BB:CC:DD:EE:01");
+    }
+
+    #[tokio::test]
+    async fn unpaired_or_unknown_devices_are_not_found() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+
+        let err = bluez.connect("AA:BB:CC:DD:EE:03").await.unwrap_err();
+        assert!(matches!(err, Error::NoSuchDevice(_)));
+        assert_eq!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::not_found("bluetooth device AA:BB:CC:DD:EE:03")
+        );
+    }
+
+    #[tokio::test]
+    async fn pairing_needs_the_adapter_powered() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+
+        let err = bluez.start_pairing().await.err().unwrap();
+        assert!(matches!(err, Error::PoweredOff));
+    }
+
+    #[tokio::test]
+    async fn pairs_a_discovered_device_after_confirmation() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+        bluez.set_powered(true).await.unwrap();
+
+        let mut session = bluez.start_pairing().await.unwrap();
+        let discovered = session.discovered.recv().await.unwrap();
+        assert_eq!(discovered.address, "AA:BB:CC:DD:EE:03");
+        assert!(matches!(
+            bluez.start_pairing().await.err(),
+            Some(Error::AlreadyPairing)
+        ));
+
+        let pairing = tokio::spawn({
+            let bluez = bluez.clone();
+            async move { bluez.pair("AA:BB:CC:DD:EE:03").await }
+        });
+        let prompt = match session.prompts.recv().await.unwrap() {
+            AgentEvent::Prompt(prompt) => prompt,
+            event => panic!("unexpected {event:?}"),
+        };
+        assert_eq!(prompt.kind, agent::PromptKind::ConfirmPasskey);
+        assert_eq!(prompt.address, "AA:BB:CC:DD:EE:03");
+        assert_eq!(prompt.passkey, Some(123456));
+        prompt.reply.unwrap().send(agent::Answer::Accept).unwrap();
+
+        let device = pairing.await.unwrap().unwrap();
+        assert_eq!(device.name, "Stranger");
+        assert!(device.connected);
+    }
+
+    #[tokio::test]
+    async fn rejected_prompts_fail_the_pairing() {
+        let bus = require_bus!();
+        let (_server, bluez) = stub_bluez(&bus).await;
+        bluez.set_powered(true).await.unwrap();
+
+        let mut session = bluez.start_pairing().await.unwrap();
+        let pairing = tokio::spawn({
+            let bluez = bluez.clone();
+            async move { bluez.pair("AA:BB:CC:DD:EE:03").await }
+        });
+        let prompt = match session.prompts.recv().await.unwrap() {
+            AgentEvent::Prompt(prompt) => prompt,
+            event => panic!("unexpected {event:?}"),
+        };
+        prompt.reply.unwrap().send(agent::Answer::Reject).unwrap();
+
+        assert!(pairing.await.unwrap().is_err());
+        assert_eq!(bluez.devices().await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,701 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use shortcut_core::futures::{Stream, StreamExt};
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::{mpsc, oneshot};
+use shortcut_core::tokio_stream::wrappers::ReceiverStream;
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::bluetooth;
+use shortcut_core::bluetooth::bluetooth_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{Service, WatchStream};
+
+pub(crate) mod agent;
+pub(crate) mod bluez;
+
+fn to_devices(devices: Vec<bluez::Device>) -> Vec<bluetooth::Device> {
+    devices.into_iter().map(bluetooth::Device::from).collect()
+}
+
+/// Checks for the `XX:XX:XX:XX:XX:XX` form BlueZ uses for addresses.
+fn validate_address(address: &str) -> Result<(), Error> {
+    let valid = address.len() == 17
+        && address.split(':').count() == 6
+        && address
+            .split(':')
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        return Err(Error::InvalidArgument(format!(
+            "{address:?} is not a bluetooth address"
+        )));
+    }
+    Ok(())
+}
+
+impl From<agent::PromptKind> for bluetooth::PromptKind {
+    fn from(kind: agent::PromptKind) -> Self {
+        match kind {
+            agent::PromptKind::ConfirmPasskey => bluetooth::PromptKind::ConfirmPasskey,
+            agent::PromptKind::DisplayPasskey => bluetooth::PromptKind::DisplayPasskey,
+            agent::PromptKind::EnterPasskey => bluetooth::PromptKind::EnterPasskey,
+            agent::PromptKind::Authorize => bluetooth::PromptKind::Authorize,
+            agent::PromptKind::DisplayPinCode => bluetooth::PromptKind::DisplayPinCode,
+            agent::PromptKind::EnterPinCode => bluetooth::PromptKind::EnterPinCode,
+        }
+    }
+}
+
+fn pair_result(address: String, result: Result<bluez::Device, Error>) -> bluetooth::PairResult {
+    match result {
+        Ok(device) => bluetooth::PairResult {
+            address,
+            paired: true,
+            message: String::new(),
+            device: Some(device.into()),
+        },
+        Err(err) => bluetooth::PairResult {
+            address,
+            paired: false,
+            message: err.to_string(),
+            device: None,
+        },
+    }
+}
+
+/// Relays a pairing session between a client and BlueZ until either of them goes away.
+///
+/// Prompts get an id the client answers with; pair requests run in the background so prompts
+/// for them can be answered meanwhile.
+async fn run_pairing<S>(
+    backend: Arc<dyn SystemBackend>,
+    mut session: bluez::PairingSession,
+    mut requests: S,
+    tx: mpsc::Sender<Result<bluetooth::PairResponse, Status>>,
+    service: Service,
+) where
+    S: Stream<Item = Result<bluetooth::PairRequest, Status>> + Unpin,
+{
+    use bluetooth::pair_request::Action;
+    use bluetooth::pair_response::Event;
+
+    let mut next_id = 0;
+    let mut pending: HashMap<u64, (agent::PromptKind, oneshot::Sender<agent::Answer>)> =
+        HashMap::new();
+    let (results_tx, mut results) = mpsc::unbounded_channel();
+
+    loop {
+        let event = tokio::select! {
+            Some(device) = session.discovered.recv() => Event::Discovered(device.into()),
+            Some(event) = session.prompts.recv() => match event {
+                agent::AgentEvent::Prompt(prompt) => {
+                    next_id += 1;
+                    if let Some(reply) = prompt.reply {
+                        pending.insert(next_id, (prompt.kind, reply));
+                    }
+                    Event::Prompt(bluetooth::Prompt {
+                        id: next_id,
+                        kind: bluetooth::PromptKind::from(prompt.kind) as i32,
+                        address: prompt.address,
+                        passkey: prompt.passkey.unwrap_or_default(),
+                        pin_code: prompt.pin_code.unwrap_or_default(),
+                    })
+                }
+                agent::AgentEvent::Cancelled => {
+                    // BlueZ asks one question at a time, dropping the replies ends it
+                    let mut prompt_ids: Vec<u64> = pending.drain().map(|(id, _)| id).collect();
+                    prompt_ids.sort_unstable();
+                    Event::Cancelled(bluetooth::PromptsCancelled { prompt_ids })
+                }
+            },
+            Some(result) = results.recv() => {
+                service.notify();
+                Event::Result(result)
+            }
+            request = requests.next() => match request {
+                Some(Ok(bluetooth::PairRequest { action: Some(Action::PairDevice(pair)) })) => {
+                    if let Err(err) = validate_address(&pair.address) {
+                        Event::Result(pair_result(pair.address, Err(err)))
+                    } else {
+                        tracing::info!("Pairing with bluetooth device {}", pair.address);
+                        let backend = backend.clone();
+                        let results_tx = results_tx.clone();
+                        tokio::spawn(async move {
+                            let result = backend.pair_bluetooth(&pair.address).await;
+                            if let Err(err) = &result {
+                                tracing::error!("error when pair: {err}");
+                            }
+                            results_tx.send(pair_result(pair.address, result)).ok();
+                        });
+                        continue;
+                    }
+                }
+                Some(Ok(bluetooth::PairRequest { action: Some(Action::Reply(reply)) })) => {
+                    match pending.remove(&reply.prompt_id) {
+                        Some((kind, reply_tx)) => {
+                            let answer = match kind {
+                                _ if !reply.accept => agent::Answer::Reject,
+                                agent::PromptKind::EnterPasskey => {
+                                    agent::Answer::Passkey(reply.passkey)
+                                }
+                                agent::PromptKind::EnterPinCode => {
+                                    agent::Answer::PinCode(reply.pin_code)
+                                }
+                                _ => agent::Answer::Accept,
+                            };
+                            reply_tx.send(answer).ok();
+                        }
+                        None => tracing::warn!("reply to unknown prompt {}", reply.prompt_id),
+                    }
+                    continue;
+                }
+                Some(Ok(bluetooth::PairRequest { action: None })) => continue,
+                Some(Err(status)) => {
+                    tracing::debug!("pairing client failed: {status}");
+                    break;
+                }
+                None => break,
+            },
+            _ = tx.closed() => break,
+        };
+
+        let response = bluetooth::PairResponse { event: Some(event) };
+        if tx.send(Ok(response)).await.is_err() {
+            break;
+        }
+    }
+    tracing::info!("Bluetooth pairing ended");
+}
+
+pub struct BluetoothServer {
+    backend: Arc<dyn SystemBackend>,
+    service: Service,
+}
+
+impl BluetoothServer {
+    pub fn new(backend: Arc<dyn SystemBackend>, config: SharedConfig) -> Self {
+        Self {
+            backend,
+            service: Service::new("bluetooth", config, |config| config.bluetooth.enabled),
+        }
+    }
+
+    fn validate_device(&self, address: &str) -> Result<(), Error> {
+        self.service.check_enabled()?;
+        validate_address(address)
+    }
+}
+
+#[tonic::async_trait]
+impl bluetooth_service_server::BluetoothService for BluetoothServer {
+    async fn set_powered(
+        &self,
+        request: Request<bluetooth::SetPoweredRequest>,
+    ) -> Result<Response<bluetooth::SetPoweredResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        tracing::info!(
+            "Powering bluetooth {} for {caller}",
+            if inner.powered { "on" } else { "off" }
+        );
+        let powered = self
+            .backend
+            .set_bluetooth_powered(inner.powered)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_powered: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = bluetooth::SetPoweredResponse { powered };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_powered(
+        &self,
+        request: Request<bluetooth::GetPoweredRequest>,
+    ) -> Result<Response<bluetooth::GetPoweredResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let powered = self.backend.bluetooth_powered().await.map_err(|err| {
+            tracing::error!("error when get_powered: {err}");
+            err
+        })?;
+
+        let reply = bluetooth::GetPoweredResponse { powered };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn list_devices(
+        &self,
+        request: Request<bluetooth::ListDevicesRequest>,
+    ) -> Result<Response<bluetooth::ListDevicesResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let devices = self.backend.bluetooth_devices().await.map_err(|err| {
+            tracing::error!("error when list_devices: {err}");
+            err
+        })?;
+
+        let reply = bluetooth::ListDevicesResponse {
+            devices: to_devices(devices),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn connect_device(
+        &self,
+        request: Request<bluetooth::ConnectDeviceRequest>,
+    ) -> Result<Response<bluetooth::ConnectDeviceResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.address)?;
+        tracing::info!("Connecting bluetooth device {} for {caller}", inner.address);
+        let device = self
+            .backend
+            .connect_bluetooth(&inner.address)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when connect_device: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = bluetooth::ConnectDeviceResponse {
+            device: Some(device.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn disconnect_device(
+        &self,
+        request: Request<bluetooth::DisconnectDeviceRequest>,
+    ) -> Result<Response<bluetooth::DisconnectDeviceResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.address)?;
+        tracing::info!(
+            "Disconnecting bluetooth device {} for {caller}",
+            inner.address
+        );
+        let device = self
+            .backend
+            .disconnect_bluetooth(&inner.address)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when disconnect_device: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = bluetooth::DisconnectDeviceResponse {
+            device: Some(device.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn remove_device(
+        &self,
+        request: Request<bluetooth::RemoveDeviceRequest>,
+    ) -> Result<Response<bluetooth::RemoveDeviceResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.address)?;
+        tracing::info!("Removing bluetooth device {} for {caller}", inner.address);
+        self.backend
+            .remove_bluetooth(&inner.address)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when remove_device: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        Ok(Response::new(bluetooth::RemoveDeviceResponse {}))
+    }
+
+    type WatchStateStream = WatchStream<bluetooth::WatchStateResponse>;
+
+    async fn watch_state(
+        &self,
+        request: Request<bluetooth::WatchStateRequest>,
+    ) -> Result<Response<Self::WatchStateStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            async move {
+                let powered = backend.bluetooth_powered().await?;
+                let devices = backend.bluetooth_devices().await?;
+                Ok(bluetooth::WatchStateResponse {
+                    powered,
+                    devices: to_devices(devices),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    type PairStream = WatchStream<bluetooth::PairResponse>;
+
+    async fn pair(
+        &self,
+        request: Request<tonic::Streaming<bluetooth::PairRequest>>,
+    ) -> Result<Response<Self::PairStream>, Status> {
+        let caller = Caller::describe(&request);
+
+        self.service.check_enabled()?;
+        tracing::info!("Starting bluetooth pairing for {caller}");
+        let session = self
+            .backend
+            .start_bluetooth_pairing()
+            .await
+            .map_err(|err| {
+                tracing::error!("error when pair: {err}");
+                err
+            })?;
+
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(run_pairing(
+            self.backend.clone(),
+            session,
+            request.into_inner(),
+            tx,
+            self.service.clone(),
+        ));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::bluetooth::bluetooth_service_server::BluetoothService;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::backend::fake::{bluetooth_device, Call, FakeBackend};
+    use crate::config::{self, BluetoothConfig, Config};
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, BluetoothServer) {
+        let backend = Arc::new(backend);
+        let server = BluetoothServer::new(backend.clone(), config::fixed(Config::default()));
+        (backend, server)
+    }
+
+    #[test]
+    fn validates_addresses() {
+        assert!(validate_address("AA:BB:CC:DD:EE:0f").is_ok());
+        assert!(validate_address("").is_err());
+        assert!(validate_address("AA:BB:CC:DD:EE").is_err());
+        assert!(validate_address("AA:BB:CC:DD:EE:0G").is_err());
+        assert!(validate_address("AAA:B:CC:DD:EE:00").is_err());
+    }
+
+    #[tokio::test]
+    async fn list_devices_reports_battery() {
+        let mut headphones = bluetooth_device("AA:BB:CC:DD:EE:02", "Headphones");
+        headphones.battery = Some(80);
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_bluetooth_adapter(true)
+                .with_bluetooth_device(headphones)
+                .with_bluetooth_device(bluetooth_device("AA:BB:CC:DD:EE:01", "Keyboard")),
+        );
+
+        let reply = server
+            .list_devices(Request::new(bluetooth::ListDevicesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.devices.len(), 2);
+        assert_eq!(reply.devices[0].name, "Headphones");
+        assert_eq!(reply.devices[0].battery_percentage, 80);
+        assert_eq!(reply.devices[1].battery_percentage, -1);
+    }
+
+    #[tokio::test]
+    async fn missing_adapter_is_unavailable() {
+        let (_, server) = server(FakeBackend::default());
+
+        let status = server
+            .get_powered(Request::new(bluetooth::GetPoweredRequest {}))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn connect_returns_the_connected_device() {
+        let (backend, server) = server(
+            FakeBackend::default()
+                .with_bluetooth_adapter(true)
+                .with_bluetooth_device(bluetooth_device("AA:BB:CC:DD:EE:01", "Keyboard")),
+        );
+
+        let reply = server
+            .connect_device(Request::new(bluetooth::ConnectDeviceRequest {
+                address: "AA:BB:CC:DD:EE:01".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(reply.device.unwrap().connected);
+        assert_eq!(
+            backend.calls(),
+            vec![Call::ConnectBluetooth("AA:BB:CC:DD:EE:01".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_addresses_before_calling_bluez() {
+        let (backend, server) = server(FakeBackend::default().with_bluetooth_adapter(true));
+
+        let status = server
+            .remove_device(Request::new(bluetooth::RemoveDeviceRequest {
+                address: "keyboard".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let backend = Arc::new(FakeBackend::default().with_bluetooth_adapter(true));
+        let config = config::fixed(Config {
+            bluetooth: BluetoothConfig { enabled: false },
+            ..Config::default()
+        });
+        let server = BluetoothServer::new(backend.clone(), config);
+
+        let status = server
+            .set_powered(Request::new(bluetooth::SetPoweredRequest {
+                powered: false,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_state_follows_power_changes() {
+        let (_, mut server) = server(FakeBackend::default().with_bluetooth_adapter(false));
+        server.service.interval = Duration::from_secs(3600);
+
+        let mut stream = server
+            .watch_state(Request::new(bluetooth::WatchStateRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!stream.next().await.unwrap().unwrap().powered);
+
+        server
+            .set_powered(Request::new(bluetooth::SetPoweredRequest { powered: true }))
+            .await
+            .unwrap();
+
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(next.unwrap().unwrap().unwrap().powered);
+    }
+
+    /// Runs a pairing session against `backend`, driven through the returned request sender.
+    async fn pairing(
+        backend: &Arc<FakeBackend>,
+    ) -> (
+        mpsc::Sender<Result<bluetooth::PairRequest, Status>>,
+        ReceiverStream<Result<bluetooth::PairResponse, Status>>,
+    ) {
+        let session = backend.start_bluetooth_pairing().await.unwrap();
+        let (requests_tx, requests) = mpsc::channel(4);
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(run_pairing(
+            backend.clone(),
+            session,
+            ReceiverStream::new(requests),
+            tx,
+            Service::new("bluetooth", config::fixed(Config::default()), |config| {
+                config.bluetooth.enabled
+            }),
+        ));
+        (requests_tx, ReceiverStream::new(rx))
+    }
+
+    async fn next_event(
+        responses: &mut ReceiverStream<Result<bluetooth::PairResponse, Status>>,
+    ) -> bluetooth::pair_response::Event {
+        let next = tokio::time::timeout(Duration::from_secs(5), responses.next()).await;
+        next.unwrap().unwrap().unwrap().event.unwrap()
+    }
+
+    fn pair_device(address: &str) -> Result<bluetooth::PairRequest, Status> {
+        Ok(bluetooth::PairRequest {
+            action: Some(bluetooth::pair_request::Action::PairDevice(
+                bluetooth::PairDevice {
+                    address: address.to_string(),
+                },
+            )),
+        })
+    }
+
+    fn reply(prompt_id: u64, accept: bool) -> Result<bluetooth::PairRequest, Status> {
+        Ok(bluetooth::PairRequest {
+            action: Some(bluetooth::pair_request::Action::Reply(
+                bluetooth::PromptReply {
+                    prompt_id,
+                    accept,
+                    passkey: 0,
+                    pin_code: String::new(),
+                },
+            )),
+        })
+    }
+
+    #[tokio::test]
+    async fn pairing_relays_prompts_and_results() {
+        use bluetooth::pair_response::Event;
+
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_bluetooth_adapter(true)
+                .with_discoverable_device(bluetooth_device("AA:BB:CC:DD:EE:03", "Phone")),
+        );
+        let (requests, mut responses) = pairing(&backend).await;
+
+        match next_event(&mut responses).await {
+            Event::Discovered(device) => assert_eq!(device.name, "Phone"),
+            event => panic!("unexpected {event:?}"),
+        }
+
+        requests
+            .send(pair_device("AA:BB:CC:DD:EE:03"))
+            .await
+            .unwrap();
+        let prompt = match next_event(&mut responses).await {
+            Event::Prompt(prompt) => prompt,
+            event => panic!("unexpected {event:?}"),
+        };
+        assert_eq!(prompt.kind(), bluetooth::PromptKind::ConfirmPasskey);
+        assert_eq!(prompt.passkey, 123456);
+
+        requests.send(reply(prompt.id, true)).await.unwrap();
+        match next_event(&mut responses).await {
+            Event::Result(result) => {
+                assert!(result.paired, "{}", result.message);
+                assert!(result.device.unwrap().connected);
+            }
+            event => panic!("unexpected {event:?}"),
+        }
+        assert_eq!(backend.bluetooth_devices().await.unwrap().len(), 1);
+
+        drop(requests);
+        assert!(responses.next().await.is_none());
+        assert!(backend.pairing_ended());
+    }
+
+    #[tokio::test]
+    async fn rejected_pairing_reports_the_failure() {
+        use bluetooth::pair_response::Event;
+
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_bluetooth_adapter(true)
+                .with_discoverable_device(bluetooth_device("AA:BB:CC:DD:EE:03", "Phone")),
+        );
+        let (requests, mut responses) = pairing(&backend).await;
+        next_event(&mut responses).await;
+
+        requests.send(pair_device("phone")).await.unwrap();
+        match next_event(&mut responses).await {
+            Event::Result(result) => assert!(!result.paired),
+            event => panic!("unexpected {event:?}"),
+        }
+
+        requests
+            .send(pair_device("AA:BB:CC:DD:EE:03"))
+            .await
+            .unwrap();
+        let prompt = match next_event(&mut responses).await {
+            Event::Prompt(prompt) => prompt,
+            event => panic!("unexpected {event:?}"),
+        };
+        requests.send(reply(prompt.id, false)).await.unwrap();
+        match next_event(&mut responses).await {
+            Event::Result(result) => {
+                assert!(!result.paired);
+                assert!(!result.message.is_empty());
+            }
+            event => panic!("unexpected {event:?}"),
+        }
+        assert!(backend.bluetooth_devices().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_prompts_are_closed() {
+        use bluetooth::pair_response::Event;
+
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_bluetooth_adapter(true)
+                .with_discoverable_device(bluetooth_device("AA:BB:CC:DD:EE:03", "Phone")),
+        );
+        let (requests, mut responses) = pairing(&backend).await;
+        next_event(&mut responses).await;
+
+        requests
+            .send(pair_device("AA:BB:CC:DD:EE:03"))
+            .await
+            .unwrap();
+        let prompt = match next_event(&mut responses).await {
+            Event::Prompt(prompt) => prompt,
+            event => panic!("unexpected {event:?}"),
+        };
+        backend.cancel_bluetooth_prompts();
+
+        match next_event(&mut responses).await {
+            Event::Cancelled(cancelled) => assert_eq!(cancelled.prompt_ids, [prompt.id]),
+            event => panic!("unexpected {event:?}"),
+        }
+        match next_event(&mut responses).await {
+            Event::Result(result) => assert!(!result.paired),
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+}
@@ -0,0 +1,490 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use shortcut_core::tokio::sync::watch;
+
+use crate::auth::{self, Allowlist};
+
+pub const CONFIG_PATH: &str = "/etc/shortcut/daemon.toml";
+pub const STATE_PATH: &str = "/var/lib/shortcut/state.toml";
+
+/// Longest interface name the kernel accepts, `IFNAMSIZ` minus the terminating NUL.
+const MAX_IFNAME_LEN: usize = 15;
+
+/// The daemon configuration, shared with the services so it can be swapped on SIGHUP.
+pub type SharedConfig = watch::Receiver<Arc<Config>>;
+
+#[derive(Debug)]
+pub enum Error {
+    Read { path: PathBuf, err: std::io::Error },
+    Parse { path: PathBuf, err: toml::de::Error },
+    Invalid { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read { path, err } => write!(f, "unable to read {}: {err}", path.display()),
+            Error::Parse { path, err } => write!(f, "invalid config {}: {err}", path.display()),
+            Error::Invalid { path, reason } => {
+                write!(f, "invalid config {}: {reason}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Socket to bind when not socket activated.
+    pub socket: PathBuf,
+    /// Users allowed to call the daemon, by name or uid.
+    pub allowed_users: Vec<String>,
+    /// Groups allowed to call the daemon, by name or gid, matched against the caller's primary
+    /// and supplementary groups.
+    pub allowed_groups: Vec<String>,
+    /// Where the settings to restore after reboot and resume are kept.
+    pub state_file: PathBuf,
+    pub wifi: WifiConfig,
+    pub ssh: SshConfig,
+    pub bluetooth: BluetoothConfig,
+    pub cpu: CpuConfig,
+    pub sensors: SensorsConfig,
+    pub fan: FanConfig,
+    pub display: DisplayConfig,
+    pub battery: BatteryConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WifiConfig {
+    pub enabled: bool,
+    /// Interfaces the service may touch, all of them when empty.
+    pub interfaces: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    pub enabled: bool,
+    /// The systemd unit that runs sshd.
+    pub unit: String,
+    /// The user whose `authorized_keys` the service manages.
+    pub user: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BluetoothConfig {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorsConfig {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FanConfig {
+    /// Whether a custom fan curve may take over from the firmware.
+    pub enabled: bool,
+    /// The hwmon device whose `pwm1` drives the fan, the first one that has it when empty.
+    pub device: String,
+    /// Temperatures the curve follows as `device` or `device:label`, the hottest one wins.
+    /// Every temperature sensor when empty.
+    pub sensors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub enabled: bool,
+    /// The lowest brightness callers may set, so the screen never goes fully dark.
+    pub min_brightness_percent: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatteryConfig {
+    pub enabled: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            socket: PathBuf::from(shortcut_core::SOCKET_PATH),
+            allowed_users: auth::DEFAULT_ALLOWED_USERS
+                .iter()
+                .map(|user| user.to_string())
+                .collect(),
+            allowed_groups: vec![],
+            state_file: PathBuf::from(STATE_PATH),
+            wifi: WifiConfig::default(),
+            ssh: SshConfig::default(),
+            bluetooth: BluetoothConfig::default(),
+            cpu: CpuConfig::default(),
+            sensors: SensorsConfig::default(),
+            fan: FanConfig::default(),
+            display: DisplayConfig::default(),
+            battery: BatteryConfig::default(),
+        }
+    }
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interfaces: vec![],
+        }
+    }
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            unit: "sshd.service".to_string(),
+            user: "deck".to_string(),
+        }
+    }
+}
+
+impl Default for BluetoothConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for SensorsConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for FanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            device: String::new(),
+            sensors: vec![],
+        }
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_brightness_percent: 5,
+        }
+    }
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl WifiConfig {
+    pub fn allows(&self, device: &str) -> bool {
+        self.interfaces.is_empty() || self.interfaces.iter().any(|iface| iface == device)
+    }
+}
+
+impl FanConfig {
+    /// Whether the curve follows the temperature `label` of `device`.
+    pub fn follows(&self, device: &str, label: &str) -> bool {
+        self.sensors.is_empty()
+            || self
+                .sensors
+                .iter()
+                .any(|sensor| match sensor.split_once(':') {
+                    Some((d, l)) => d == device && l == label,
+                    None => sensor == device,
+                })
+    }
+}
+
+impl Config {
+    /// Reads and validates the config at `path`.
+    ///
+    /// A missing file is only an error when `required` is set, otherwise the defaults are used.
+    pub fn load(path: &Path, required: bool) -> Result<Self, Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !required => {
+                tracing::info!("No config at {}, using defaults", path.display());
+                return Ok(Self::default());
+            }
+            Err(err) => {
+                return Err(Error::Read {
+                    path: path.to_path_buf(),
+                    err,
+                })
+            }
+        };
+
+        Self::parse(path, &content)
+    }
+
+    fn parse(path: &Path, content: &str) -> Result<Self, Error> {
+        let config: Config = toml::from_str(content).map_err(|err| Error::Parse {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        config.validate().map_err(|reason| Error::Invalid {
+            path: path.to_path_buf(),
+            reason,
+        })?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.socket.is_absolute() {
+            return Err(format!(
+                "socket must be an absolute path, got {}",
+                self.socket.display()
+            ));
+        }
+        if !self.state_file.is_absolute() {
+            return Err(format!(
+                "state_file must be an absolute path, got {}",
+                self.state_file.display()
+            ));
+        }
+        if self.allowed_users.iter().any(|user| user.trim().is_empty()) {
+            return Err("allowed_users contains an empty entry".to_string());
+        }
+        if self
+            .allowed_groups
+            .iter()
+            .any(|group| group.trim().is_empty())
+        {
+            return Err("allowed_groups contains an empty entry".to_string());
+        }
+        let enabled = [
+            self.wifi.enabled,
+            self.ssh.enabled,
+            self.bluetooth.enabled,
+            self.cpu.enabled,
+            self.sensors.enabled,
+            self.display.enabled,
+            self.battery.enabled,
+        ];
+        if !enabled.contains(&true) {
+            return Err("all services are disabled".to_string());
+        }
+        if let Some(iface) = self.wifi.interfaces.iter().find(|iface| {
+            iface.is_empty()
+                || iface.len() > MAX_IFNAME_LEN
+                || iface.contains(|c: char| c == '/' || c.is_whitespace())
+        }) {
+            return Err(format!(
+                "wifi.interfaces: {iface:?} is not an interface name"
+            ));
+        }
+        if let Some(sensor) =
+            self.fan.sensors.iter().find(|sensor| {
+                sensor.is_empty() || sensor.starts_with(':') || sensor.ends_with(':')
+            })
+        {
+            return Err(format!("fan.sensors: {sensor:?} is not a sensor"));
+        }
+        if self.display.min_brightness_percent > 100 {
+            return Err(format!(
+                "display.min_brightness_percent must be at most 100, got {}",
+                self.display.min_brightness_percent
+            ));
+        }
+        if !self.ssh.unit.ends_with(".service") || self.ssh.unit.contains('/') {
+            return Err(format!(
+                "ssh.unit: {:?} is not a service unit name",
+                self.ssh.unit
+            ));
+        }
+        if self.ssh.user.is_empty() {
+            return Err("ssh.user must not be empty".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `allowed_users` and `allowed_groups`, failing when nobody would be able to call
+    /// the daemon.
+    pub fn allowlist(&self, path: &Path) -> Result<Allowlist, Error> {
+        let allowlist = Allowlist::from_names(&self.allowed_users, &self.allowed_groups);
+        if allowlist.is_empty() {
+            return Err(Error::Invalid {
+                path: path.to_path_buf(),
+                reason: format!(
+                    "none of allowed_users {:?} or allowed_groups {:?} exist",
+                    self.allowed_users, self.allowed_groups
+                ),
+            });
+        }
+
+        Ok(allowlist)
+    }
+}
+
+/// A config that never changes, for tests.
+#[cfg(test)]
+pub fn fixed(config: Config) -> SharedConfig {
+    watch::channel(Arc::new(config)).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Config, Error> {
+        Config::parse(Path::new("daemon.toml"), content)
+    }
+
+    fn invalid_reason(content: &str) -> String {
+        match parse(content) {
+            Err(Error::Invalid { reason, .. }) => reason,
+            other => panic!("expected validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        assert_eq!(parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parses_full_config() {
+        let config = parse(
+            r#"
+            socket = "/run/custom/shortcutd.sock"
+            allowed_users = ["deck", "1001"]
+            allowed_groups = ["wheel"]
+
+            [wifi]
+            interfaces = ["wlan0"]
+
+            [ssh]
+            enabled = false
+            unit = "ssh.service"
+            user = "steam"
+
+            [bluetooth]
+            enabled = false
+
+            [cpu]
+            enabled = false
+
+            [sensors]
+            enabled = false
+
+            [fan]
+            device = "steamdeck_hwmon"
+            sensors = ["k10temp:Tctl", "amdgpu"]
+
+            [display]
+            min_brightness_percent = 10
+
+            [battery]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.socket, Path::new("/run/custom/shortcutd.sock"));
+        assert_eq!(config.allowed_users, vec!["deck", "1001"]);
+        assert_eq!(config.allowed_groups, vec!["wheel"]);
+        assert!(config.wifi.enabled);
+        assert!(config.wifi.allows("wlan0"));
+        assert!(!config.wifi.allows("wlan1"));
+        assert!(!config.ssh.enabled);
+        assert_eq!(config.ssh.unit, "ssh.service");
+        assert_eq!(config.ssh.user, "steam");
+        assert!(!config.bluetooth.enabled);
+        assert!(!config.cpu.enabled);
+        assert!(!config.sensors.enabled);
+        assert_eq!(config.fan.device, "steamdeck_hwmon");
+        assert!(config.fan.follows("k10temp", "Tctl"));
+        assert!(!config.fan.follows("k10temp", "Tccd1"));
+        assert!(config.fan.follows("amdgpu", "edge"));
+        assert!(!config.fan.follows("nvme", "Composite"));
+        assert!(config.display.enabled);
+        assert_eq!(config.display.min_brightness_percent, 10);
+        assert!(!config.battery.enabled);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = parse("[ssh]\nunti = \"sshd.service\"").unwrap_err();
+
+        assert!(err.to_string().contains("unti"), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(invalid_reason("socket = \"shortcutd.sock\"").contains("absolute"));
+        assert!(invalid_reason("state_file = \"state.toml\"").contains("state_file"));
+        assert!(invalid_reason("[ssh]\nunit = \"sshd\"").contains("ssh.unit"));
+        assert!(invalid_reason("[ssh]\nuser = \"\"").contains("ssh.user"));
+        assert!(invalid_reason("[wifi]\ninterfaces = [\"\"]").contains("wifi.interfaces"));
+        assert!(invalid_reason("[wifi]\ninterfaces = [\"../wlan0\"]").contains("wifi.interfaces"));
+        assert!(invalid_reason("allowed_users = [\" \"]").contains("allowed_users"));
+        assert!(invalid_reason("allowed_groups = [\"\"]").contains("allowed_groups"));
+        assert!(invalid_reason("[fan]\nsensors = [\"k10temp:\"]").contains("fan.sensors"));
+        assert!(invalid_reason("[display]\nmin_brightness_percent = 101")
+            .contains("display.min_brightness_percent"));
+        assert!(invalid_reason(
+            &[
+                "wifi",
+                "ssh",
+                "bluetooth",
+                "cpu",
+                "sensors",
+                "display",
+                "battery",
+            ]
+            .map(|service| format!("[{service}]\nenabled = false\n"))
+            .concat()
+        )
+        .contains("all services"));
+    }
+
+    #[test]
+    fn missing_file_is_only_an_error_when_required() {
+        let path = Path::new("/nonexistent/shortcut/daemon.toml");
+
+        assert_eq!(Config::load(path, false).unwrap(), Config::default());
+        assert!(matches!(Config::load(path, true), Err(Error::Read { .. })));
+    }
+
+    #[test]
+    fn allowlist_requires_an_existing_user() {
+        let config = Config {
+            allowed_users: vec!["no-such-user-here".to_string()],
+            ..Config::default()
+        };
+
+        assert!(config.allowlist(Path::new("daemon.toml")).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::cpu;
+use shortcut_core::cpu::cpu_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{Service, WatchStream};
+
+pub(crate) mod sysfs;
+
+use sysfs::CpuState;
+
+pub struct CpuServer {
+    backend: Arc<dyn SystemBackend>,
+    service: Service,
+}
+
+impl CpuServer {
+    pub fn new(backend: Arc<dyn SystemBackend>, config: SharedConfig) -> Self {
+        Self {
+            backend,
+            service: Service::new("cpu", config, |config| config.cpu.enabled),
+        }
+    }
+
+    /// The current state, which requests are validated against.
+    async fn current(&self, method: &str) -> Result<CpuState, Error> {
+        self.service.check_enabled()?;
+        self.backend.cpu_state().await.map_err(|err| {
+            tracing::error!("error when {method}: {err}");
+            err
+        })
+    }
+
+    /// Wraps up a change, logging failures and notifying the watch streams on success.
+    fn applied(
+        &self,
+        method: &str,
+        result: Result<CpuState, Error>,
+    ) -> Result<cpu::CpuState, Error> {
+        let state = result.map_err(|err| {
+            tracing::error!("error when {method}: {err}");
+            err
+        })?;
+        self.service.notify();
+        Ok(state.into())
+    }
+}
+
+#[tonic::async_trait]
+impl cpu_service_server::CpuService for CpuServer {
+    async fn get_state(
+        &self,
+        request: Request<cpu::GetStateRequest>,
+    ) -> Result<Response<cpu::GetStateResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let state = self.current("get_state").await?;
+
+        let reply = cpu::GetStateResponse {
+            state: Some(state.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_governor(
+        &self,
+        request: Request<cpu::SetGovernorRequest>,
+    ) -> Result<Response<cpu::SetGovernorResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let current = self.current("set_governor").await?;
+        if !current.available_governors.contains(&inner.governor) {
+            return Err(Error::InvalidArgument(format!(
+                "unknown governor {:?}, expected one of {}",
+                inner.governor,
+                current.available_governors.join(", ")
+            ))
+            .into());
+        }
+        tracing::info!("Setting cpu governor {} for {caller}", inner.governor);
+        let result = self.backend.set_cpu_governor(&inner.governor).await;
+        let state = self.applied("set_governor", result)?;
+
+        let reply = cpu::SetGovernorResponse { state: Some(state) };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_smt(
+        &self,
+        request: Request<cpu::SetSmtRequest>,
+    ) -> Result<Response<cpu::SetSmtResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        if self.current("set_smt").await?.smt.is_none() {
+            return Err(Error::Unavailable("SMT is not supported on this CPU".to_string()).into());
+        }
+        tracing::info!(
+            "Turning SMT {} for {caller}",
+            if inner.enabled { "on" } else { "off" }
+        );
+        let result = self.backend.set_cpu_smt(inner.enabled).await;
+        let state = self.applied("set_smt", result)?;
+
+        let reply = cpu::SetSmtResponse { state: Some(state) };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_boost(
+        &self,
+        request: Request<cpu::SetBoostRequest>,
+    ) -> Result<Response<cpu::SetBoostResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        if self.current("set_boost").await?.boost.is_none() {
+            return Err(
+                Error::Unavailable("boost is not supported on this CPU".to_string()).into(),
+            );
+        }
+        tracing::info!(
+            "Turning cpu boost {} for {caller}",
+            if inner.enabled { "on" } else { "off" }
+        );
+        let result = self.backend.set_cpu_boost(inner.enabled).await;
+        let state = self.applied("set_boost", result)?;
+
+        let reply = cpu::SetBoostResponse { state: Some(state) };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_max_frequency(
+        &self,
+        request: Request<cpu::SetMaxFrequencyRequest>,
+    ) -> Result<Response<cpu::SetMaxFrequencyResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let current = self.current("set_max_frequency").await?;
+        let range = current.hardware_min_frequency..=current.hardware_max_frequency;
+        if !range.contains(&inner.frequency_khz) {
+            return Err(Error::InvalidArgument(format!(
+                "{} kHz is outside of {}..={} kHz",
+                inner.frequency_khz, current.hardware_min_frequency, current.hardware_max_frequency
+            ))
+            .into());
+        }
+        tracing::info!(
+            "Limiting cpu frequency to {} kHz for {caller}",
+            inner.frequency_khz
+        );
+        let result = self
+            .backend
+            .set_cpu_max_frequency(inner.frequency_khz)
+            .await;
+        let state = self.applied("set_max_frequency", result)?;
+
+        let reply = cpu::SetMaxFrequencyResponse { state: Some(state) };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchStateStream = WatchStream<cpu::WatchStateResponse>;
+
+    async fn watch_state(
+        &self,
+        request: Request<cpu::WatchStateRequest>,
+    ) -> Result<Response<Self::WatchStateStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            async move {
+                let state = backend.cpu_state().await?;
+                Ok(cpu::WatchStateResponse {
+                    state: Some(state.into()),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::cpu::cpu_service_server::CpuService;
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::tokio;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::backend::fake::{cpu_state, Call, FakeBackend};
+    use crate::config::{self, Config, CpuConfig};
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, CpuServer) {
+        let backend = Arc::new(backend);
+        let server = CpuServer::new(backend.clone(), config::fixed(Config::default()));
+        (backend, server)
+    }
+
+    #[tokio::test]
+    async fn set_governor_returns_the_new_state() {
+        let (backend, server) = server(FakeBackend::default().with_cpu(cpu_state()));
+
+        let reply = server
+            .set_governor(Request::new(cpu::SetGovernorRequest {
+                governor: "performance".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.state.unwrap().governor, "performance");
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::CpuState,
+                Call::SetCpuGovernor("performance".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_governors() {
+        let (backend, server) = server(FakeBackend::default().with_cpu(cpu_state()));
+
+        let status = server
+            .set_governor(Request::new(cpu::SetGovernorRequest {
+                governor: "turbo".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(backend.calls(), vec![Call::CpuState]);
+    }
+
+    #[tokio::test]
+    async fn max_frequency_must_be_within_the_hardware_range() {
+        let (backend, server) = server(FakeBackend::default().with_cpu(cpu_state()));
+
+        for frequency_khz in [0, 1399999, 3500001] {
+            let status = server
+                .set_max_frequency(Request::new(cpu::SetMaxFrequencyRequest { frequency_khz }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{frequency_khz}");
+        }
+
+        let reply = server
+            .set_max_frequency(Request::new(cpu::SetMaxFrequencyRequest {
+                frequency_khz: 1400000,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.state.unwrap().max_frequency_khz, 1400000);
+        assert!(backend.calls().contains(&Call::SetCpuMaxFrequency(1400000)));
+    }
+
+    #[tokio::test]
+    async fn unsupported_smt_is_unavailable() {
+        let mut cpu = cpu_state();
+        cpu.smt = None;
+        let (backend, server) = server(FakeBackend::default().with_cpu(cpu));
+
+        let status = server
+            .set_smt(Request::new(cpu::SetSmtRequest { enabled: false }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(backend.calls(), vec![Call::CpuState]);
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let backend = Arc::new(FakeBackend::default().with_cpu(cpu_state()));
+        let config = config::fixed(Config {
+            cpu: CpuConfig { enabled: false },
+            ..Config::default()
+        });
+        let server = CpuServer::new(backend.clone(), config);
+
+        let status = server
+            .set_boost(Request::new(cpu::SetBoostRequest { enabled: false }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_state_follows_changes() {
+        let (_, mut server) = server(FakeBackend::default().with_cpu(cpu_state()));
+        server.service.interval = Duration::from_secs(3600);
+
+        let mut stream = server
+            .watch_state(Request::new(cpu::WatchStateRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = stream.next().await.unwrap().unwrap().state.unwrap();
+        assert!(first.boost_enabled);
+
+        server
+            .set_boost(Request::new(cpu::SetBoostRequest { enabled: false }))
+            .await
+            .unwrap();
+
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(!next.unwrap().unwrap().unwrap().state.unwrap().boost_enabled);
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the kernel exposes CPU hotplug and cpufreq settings.
+pub const CPU_ROOT: &str = "/sys/devices/system/cpu";
+
+#[derive(Debug)]
+pub enum Error {
+    /// There are no cpufreq policies, i.e. no cpufreq driver is loaded.
+    NoCpufreq,
+    /// The kernel offers no switch for the named setting on this CPU.
+    Unsupported(&'static str),
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        value: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoCpufreq => write!(f, "cpufreq is not available"),
+            Error::Unsupported(setting) => write!(f, "{setting} is not supported on this CPU"),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+            Error::Parse { path, value } => {
+                write!(f, "unexpected value in {}: {value:?}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoCpufreq | Error::Unsupported(_) => {
+                shortcut_core::Error::Unavailable(err.to_string())
+            }
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            // The kernel answers EINVAL for values the driver refuses
+            Error::Io { err: ref io, .. } if io.raw_os_error() == Some(libc::EINVAL) => {
+                shortcut_core::Error::InvalidArgument(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// CPU settings as the kernel reports them, frequencies in kHz.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub governor: String,
+    pub available_governors: Vec<String>,
+    /// `None` when SMT can't be switched.
+    pub smt: Option<bool>,
+    /// `None` when the cpufreq driver has no boost switch.
+    pub boost: Option<bool>,
+    pub max_frequency: u32,
+    pub hardware_min_frequency: u32,
+    pub hardware_max_frequency: u32,
+}
+
+impl From<CpuState> for shortcut_core::cpu::CpuState {
+    fn from(state: CpuState) -> Self {
+        Self {
+            governor: state.governor,
+            available_governors: state.available_governors,
+            smt_supported: state.smt.is_some(),
+            smt_enabled: state.smt.unwrap_or_default(),
+            boost_supported: state.boost.is_some(),
+            boost_enabled: state.boost.unwrap_or_default(),
+            max_frequency_khz: state.max_frequency,
+            hardware_min_frequency_khz: state.hardware_min_frequency,
+            hardware_max_frequency_khz: state.hardware_max_frequency,
+        }
+    }
+}
+
+/// Reads and writes the CPU settings below a sysfs root, [`CPU_ROOT`] outside of tests.
+///
+/// Governor and frequency settings are applied to every cpufreq policy, so all cores follow.
+#[derive(Debug, Clone)]
+pub struct Sysfs {
+    root: PathBuf,
+}
+
+impl Sysfs {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn read(&self, path: &Path) -> Result<String, Error> {
+        fs::read_to_string(path)
+            .map(|content| content.trim().to_string())
+            .map_err(|err| Error::Io {
+                path: path.to_path_buf(),
+                err,
+            })
+    }
+
+    fn write(&self, path: &Path, value: &str) -> Result<(), Error> {
+        tracing::debug!("Writing {value:?} to {}", path.display());
+        fs::write(path, value).map_err(|err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        })
+    }
+
+    fn read_khz(&self, path: &Path) -> Result<u32, Error> {
+        let value = self.read(path)?;
+        value.parse().map_err(|_| Error::Parse {
+            path: path.to_path_buf(),
+            value,
+        })
+    }
+
+    /// The cpufreq policies of online CPUs, in CPU order.
+    ///
+    /// Policies of CPUs taken offline, e.g. by turning SMT off, stay around but can't be changed.
+    fn policies(&self) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.root.join("cpufreq");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(Error::NoCpufreq),
+            Err(err) => return Err(Error::Io { path: dir, err }),
+        };
+
+        let mut policies: Vec<(u32, PathBuf)> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let index = entry
+                    .file_name()
+                    .to_str()?
+                    .strip_prefix("policy")?
+                    .parse()
+                    .ok()?;
+                Some((index, entry.path()))
+            })
+            .filter(|(_, path)| {
+                fs::read_to_string(path.join("affected_cpus"))
+                    .map(|cpus| !cpus.trim().is_empty())
+                    .unwrap_or(true)
+            })
+            .collect();
+        if policies.is_empty() {
+            return Err(Error::NoCpufreq);
+        }
+
+        policies.sort();
+        Ok(policies.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn smt_control(&self) -> PathBuf {
+        self.root.join("smt/control")
+    }
+
+    fn boost_path(&self) -> PathBuf {
+        self.root.join("cpufreq/boost")
+    }
+
+    /// Reads the settings, taking governor and frequencies from the first policy.
+    pub fn state(&self) -> Result<CpuState, Error> {
+        let policy = self.policies()?.remove(0);
+
+        let smt = match self.read(&self.smt_control()) {
+            Ok(control) if control == "on" => Some(true),
+            // "forceoff", "notsupported" and "notimplemented" can't be changed at runtime
+            Ok(control) if control == "off" => Some(false),
+            Ok(_) => None,
+            Err(Error::Io { err, .. }) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let boost = match self.read(&self.boost_path()) {
+            Ok(boost) => Some(boost == "1"),
+            Err(Error::Io { err, .. }) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(CpuState {
+            governor: self.read(&policy.join("scaling_governor"))?,
+            available_governors: self
+                .read(&policy.join("scaling_available_governors"))?
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            smt,
+            boost,
+            max_frequency: self.read_khz(&policy.join("scaling_max_freq"))?,
+            hardware_min_frequency: self.read_khz(&policy.join("cpuinfo_min_freq"))?,
+            hardware_max_frequency: self.read_khz(&policy.join("cpuinfo_max_freq"))?,
+        })
+    }
+
+    pub fn set_governor(&self, governor: &str) -> Result<(), Error> {
+        for policy in self.policies()? {
+            self.write(&policy.join("scaling_governor"), governor)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_max_frequency(&self, khz: u32) -> Result<(), Error> {
+        for policy in self.policies()? {
+            self.write(&policy.join("scaling_max_freq"), &khz.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn set_boost(&self, enabled: bool) -> Result<(), Error> {
+        let path = self.boost_path();
+        if !path.exists() {
+            return Err(Error::Unsupported("boost"));
+        }
+        self.write(&path, if enabled { "1" } else { "0" })
+    }
+
+    pub fn set_smt(&self, enabled: bool) -> Result<(), Error> {
+        if self.state()?.smt.is_none() {
+            return Err(Error::Unsupported("SMT"));
+        }
+        self.write(&self.smt_control(), if enabled { "on" } else { "off" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    /// Two cores with a policy each, SMT on and boost off.
+    fn fake_cpu(name: &str) -> FakeSysfs {
+        let sysfs = FakeSysfs::new(name);
+        for cpu in 0..2 {
+            let policy = format!("cpufreq/policy{cpu}");
+            sysfs.write(&format!("{policy}/affected_cpus"), &cpu.to_string());
+            sysfs.write(&format!("{policy}/scaling_governor"), "schedutil");
+            sysfs.write(
+                &format!("{policy}/scaling_available_governors"),
+                "conservative ondemand userspace powersave performance schedutil",
+            );
+            sysfs.write(&format!("{policy}/scaling_max_freq"), "3500000");
+            sysfs.write(&format!("{policy}/cpuinfo_min_freq"), "1400000");
+            sysfs.write(&format!("{policy}/cpuinfo_max_freq"), "3500000");
+        }
+        sysfs.write("cpufreq/boost", "0");
+        sysfs.write("smt/control", "on");
+        sysfs
+    }
+
+    #[test]
+    fn reads_the_first_policy() {
+        let sysfs = fake_cpu("cpu-state");
+        sysfs.write("cpufreq/policy0/scaling_max_freq", "2800000");
+
+        let state = Sysfs::new(sysfs.root()).state().unwrap();
+
+        assert_eq!(
+            state,
+            CpuState {
+                governor: "schedutil".to_string(),
+                available_governors: vec![
+                    "conservative".to_string(),
+                    "ondemand".to_string(),
+                    "userspace".to_string(),
+                    "powersave".to_string(),
+                    "performance".to_string(),
+                    "schedutil".to_string(),
+                ],
+                smt: Some(true),
+                boost: Some(false),
+                max_frequency: 2800000,
+                hardware_min_frequency: 1400000,
+                hardware_max_frequency: 3500000,
+            }
+        );
+    }
+
+    #[test]
+    fn writes_every_online_policy() {
+        let sysfs = fake_cpu("cpu-write");
+        sysfs.write("cpufreq/policy2/affected_cpus", "");
+        sysfs.write("cpufreq/policy2/scaling_governor", "schedutil");
+        let cpu = Sysfs::new(sysfs.root());
+
+        cpu.set_governor("performance").unwrap();
+        cpu.set_max_frequency(2000000).unwrap();
+
+        for policy in ["policy0", "policy1"] {
+            assert_eq!(
+                sysfs.read(&format!("cpufreq/{policy}/scaling_governor")),
+                "performance"
+            );
+            assert_eq!(
+                sysfs.read(&format!("cpufreq/{policy}/scaling_max_freq")),
+                "2000000"
+            );
+        }
+        assert_eq!(sysfs.read("cpufreq/policy2/scaling_governor"), "schedutil");
+    }
+
+    #[test]
+    fn switches_smt_and_boost() {
+        let sysfs = fake_cpu("cpu-switches");
+        let cpu = Sysfs::new(sysfs.root());
+
+        cpu.set_smt(false).unwrap();
+        cpu.set_boost(true).unwrap();
+
+        let state = cpu.state().unwrap();
+        assert_eq!(state.smt, Some(false));
+        assert_eq!(state.boost, Some(true));
+    }
+
+    #[test]
+    fn missing_switches_are_unsupported() {
+        let sysfs = fake_cpu("cpu-unsupported");
+        sysfs.write("smt/control", "notsupported");
+        std::fs::remove_file(sysfs.root().join("cpufreq/boost")).unwrap();
+        let cpu = Sysfs::new(sysfs.root());
+
+        let state = cpu.state().unwrap();
+        assert_eq!((state.smt, state.boost), (None, None));
+        assert!(matches!(cpu.set_smt(true), Err(Error::Unsupported(_))));
+        assert!(matches!(cpu.set_boost(true), Err(Error::Unsupported(_))));
+        assert_eq!(sysfs.read("smt/control"), "notsupported");
+    }
+
+    #[test]
+    fn no_policies_means_no_cpufreq() {
+        let sysfs = FakeSysfs::new("cpu-none");
+        sysfs.write("smt/control", "on");
+
+        let err = Sysfs::new(sysfs.root()).state().unwrap_err();
+
+        assert!(matches!(err, Error::NoCpufreq));
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::Unavailable(_)
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the kernel lists the backlight devices.
+pub const BACKLIGHT_ROOT: &str = "/sys/class/backlight";
+
+#[derive(Debug)]
+pub enum Error {
+    /// There is no backlight of that name, an empty name means none at all.
+    NoBacklight(String),
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        value: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoBacklight(name) if name.is_empty() => write!(f, "no backlight found"),
+            Error::NoBacklight(name) => write!(f, "no backlight named {name}"),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+            Error::Parse { path, value } => {
+                write!(f, "unexpected value in {}: {value:?}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoBacklight(ref name) if name.is_empty() => {
+                shortcut_core::Error::Unavailable(err.to_string())
+            }
+            Error::NoBacklight(name) => {
+                shortcut_core::Error::not_found(format!("backlight {name}"))
+            }
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// A backlight in the raw steps of its driver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backlight {
+    pub name: String,
+    /// What the hardware currently uses, which lags behind a change for fading drivers.
+    pub brightness: u32,
+    pub max_brightness: u32,
+}
+
+/// The backlight devices below a sysfs root, [`BACKLIGHT_ROOT`] outside of tests.
+#[derive(Debug, Clone)]
+pub struct Backlights {
+    root: PathBuf,
+}
+
+impl Backlights {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn read(path: &Path) -> Result<u32, Error> {
+        let value = fs::read_to_string(path).map_err(|err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        value.trim().parse().map_err(|_| Error::Parse {
+            path: path.to_path_buf(),
+            value: value.trim().to_string(),
+        })
+    }
+
+    fn backlight(&self, name: &str) -> Result<Backlight, Error> {
+        let device = self.root.join(name);
+        Ok(Backlight {
+            name: name.to_string(),
+            brightness: Self::read(&device.join("actual_brightness"))?,
+            max_brightness: Self::read(&device.join("max_brightness"))?,
+        })
+    }
+
+    /// How much the kernel's `type` of a backlight is preferred, like desktops do. Firmware and
+    /// platform interfaces know about the panel, raw ones only drive a register.
+    fn rank(&self, name: &str) -> u8 {
+        let kind = fs::read_to_string(self.root.join(name).join("type")).unwrap_or_default();
+        match kind.trim() {
+            "firmware" => 0,
+            "platform" => 1,
+            _ => 2,
+        }
+    }
+
+    /// Every backlight, the preferred one first and then by name, without any when the class is
+    /// missing.
+    pub fn list(&self) -> Result<Vec<Backlight>, Error> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => {
+                return Err(Error::Io {
+                    path: self.root.clone(),
+                    err,
+                })
+            }
+        };
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| Some(entry.ok()?.file_name().to_str()?.to_string()))
+            .collect();
+        names.sort_by_cached_key(|name| (self.rank(name), name.clone()));
+        names.iter().map(|name| self.backlight(name)).collect()
+    }
+
+    /// Sets the raw brightness of the backlight `name`, the preferred one when empty.
+    ///
+    /// Returns the brightness written since `actual_brightness` may still be fading.
+    pub fn set(&self, name: &str, brightness: u32) -> Result<Backlight, Error> {
+        let current = match name {
+            "" => self.list()?.into_iter().next(),
+            name => self
+                .list()?
+                .into_iter()
+                .find(|backlight| backlight.name == name),
+        }
+        .ok_or_else(|| Error::NoBacklight(name.to_string()))?;
+
+        let path = self.root.join(&current.name).join("brightness");
+        let brightness = brightness.min(current.max_brightness);
+        tracing::debug!("Writing {brightness} to {}", path.display());
+        fs::write(&path, brightness.to_string()).map_err(|err| Error::Io { path, err })?;
+
+        Ok(Backlight {
+            brightness,
+            ..current
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    /// The Steam Deck panel and a coarse ACPI one.
+    fn fake_backlights(name: &str) -> FakeSysfs {
+        let sysfs = FakeSysfs::new(name);
+        sysfs.write("amdgpu_bl0/max_brightness", "65535");
+        sysfs.write("amdgpu_bl0/actual_brightness", "32768");
+        sysfs.write("amdgpu_bl0/brightness", "32768");
+        sysfs.write("amdgpu_bl0/type", "raw");
+        sysfs.write("acpi_video0/max_brightness", "15");
+        sysfs.write("acpi_video0/actual_brightness", "15");
+        sysfs.write("acpi_video0/brightness", "15");
+        sysfs.write("acpi_video0/type", "firmware");
+        sysfs
+    }
+
+    #[test]
+    fn lists_backlights_by_name() {
+        let sysfs = fake_backlights("backlight-list");
+
+        let backlights = Backlights::new(sysfs.root()).list().unwrap();
+
+        assert_eq!(
+            backlights,
+            vec![
+                Backlight {
+                    name: "acpi_video0".to_string(),
+                    brightness: 15,
+                    max_brightness: 15,
+                },
+                Backlight {
+                    name: "amdgpu_bl0".to_string(),
+                    brightness: 32768,
+                    max_brightness: 65535,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_firmware_and_platform_backlights() {
+        let sysfs = fake_backlights("backlight-type");
+        sysfs.write("acpi_video0/type", "raw");
+        sysfs.write("steamdeck_bl/max_brightness", "100");
+        sysfs.write("steamdeck_bl/actual_brightness", "50");
+        sysfs.write("steamdeck_bl/brightness", "50");
+        sysfs.write("steamdeck_bl/type", "platform");
+        let backlights = Backlights::new(sysfs.root());
+
+        let names: Vec<_> = backlights
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|backlight| backlight.name)
+            .collect();
+        assert_eq!(names, ["steamdeck_bl", "acpi_video0", "amdgpu_bl0"]);
+
+        let backlight = backlights.set("", 20).unwrap();
+        assert_eq!(backlight.name, "steamdeck_bl");
+        assert_eq!(sysfs.read("steamdeck_bl/brightness"), "20");
+    }
+
+    #[test]
+    fn writes_brightness_within_range() {
+        let sysfs = fake_backlights("backlight-set");
+        let backlights = Backlights::new(sysfs.root());
+
+        let backlight = backlights.set("amdgpu_bl0", 1000).unwrap();
+        assert_eq!(backlight.brightness, 1000);
+        assert_eq!(sysfs.read("amdgpu_bl0/brightness"), "1000");
+
+        let backlight = backlights.set("", 100).unwrap();
+        assert_eq!(backlight.name, "acpi_video0");
+        assert_eq!(sysfs.read("acpi_video0/brightness"), "15");
+    }
+
+    #[test]
+    fn unknown_backlight_is_not_found() {
+        let sysfs = fake_backlights("backlight-unknown");
+
+        let err = Backlights::new(sysfs.root())
+            .set("intel_backlight", 10)
+            .unwrap_err();
+
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::NotFound { .. }
+        ));
+    }
+
+    #[test]
+    fn missing_class_has_no_backlights() {
+        let backlights = Backlights::new("/nonexistent/backlight").list().unwrap();
+
+        assert!(backlights.is_empty());
+        let err = Backlights::new("/nonexistent/backlight")
+            .set("", 10)
+            .unwrap_err();
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::Unavailable(_)
+        ));
+    }
+}
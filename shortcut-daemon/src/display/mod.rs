@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::display;
+use shortcut_core::display::display_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{Service, WatchStream};
+
+pub(crate) mod backlight;
+
+use backlight::Backlight;
+
+/// The eye is far more sensitive to changes in the dark, so percentages are raised to this power
+/// before scaling them to the raw steps, which the drivers map linearly to light output.
+const GAMMA: f64 = 2.2;
+
+/// The raw driver step for a perceived brightness, at least 1 unless it is 0.
+fn to_raw(percent: u32, max_brightness: u32) -> u32 {
+    // Some broken drivers report no steps at all
+    if max_brightness == 0 {
+        return 0;
+    }
+    let share = (f64::from(percent.min(100)) / 100.0).powf(GAMMA);
+    let raw = (f64::from(max_brightness) * share).round() as u32;
+    if percent > 0 {
+        raw.clamp(1, max_brightness)
+    } else {
+        raw
+    }
+}
+
+/// The perceived brightness of a raw driver step.
+fn to_percent(raw: u32, max_brightness: u32) -> u32 {
+    if max_brightness == 0 {
+        return 0;
+    }
+    let share = f64::from(raw.min(max_brightness)) / f64::from(max_brightness);
+    (share.powf(1.0 / GAMMA) * 100.0).round() as u32
+}
+
+fn to_backlight(backlight: Backlight, min_brightness_percent: u32) -> display::Backlight {
+    display::Backlight {
+        brightness_percent: to_percent(backlight.brightness, backlight.max_brightness),
+        name: backlight.name,
+        min_brightness_percent,
+    }
+}
+
+pub struct DisplayServer {
+    backend: Arc<dyn SystemBackend>,
+    config: SharedConfig,
+    service: Service,
+}
+
+impl DisplayServer {
+    pub fn new(backend: Arc<dyn SystemBackend>, config: SharedConfig) -> Self {
+        Self {
+            backend,
+            service: Service::new("display", config.clone(), |config| config.display.enabled),
+            config,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl display_service_server::DisplayService for DisplayServer {
+    async fn list_backlights(
+        &self,
+        request: Request<display::ListBacklightsRequest>,
+    ) -> Result<Response<display::ListBacklightsResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backlights = self.backend.backlights().await.map_err(|err| {
+            tracing::error!("error when list_backlights: {err}");
+            err
+        })?;
+
+        let min = self.config.borrow().display.min_brightness_percent;
+        let reply = display::ListBacklightsResponse {
+            backlights: backlights
+                .into_iter()
+                .map(|backlight| to_backlight(backlight, min))
+                .collect(),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_brightness(
+        &self,
+        request: Request<display::SetBrightnessRequest>,
+    ) -> Result<Response<display::SetBrightnessResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        if inner.brightness_percent > 100 {
+            return Err(Error::InvalidArgument(format!(
+                "brightness must be at most 100 %, got {}",
+                inner.brightness_percent
+            ))
+            .into());
+        }
+        let min = self.config.borrow().display.min_brightness_percent;
+        let percent = inner.brightness_percent.max(min);
+
+        let backlights = self.backend.backlights().await.map_err(|err| {
+            tracing::error!("error when set_brightness: {err}");
+            err
+        })?;
+        let current = match inner.name.as_str() {
+            "" => backlights.into_iter().next(),
+            name => backlights
+                .into_iter()
+                .find(|backlight| backlight.name == name),
+        };
+        let current = match (current, inner.name.as_str()) {
+            (Some(current), _) => current,
+            (None, "") => return Err(Error::Unavailable("no backlight found".to_string()).into()),
+            (None, name) => return Err(Error::not_found(format!("backlight {name}")).into()),
+        };
+
+        tracing::info!(
+            "Setting brightness of {} to {percent} % for {caller}",
+            current.name
+        );
+        let raw = to_raw(percent, current.max_brightness);
+        let backlight = self
+            .backend
+            .set_backlight(&current.name, raw)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_brightness: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = display::SetBrightnessResponse {
+            backlight: Some(to_backlight(backlight, min)),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchBacklightsStream = WatchStream<display::WatchBacklightsResponse>;
+
+    async fn watch_backlights(
+        &self,
+        request: Request<display::WatchBacklightsRequest>,
+    ) -> Result<Response<Self::WatchBacklightsStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backend = self.backend.clone();
+        let config = self.config.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            let min = config.borrow().display.min_brightness_percent;
+            async move {
+                let backlights = backend.backlights().await?;
+                Ok(display::WatchBacklightsResponse {
+                    backlights: backlights
+                        .into_iter()
+                        .map(|backlight| to_backlight(backlight, min))
+                        .collect(),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::display::display_service_server::DisplayService;
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::tokio;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::backend::fake::{backlight, Call, FakeBackend};
+    use crate::config::{self, Config};
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, DisplayServer) {
+        let backend = Arc::new(backend);
+        let server = DisplayServer::new(backend.clone(), config::fixed(Config::default()));
+        (backend, server)
+    }
+
+    fn set_brightness(
+        name: &str,
+        brightness_percent: u32,
+    ) -> Request<display::SetBrightnessRequest> {
+        Request::new(display::SetBrightnessRequest {
+            name: name.to_string(),
+            brightness_percent,
+        })
+    }
+
+    #[test]
+    fn maps_percentages_perceptually() {
+        assert_eq!(to_raw(0, 65535), 0);
+        assert_eq!(to_raw(50, 100), 22);
+        assert_eq!(to_raw(100, 65535), 65535);
+        assert_eq!(to_raw(1, 15), 1);
+        for percent in [1, 5, 33, 50, 99] {
+            assert_eq!(to_percent(to_raw(percent, 65535), 65535), percent);
+        }
+        assert_eq!(to_percent(3, 0), 0);
+        assert_eq!(to_raw(50, 0), 0);
+    }
+
+    #[tokio::test]
+    async fn set_brightness_writes_raw_steps() {
+        let (backend, server) = server(
+            FakeBackend::default()
+                .with_backlight(backlight("acpi_video0", 15, 15))
+                .with_backlight(backlight("amdgpu_bl0", 65535, 65535)),
+        );
+
+        let reply = server
+            .set_brightness(set_brightness("amdgpu_bl0", 50))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let backlight = reply.backlight.unwrap();
+        assert_eq!(backlight.name, "amdgpu_bl0");
+        assert_eq!(backlight.brightness_percent, 50);
+        assert!(backend.calls().contains(&Call::SetBacklight(
+            "amdgpu_bl0".to_string(),
+            to_raw(50, 65535)
+        )));
+    }
+
+    #[tokio::test]
+    async fn never_goes_below_the_floor() {
+        let (backend, server) =
+            server(FakeBackend::default().with_backlight(backlight("amdgpu_bl0", 65535, 65535)));
+
+        let reply = server
+            .set_brightness(set_brightness("", 0))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let backlight = reply.backlight.unwrap();
+        assert_eq!(backlight.brightness_percent, 5);
+        assert_eq!(backlight.min_brightness_percent, 5);
+        assert!(backend.calls().contains(&Call::SetBacklight(
+            "amdgpu_bl0".to_string(),
+            to_raw(5, 65535)
+        )));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() {
+        let (backend, server) =
+            server(FakeBackend::default().with_backlight(backlight("amdgpu_bl0", 100, 255)));
+
+        let status = server
+            .set_brightness(set_brightness("", 101))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let status = server
+            .set_brightness(set_brightness("intel_backlight", 50))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        assert_eq!(backend.calls(), vec![Call::Backlights]);
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let backend =
+            Arc::new(FakeBackend::default().with_backlight(backlight("amdgpu_bl0", 1, 1)));
+        let mut config = Config::default();
+        config.display.enabled = false;
+        let server = DisplayServer::new(backend.clone(), config::fixed(config));
+
+        let status = server
+            .set_brightness(set_brightness("", 50))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_backlights_follows_changes() {
+        let (_, mut server) =
+            server(FakeBackend::default().with_backlight(backlight("amdgpu_bl0", 65535, 65535)));
+        server.service.interval = Duration::from_secs(3600);
+
+        let mut stream = server
+            .watch_backlights(Request::new(display::WatchBacklightsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.backlights[0].brightness_percent, 100);
+
+        server
+            .set_brightness(set_brightness("amdgpu_bl0", 40))
+            .await
+            .unwrap();
+
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        let next = next.unwrap().unwrap().unwrap();
+        assert_eq!(next.backlights[0].brightness_percent, 40);
+    }
+}
@@ -0,0 +1,45 @@
+//! Files other programs read while the daemon writes them.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Replaces the file at `path` with `content`, creating its directory.
+///
+/// Readers see the old or the new file, never a half written one, also when the deck loses power
+/// midway: the content reaches the disk before the rename and the rename before returning.
+pub(crate) fn replace(path: &Path, content: &str) -> io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    match dir {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    #[test]
+    fn replaces_the_whole_file() {
+        let fake = FakeSysfs::new("files-replace");
+        let path = fake.root().join("conf.d/10-test.conf");
+
+        replace(&path, "first\nsecond\n").unwrap();
+        replace(&path, "third\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
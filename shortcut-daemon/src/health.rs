@@ -0,0 +1,321 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use shortcut_core::{battery, bluetooth, cpu, display, sensors, ssh, tokio, wifi};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::backend::SystemBackend;
+use crate::config::{Config, SharedConfig};
+
+/// How often the health of the services is re-checked, e.g. after a driver got loaded.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// WiFi is served when enabled and nl80211 answers, listing no devices is fine.
+async fn wifi_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.wifi.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.wifi_interfaces().await {
+        Ok(_) => ServingStatus::Serving,
+        Err(err) => {
+            tracing::debug!("wifi is not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// SSH is served when enabled and systemd knows the configured unit.
+async fn ssh_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.ssh.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.unit_state(&config.ssh.unit).await {
+        Ok(_) => ServingStatus::Serving,
+        Err(err) => {
+            tracing::debug!("ssh is not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// Bluetooth is served when enabled and BlueZ has an adapter, powered or not.
+async fn bluetooth_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.bluetooth.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.bluetooth_powered().await {
+        Ok(_) => ServingStatus::Serving,
+        Err(err) => {
+            tracing::debug!("bluetooth is not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// The CPU service is served when enabled and a cpufreq driver is loaded.
+async fn cpu_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.cpu.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.cpu_state().await {
+        Ok(_) => ServingStatus::Serving,
+        Err(err) => {
+            tracing::debug!("cpu is not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// Sensors are served when enabled and the kernel has hwmon, even without any devices.
+async fn sensors_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.sensors.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.sensors().await {
+        Ok(_) => ServingStatus::Serving,
+        Err(err) => {
+            tracing::debug!("sensors are not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// The display service is served when enabled and there is a backlight to control.
+async fn display_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.display.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.backlights().await {
+        Ok(backlights) if !backlights.is_empty() => ServingStatus::Serving,
+        Ok(_) => {
+            tracing::debug!("display is not serving: no backlight found");
+            ServingStatus::NotServing
+        }
+        Err(err) => {
+            tracing::debug!("display is not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// The battery service is served when enabled and the system runs on a battery.
+async fn battery_status(backend: &dyn SystemBackend, config: &Config) -> ServingStatus {
+    if !config.battery.enabled {
+        return ServingStatus::NotServing;
+    }
+
+    match backend.battery().await {
+        Ok(_) => ServingStatus::Serving,
+        Err(err) => {
+            tracing::debug!("battery is not serving: {err}");
+            ServingStatus::NotServing
+        }
+    }
+}
+
+/// The status of every service, by its fully qualified gRPC name.
+async fn statuses(
+    backend: &dyn SystemBackend,
+    config: &Config,
+) -> [(&'static str, ServingStatus); 7] {
+    [
+        (wifi::SERVICE_NAME, wifi_status(backend, config).await),
+        (ssh::SERVICE_NAME, ssh_status(backend, config).await),
+        (
+            bluetooth::SERVICE_NAME,
+            bluetooth_status(backend, config).await,
+        ),
+        (cpu::SERVICE_NAME, cpu_status(backend, config).await),
+        (sensors::SERVICE_NAME, sensors_status(backend, config).await),
+        (display::SERVICE_NAME, display_status(backend, config).await),
+        (battery::SERVICE_NAME, battery_status(backend, config).await),
+    ]
+}
+
+/// Keeps the `grpc.health.v1.Health` statuses up to date with the config and the system.
+pub async fn report(
+    mut reporter: HealthReporter,
+    backend: Arc<dyn SystemBackend>,
+    mut config: SharedConfig,
+) {
+    let mut last = None;
+    loop {
+        let current = config.borrow().clone();
+        let statuses = statuses(backend.as_ref(), &current).await;
+        if last.as_ref() != Some(&statuses) {
+            for (service, status) in statuses {
+                tracing::info!("Health of {service}: {status:?}");
+                reporter.set_service_status(service, status).await;
+            }
+            last = Some(statuses);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+            result = config.changed() => {
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shortcut_core::battery::battery_service_server::BatteryServiceServer;
+    use shortcut_core::bluetooth::bluetooth_service_server::BluetoothServiceServer;
+    use shortcut_core::cpu::cpu_service_server::CpuServiceServer;
+    use shortcut_core::display::display_service_server::DisplayServiceServer;
+    use shortcut_core::sensors::sensors_service_server::SensorsServiceServer;
+    use shortcut_core::ssh::ssh_service_server::SshServiceServer;
+    use shortcut_core::tonic::transport::NamedService;
+    use shortcut_core::wifi::wifi_service_server::WifiServiceServer;
+    use shortcut_core::{tokio, Error};
+
+    use super::*;
+    use crate::backend::fake::{
+        backlight, battery_state, cpu_state, interface, unit_state, FakeBackend, Method,
+    };
+    use crate::battery::BatteryServer;
+    use crate::bluetooth::BluetoothServer;
+    use crate::cpu::CpuServer;
+    use crate::display::DisplayServer;
+    use crate::sensors::SensorsServer;
+    use crate::ssh::SshServer;
+    use crate::wifi::WifiServer;
+
+    const WIFI_SERVICE: &str = wifi::SERVICE_NAME;
+    const SSH_SERVICE: &str = ssh::SERVICE_NAME;
+    const BLUETOOTH_SERVICE: &str = bluetooth::SERVICE_NAME;
+    const CPU_SERVICE: &str = cpu::SERVICE_NAME;
+    const SENSORS_SERVICE: &str = sensors::SERVICE_NAME;
+    const DISPLAY_SERVICE: &str = display::SERVICE_NAME;
+    const BATTERY_SERVICE: &str = battery::SERVICE_NAME;
+
+    fn status_of(statuses: &[(&'static str, ServingStatus)], service: &str) -> ServingStatus {
+        statuses
+            .iter()
+            .find(|(name, _)| *name == service)
+            .map(|(_, status)| *status)
+            .unwrap()
+    }
+
+    #[test]
+    fn service_names_match_the_servers() {
+        assert_eq!(
+            WIFI_SERVICE,
+            <WifiServiceServer<WifiServer> as NamedService>::NAME
+        );
+        assert_eq!(
+            SSH_SERVICE,
+            <SshServiceServer<SshServer> as NamedService>::NAME
+        );
+        assert_eq!(
+            BLUETOOTH_SERVICE,
+            <BluetoothServiceServer<BluetoothServer> as NamedService>::NAME
+        );
+        assert_eq!(
+            CPU_SERVICE,
+            <CpuServiceServer<CpuServer> as NamedService>::NAME
+        );
+        assert_eq!(
+            SENSORS_SERVICE,
+            <SensorsServiceServer<SensorsServer> as NamedService>::NAME
+        );
+        assert_eq!(
+            DISPLAY_SERVICE,
+            <DisplayServiceServer<DisplayServer> as NamedService>::NAME
+        );
+        assert_eq!(
+            BATTERY_SERVICE,
+            <BatteryServiceServer<BatteryServer> as NamedService>::NAME
+        );
+    }
+
+    #[tokio::test]
+    async fn serving_when_the_system_answers() {
+        let backend = FakeBackend::default()
+            .with_interface(interface("wlan0", 3), true)
+            .with_unit("sshd.service", unit_state("inactive", "dead"))
+            .with_bluetooth_adapter(false)
+            .with_cpu(cpu_state())
+            .with_backlight(backlight("amdgpu_bl0", 100, 255))
+            .with_battery(battery_state());
+
+        let statuses = statuses(&backend, &Config::default()).await;
+
+        assert_eq!(status_of(&statuses, WIFI_SERVICE), ServingStatus::Serving);
+        assert_eq!(status_of(&statuses, SSH_SERVICE), ServingStatus::Serving);
+        assert_eq!(
+            status_of(&statuses, BLUETOOTH_SERVICE),
+            ServingStatus::Serving
+        );
+        assert_eq!(status_of(&statuses, CPU_SERVICE), ServingStatus::Serving);
+        assert_eq!(
+            status_of(&statuses, SENSORS_SERVICE),
+            ServingStatus::Serving
+        );
+        assert_eq!(
+            status_of(&statuses, DISPLAY_SERVICE),
+            ServingStatus::Serving
+        );
+        assert_eq!(
+            status_of(&statuses, BATTERY_SERVICE),
+            ServingStatus::Serving
+        );
+    }
+
+    #[tokio::test]
+    async fn wifi_is_not_serving_without_nl80211() {
+        let backend =
+            FakeBackend::default().with_unit("sshd.service", unit_state("inactive", "dead"));
+        backend.fail(
+            Method::WifiInterfaces,
+            Error::Unavailable("nl80211 is not available".to_string()),
+        );
+
+        let statuses = statuses(&backend, &Config::default()).await;
+
+        assert_eq!(
+            status_of(&statuses, WIFI_SERVICE),
+            ServingStatus::NotServing
+        );
+        assert_eq!(status_of(&statuses, SSH_SERVICE), ServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn disabled_or_missing_services_are_not_serving() {
+        let backend = FakeBackend::default().with_interface(interface("wlan0", 3), true);
+        let mut config = Config::default();
+        config.wifi.enabled = false;
+
+        let statuses = statuses(&backend, &config).await;
+
+        assert_eq!(
+            status_of(&statuses, WIFI_SERVICE),
+            ServingStatus::NotServing
+        );
+        assert_eq!(status_of(&statuses, SSH_SERVICE), ServingStatus::NotServing);
+        assert_eq!(
+            status_of(&statuses, BLUETOOTH_SERVICE),
+            ServingStatus::NotServing
+        );
+        assert_eq!(status_of(&statuses, CPU_SERVICE), ServingStatus::NotServing);
+        assert_eq!(
+            status_of(&statuses, DISPLAY_SERVICE),
+            ServingStatus::NotServing
+        );
+        assert_eq!(
+            status_of(&statuses, BATTERY_SERVICE),
+            ServingStatus::NotServing
+        );
+    }
+}
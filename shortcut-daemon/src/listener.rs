@@ -0,0 +1,241 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream};
+use std::path::{Path, PathBuf};
+
+use shortcut_core::tokio::net::UnixListener;
+
+/// First descriptor passed by the service manager, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// `FileDescriptorName=` the socket unit is expected to use.
+pub const FD_NAME: &str = "shortcutd";
+
+/// The `LISTEN_*` variables the service manager passes along with the socket.
+#[derive(Debug)]
+pub struct Activation {
+    pid: Option<String>,
+    fds: Option<String>,
+    names: Option<String>,
+}
+
+impl Activation {
+    /// Takes the variables out of the environment so child processes (e.g. sshd -T) don't think
+    /// the sockets are meant for them.
+    ///
+    /// Changing the environment is only sound while no other thread runs, so this has to happen
+    /// before the runtime is started.
+    pub fn take() -> Self {
+        let activation = Self {
+            pid: std::env::var("LISTEN_PID").ok(),
+            fds: std::env::var("LISTEN_FDS").ok(),
+            names: std::env::var("LISTEN_FDNAMES").ok(),
+        };
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(var);
+        }
+        activation
+    }
+}
+
+/// The socket the daemon serves on.
+pub struct Listener {
+    pub uds: UnixListener,
+    /// Set when the daemon bound the socket itself and has to remove it again on shutdown.
+    pub path: Option<PathBuf>,
+}
+
+impl Listener {
+    /// Takes over the socket passed in by systemd, or binds `path` when not socket activated.
+    pub fn open(path: &Path, activation: Activation) -> io::Result<Self> {
+        if let Some(uds) = activated(activation)? {
+            tracing::info!("Listening on inherited socket {:?}", uds.local_addr()?);
+            return Ok(Self { uds, path: None });
+        }
+
+        let uds = bind(path)?;
+        tracing::info!("Listening on {}", path.display());
+
+        Ok(Self {
+            uds,
+            path: Some(path.to_path_buf()),
+        })
+    }
+}
+
+fn activated(activation: Activation) -> io::Result<Option<UnixListener>> {
+    let fd = match listen_fd(
+        activation.pid.as_deref(),
+        activation.fds.as_deref(),
+        activation.names.as_deref(),
+        std::process::id(),
+    )? {
+        Some(fd) => fd,
+        None => return Ok(None),
+    };
+
+    // SAFETY: the service manager hands ownership of the descriptor to this process and nothing
+    // else in the daemon touches it
+    let listener = unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        StdUnixListener::from_raw_fd(fd)
+    };
+    listener.set_nonblocking(true)?;
+
+    Ok(Some(UnixListener::from_std(listener)?))
+}
+
+/// Picks the descriptor named [`FD_NAME`] out of the `LISTEN_*` variables.
+///
+/// A single unnamed descriptor is accepted as well so the socket unit doesn't need
+/// `FileDescriptorName=`.
+fn listen_fd(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+) -> io::Result<Option<RawFd>> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(None),
+    };
+    if pid.parse::<u32>().ok() != Some(own_pid) {
+        tracing::debug!("Ignoring sockets passed to pid {pid}");
+        return Ok(None);
+    }
+
+    let count: RawFd = fds.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid LISTEN_FDS: {fds}"),
+        )
+    })?;
+    if count <= 0 {
+        return Ok(None);
+    }
+
+    let named = names.and_then(|names| {
+        names
+            .split(':')
+            .zip(SD_LISTEN_FDS_START..)
+            .take(count as usize)
+            .find(|(name, _)| *name == FD_NAME)
+            .map(|(_, fd)| fd)
+    });
+
+    match named {
+        Some(fd) => Ok(Some(fd)),
+        None if count == 1 => Ok(Some(SD_LISTEN_FDS_START)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("none of the {count} inherited sockets is named {FD_NAME}"),
+        )),
+    }
+}
+
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if StdUnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("a daemon is already listening on {}", path.display()),
+                ));
+            }
+            tracing::warn!("Removing stale socket {}", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let uds = UnixListener::bind(path)?;
+
+    // Anyone may connect, callers are checked against the allowlist per request
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+
+    Ok(uds)
+}
+
+#[cfg(test)]
+mod tests {
+    use shortcut_core::tokio;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shortcutd-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("shortcutd.sock")
+    }
+
+    #[test]
+    fn listen_fd_requires_matching_pid() {
+        assert_eq!(listen_fd(None, None, None, 42).unwrap(), None);
+        assert_eq!(listen_fd(Some("41"), Some("1"), None, 42).unwrap(), None);
+        assert_eq!(listen_fd(Some("42"), Some("1"), None, 42).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn listen_fd_picks_named_socket() {
+        let fd = listen_fd(Some("42"), Some("3"), Some("other:shortcutd:more"), 42).unwrap();
+
+        assert_eq!(fd, Some(4));
+    }
+
+    #[test]
+    fn listen_fd_rejects_ambiguous_sockets() {
+        assert!(listen_fd(Some("42"), Some("2"), None, 42).is_err());
+        assert!(listen_fd(Some("42"), Some("2"), Some("a:b"), 42).is_err());
+        assert!(listen_fd(Some("42"), Some("many"), None, 42).is_err());
+    }
+
+    #[tokio::test]
+    async fn bind_creates_directory_and_replaces_stale_socket() {
+        let path = temp_path("stale");
+        drop(bind(&path).unwrap());
+        assert!(path.exists());
+
+        let _uds = bind(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bind_refuses_live_socket() {
+        let path = temp_path("live");
+        let _uds = bind(&path).unwrap();
+
+        let err = bind(&path).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bind_leaves_other_files_alone() {
+        let path = temp_path("file");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "keep me").unwrap();
+
+        let err = bind(&path).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use shortcut_core::futures::stream::{BoxStream, StreamExt};
+use zbus::{dbus_proxy, Connection};
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    #[dbus_proxy(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// Client for `org.freedesktop.login1` on a D-Bus connection.
+#[derive(Debug, Clone)]
+pub struct Logind {
+    conn: Connection,
+}
+
+impl Logind {
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    /// Yields every time the system comes back from suspend or hibernation.
+    pub async fn resumed(&self) -> zbus::Result<BoxStream<'static, ()>> {
+        let manager = ManagerProxy::new(&self.conn).await?;
+        let signals = manager.receive_prepare_for_sleep().await?;
+
+        Ok(signals
+            .filter_map(|signal| async move {
+                match signal.args() {
+                    // PrepareForSleep(false) is sent after waking up
+                    Ok(args) if !args.start => Some(()),
+                    Ok(_) => None,
+                    Err(err) => {
+                        tracing::warn!("malformed PrepareForSleep signal: {err}");
+                        None
+                    }
+                }
+            })
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::tokio;
+    use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
+
+    use super::*;
+    use crate::test_bus::{self, require_bus};
+
+    struct StubManager;
+
+    #[dbus_interface(name = "org.freedesktop.login1.Manager")]
+    impl StubManager {
+        #[dbus_interface(signal)]
+        async fn prepare_for_sleep(ctxt: &SignalContext<'_>, start: bool) -> zbus::Result<()>;
+    }
+
+    #[tokio::test]
+    async fn resumed_yields_after_waking_up() {
+        let bus = require_bus!();
+        let server = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.freedesktop.login1")
+            .unwrap()
+            .serve_at("/org/freedesktop/login1", StubManager)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let logind = Logind::new(test_bus::connect(&bus).await);
+        let mut resumed = logind.resumed().await.unwrap();
+
+        let ctxt = SignalContext::new(&server, "/org/freedesktop/login1").unwrap();
+        StubManager::prepare_for_sleep(&ctxt, true).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), resumed.next())
+                .await
+                .is_err(),
+            "going to sleep must not count as resuming"
+        );
+
+        StubManager::prepare_for_sleep(&ctxt, false).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), resumed.next())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -1,315 +1,287 @@
-use std::fs;
-use std::os::unix::prelude::PermissionsExt;
-use std::process::Stdio;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use shortcut_core::tokio::net::UnixListener;
-use shortcut_core::tokio::process::Command;
+use clap::Parser;
+use shortcut_core::tokio::signal::unix::{signal, SignalKind};
+use shortcut_core::tokio::sync::watch;
 use shortcut_core::tokio_stream::wrappers::UnixListenerStream;
 
 use shortcut_core::tokio;
-use shortcut_core::tonic::{self, transport::Server, Request, Response, Status};
-
-use shortcut_core::wifi;
+use shortcut_core::tonic::service::interceptor::InterceptedService;
+use shortcut_core::tonic::transport::Server;
+
+use shortcut_core::battery::battery_service_server;
+use shortcut_core::bluetooth::bluetooth_service_server;
+use shortcut_core::cpu::cpu_service_server;
+use shortcut_core::display::display_service_server;
+use shortcut_core::sensors::sensors_service_server;
 use shortcut_core::wifi::wifi_service_server;
 
-use shortcut_core::ssh;
 use shortcut_core::ssh::ssh_service_server;
 
 use tracing_subscriber::fmt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
-#[derive(Debug, Default)]
-pub struct SshServer {}
-
-#[tonic::async_trait]
-impl ssh_service_server::SshService for SshServer {
-    async fn set_enabled(
-        &self,
-        request: Request<ssh::SetEnabledRequest>,
-    ) -> Result<Response<ssh::SetEnabledResponse>, Status> {
-        let inner = request.into_inner();
-        tracing::debug!("{:?}", inner);
-
-        let cmd = Command::new("systemctl")
-            .arg(if inner.enabled { "start" } else { "stop" })
-            .arg("sshd")
-            .status()
-            .await
-            .expect("failed to execute iw");
-
-        let enabled = if cmd.success() {
-            inner.enabled
-        } else {
-            !inner.enabled
-        };
-
-        let reply = ssh::SetEnabledResponse { enabled };
-        Ok(Response::new(reply))
-    }
-
-    async fn get_enabled(
-        &self,
-        request: Request<ssh::GetEnabledRequest>,
-    ) -> Result<Response<ssh::GetEnabledResponse>, Status> {
-        let inner = request.into_inner();
-        tracing::debug!("{:?}", inner);
-
-        let mut sctl = Command::new("systemctl")
-            .arg("status")
-            .arg("sshd")
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute systemctl");
-
-        let sctl_stdout: Stdio = sctl
-            .stdout
-            .take()
-            .unwrap()
-            .try_into()
-            .expect("sctl did not give output");
-
-        let mut grep = Command::new("grep")
-            .arg("running")
-            .stdin(sctl_stdout)
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute grep");
-
-        let grep_stdout: Stdio = grep
-            .stdout
-            .take()
-            .unwrap()
-            .try_into()
-            .expect("grep did not give output");
-
-        let cmd = Command::new("wc")
-            .arg("-l")
-            .stdin(grep_stdout)
-            .output()
-            .await;
-
-        let enabled = match cmd {
-            Ok(output) => {
-                let out = String::from_utf8(output.stdout).unwrap_or_else(|_| "0".to_string());
-
-                match out.trim().parse::<i32>() {
-                    Ok(val) => val == 1,
-                    Err(err) => {
-                        tracing::error!("error when parsing output from get_enabled: {err}");
-                        false
-                    }
-                }
-            }
-            Err(err) => {
-                tracing::error!("error when get_enabled: {err}");
-                false
-            }
-        };
-
-        let reply = ssh::GetEnabledResponse { enabled };
-
-        Ok(Response::new(reply))
-    }
+mod auth;
+mod backend;
+mod battery;
+mod bluetooth;
+mod config;
+mod cpu;
+mod display;
+mod files;
+mod health;
+mod listener;
+mod logind;
+mod poll;
+mod sensors;
+mod ssh;
+mod state;
+mod systemd;
+#[cfg(test)]
+mod test_bus;
+#[cfg(test)]
+mod test_sysfs;
+#[cfg(test)]
+mod test_watch;
+mod wifi;
+
+use auth::{Allowlist, Authenticator};
+use backend::LinuxBackend;
+use battery::power_supply::{PowerSupply, POWER_SUPPLY_ROOT};
+use battery::BatteryServer;
+use bluetooth::bluez::Bluez;
+use bluetooth::BluetoothServer;
+use config::Config;
+use cpu::sysfs::{Sysfs, CPU_ROOT};
+use cpu::CpuServer;
+use display::backlight::{Backlights, BACKLIGHT_ROOT};
+use display::DisplayServer;
+use listener::{Activation, Listener};
+use logind::Logind;
+use sensors::fan::{self, FanControl};
+use sensors::hwmon::{Hwmon, HWMON_ROOT};
+use sensors::SensorsServer;
+use ssh::sshd_config::{SshdConfig, SSHD, SSHD_CONFIG_DIR};
+use ssh::SshServer;
+use state::StateFile;
+use systemd::Systemd;
+use wifi::auto_power_save::{self, AutoPowerSave};
+use wifi::network_manager::NetworkManager;
+use wifi::nm_config::{NmConfig, NM_CONFIG_ROOT};
+use wifi::WifiServer;
+
+#[derive(Debug, Parser)]
+#[clap(about, version)]
+struct Args {
+    /// Config file, defaults to /etc/shortcut/daemon.toml if it exists
+    #[clap(long, env = "SHORTCUT_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Socket to listen on when not started through socket activation, overrides the config
+    #[clap(long, env = shortcut_core::SOCKET_PATH_ENV)]
+    socket: Option<PathBuf>,
 }
 
-#[derive(Debug, Default)]
-pub struct WifiServer {}
-
-#[tonic::async_trait]
-impl wifi_service_server::WifiService for WifiServer {
-    async fn set_power_save(
-        &self,
-        request: Request<wifi::SetPowerSaveRequest>,
-    ) -> Result<Response<wifi::SetPowerSaveResponse>, Status> {
-        let inner = request.into_inner();
-        tracing::debug!("{:?}", inner);
-
-        let cmd = Command::new("iw")
-            .arg("dev")
-            .arg(inner.device)
-            .arg("set")
-            .arg("power_save")
-            .arg(if inner.enabled { "on" } else { "off" })
-            .status()
-            .await
-            .expect("failed to execute iw");
-
-        let enabled = if cmd.success() {
-            inner.enabled
-        } else {
-            !inner.enabled
-        };
-
-        let reply = wifi::SetPowerSaveResponse { enabled };
-
-        Ok(Response::new(reply))
-    }
-
-    async fn get_power_save(
-        &self,
-        request: Request<wifi::GetPowerSaveRequest>,
-    ) -> Result<Response<wifi::GetPowerSaveResponse>, Status> {
-        let inner = request.into_inner();
-        tracing::debug!("{:?}", inner);
-
-        let mut iw = Command::new("iw")
-            .arg("dev")
-            .arg(inner.device)
-            .arg("get")
-            .arg("power_save")
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute iw");
-
-        let iw_stdout: Stdio = iw
-            .stdout
-            .take()
-            .unwrap()
-            .try_into()
-            .expect("iw did not give output");
-
-        let mut grep = Command::new("grep")
-            .arg(" on")
-            .stdin(iw_stdout)
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute grep");
-
-        let grep_stdout: Stdio = grep
-            .stdout
-            .take()
-            .unwrap()
-            .try_into()
-            .expect("grep did not give output");
-
-        let cmd = Command::new("wc")
-            .arg("-l")
-            .stdin(grep_stdout)
-            .output()
-            .await;
+fn load_config(path: &Path, required: bool) -> Result<(Config, Allowlist), config::Error> {
+    let config = Config::load(path, required)?;
+    let allowlist = config.allowlist(path)?;
+    Ok((config, allowlist))
+}
 
-        let enabled = match cmd {
-            Ok(output) => {
-                let out = String::from_utf8(output.stdout).unwrap_or_else(|_| "1".to_string());
-                out.trim() == "1"
-            }
+/// Re-reads the config on SIGHUP, keeping the current one if the new one is invalid.
+async fn reload_on_sighup(
+    path: PathBuf,
+    required: bool,
+    config: watch::Sender<Arc<Config>>,
+    allowlist: watch::Sender<Arc<Allowlist>>,
+) {
+    let mut hangup = signal(SignalKind::hangup()).expect("unable to listen for SIGHUP");
+    while hangup.recv().await.is_some() {
+        tracing::info!("Reloading {}", path.display());
+        let (new_config, new_allowlist) = match load_config(&path, required) {
+            Ok(loaded) => loaded,
             Err(err) => {
-                tracing::error!("error when parsing output from get_power_save: {err}");
-                false
+                tracing::error!("keeping the current config: {err}");
+                continue;
             }
         };
 
-        let reply = wifi::GetPowerSaveResponse { enabled };
-
-        Ok(Response::new(reply))
+        if new_config.socket != config.borrow().socket {
+            tracing::warn!("Changing the socket path requires a restart");
+        }
+        if new_config.state_file != config.borrow().state_file {
+            tracing::warn!("Changing the state file requires a restart");
+        }
+        tracing::info!("Allowing calls from {new_allowlist}");
+        allowlist.send_replace(Arc::new(new_allowlist));
+        config.send_replace(Arc::new(new_config));
     }
+}
 
-    async fn list_devices(
-        &self,
-        request: Request<wifi::ListDevicesRequest>,
-    ) -> Result<Response<wifi::ListDevicesResponse>, Status> {
-        let inner = request.into_inner();
-        tracing::debug!("{:?}", inner);
-
-        let mut iw = Command::new("iw")
-            .arg("dev")
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute iw");
-
-        let iw_stdout: Stdio = iw
-            .stdout
-            .take()
-            .unwrap()
-            .try_into()
-            .expect("iw did not give output");
-
-        let mut grep = Command::new("grep")
-            .arg("Interface")
-            .stdin(iw_stdout)
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute grep");
-
-        let grep_stdout: Stdio = grep
-            .stdout
-            .take()
-            .unwrap()
-            .try_into()
-            .expect("grep did not give output");
-
-        let cmd = Command::new("awk")
-            .arg("{ print $2 }")
-            .stdin(grep_stdout)
-            .output()
-            .await;
-
-        let devices: Vec<String> = match cmd {
-            Ok(output) => match String::from_utf8(output.stdout) {
-                Ok(val) => val
-                    .split('\n')
-                    .filter_map(|s| {
-                        if !s.is_empty() {
-                            Some(s.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect(),
-                Err(err) => {
-                    tracing::error!("error when parsing output in list_devices: {err}");
-                    vec![]
-                }
-            },
-            Err(err) => {
-                tracing::error!("error when list_devices: {err}");
-                vec![]
-            }
-        };
-        let reply = wifi::ListDevicesResponse { devices };
-
-        Ok(Response::new(reply))
+async fn shutdown_signal(fan: FanControl) {
+    let mut terminate = signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
+    tracing::info!("Shutting down");
+    // Open watch streams can keep the server draining until systemd kills it, the fan must not
+    // wait for them
+    fan.stop();
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    // The environment is only changed here, before the runtime starts any threads
+    let activation = Activation::take();
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
 
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(args, activation))
+}
+
+async fn run(args: Args, activation: Activation) -> Result<(), Box<dyn std::error::Error>> {
     let collector = tracing_subscriber::registry()
         .with(fmt::layer().with_writer(std::io::stdout))
         .with(EnvFilter::from_default_env());
     tracing::subscriber::set_global_default(collector).expect("Unable to set a global collector");
     tracing::info!("Logging initialized");
 
-    let wifi_service = WifiServer::default();
-    let ssh_service = SshServer::default();
-
-    if std::path::Path::new(shortcut_core::SOCKET_PATH).exists() {
-        tracing::warn!("Removing existing socket");
-        fs::remove_file(shortcut_core::SOCKET_PATH).expect("unable to remove existing socket")
+    let required = args.config.is_some();
+    let config_path = args
+        .config
+        .unwrap_or_else(|| PathBuf::from(config::CONFIG_PATH));
+    let (config, allowlist) = load_config(&config_path, required)?;
+    tracing::debug!("{:?}", config);
+    tracing::info!("Allowing calls from {allowlist}");
+    let socket = args.socket.unwrap_or_else(|| config.socket.clone());
+    let state = Arc::new(StateFile::load(&config.state_file));
+
+    let (config_tx, config_rx) = watch::channel(Arc::new(config));
+    let (allowlist_tx, allowlist_rx) = watch::channel(Arc::new(allowlist));
+    tokio::spawn(reload_on_sighup(
+        config_path,
+        required,
+        config_tx,
+        allowlist_tx,
+    ));
+    let auth = Authenticator::new(allowlist_rx);
+
+    let bus = zbus::Connection::system().await?;
+    let backend = Arc::new(LinuxBackend::new(
+        Systemd::new(bus.clone()),
+        Logind::new(bus.clone()),
+        NetworkManager::new(bus.clone()),
+        NmConfig::new(NM_CONFIG_ROOT),
+        Bluez::new(bus),
+        Sysfs::new(CPU_ROOT),
+        Hwmon::new(HWMON_ROOT),
+        Backlights::new(BACKLIGHT_ROOT),
+        PowerSupply::new(POWER_SUPPLY_ROOT),
+        SshdConfig::new(SSHD_CONFIG_DIR, SSHD),
+    ));
+    tokio::spawn(state::restore(
+        backend.clone(),
+        config_rx.clone(),
+        state.clone(),
+    ));
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(health::report(
+        health_reporter,
+        backend.clone(),
+        config_rx.clone(),
+    ));
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(shortcut_core::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(
+            tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET,
+        )
+        .build()?;
+
+    let auto_power_save = AutoPowerSave::spawn(
+        backend.clone(),
+        config_rx.clone(),
+        state.desired().auto_power_save,
+        auto_power_save::SAMPLE_INTERVAL,
+    );
+    let wifi_service = WifiServer::new(
+        backend.clone(),
+        config_rx.clone(),
+        state.clone(),
+        auto_power_save,
+    );
+    let ssh_service = SshServer::new(backend.clone(), config_rx.clone());
+    let bluetooth_service = BluetoothServer::new(backend.clone(), config_rx.clone());
+    let cpu_service = CpuServer::new(backend.clone(), config_rx.clone());
+    let display_service = DisplayServer::new(backend.clone(), config_rx.clone());
+    let battery_service = BatteryServer::new(backend.clone(), config_rx.clone(), state);
+    let (fan, fan_task) =
+        FanControl::spawn(backend.clone(), config_rx.clone(), fan::CONTROL_INTERVAL);
+    let sensors_service = SensorsServer::new(backend, config_rx, fan.clone());
+
+    let listener = Listener::open(&socket, activation)?;
+    let uds_stream = UnixListenerStream::new(listener.uds);
+
+    // Health and reflection tell which services are enabled, so they are for callers on the
+    // allowlist too
+    let served = Server::builder()
+        .add_service(InterceptedService::new(health_service, auth.clone()))
+        .add_service(InterceptedService::new(reflection_service, auth.clone()))
+        .add_service(wifi_service_server::WifiServiceServer::with_interceptor(
+            wifi_service,
+            auth.clone(),
+        ))
+        .add_service(ssh_service_server::SshServiceServer::with_interceptor(
+            ssh_service,
+            auth.clone(),
+        ))
+        .add_service(
+            bluetooth_service_server::BluetoothServiceServer::with_interceptor(
+                bluetooth_service,
+                auth.clone(),
+            ),
+        )
+        .add_service(cpu_service_server::CpuServiceServer::with_interceptor(
+            cpu_service,
+            auth.clone(),
+        ))
+        .add_service(
+            sensors_service_server::SensorsServiceServer::with_interceptor(
+                sensors_service,
+                auth.clone(),
+            ),
+        )
+        .add_service(
+            display_service_server::DisplayServiceServer::with_interceptor(
+                display_service,
+                auth.clone(),
+            ),
+        )
+        .add_service(
+            battery_service_server::BatteryServiceServer::with_interceptor(battery_service, auth),
+        )
+        .serve_with_incoming_shutdown(uds_stream, shutdown_signal(fan.clone()))
+        .await;
+
+    // Never leave the fan at a fixed speed without the daemon watching the temperature, also
+    // when serving failed
+    fan.stop();
+    if let Err(err) = fan_task.await {
+        tracing::error!("fan control failed: {err}");
     }
 
-    let uds = UnixListener::bind(shortcut_core::SOCKET_PATH)?;
-    let uds_stream = UnixListenerStream::new(uds);
-
-    tracing::info!("Listening on {}", shortcut_core::SOCKET_PATH);
-
-    let mut perms = fs::metadata(shortcut_core::SOCKET_PATH)?.permissions();
-    perms.set_mode(0o777);
-    fs::set_permissions(shortcut_core::SOCKET_PATH, perms).unwrap();
-
-    Server::builder()
-        .add_service(wifi_service_server::WifiServiceServer::new(wifi_service))
-        .add_service(ssh_service_server::SshServiceServer::new(ssh_service))
-        .serve_with_incoming_shutdown(uds_stream, async move {
-            tokio::signal::ctrl_c().await.unwrap();
-            fs::remove_file(shortcut_core::SOCKET_PATH).expect("unable to remove existing socket")
-        })
-        .await?;
+    if let Some(path) = listener.path {
+        if let Err(err) = std::fs::remove_file(&path) {
+            tracing::warn!("unable to remove socket {}: {err}", path.display());
+        }
+    }
 
-    Ok(())
+    Ok(served?)
 }
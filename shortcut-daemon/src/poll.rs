@@ -0,0 +1,226 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use shortcut_core::futures::Stream;
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::{mpsc, watch};
+use shortcut_core::tokio_stream::wrappers::ReceiverStream;
+use shortcut_core::tonic::Status;
+use shortcut_core::Error;
+
+use crate::config::{Config, SharedConfig};
+
+/// How often watch streams re-read state the kernel or systemd don't report changes for.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub type WatchStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Streams the value returned by `read` right away and then whenever it changes.
+///
+/// `read` runs every `interval` and as soon as `changed` is notified, so changes made through
+/// the daemon show up without waiting for the next poll. The stream ends with the first error
+/// and the polling stops once the client goes away.
+pub fn watch<T, F, Fut>(
+    mut changed: watch::Receiver<()>,
+    interval: Duration,
+    mut read: F,
+) -> WatchStream<T>
+where
+    T: PartialEq + Clone + Send + 'static,
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, Error>> + Send,
+{
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut last = None;
+        loop {
+            match read().await {
+                Ok(value) if last.as_ref() == Some(&value) => {}
+                Ok(value) => {
+                    last = Some(value.clone());
+                    if tx.send(Ok(value)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    tx.send(Err(err.into())).await.ok();
+                    break;
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                result = changed.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                _ = tx.closed() => break,
+            }
+        }
+    });
+
+    Box::pin(ReceiverStream::new(rx))
+}
+
+/// What every service server needs besides the backend: whether the config enables it and how its
+/// watch streams learn about changes.
+#[derive(Debug, Clone)]
+pub struct Service {
+    name: &'static str,
+    config: SharedConfig,
+    enabled: fn(&Config) -> bool,
+    /// Notified after every change so watch streams don't wait for the next poll.
+    changed: Arc<watch::Sender<()>>,
+    /// How often watch streams poll, [`POLL_INTERVAL`] outside of tests.
+    pub interval: Duration,
+}
+
+impl Service {
+    /// The service called `name` in errors, enabled as long as `enabled` says so for the
+    /// current config.
+    pub fn new(name: &'static str, config: SharedConfig, enabled: fn(&Config) -> bool) -> Self {
+        Self {
+            name,
+            config,
+            enabled,
+            changed: Arc::new(watch::channel(()).0),
+            interval: POLL_INTERVAL,
+        }
+    }
+
+    pub fn check_enabled(&self) -> Result<(), Error> {
+        if !(self.enabled)(&self.config.borrow()) {
+            return Err(Error::Unavailable(format!(
+                "the {} service is disabled",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Wakes up the watch streams after a change made through the daemon.
+    pub fn notify(&self) {
+        self.changed.send_replace(());
+    }
+
+    /// Like [`watch`], polling every [`Service::interval`] and woken up by [`Service::notify`].
+    pub fn watch<T, F, Fut>(&self, read: F) -> WatchStream<T>
+    where
+        T: PartialEq + Clone + Send + 'static,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + Send,
+    {
+        self.watch_every(self.interval, read)
+    }
+
+    /// Like [`Service::watch`] for state that has to be polled more often.
+    pub fn watch_every<T, F, Fut>(&self, interval: Duration, read: F) -> WatchStream<T>
+    where
+        T: PartialEq + Clone + Send + 'static,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + Send,
+    {
+        watch(self.changed.subscribe(), interval, read)
+    }
+}
+
+/// Like [`watch`] for state only the system changes, which is read every `interval`.
+pub fn every<T, F, Fut>(interval: Duration, mut read: F) -> WatchStream<T>
+where
+    T: PartialEq + Clone + Send + 'static,
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, Error>> + Send,
+{
+    // Nobody notifies, the sender only has to live as long as the polling
+    let (changed_tx, changed) = watch::channel(());
+    self::watch(changed, interval, move || {
+        let _changed_tx = &changed_tx;
+        read()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::config::{self, CpuConfig};
+
+    #[tokio::test]
+    async fn emits_current_value_and_changes_only() {
+        let (_changed_tx, changed) = watch::channel(());
+        let reads = Arc::new(AtomicU32::new(0));
+        let counter = reads.clone();
+        let mut stream = watch(changed, Duration::from_millis(5), move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            async move { Ok(n / 3) }
+        });
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+        assert!(reads.load(Ordering::SeqCst) >= 7);
+    }
+
+    #[tokio::test]
+    async fn change_notification_skips_the_wait() {
+        let (changed_tx, changed) = watch::channel(());
+        let value = Arc::new(AtomicU32::new(0));
+        let current = value.clone();
+        let mut stream = watch(changed, Duration::from_secs(3600), move || {
+            let n = current.load(Ordering::SeqCst);
+            async move { Ok(n) }
+        });
+        assert_eq!(stream.next().await.unwrap().unwrap(), 0);
+
+        value.store(1, Ordering::SeqCst);
+        changed_tx.send(()).unwrap();
+
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert_eq!(next.unwrap().unwrap().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn ends_after_an_error() {
+        let (_changed_tx, changed) = watch::channel(());
+        let mut stream = watch(changed, Duration::from_millis(5), || async {
+            Err::<u32, _>(Error::not_found("wifi device wlan9"))
+        });
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap_err().code(),
+            Code::NotFound
+        );
+        assert!(stream.next().await.is_none());
+    }
+
+    #[test]
+    fn disabled_service_is_unavailable() {
+        let config = config::fixed(Config {
+            cpu: CpuConfig { enabled: false },
+            ..Config::default()
+        });
+        let service = Service::new("cpu", config, |config| config.cpu.enabled);
+
+        assert_eq!(
+            service.check_enabled(),
+            Err(Error::Unavailable(
+                "the cpu service is disabled".to_string()
+            ))
+        );
+        assert!(
+            Service::new("wifi", config::fixed(Config::default()), |config| {
+                config.wifi.enabled
+            })
+            .check_enabled()
+            .is_ok()
+        );
+    }
+}
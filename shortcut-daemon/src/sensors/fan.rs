@@ -0,0 +1,585 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use shortcut_core::sensors;
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::{watch, Notify};
+use shortcut_core::tokio::task::JoinHandle;
+use shortcut_core::Error;
+
+use super::hwmon::{Kind, PWM_AUTOMATIC, PWM_MANUAL};
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+
+/// How often the temperature is read and the fan adjusted.
+pub const CONTROL_INTERVAL: Duration = Duration::from_secs(2);
+/// A control step that takes longer counts as failed, e.g. a hung sysfs read.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// From this temperature on the fan runs at least at [`MIN_HOT_DUTY`], whatever the curve says.
+pub const HOT_TEMPERATURE: f64 = 80.0;
+pub const MIN_HOT_DUTY: u8 = 70;
+/// Curves only go up to this temperature, the firmware shuts down before that.
+const MAX_TEMPERATURE: f64 = 105.0;
+const MAX_POINTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurvePoint {
+    /// In degrees Celsius.
+    pub temperature: f64,
+    /// In percent.
+    pub duty: u8,
+}
+
+/// Maps temperatures to fan duty cycles, interpolating between the points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanCurve {
+    points: Vec<CurvePoint>,
+}
+
+impl FanCurve {
+    /// Checks that the points rise in both temperature and duty.
+    pub fn new(points: Vec<CurvePoint>) -> Result<Self, Error> {
+        if !(2..=MAX_POINTS).contains(&points.len()) {
+            return Err(Error::InvalidArgument(format!(
+                "a fan curve needs 2 to {MAX_POINTS} points, got {}",
+                points.len()
+            )));
+        }
+        if let Some(point) = points
+            .iter()
+            .find(|point| !(0.0..=MAX_TEMPERATURE).contains(&point.temperature) || point.duty > 100)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "{point:?} is outside of 0..={MAX_TEMPERATURE} °C or 0..=100 %"
+            )));
+        }
+        if points
+            .windows(2)
+            .any(|pair| pair[0].temperature >= pair[1].temperature || pair[0].duty > pair[1].duty)
+        {
+            return Err(Error::InvalidArgument(
+                "fan curve points must rise in temperature and must not lower the duty".to_string(),
+            ));
+        }
+
+        Ok(Self { points })
+    }
+
+    pub fn points(&self) -> &[CurvePoint] {
+        &self.points
+    }
+
+    /// The duty cycle in percent at `temperature`, never below [`MIN_HOT_DUTY`] when hot.
+    pub fn duty(&self, temperature: f64) -> u8 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        let duty = if temperature <= first.temperature {
+            first.duty
+        } else if temperature >= last.temperature {
+            last.duty
+        } else {
+            let pair = self
+                .points
+                .windows(2)
+                .find(|pair| temperature <= pair[1].temperature)
+                .unwrap_or(&self.points[self.points.len() - 2..]);
+            let (low, high) = (pair[0], pair[1]);
+            let position = (temperature - low.temperature) / (high.temperature - low.temperature);
+            let duty = f64::from(low.duty) + position * f64::from(high.duty - low.duty);
+            duty.round() as u8
+        };
+
+        if temperature >= HOT_TEMPERATURE {
+            duty.max(MIN_HOT_DUTY)
+        } else {
+            duty
+        }
+    }
+}
+
+/// Who drives the fan right now.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FanStatus {
+    Firmware,
+    Curve {
+        temperature: f64,
+        duty: u8,
+    },
+    /// The curve was dropped for the firmware after a failure, until a new one is set.
+    Failed(String),
+}
+
+/// Converts a duty cycle in percent to a `pwm1` value.
+fn pwm(duty: u8) -> u8 {
+    (u32::from(duty.min(100)) * 255 / 100) as u8
+}
+
+/// The fan device under control and the `pwm1_enable` mode to give back.
+///
+/// The fan is handed back when this is dropped, so a task that panics or is dropped with the
+/// runtime doesn't leave it at a fixed speed.
+struct Taken {
+    backend: Arc<dyn SystemBackend>,
+    device: String,
+    mode: u8,
+}
+
+impl Drop for Taken {
+    fn drop(&mut self) {
+        // Left in manual mode by an earlier run that didn't get to clean up
+        let mode = if self.mode == PWM_MANUAL {
+            PWM_AUTOMATIC
+        } else {
+            self.mode
+        };
+
+        tracing::info!("Returning the fan to mode {mode}");
+        if let Err(err) = self.backend.restore_fan_mode(&self.device, mode) {
+            tracing::error!("unable to return the fan to the firmware: {err}");
+        }
+    }
+}
+
+struct Controller {
+    backend: Arc<dyn SystemBackend>,
+    config: SharedConfig,
+    taken: Option<Taken>,
+}
+
+impl Controller {
+    /// Reads the temperature the curve follows and applies the duty cycle for it.
+    async fn step(&mut self, curve: &FanCurve) -> Result<FanStatus, Error> {
+        let fan = self.config.borrow().fan.clone();
+        if matches!(&self.taken, Some(taken) if taken.device != fan.device) {
+            self.release();
+        }
+
+        let temperature = self
+            .backend
+            .sensors()
+            .await?
+            .into_iter()
+            .filter(|reading| reading.kind == Kind::Temperature)
+            .filter(|reading| fan.follows(&reading.device, &reading.label))
+            .try_fold(None, |hottest: Option<f64>, reading| {
+                // Following the other sensors could miss the one that overheats
+                let value = reading.value.map_err(|err| {
+                    Error::Unavailable(format!("{} {}: {err}", reading.device, reading.label))
+                })?;
+                Ok::<_, Error>(Some(hottest.map_or(value, |hottest| hottest.max(value))))
+            })?
+            .ok_or_else(|| Error::Unavailable("no temperature sensor to follow".to_string()))?;
+        let duty = curve.duty(temperature);
+
+        if self.taken.is_none() {
+            let mode = self.backend.fan_mode(&fan.device).await?;
+            tracing::info!("Taking over the fan from mode {mode}");
+            // Anything the curve does from here on has to be undone by release
+            self.taken = Some(Taken {
+                backend: self.backend.clone(),
+                device: fan.device.clone(),
+                mode,
+            });
+            self.backend.set_fan_mode(&fan.device, PWM_MANUAL).await?;
+        }
+        self.backend.set_fan_pwm(&fan.device, pwm(duty)).await?;
+
+        Ok(FanStatus::Curve { temperature, duty })
+    }
+
+    /// Hands the fan back to whatever controlled it before.
+    fn release(&mut self) {
+        self.taken = None;
+    }
+
+    /// A daemon that crashed left the fan in manual mode at whatever speed it had last, the
+    /// firmware gets it back before anything else.
+    async fn recover(&self) {
+        let fan = self.config.borrow().fan.clone();
+        if !fan.enabled {
+            return;
+        }
+        match self.backend.fan_mode(&fan.device).await {
+            Ok(PWM_MANUAL) => {
+                tracing::warn!("The fan was left in manual mode, returning it to the firmware");
+                if let Err(err) = self.backend.set_fan_mode(&fan.device, PWM_AUTOMATIC).await {
+                    tracing::error!("unable to return the fan to the firmware: {err}");
+                }
+            }
+            Ok(_) => {}
+            Err(err) => tracing::debug!("no fan to recover: {err}"),
+        }
+    }
+
+    async fn run(
+        mut self,
+        interval: Duration,
+        mut curve: watch::Receiver<Option<FanCurve>>,
+        status: watch::Sender<FanStatus>,
+        shutdown: Arc<Notify>,
+    ) {
+        self.recover().await;
+        let mut failed = false;
+        loop {
+            let current = curve.borrow().clone();
+            let enabled = self.config.borrow().fan.enabled;
+            match current {
+                Some(current) if enabled && !failed => {
+                    let step = tokio::time::timeout(WATCHDOG_TIMEOUT, self.step(&current)).await;
+                    let result = step.unwrap_or_else(|_| {
+                        Err(Error::Internal("controlling the fan timed out".to_string()))
+                    });
+                    match result {
+                        Ok(new_status) => {
+                            status.send_replace(new_status);
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                "fan curve failed, falling back to the firmware: {err}"
+                            );
+                            self.release();
+                            status.send_replace(FanStatus::Failed(err.to_string()));
+                            failed = true;
+                        }
+                    }
+                }
+                Some(_) if failed => {}
+                _ => {
+                    self.release();
+                    status.send_replace(FanStatus::Firmware);
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                result = curve.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                    failed = false;
+                }
+                _ = shutdown.notified() => break,
+            }
+        }
+
+        self.release();
+    }
+}
+
+/// Runs the fan curve in the background and returns the fan to the firmware when stopped.
+#[derive(Clone)]
+pub struct FanControl {
+    curve: Arc<watch::Sender<Option<FanCurve>>>,
+    status: watch::Receiver<FanStatus>,
+    shutdown: Arc<Notify>,
+}
+
+impl FanControl {
+    /// Starts with the firmware in control, the task ends once the fan is handed back.
+    pub fn spawn(
+        backend: Arc<dyn SystemBackend>,
+        config: SharedConfig,
+        interval: Duration,
+    ) -> (Self, JoinHandle<()>) {
+        let (curve, curve_rx) = watch::channel(None);
+        let (status_tx, status) = watch::channel(FanStatus::Firmware);
+        let shutdown = Arc::new(Notify::new());
+        let controller = Controller {
+            backend,
+            config,
+            taken: None,
+        };
+        let task = tokio::spawn(controller.run(interval, curve_rx, status_tx, shutdown.clone()));
+
+        let control = Self {
+            curve: Arc::new(curve),
+            status,
+            shutdown,
+        };
+        (control, task)
+    }
+
+    /// Follows `curve` from now on, or lets the firmware drive the fan for `None`.
+    pub fn set_curve(&self, curve: Option<FanCurve>) {
+        self.curve.send_replace(curve);
+    }
+
+    pub fn curve(&self) -> Option<FanCurve> {
+        self.curve.borrow().clone()
+    }
+
+    pub fn status(&self) -> watch::Receiver<FanStatus> {
+        self.status.clone()
+    }
+
+    /// Gives the fan back to the firmware for good, e.g. when the daemon shuts down.
+    pub fn stop(&self) {
+        self.shutdown.notify_one();
+    }
+}
+
+impl From<sensors::CurvePoint> for CurvePoint {
+    fn from(point: sensors::CurvePoint) -> Self {
+        Self {
+            temperature: point.temperature,
+            duty: point.duty_percent.min(u8::MAX.into()) as u8,
+        }
+    }
+}
+
+impl From<&FanCurve> for sensors::FanCurve {
+    fn from(curve: &FanCurve) -> Self {
+        Self {
+            points: curve
+                .points
+                .iter()
+                .map(|point| sensors::CurvePoint {
+                    temperature: point.temperature,
+                    duty_percent: point.duty.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&FanStatus> for sensors::FanStatus {
+    fn from(status: &FanStatus) -> Self {
+        match status {
+            FanStatus::Firmware => Self {
+                control: sensors::FanControl::Firmware as i32,
+                ..Self::default()
+            },
+            FanStatus::Curve { temperature, duty } => Self {
+                control: sensors::FanControl::Curve as i32,
+                temperature: *temperature,
+                duty_percent: (*duty).into(),
+                ..Self::default()
+            },
+            FanStatus::Failed(error) => Self {
+                control: sensors::FanControl::Failed as i32,
+                error: error.clone(),
+                ..Self::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{sensor, Call, FakeBackend};
+    use crate::config::{self, Config};
+    use crate::sensors::hwmon::Hwmon;
+    use crate::test_sysfs::FakeSysfs;
+    use crate::test_watch::wait_for;
+
+    fn point(temperature: f64, duty: u8) -> CurvePoint {
+        CurvePoint { temperature, duty }
+    }
+
+    fn curve() -> FanCurve {
+        FanCurve::new(vec![point(40.0, 20), point(60.0, 40), point(90.0, 100)]).unwrap()
+    }
+
+    fn backend(temperature: f64) -> Arc<FakeBackend> {
+        Arc::new(
+            FakeBackend::default()
+                .with_sensor(sensor("k10temp", "Tctl", Kind::Temperature, temperature))
+                .with_sensor(sensor("amdgpu", "edge", Kind::Temperature, 30.0))
+                .with_fan(PWM_AUTOMATIC),
+        )
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = curve();
+
+        assert_eq!(curve.duty(20.0), 20);
+        assert_eq!(curve.duty(50.0), 30);
+        assert_eq!(curve.duty(75.0), 70);
+        assert_eq!(curve.duty(100.0), 100);
+    }
+
+    #[test]
+    fn never_runs_slow_when_hot() {
+        let quiet = FanCurve::new(vec![point(30.0, 0), point(100.0, 10)]).unwrap();
+
+        assert_eq!(quiet.duty(79.0), 7);
+        assert_eq!(quiet.duty(HOT_TEMPERATURE), MIN_HOT_DUTY);
+        assert_eq!(quiet.duty(95.0), MIN_HOT_DUTY);
+    }
+
+    #[test]
+    fn rejects_unsafe_curves() {
+        assert!(FanCurve::new(vec![point(40.0, 20)]).is_err());
+        assert!(FanCurve::new(vec![point(60.0, 20), point(40.0, 40)]).is_err());
+        assert!(FanCurve::new(vec![point(40.0, 60), point(60.0, 40)]).is_err());
+        assert!(FanCurve::new(vec![point(40.0, 20), point(60.0, 101)]).is_err());
+        assert!(FanCurve::new(vec![point(40.0, 20), point(f64::NAN, 40)]).is_err());
+    }
+
+    #[test]
+    fn converts_duty_to_pwm() {
+        assert_eq!(pwm(0), 0);
+        assert_eq!(pwm(50), 127);
+        assert_eq!(pwm(100), 255);
+    }
+
+    #[tokio::test]
+    async fn follows_the_hottest_sensor_and_gives_the_fan_back() {
+        let backend = backend(75.0);
+        let (fan, task) = FanControl::spawn(
+            backend.clone(),
+            config::fixed(Config::default()),
+            Duration::from_millis(10),
+        );
+        let mut status = fan.status();
+
+        fan.set_curve(Some(curve()));
+        wait_for(&mut status, |status| {
+            matches!(status, FanStatus::Curve { duty: 70, .. })
+        })
+        .await;
+        assert_eq!(backend.fan(), (PWM_MANUAL, pwm(70)));
+
+        fan.stop();
+        task.await.unwrap();
+        assert_eq!(backend.fan().0, PWM_AUTOMATIC);
+    }
+
+    #[tokio::test]
+    async fn sensor_failure_returns_the_fan_to_the_firmware() {
+        let sysfs = FakeSysfs::new("fan-sensor-failure");
+        sysfs.write("hwmon0/name", "k10temp");
+        sysfs.write("hwmon0/temp1_input", "50000");
+        sysfs.write("hwmon0/temp1_label", "Tctl");
+        sysfs.write("hwmon1/name", "amdgpu");
+        sysfs.write("hwmon1/temp1_input", "30000");
+        sysfs.write("hwmon2/name", "steamdeck_hwmon");
+        sysfs.write("hwmon2/pwm1", "0");
+        sysfs.write("hwmon2/pwm1_enable", "2");
+        let backend = Arc::new(FakeBackend::default().with_hwmon(Hwmon::new(sysfs.root())));
+        let (fan, task) = FanControl::spawn(
+            backend.clone(),
+            config::fixed(Config::default()),
+            Duration::from_millis(10),
+        );
+        let mut status = fan.status();
+        fan.set_curve(Some(curve()));
+        wait_for(&mut status, |status| {
+            matches!(status, FanStatus::Curve { .. })
+        })
+        .await;
+        assert_eq!(sysfs.read("hwmon2/pwm1_enable"), "1");
+
+        // The other sensor still reads fine
+        sysfs.write("hwmon0/temp1_input", "N/A");
+
+        wait_for(&mut status, |status| matches!(status, FanStatus::Failed(_))).await;
+        assert_eq!(sysfs.read("hwmon2/pwm1_enable"), "2");
+        let calls = backend.calls();
+        let failed_at = calls
+            .iter()
+            .rposition(|call| *call == Call::Sensors)
+            .unwrap();
+        assert_eq!(
+            calls[failed_at + 1..],
+            [Call::RestoreFanMode(PWM_AUTOMATIC)]
+        );
+        fan.stop();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropping_the_task_gives_the_fan_back() {
+        let backend = backend(75.0);
+        let (fan, task) = FanControl::spawn(
+            backend.clone(),
+            config::fixed(Config::default()),
+            Duration::from_millis(10),
+        );
+        let mut status = fan.status();
+        fan.set_curve(Some(curve()));
+        wait_for(&mut status, |status| {
+            matches!(status, FanStatus::Curve { .. })
+        })
+        .await;
+
+        task.abort();
+
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert_eq!(backend.fan().0, PWM_AUTOMATIC);
+    }
+
+    #[tokio::test]
+    async fn recovers_a_fan_left_in_manual_mode() {
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_sensor(sensor("k10temp", "Tctl", Kind::Temperature, 50.0))
+                .with_fan(PWM_MANUAL),
+        );
+        let (fan, task) = FanControl::spawn(
+            backend.clone(),
+            config::fixed(Config::default()),
+            Duration::from_secs(3600),
+        );
+
+        wait_for(&mut fan.status(), |status| *status == FanStatus::Firmware).await;
+        fan.stop();
+        task.await.unwrap();
+
+        assert_eq!(backend.fan().0, PWM_AUTOMATIC);
+        assert_eq!(
+            backend.calls()[..2],
+            [Call::FanMode, Call::SetFanMode(PWM_AUTOMATIC)]
+        );
+    }
+
+    #[tokio::test]
+    async fn clearing_the_curve_restores_the_previous_mode() {
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_sensor(sensor("k10temp", "Tctl", Kind::Temperature, 50.0))
+                .with_fan(3),
+        );
+        let (fan, task) = FanControl::spawn(
+            backend.clone(),
+            config::fixed(Config::default()),
+            Duration::from_secs(3600),
+        );
+        let mut status = fan.status();
+        fan.set_curve(Some(curve()));
+        wait_for(&mut status, |status| {
+            matches!(status, FanStatus::Curve { .. })
+        })
+        .await;
+
+        fan.set_curve(None);
+
+        wait_for(&mut status, |status| *status == FanStatus::Firmware).await;
+        assert_eq!(backend.fan().0, 3);
+        fan.stop();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disabled_fan_control_leaves_the_firmware_in_charge() {
+        let backend = backend(50.0);
+        let mut config = Config::default();
+        config.fan.enabled = false;
+        let (fan, task) = FanControl::spawn(
+            backend.clone(),
+            config::fixed(config),
+            Duration::from_millis(10),
+        );
+
+        fan.set_curve(Some(curve()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        fan.stop();
+        task.await.unwrap();
+
+        assert!(!backend.calls().contains(&Call::SetFanMode(PWM_MANUAL)));
+        assert_eq!(backend.fan(), (PWM_AUTOMATIC, 0));
+    }
+}
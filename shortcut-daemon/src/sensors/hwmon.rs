@@ -0,0 +1,364 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the kernel lists the hardware monitoring devices.
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+#[derive(Debug)]
+pub enum Error {
+    /// There is no hwmon class, i.e. the kernel was built without hardware monitoring.
+    NoHwmon,
+    /// No device of that name has a `pwm1`, an empty name means none at all.
+    NoFan(String),
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        value: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoHwmon => write!(f, "hwmon is not available"),
+            Error::NoFan(name) if name.is_empty() => write!(f, "no controllable fan found"),
+            Error::NoFan(name) => write!(f, "no controllable fan on {name}"),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+            Error::Parse { path, value } => {
+                write!(f, "unexpected value in {}: {value:?}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoHwmon | Error::NoFan(_) => shortcut_core::Error::Unavailable(err.to_string()),
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Temperature,
+    Fan,
+    Power,
+}
+
+impl From<Kind> for shortcut_core::sensors::SensorKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Temperature => shortcut_core::sensors::SensorKind::Temperature,
+            Kind::Fan => shortcut_core::sensors::SensorKind::Fan,
+            Kind::Power => shortcut_core::sensors::SensorKind::Power,
+        }
+    }
+}
+
+/// `pwm1_enable` value that hands the fan to `pwm1`.
+pub const PWM_MANUAL: u8 = 1;
+/// `pwm1_enable` value for automatic control by the driver or firmware.
+pub const PWM_AUTOMATIC: u8 = 2;
+
+/// A sensor value in degrees Celsius, RPM or watts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub device: String,
+    pub label: String,
+    pub kind: Kind,
+    /// Why the input couldn't be read, e.g. a sensor that is powered down.
+    pub value: Result<f64, String>,
+}
+
+impl TryFrom<Reading> for shortcut_core::sensors::Sensor {
+    type Error = String;
+
+    fn try_from(reading: Reading) -> Result<Self, Self::Error> {
+        Ok(Self {
+            value: reading.value?,
+            device: reading.device,
+            label: reading.label,
+            kind: shortcut_core::sensors::SensorKind::from(reading.kind) as i32,
+        })
+    }
+}
+
+/// Splits an input file name like `temp2_input` into its kind, channel and divisor.
+///
+/// The kernel reports millidegrees and microwatts. Power prefers `power*_average`, which is
+/// what most drivers provide, over `power*_input`.
+fn parse_input(name: &str) -> Option<(Kind, &str, u32, f64)> {
+    let (channel, attribute) = name.split_once('_')?;
+    let (kind, prefix, divisor) = match (channel.trim_end_matches(char::is_numeric), attribute) {
+        ("temp", "input") => (Kind::Temperature, "temp", 1000.0),
+        ("fan", "input") => (Kind::Fan, "fan", 1.0),
+        ("power", "average" | "input") => (Kind::Power, "power", 1_000_000.0),
+        _ => return None,
+    };
+    let index = channel[prefix.len()..].parse().ok()?;
+    Some((kind, channel, index, divisor))
+}
+
+/// The hwmon devices below a sysfs root, [`HWMON_ROOT`] outside of tests.
+#[derive(Debug, Clone)]
+pub struct Hwmon {
+    root: PathBuf,
+}
+
+impl Hwmon {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The `hwmon*` devices in kernel order.
+    pub fn devices(&self) -> Result<Vec<PathBuf>, Error> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(Error::NoHwmon),
+            Err(err) => {
+                return Err(Error::Io {
+                    path: self.root.clone(),
+                    err,
+                })
+            }
+        };
+
+        let mut devices: Vec<(u32, PathBuf)> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let index = entry
+                    .file_name()
+                    .to_str()?
+                    .strip_prefix("hwmon")?
+                    .parse()
+                    .ok()?;
+                Some((index, entry.path()))
+            })
+            .collect();
+        devices.sort();
+        Ok(devices.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// The driver name of a device, falling back to the directory name.
+    pub fn name(device: &Path) -> String {
+        fs::read_to_string(device.join("name"))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| {
+                device
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            })
+    }
+
+    /// The first device named `name` with a `pwm1`, any device when `name` is empty.
+    fn pwm_device(&self, name: &str) -> Result<PathBuf, Error> {
+        self.devices()?
+            .into_iter()
+            .find(|device| {
+                (name.is_empty() || Self::name(device) == name) && device.join("pwm1").exists()
+            })
+            .ok_or_else(|| Error::NoFan(name.to_string()))
+    }
+
+    fn write(path: &Path, value: u8) -> Result<(), Error> {
+        tracing::debug!("Writing {value} to {}", path.display());
+        fs::write(path, value.to_string()).map_err(|err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        })
+    }
+
+    /// The `pwm1_enable` mode of the fan on device `name`.
+    pub fn fan_mode(&self, name: &str) -> Result<u8, Error> {
+        let path = self.pwm_device(name)?.join("pwm1_enable");
+        let value = fs::read_to_string(&path).map_err(|err| Error::Io {
+            path: path.clone(),
+            err,
+        })?;
+        value.trim().parse().map_err(|_| Error::Parse {
+            path,
+            value: value.trim().to_string(),
+        })
+    }
+
+    pub fn set_fan_mode(&self, name: &str, mode: u8) -> Result<(), Error> {
+        Self::write(&self.pwm_device(name)?.join("pwm1_enable"), mode)
+    }
+
+    /// Sets the duty cycle of a fan in [`PWM_MANUAL`] mode, from 0 to 255.
+    pub fn set_fan_pwm(&self, name: &str, pwm: u8) -> Result<(), Error> {
+        Self::write(&self.pwm_device(name)?.join("pwm1"), pwm)
+    }
+
+    /// Every temperature, fan and power reading of every device.
+    ///
+    /// Inputs that can't be read right now are kept with the error, a fan curve must not
+    /// silently stop following them.
+    pub fn readings(&self) -> Result<Vec<Reading>, Error> {
+        let mut readings = vec![];
+        for device in self.devices()? {
+            let name = Self::name(&device);
+            let entries = fs::read_dir(&device).map_err(|err| Error::Io {
+                path: device.clone(),
+                err,
+            })?;
+
+            let mut inputs: Vec<(Kind, u32, String, PathBuf, f64)> = entries
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let file_name = entry.file_name();
+                    let (kind, channel, index, divisor) = parse_input(file_name.to_str()?)?;
+                    Some((kind, index, channel.to_string(), entry.path(), divisor))
+                })
+                .collect();
+            inputs.sort_by(|a, b| (a.0, a.1, &a.3).cmp(&(b.0, b.1, &b.3)));
+            // `power1_average` sorts before `power1_input`
+            inputs.dedup_by(|b, a| (a.0, a.1) == (b.0, b.1));
+
+            for (kind, _, channel, path, divisor) in inputs {
+                let value = match fs::read_to_string(&path) {
+                    Ok(raw) => match raw.trim().parse::<f64>() {
+                        Ok(value) => Ok(value / divisor),
+                        Err(_) => Err(format!("unexpected value {:?}", raw.trim())),
+                    },
+                    Err(err) => Err(err.to_string()),
+                };
+                let label = fs::read_to_string(device.join(format!("{channel}_label")))
+                    .map(|label| label.trim().to_string())
+                    .unwrap_or(channel);
+
+                readings.push(Reading {
+                    device: name.clone(),
+                    label,
+                    kind,
+                    value,
+                });
+            }
+        }
+
+        Ok(readings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    /// A CPU, a GPU and the Steam Deck's fan controller.
+    fn fake_hwmon(name: &str) -> FakeSysfs {
+        let sysfs = FakeSysfs::new(name);
+        sysfs.write("hwmon0/name", "k10temp");
+        sysfs.write("hwmon0/temp1_input", "54250");
+        sysfs.write("hwmon0/temp1_label", "Tctl");
+        sysfs.write("hwmon1/name", "amdgpu");
+        sysfs.write("hwmon1/temp1_input", "49000");
+        sysfs.write("hwmon1/temp1_label", "edge");
+        sysfs.write("hwmon1/power1_average", "12500000");
+        sysfs.write("hwmon1/power1_input", "13000000");
+        sysfs.write("hwmon1/in0_input", "1100");
+        sysfs.write("hwmon10/name", "steamdeck_hwmon");
+        sysfs.write("hwmon10/fan1_input", "2950");
+        sysfs
+    }
+
+    fn reading(device: &str, label: &str, kind: Kind, value: f64) -> Reading {
+        Reading {
+            device: device.to_string(),
+            label: label.to_string(),
+            kind,
+            value: Ok(value),
+        }
+    }
+
+    #[test]
+    fn reads_labelled_values_in_device_order() {
+        let sysfs = fake_hwmon("hwmon-readings");
+
+        let readings = Hwmon::new(sysfs.root()).readings().unwrap();
+
+        assert_eq!(
+            readings,
+            vec![
+                reading("k10temp", "Tctl", Kind::Temperature, 54.25),
+                reading("amdgpu", "edge", Kind::Temperature, 49.0),
+                reading("amdgpu", "power1", Kind::Power, 12.5),
+                reading("steamdeck_hwmon", "fan1", Kind::Fan, 2950.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_power_input() {
+        let sysfs = fake_hwmon("hwmon-power-input");
+        std::fs::remove_file(sysfs.root().join("hwmon1/power1_average")).unwrap();
+
+        let readings = Hwmon::new(sysfs.root()).readings().unwrap();
+
+        assert!(readings.contains(&reading("amdgpu", "power1", Kind::Power, 13.0)));
+    }
+
+    #[test]
+    fn marks_unreadable_inputs() {
+        let sysfs = fake_hwmon("hwmon-unreadable");
+        sysfs.write("hwmon0/temp2_input", "N/A");
+
+        let readings = Hwmon::new(sysfs.root()).readings().unwrap();
+
+        assert_eq!(readings.len(), 5);
+        assert_eq!(readings[1].label, "temp2");
+        assert_eq!(
+            readings[1].value,
+            Err("unexpected value \"N/A\"".to_string())
+        );
+    }
+
+    #[test]
+    fn drives_the_fan_through_pwm1() {
+        let sysfs = fake_hwmon("hwmon-pwm");
+        sysfs.write("hwmon10/pwm1", "128");
+        sysfs.write("hwmon10/pwm1_enable", "2");
+        let hwmon = Hwmon::new(sysfs.root());
+
+        assert_eq!(hwmon.fan_mode("").unwrap(), PWM_AUTOMATIC);
+        hwmon.set_fan_mode("steamdeck_hwmon", PWM_MANUAL).unwrap();
+        hwmon.set_fan_pwm("steamdeck_hwmon", 200).unwrap();
+
+        assert_eq!(sysfs.read("hwmon10/pwm1_enable"), "1");
+        assert_eq!(sysfs.read("hwmon10/pwm1"), "200");
+    }
+
+    #[test]
+    fn devices_without_pwm_have_no_fan() {
+        let sysfs = fake_hwmon("hwmon-no-pwm");
+        let hwmon = Hwmon::new(sysfs.root());
+
+        let err = hwmon.set_fan_pwm("steamdeck_hwmon", 255).unwrap_err();
+
+        assert!(matches!(err, Error::NoFan(_)));
+        assert!(!sysfs.root().join("hwmon10/pwm1").exists());
+    }
+
+    #[test]
+    fn missing_class_is_unavailable() {
+        let err = Hwmon::new("/nonexistent/hwmon").readings().unwrap_err();
+
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::Unavailable(_)
+        ));
+    }
+}
@@ -0,0 +1,413 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use shortcut_core::futures::stream;
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::sensors;
+use shortcut_core::sensors::sensors_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{self, Service, WatchStream};
+
+pub(crate) mod fan;
+pub(crate) mod hwmon;
+
+use fan::{FanControl, FanCurve};
+
+/// Used when a watch doesn't ask for an interval.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+/// Bounds for the requested interval, reading faster only costs power.
+const MIN_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Inputs that can't be read right now are left out.
+fn to_sensors(readings: Vec<hwmon::Reading>) -> Vec<sensors::Sensor> {
+    readings
+        .into_iter()
+        .filter_map(|reading| sensors::Sensor::try_from(reading).ok())
+        .collect()
+}
+
+fn interval(interval_ms: u32) -> Result<Duration, Error> {
+    if interval_ms == 0 {
+        return Ok(DEFAULT_INTERVAL);
+    }
+
+    let interval = Duration::from_millis(interval_ms.into());
+    if !(MIN_INTERVAL..=MAX_INTERVAL).contains(&interval) {
+        return Err(Error::InvalidArgument(format!(
+            "interval must be between {} and {} ms, got {interval_ms}",
+            MIN_INTERVAL.as_millis(),
+            MAX_INTERVAL.as_millis()
+        )));
+    }
+    Ok(interval)
+}
+
+/// An empty curve hands the fan back to the firmware.
+fn to_curve(curve: Option<sensors::FanCurve>) -> Result<Option<FanCurve>, Error> {
+    let points = curve.map(|curve| curve.points).unwrap_or_default();
+    if points.is_empty() {
+        return Ok(None);
+    }
+    FanCurve::new(points.into_iter().map(fan::CurvePoint::from).collect()).map(Some)
+}
+
+pub struct SensorsServer {
+    backend: Arc<dyn SystemBackend>,
+    config: SharedConfig,
+    service: Service,
+    fan: FanControl,
+}
+
+impl SensorsServer {
+    pub fn new(backend: Arc<dyn SystemBackend>, config: SharedConfig, fan: FanControl) -> Self {
+        Self {
+            backend,
+            service: Service::new("sensors", config.clone(), |config| config.sensors.enabled),
+            config,
+            fan,
+        }
+    }
+
+    fn check_fan_enabled(&self) -> Result<(), Error> {
+        self.service.check_enabled()?;
+        if !self.config.borrow().fan.enabled {
+            return Err(Error::Unavailable("fan control is disabled".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl sensors_service_server::SensorsService for SensorsServer {
+    async fn get_sensors(
+        &self,
+        request: Request<sensors::GetSensorsRequest>,
+    ) -> Result<Response<sensors::GetSensorsResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let readings = self.backend.sensors().await.map_err(|err| {
+            tracing::error!("error when get_sensors: {err}");
+            err
+        })?;
+
+        let reply = sensors::GetSensorsResponse {
+            sensors: to_sensors(readings),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchSensorsStream = WatchStream<sensors::WatchSensorsResponse>;
+
+    async fn watch_sensors(
+        &self,
+        request: Request<sensors::WatchSensorsRequest>,
+    ) -> Result<Response<Self::WatchSensorsStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let interval = interval(inner.interval_ms)?;
+        let backend = self.backend.clone();
+        let stream = poll::every(interval, move || {
+            let backend = backend.clone();
+            async move {
+                let readings = backend.sensors().await?;
+                Ok(sensors::WatchSensorsResponse {
+                    sensors: to_sensors(readings),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn get_fan_curve(
+        &self,
+        request: Request<sensors::GetFanCurveRequest>,
+    ) -> Result<Response<sensors::GetFanCurveResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.check_fan_enabled()?;
+        let reply = sensors::GetFanCurveResponse {
+            curve: Some(
+                self.fan
+                    .curve()
+                    .as_ref()
+                    .map(sensors::FanCurve::from)
+                    .unwrap_or_default(),
+            ),
+            status: Some(sensors::FanStatus::from(&*self.fan.status().borrow())),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_fan_curve(
+        &self,
+        request: Request<sensors::SetFanCurveRequest>,
+    ) -> Result<Response<sensors::SetFanCurveResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.check_fan_enabled()?;
+        let curve = to_curve(inner.curve)?;
+
+        match &curve {
+            Some(curve) => tracing::info!(
+                "Setting a fan curve with {} points for {caller}",
+                curve.points().len()
+            ),
+            None => tracing::info!("Returning the fan to the firmware for {caller}"),
+        }
+        let reply = sensors::SetFanCurveResponse {
+            curve: Some(
+                curve
+                    .as_ref()
+                    .map(sensors::FanCurve::from)
+                    .unwrap_or_default(),
+            ),
+        };
+        self.fan.set_curve(curve);
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchFanStatusStream = WatchStream<sensors::WatchFanStatusResponse>;
+
+    async fn watch_fan_status(
+        &self,
+        request: Request<sensors::WatchFanStatusRequest>,
+    ) -> Result<Response<Self::WatchFanStatusStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.check_fan_enabled()?;
+        // The current status first, then every change until the controller stops
+        let stream = stream::unfold(
+            (self.fan.status(), true),
+            |(mut status, first)| async move {
+                if !first {
+                    status.changed().await.ok()?;
+                }
+                let reply = sensors::WatchFanStatusResponse {
+                    status: Some(sensors::FanStatus::from(&*status.borrow())),
+                };
+                Some((Ok(reply), (status, false)))
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::sensors::sensors_service_server::SensorsService;
+    use shortcut_core::tokio;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::backend::fake::{sensor, FakeBackend};
+    use crate::config::{self, Config, SensorsConfig};
+    use hwmon::{Kind, PWM_AUTOMATIC, PWM_MANUAL};
+
+    fn server_with(backend: FakeBackend, config: Config) -> (Arc<FakeBackend>, SensorsServer) {
+        let backend = Arc::new(backend);
+        let config = config::fixed(config);
+        let (fan, _) =
+            FanControl::spawn(backend.clone(), config.clone(), Duration::from_millis(10));
+        let server = SensorsServer::new(backend.clone(), config, fan);
+        (backend, server)
+    }
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, SensorsServer) {
+        server_with(backend, Config::default())
+    }
+
+    fn curve(points: &[(f64, u32)]) -> Option<sensors::FanCurve> {
+        Some(sensors::FanCurve {
+            points: points
+                .iter()
+                .map(|&(temperature, duty_percent)| sensors::CurvePoint {
+                    temperature,
+                    duty_percent,
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn validates_intervals() {
+        assert_eq!(interval(0).unwrap(), DEFAULT_INTERVAL);
+        assert_eq!(interval(500).unwrap(), Duration::from_millis(500));
+        assert!(interval(10).is_err());
+        assert!(interval(3_600_000).is_err());
+    }
+
+    #[tokio::test]
+    async fn get_sensors_converts_units_and_kinds() {
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_sensor(sensor("k10temp", "Tctl", Kind::Temperature, 54.25))
+                .with_sensor(sensor("steamdeck_hwmon", "fan1", Kind::Fan, 2950.0)),
+        );
+
+        let reply = server
+            .get_sensors(Request::new(sensors::GetSensorsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.sensors.len(), 2);
+        assert_eq!(reply.sensors[0].label, "Tctl");
+        assert_eq!(reply.sensors[0].kind(), sensors::SensorKind::Temperature);
+        assert_eq!(reply.sensors[1].kind(), sensors::SensorKind::Fan);
+        assert_eq!(reply.sensors[1].value, 2950.0);
+    }
+
+    #[tokio::test]
+    async fn watch_sensors_follows_new_readings() {
+        let (backend, server) = server(FakeBackend::default().with_sensor(sensor(
+            "amdgpu",
+            "edge",
+            Kind::Temperature,
+            49.0,
+        )));
+
+        let mut stream = server
+            .watch_sensors(Request::new(sensors::WatchSensorsRequest {
+                interval_ms: 250,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().sensors[0].value, 49.0);
+
+        backend.set_sensor_value("edge", 51.0);
+
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert_eq!(next.unwrap().unwrap().unwrap().sensors[0].value, 51.0);
+    }
+
+    #[tokio::test]
+    async fn rejects_too_short_intervals() {
+        let (backend, server) = server(FakeBackend::default());
+
+        let status = server
+            .watch_sensors(Request::new(sensors::WatchSensorsRequest {
+                interval_ms: 1,
+            }))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fan_follows_the_curve_until_cleared() {
+        let (backend, server) = server(
+            FakeBackend::default()
+                .with_sensor(sensor("k10temp", "Tctl", Kind::Temperature, 60.0))
+                .with_fan(PWM_AUTOMATIC),
+        );
+        let mut stream = server
+            .watch_fan_status(Request::new(sensors::WatchFanStatusRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let firmware = stream.next().await.unwrap().unwrap().status.unwrap();
+        assert_eq!(firmware.control(), sensors::FanControl::Firmware);
+
+        let reply = server
+            .set_fan_curve(Request::new(sensors::SetFanCurveRequest {
+                curve: curve(&[(40.0, 20), (80.0, 60)]),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.curve.unwrap().points.len(), 2);
+
+        let following = stream.next().await.unwrap().unwrap().status.unwrap();
+        assert_eq!(following.control(), sensors::FanControl::Curve);
+        assert_eq!(following.duty_percent, 40);
+        assert_eq!(backend.fan().0, PWM_MANUAL);
+
+        server
+            .set_fan_curve(Request::new(sensors::SetFanCurveRequest { curve: None }))
+            .await
+            .unwrap();
+
+        let released = stream.next().await.unwrap().unwrap().status.unwrap();
+        assert_eq!(released.control(), sensors::FanControl::Firmware);
+        assert_eq!(backend.fan().0, PWM_AUTOMATIC);
+        let reply = server
+            .get_fan_curve(Request::new(sensors::GetFanCurveRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(reply.curve.unwrap().points.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_fan_curves() {
+        let (backend, server) = server(FakeBackend::default().with_fan(PWM_AUTOMATIC));
+
+        let status = server
+            .set_fan_curve(Request::new(sensors::SetFanCurveRequest {
+                curve: curve(&[(60.0, 50), (40.0, 30)]),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_fan_control_is_unavailable() {
+        let mut config = Config::default();
+        config.fan.enabled = false;
+        let (_, server) = server_with(FakeBackend::default(), config);
+
+        let status = server
+            .set_fan_curve(Request::new(sensors::SetFanCurveRequest {
+                curve: curve(&[(40.0, 20), (80.0, 60)]),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let (_, server) = server_with(
+            FakeBackend::default(),
+            Config {
+                sensors: SensorsConfig { enabled: false },
+                ..Config::default()
+            },
+        );
+
+        let status = server
+            .get_sensors(Request::new(sensors::GetSensorsRequest {}))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+}
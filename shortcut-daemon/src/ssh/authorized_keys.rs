@@ -0,0 +1,570 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// The key types sshd accepts by default, DSA keys are left out as OpenSSH dropped them.
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// Longer lines are no key, even 16384 bit RSA keys stay well below it.
+const MAX_LINE_LENGTH: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The text is not a single public key line sshd would accept.
+    InvalidKey(String),
+    /// No key in the file has this fingerprint.
+    NoKey(String),
+    /// The path is a symlink, which the daemon never follows into a user's home.
+    Symlink(PathBuf),
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidKey(reason) => write!(f, "invalid public key: {reason}"),
+            Error::NoKey(fingerprint) => write!(f, "no authorized key {fingerprint}"),
+            Error::Symlink(path) => write!(f, "{} is a symlink", path.display()),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::InvalidKey(_) => shortcut_core::Error::InvalidArgument(err.to_string()),
+            Error::NoKey(fingerprint) => {
+                shortcut_core::Error::not_found(format!("authorized key {fingerprint}"))
+            }
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// A public key line of an `authorized_keys` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizedKey {
+    /// The options in front of the key type as written, e.g. `no-pty,from="10.0.0.0/8"`.
+    pub options: String,
+    pub key_type: String,
+    /// The base64 encoded key.
+    pub key: String,
+    pub comment: String,
+    /// The SHA256 fingerprint as `ssh-keygen -l` prints it.
+    pub fingerprint: String,
+}
+
+impl AuthorizedKey {
+    /// Parses a single line, checking that the key decodes and is of the type it claims.
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let line = line.trim();
+        if line.len() > MAX_LINE_LENGTH {
+            return Err(Error::InvalidKey("the key is too long".to_string()));
+        }
+        if line.contains(['\n', '\r', '\0']) {
+            return Err(Error::InvalidKey("expected a single line".to_string()));
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return Err(Error::InvalidKey("the line holds no key".to_string()));
+        }
+
+        let (options, rest) = match split_field(line) {
+            (first, _) if KEY_TYPES.contains(&first) => ("", line),
+            split => split,
+        };
+        let (key_type, rest) = split_field(rest);
+        if !KEY_TYPES.contains(&key_type) {
+            return Err(Error::InvalidKey(format!(
+                "unsupported key type {key_type:?}"
+            )));
+        }
+        let (key, comment) = split_field(rest);
+        let blob = base64::decode(key)
+            .map_err(|_| Error::InvalidKey("the key is not valid base64".to_string()))?;
+        if blob_type(&blob) != Some(key_type.as_bytes()) {
+            return Err(Error::InvalidKey(format!(
+                "the key is not of type {key_type}"
+            )));
+        }
+
+        Ok(Self {
+            options: options.to_string(),
+            key_type: key_type.to_string(),
+            key: key.to_string(),
+            comment: comment.trim().to_string(),
+            fingerprint: format!(
+                "SHA256:{}",
+                base64::encode_config(Sha256::digest(&blob), base64::STANDARD_NO_PAD)
+            ),
+        })
+    }
+
+    fn line(&self) -> String {
+        let mut line = [
+            self.options.as_str(),
+            &self.key_type,
+            &self.key,
+            &self.comment,
+        ]
+        .iter()
+        .filter(|field| !field.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+        line.push('\n');
+        line
+    }
+}
+
+impl From<AuthorizedKey> for shortcut_core::ssh::AuthorizedKey {
+    fn from(key: AuthorizedKey) -> Self {
+        Self {
+            key_type: key.key_type,
+            fingerprint: key.fingerprint,
+            comment: key.comment,
+            options: key.options,
+        }
+    }
+}
+
+/// Splits off the first whitespace separated field, whitespace within double quotes doesn't
+/// count as options like `command="..."` may contain it.
+fn split_field(text: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return (&text[..i], text[i..].trim_start()),
+            _ => {}
+        }
+    }
+    (text, "")
+}
+
+/// The key type every SSH public key blob starts with, as a length prefixed string.
+fn blob_type(blob: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_be_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+    blob.get(4..4 + len)
+}
+
+/// The `authorized_keys` file of a user.
+///
+/// Lines the daemon doesn't understand, comments included, are kept as they are and a new file
+/// belongs to the user with mode `0600`, so sshd's `StrictModes` keeps accepting it.
+#[derive(Debug, Clone)]
+pub struct AuthorizedKeys {
+    path: PathBuf,
+    uid: u32,
+    gid: u32,
+}
+
+impl AuthorizedKeys {
+    pub fn new(path: impl Into<PathBuf>, uid: u32, gid: u32) -> Self {
+        Self {
+            path: path.into(),
+            uid,
+            gid,
+        }
+    }
+
+    fn io_error(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        }
+    }
+
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new("/"))
+    }
+
+    fn file_name(&self) -> CString {
+        let name = self.path.file_name().unwrap_or_default();
+        // Paths never hold a NUL
+        CString::new(name.as_bytes()).unwrap_or_default()
+    }
+
+    fn tmp_name(&self) -> CString {
+        let mut name = self.file_name().into_bytes();
+        name.extend_from_slice(b".shortcut-tmp");
+        CString::new(name).unwrap_or_default()
+    }
+
+    /// Opens the directory of the file, which must not be a symlink, `None` when it doesn't
+    /// exist.
+    ///
+    /// The user owns the directory and can swap it for a symlink at any time, so everything
+    /// after is done relative to the descriptor rather than by path.
+    fn open_dir(&self) -> Result<Option<File>, Error> {
+        let dir = self.dir();
+        let path = CString::new(dir.as_os_str().as_bytes()).unwrap_or_default();
+        // SAFETY: plain open(2) on a NUL terminated path, the descriptor is checked before use
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOENT) => Ok(None),
+                Some(libc::ELOOP) => Err(Error::Symlink(dir.to_path_buf())),
+                // O_DIRECTORY wins over O_NOFOLLOW for symlinks to directories on some kernels
+                Some(libc::ENOTDIR) if matches!(fs::symlink_metadata(dir), Ok(meta) if meta.file_type().is_symlink()) => {
+                    Err(Error::Symlink(dir.to_path_buf()))
+                }
+                _ => Err(Self::io_error(dir)(err)),
+            };
+        }
+        // SAFETY: the descriptor was just opened and nothing else owns it
+        Ok(Some(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    /// Opens the directory of the file, creating it for the user when it is missing.
+    fn open_or_create_dir(&self) -> Result<File, Error> {
+        if let Some(dir) = self.open_dir()? {
+            return Ok(dir);
+        }
+
+        let path = self.dir();
+        match fs::create_dir(path) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+                return Err(Self::io_error(path)(err))
+            }
+            _ => {}
+        }
+        let dir = self
+            .open_dir()?
+            .ok_or_else(|| Self::io_error(path)(io::ErrorKind::NotFound.into()))?;
+        dir.set_permissions(fs::Permissions::from_mode(0o700))
+            .and_then(|()| std::os::unix::fs::fchown(&dir, Some(self.uid), Some(self.gid)))
+            .map_err(Self::io_error(path))?;
+        Ok(dir)
+    }
+
+    /// Opens `name` within `dir` without following a symlink.
+    fn open_at(&self, dir: &File, name: &CStr, flags: libc::c_int) -> Result<File, Error> {
+        // SAFETY: openat(2) on a directory descriptor owned by `dir` and a NUL terminated name
+        let fd = unsafe {
+            libc::openat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                0o600 as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ELOOP) {
+                return Err(Error::Symlink(self.path.clone()));
+            }
+            return Err(Self::io_error(&self.path)(err));
+        }
+        // SAFETY: the descriptor was just opened and nothing else owns it
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// The lines of the file, none when it doesn't exist yet.
+    fn lines(&self) -> Result<Vec<String>, Error> {
+        let dir = match self.open_dir()? {
+            Some(dir) => dir,
+            None => return Ok(Vec::new()),
+        };
+        let mut file = match self.open_at(&dir, &self.file_name(), libc::O_RDONLY) {
+            Ok(file) => file,
+            Err(Error::Io { err, .. }) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Vec::new())
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .map_err(Self::io_error(&self.path))?;
+        Ok(content.lines().map(|line| format!("{line}\n")).collect())
+    }
+
+    fn write(&self, lines: &[String]) -> Result<(), Error> {
+        let dir = self.open_or_create_dir()?;
+        self.write_at(&dir, lines)
+    }
+
+    /// Replaces the file in `dir`, keeping the mode and owner of the one it replaces.
+    fn write_at(&self, dir: &File, lines: &[String]) -> Result<(), Error> {
+        let (name, tmp) = (self.file_name(), self.tmp_name());
+        let (mode, uid, gid) = match self.open_at(dir, &name, libc::O_RDONLY) {
+            Ok(file) => {
+                let meta = file.metadata().map_err(Self::io_error(&self.path))?;
+                (meta.mode() & 0o7777, meta.uid(), meta.gid())
+            }
+            Err(Error::Io { err, .. }) if err.kind() == io::ErrorKind::NotFound => {
+                (0o600, self.uid, self.gid)
+            }
+            Err(err) => return Err(err),
+        };
+
+        // A leftover from a crash, or something the user placed there, is never written through
+        // SAFETY: unlinkat(2) on a directory descriptor owned by `dir` and a NUL terminated name
+        if unsafe { libc::unlinkat(dir.as_raw_fd(), tmp.as_ptr(), 0) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(Self::io_error(&self.path)(err));
+            }
+        }
+        let mut file = self.open_at(dir, &tmp, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL)?;
+        let result = file
+            .write_all(lines.concat().as_bytes())
+            .and_then(|()| file.set_permissions(fs::Permissions::from_mode(mode)))
+            .and_then(|()| std::os::unix::fs::fchown(&file, Some(uid), Some(gid)))
+            .and_then(|()| file.sync_all())
+            .and_then(|()| {
+                // SAFETY: renameat(2) within the directory owned by `dir`, both names are NUL
+                // terminated
+                let renamed = unsafe {
+                    libc::renameat(
+                        dir.as_raw_fd(),
+                        tmp.as_ptr(),
+                        dir.as_raw_fd(),
+                        name.as_ptr(),
+                    )
+                };
+                if renamed < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        if let Err(err) = result {
+            // SAFETY: as above
+            unsafe { libc::unlinkat(dir.as_raw_fd(), tmp.as_ptr(), 0) };
+            return Err(Self::io_error(&self.path)(err));
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<AuthorizedKey>, Error> {
+        Ok(self
+            .lines()?
+            .iter()
+            .filter_map(|line| AuthorizedKey::parse(line).ok())
+            .collect())
+    }
+
+    /// Appends the key unless it is already authorized and returns the keys read back.
+    pub fn add(&self, key: &AuthorizedKey) -> Result<Vec<AuthorizedKey>, Error> {
+        let mut lines = self.lines()?;
+        let present = lines.iter().any(|line| match AuthorizedKey::parse(line) {
+            Ok(existing) => existing.fingerprint == key.fingerprint,
+            Err(_) => false,
+        });
+        if !present {
+            lines.push(key.line());
+            self.write(&lines)?;
+        }
+        self.list()
+    }
+
+    /// Removes every line with the fingerprint and returns the keys read back.
+    pub fn remove(&self, fingerprint: &str) -> Result<Vec<AuthorizedKey>, Error> {
+        let lines = self.lines()?;
+        let kept = lines
+            .iter()
+            .filter(|line| match AuthorizedKey::parse(line) {
+                Ok(key) => key.fingerprint != fingerprint,
+                Err(_) => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if kept.len() == lines.len() {
+            return Err(Error::NoKey(fingerprint.to_string()));
+        }
+        self.write(&kept)?;
+        self.list()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    /// `ssh-keygen -t ed25519`, its fingerprint as `ssh-keygen -l` prints it.
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJcdaIRV2+Nn4kkx671ZOtLRTYBGYI6tiRD9gdhJEcYw deck@steamdeck";
+    const ED25519_FINGERPRINT: &str = "SHA256:epTq10lZP+pY8D2/jmRFEcsFZG2XIzkosKPrB+ew0+4";
+
+    fn key_of_type(key_type: &str) -> String {
+        let mut blob = (key_type.len() as u32).to_be_bytes().to_vec();
+        blob.extend_from_slice(key_type.as_bytes());
+        blob.extend_from_slice(&[0, 0, 0, 1, 42]);
+        format!("{key_type} {}", base64::encode(blob))
+    }
+
+    fn authorized_keys(fake: &FakeSysfs) -> AuthorizedKeys {
+        AuthorizedKeys::new(
+            fake.root().join(".ssh/authorized_keys"),
+            nix::unistd::getuid().as_raw(),
+            nix::unistd::getgid().as_raw(),
+        )
+    }
+
+    #[test]
+    fn parses_keys_with_options_and_comments() {
+        let key = AuthorizedKey::parse(ED25519).unwrap();
+        assert_eq!(key.options, "");
+        assert_eq!(key.key_type, "ssh-ed25519");
+        assert_eq!(key.comment, "deck@steamdeck");
+        assert_eq!(key.fingerprint, ED25519_FINGERPRINT);
+
+        let line = format!(
+            r#"no-pty,command="echo \"a b\"" {} laptop key"#,
+            key_of_type("ssh-rsa")
+        );
+        let key = AuthorizedKey::parse(&line).unwrap();
+        assert_eq!(key.options, r#"no-pty,command="echo \"a b\"""#);
+        assert_eq!(key.key_type, "ssh-rsa");
+        assert_eq!(key.comment, "laptop key");
+        assert_eq!(key.line(), format!("{line}\n"));
+    }
+
+    #[test]
+    fn rejects_what_sshd_would() {
+        for line in [
+            String::new(),
+            "# ssh-ed25519 AAAA".to_string(),
+            "ssh-dss AAAAB3NzaC1kc3MAAACBAP".to_string(),
+            "ssh-ed25519 not-base64!".to_string(),
+            // A blob of another type than the line claims
+            key_of_type("ssh-rsa").replace("ssh-rsa ", "ssh-ed25519 "),
+            format!("{ED25519}\n{ED25519}"),
+        ] {
+            assert!(
+                matches!(AuthorizedKey::parse(&line), Err(Error::InvalidKey(_))),
+                "{line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn adds_to_a_new_file_with_strict_modes() {
+        let fake = FakeSysfs::new("authorized-keys-new");
+        let keys = authorized_keys(&fake);
+
+        let listed = keys.add(&AuthorizedKey::parse(ED25519).unwrap()).unwrap();
+
+        assert_eq!(listed.len(), 1);
+        assert_eq!(fake.read(".ssh/authorized_keys"), ED25519);
+        let dir = fs::metadata(fake.root().join(".ssh")).unwrap();
+        assert_eq!(dir.mode() & 0o777, 0o700);
+        let file = fs::metadata(fake.root().join(".ssh/authorized_keys")).unwrap();
+        assert_eq!(file.mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn keeps_other_lines_and_the_mode() {
+        let fake = FakeSysfs::new("authorized-keys-keep");
+        let rsa = key_of_type("ssh-rsa");
+        fake.write(
+            ".ssh/authorized_keys",
+            &format!("# work laptop\nfrom=\"10.0.0.0/8\" {rsa} work\n\n{ED25519}"),
+        );
+        let path = fake.root().join(".ssh/authorized_keys");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let keys = authorized_keys(&fake);
+
+        // Adding a key twice leaves the file alone
+        assert_eq!(
+            keys.add(&AuthorizedKey::parse(ED25519).unwrap())
+                .unwrap()
+                .len(),
+            2
+        );
+        let listed = keys.remove(ED25519_FINGERPRINT).unwrap();
+
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].options, "from=\"10.0.0.0/8\"");
+        assert_eq!(
+            fake.read(".ssh/authorized_keys"),
+            format!("# work laptop\nfrom=\"10.0.0.0/8\" {rsa} work")
+        );
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o640);
+        assert!(matches!(
+            keys.remove(ED25519_FINGERPRINT),
+            Err(Error::NoKey(_))
+        ));
+    }
+
+    #[test]
+    fn never_follows_symlinks() {
+        let fake = FakeSysfs::new("authorized-keys-symlink");
+        fake.write("secret", ED25519);
+        fs::create_dir(fake.root().join(".ssh")).unwrap();
+        std::os::unix::fs::symlink(
+            fake.root().join("secret"),
+            fake.root().join(".ssh/authorized_keys"),
+        )
+        .unwrap();
+        let keys = authorized_keys(&fake);
+
+        assert!(matches!(keys.list(), Err(Error::Symlink(_))));
+        assert_eq!(fake.read("secret"), ED25519);
+    }
+
+    #[test]
+    fn never_follows_a_symlinked_directory() {
+        let fake = FakeSysfs::new("authorized-keys-symlink-dir");
+        fs::create_dir(fake.root().join("root-ssh")).unwrap();
+        std::os::unix::fs::symlink(fake.root().join("root-ssh"), fake.root().join(".ssh")).unwrap();
+        let keys = authorized_keys(&fake);
+
+        let err = keys.add(&AuthorizedKey::parse(ED25519).unwrap());
+        assert!(matches!(err, Err(Error::Symlink(_))));
+        assert!(!fake.root().join("root-ssh/authorized_keys").exists());
+    }
+
+    #[test]
+    fn writes_where_the_directory_was_opened() {
+        let fake = FakeSysfs::new("authorized-keys-swap");
+        fs::create_dir(fake.root().join("root-ssh")).unwrap();
+        let keys = authorized_keys(&fake);
+        let dir = keys.open_or_create_dir().unwrap();
+
+        // The user swaps in a symlink between the check and the write
+        fs::rename(fake.root().join(".ssh"), fake.root().join("moved")).unwrap();
+        std::os::unix::fs::symlink(fake.root().join("root-ssh"), fake.root().join(".ssh")).unwrap();
+        keys.write_at(&dir, &[format!("{ED25519}\n")]).unwrap();
+
+        assert!(!fake.root().join("root-ssh/authorized_keys").exists());
+        assert_eq!(fake.read("moved/authorized_keys"), ED25519);
+        assert!(fs::read_dir(fake.root().join("moved"))
+            .unwrap()
+            .all(|entry| entry.unwrap().file_name() == "authorized_keys"));
+    }
+}
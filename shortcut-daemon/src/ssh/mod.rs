@@ -0,0 +1,748 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::ssh;
+use shortcut_core::ssh::ssh_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{Service, WatchStream};
+
+pub(crate) mod authorized_keys;
+pub(crate) mod sshd_config;
+
+use authorized_keys::AuthorizedKey;
+
+/// Checks the options before they reach sshd, which can't tell which one it choked on.
+fn to_options(options: Option<ssh::SshdOptions>) -> Result<sshd_config::Options, Error> {
+    let options =
+        options.ok_or_else(|| Error::InvalidArgument("options are required".to_string()))?;
+    let port = match u16::try_from(options.port) {
+        Ok(port) if port > 0 => port,
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "port must be between 1 and 65535, got {}",
+                options.port
+            )))
+        }
+    };
+    for address in &options.listen_addresses {
+        if address.parse::<IpAddr>().is_err() && address.parse::<SocketAddr>().is_err() {
+            return Err(Error::InvalidArgument(format!(
+                "{address:?} is not an IP address"
+            )));
+        }
+    }
+
+    Ok(sshd_config::Options {
+        password_authentication: options.password_authentication,
+        port,
+        listen_addresses: options.listen_addresses,
+    })
+}
+
+fn to_keys(keys: Vec<AuthorizedKey>) -> Vec<ssh::AuthorizedKey> {
+    keys.into_iter().map(ssh::AuthorizedKey::from).collect()
+}
+
+pub struct SshServer {
+    backend: Arc<dyn SystemBackend>,
+    config: SharedConfig,
+    service: Service,
+}
+
+impl SshServer {
+    pub fn new(backend: Arc<dyn SystemBackend>, config: SharedConfig) -> Self {
+        Self {
+            backend,
+            service: Service::new("ssh", config.clone(), |config| config.ssh.enabled),
+            config,
+        }
+    }
+
+    /// The configured sshd unit, as long as the service is enabled.
+    fn unit(&self) -> Result<String, Error> {
+        self.service.check_enabled()?;
+        Ok(self.config.borrow().ssh.unit.clone())
+    }
+
+    /// The user whose keys are managed, as long as the service is enabled.
+    fn user(&self) -> Result<String, Error> {
+        self.service.check_enabled()?;
+        Ok(self.config.borrow().ssh.user.clone())
+    }
+}
+
+#[tonic::async_trait]
+impl ssh_service_server::SshService for SshServer {
+    async fn set_enabled(
+        &self,
+        request: Request<ssh::SetEnabledRequest>,
+    ) -> Result<Response<ssh::SetEnabledResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let unit = self.unit()?;
+        tracing::info!(
+            "{} {unit} for {caller}",
+            if inner.enabled {
+                "Starting"
+            } else {
+                "Stopping"
+            }
+        );
+        let state = if inner.enabled {
+            self.backend.start_unit(&unit).await
+        } else {
+            self.backend.stop_unit(&unit).await
+        }
+        .map_err(|err| {
+            tracing::error!("error when set_enabled: {err}");
+            err
+        })?;
+        self.service.notify();
+
+        let reply = ssh::SetEnabledResponse {
+            enabled: state.is_active(),
+            enabled_at_boot: state.is_enabled(),
+            state: Some(state.into()),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn set_enabled_at_boot(
+        &self,
+        request: Request<ssh::SetEnabledAtBootRequest>,
+    ) -> Result<Response<ssh::SetEnabledAtBootResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let unit = self.unit()?;
+        tracing::info!(
+            "{} {unit} at boot for {caller}",
+            if inner.enabled {
+                "Enabling"
+            } else {
+                "Disabling"
+            }
+        );
+        let state = self
+            .backend
+            .set_unit_enabled(&unit, inner.enabled)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_enabled_at_boot: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = ssh::SetEnabledAtBootResponse {
+            enabled: state.is_active(),
+            enabled_at_boot: state.is_enabled(),
+            state: Some(state.into()),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn get_enabled(
+        &self,
+        request: Request<ssh::GetEnabledRequest>,
+    ) -> Result<Response<ssh::GetEnabledResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let unit = self.unit()?;
+        let state = self.backend.unit_state(&unit).await.map_err(|err| {
+            tracing::error!("error when get_enabled: {err}");
+            err
+        })?;
+
+        let reply = ssh::GetEnabledResponse {
+            enabled: state.is_active(),
+            enabled_at_boot: state.is_enabled(),
+            state: Some(state.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchEnabledStream = WatchStream<ssh::WatchEnabledResponse>;
+
+    async fn watch_enabled(
+        &self,
+        request: Request<ssh::WatchEnabledRequest>,
+    ) -> Result<Response<Self::WatchEnabledStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let unit = self.unit()?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            let unit = unit.clone();
+            async move {
+                let state = backend.unit_state(&unit).await?;
+                Ok(ssh::WatchEnabledResponse {
+                    enabled: state.is_active(),
+                    enabled_at_boot: state.is_enabled(),
+                    state: Some(state.into()),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn list_authorized_keys(
+        &self,
+        request: Request<ssh::ListAuthorizedKeysRequest>,
+    ) -> Result<Response<ssh::ListAuthorizedKeysResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let user = self.user()?;
+        let keys = self.backend.authorized_keys(&user).await.map_err(|err| {
+            tracing::error!("error when list_authorized_keys: {err}");
+            err
+        })?;
+
+        let reply = ssh::ListAuthorizedKeysResponse {
+            keys: to_keys(keys),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn add_authorized_key(
+        &self,
+        request: Request<ssh::AddAuthorizedKeyRequest>,
+    ) -> Result<Response<ssh::AddAuthorizedKeyResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let user = self.user()?;
+        let key = AuthorizedKey::parse(&inner.key).map_err(Error::from)?;
+
+        tracing::info!(
+            "Authorizing {} key {} for {user} for {caller}",
+            key.key_type,
+            key.fingerprint
+        );
+        let keys = self
+            .backend
+            .add_authorized_key(&user, key)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when add_authorized_key: {err}");
+                err
+            })?;
+
+        let reply = ssh::AddAuthorizedKeyResponse {
+            keys: to_keys(keys),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn remove_authorized_key(
+        &self,
+        request: Request<ssh::RemoveAuthorizedKeyRequest>,
+    ) -> Result<Response<ssh::RemoveAuthorizedKeyResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let user = self.user()?;
+        if inner.fingerprint.is_empty() {
+            return Err(Error::InvalidArgument("fingerprint must not be empty".to_string()).into());
+        }
+
+        tracing::info!("Removing key {} of {user} for {caller}", inner.fingerprint);
+        let keys = self
+            .backend
+            .remove_authorized_key(&user, &inner.fingerprint)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when remove_authorized_key: {err}");
+                err
+            })?;
+
+        let reply = ssh::RemoveAuthorizedKeyResponse {
+            keys: to_keys(keys),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_options(
+        &self,
+        request: Request<ssh::GetOptionsRequest>,
+    ) -> Result<Response<ssh::GetOptionsResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let options = self.backend.sshd_options().await.map_err(|err| {
+            tracing::error!("error when get_options: {err}");
+            err
+        })?;
+
+        let reply = ssh::GetOptionsResponse {
+            options: Some(options.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_options(
+        &self,
+        request: Request<ssh::SetOptionsRequest>,
+    ) -> Result<Response<ssh::SetOptionsResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let unit = self.unit()?;
+        let options = to_options(inner.options)?;
+
+        tracing::info!("Setting sshd options to {options:?} for {caller}");
+        let options = self
+            .backend
+            .set_sshd_options(options)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_options: {err}");
+                err
+            })?;
+        let state = self.backend.reload_unit(&unit).await.map_err(|err| {
+            tracing::error!("error when set_options: {err}");
+            err
+        })?;
+        self.service.notify();
+
+        let reply = ssh::SetOptionsResponse {
+            options: Some(options.into()),
+            state: Some(state.into()),
+        };
+
+        Ok(Response::new(reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::ssh::ssh_service_server::SshService;
+    use shortcut_core::tokio;
+    use shortcut_core::tonic::Code;
+
+    use super::*;
+    use crate::backend::fake::{unit_state, Call, FakeBackend, Method};
+    use crate::config::{self, Config, SshConfig};
+    use crate::ssh::sshd_config::Options;
+
+    const SSH_UNIT: &str = "sshd.service";
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJcdaIRV2+Nn4kkx671ZOtLRTYBGYI6tiRD9gdhJEcYw deck@steamdeck";
+    const ED25519_FINGERPRINT: &str = "SHA256:epTq10lZP+pY8D2/jmRFEcsFZG2XIzkosKPrB+ew0+4";
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, SshServer) {
+        server_with_config(backend, SshConfig::default())
+    }
+
+    fn server_with_config(backend: FakeBackend, ssh: SshConfig) -> (Arc<FakeBackend>, SshServer) {
+        let backend = Arc::new(backend);
+        let config = config::fixed(Config {
+            ssh,
+            ..Config::default()
+        });
+        (backend.clone(), SshServer::new(backend, config))
+    }
+
+    async fn get_enabled(server: &SshServer) -> Result<ssh::GetEnabledResponse, Status> {
+        server
+            .get_enabled(Request::new(ssh::GetEnabledRequest {}))
+            .await
+            .map(Response::into_inner)
+    }
+
+    async fn set_enabled(
+        server: &SshServer,
+        enabled: bool,
+    ) -> Result<ssh::SetEnabledResponse, Status> {
+        server
+            .set_enabled(Request::new(ssh::SetEnabledRequest { enabled }))
+            .await
+            .map(Response::into_inner)
+    }
+
+    #[tokio::test]
+    async fn get_enabled_reads_unit_state() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")));
+
+        let reply = get_enabled(&server).await.unwrap();
+
+        assert!(reply.enabled);
+        let state = reply.state.unwrap();
+        assert_eq!(state.active_state, "active");
+        assert_eq!(state.sub_state, "running");
+        assert_eq!(backend.calls(), vec![Call::UnitState(SSH_UNIT.to_string())]);
+    }
+
+    #[tokio::test]
+    async fn get_enabled_treats_unexpected_states_as_disabled() {
+        for active_state in ["failed", "activating", "", "bogus"] {
+            let (_, server) =
+                server(FakeBackend::default().with_unit(SSH_UNIT, unit_state(active_state, "")));
+
+            let reply = get_enabled(&server).await.unwrap();
+
+            assert!(
+                !reply.enabled,
+                "{active_state:?} should not count as enabled"
+            );
+            assert_eq!(reply.state.unwrap().active_state, active_state);
+        }
+    }
+
+    #[tokio::test]
+    async fn get_enabled_treats_reloading_as_enabled() {
+        let (_, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("reloading", "reload")));
+
+        assert!(get_enabled(&server).await.unwrap().enabled);
+    }
+
+    #[tokio::test]
+    async fn get_enabled_missing_unit_is_not_found() {
+        let (_, server) = server(FakeBackend::default());
+
+        let status = get_enabled(&server).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn get_enabled_reports_bus_errors() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")));
+        backend.fail(
+            Method::UnitState,
+            Error::Unavailable("D-Bus error: connection closed".to_string()),
+        );
+
+        let status = get_enabled(&server).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn set_enabled_starts_and_stops_unit() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("inactive", "dead")));
+
+        let reply = set_enabled(&server, true).await.unwrap();
+        assert!(reply.enabled);
+        assert_eq!(reply.state.unwrap().sub_state, "running");
+
+        let reply = set_enabled(&server, false).await.unwrap();
+        assert!(!reply.enabled);
+
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::StartUnit(SSH_UNIT.to_string()),
+                Call::StopUnit(SSH_UNIT.to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn boot_and_runtime_state_are_separate() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("inactive", "dead")));
+
+        let reply = server
+            .set_enabled_at_boot(Request::new(ssh::SetEnabledAtBootRequest { enabled: true }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(reply.enabled_at_boot);
+        assert!(!reply.enabled);
+        assert_eq!(reply.state.unwrap().unit_file_state, "enabled");
+
+        let reply = set_enabled(&server, true).await.unwrap();
+        assert!(reply.enabled);
+        assert!(reply.enabled_at_boot);
+
+        let reply = get_enabled(&server).await.unwrap();
+        assert!(reply.enabled && reply.enabled_at_boot);
+        assert_eq!(
+            backend.calls()[..2],
+            [
+                Call::SetUnitEnabled(SSH_UNIT.to_string(), true),
+                Call::StartUnit(SSH_UNIT.to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_enabled_reports_failed_jobs() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("inactive", "dead")));
+        backend.fail(
+            Method::StartUnit,
+            Error::Internal("job for sshd.service failed".to_string()),
+        );
+
+        let status = set_enabled(&server, true).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "job for sshd.service failed");
+    }
+
+    #[tokio::test]
+    async fn set_enabled_reports_permission_errors() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")));
+        backend.fail(
+            Method::StopUnit,
+            Error::PermissionDenied("Access denied".to_string()),
+        );
+
+        let status = set_enabled(&server, false).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn uses_configured_unit() {
+        let (backend, server) = server_with_config(
+            FakeBackend::default().with_unit("ssh.service", unit_state("inactive", "dead")),
+            SshConfig {
+                unit: "ssh.service".to_string(),
+                ..SshConfig::default()
+            },
+        );
+
+        assert!(set_enabled(&server, true).await.unwrap().enabled);
+        assert_eq!(
+            backend.calls(),
+            vec![Call::StartUnit("ssh.service".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let (backend, server) = server_with_config(
+            FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")),
+            SshConfig {
+                enabled: false,
+                ..SshConfig::default()
+            },
+        );
+
+        let status = set_enabled(&server, false).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_enabled_follows_changes() {
+        let (_, mut server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("inactive", "dead")));
+        server.service.interval = Duration::from_secs(3600);
+
+        let mut stream = server
+            .watch_enabled(Request::new(ssh::WatchEnabledRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!stream.next().await.unwrap().unwrap().enabled);
+
+        set_enabled(&server, true).await.unwrap();
+
+        let reply = stream.next().await.unwrap().unwrap();
+        assert!(reply.enabled);
+        assert_eq!(reply.state.unwrap().sub_state, "running");
+    }
+
+    #[tokio::test]
+    async fn add_authorized_key_validates_the_key() {
+        let (backend, server) = server(FakeBackend::default());
+
+        for key in ["", "ssh-ed25519 AAAA", "ssh-dss AAAAB3NzaC1kc3M= old"] {
+            let status = server
+                .add_authorized_key(Request::new(ssh::AddAuthorizedKeyRequest {
+                    key: key.to_string(),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{key:?}");
+        }
+        assert!(backend.calls().is_empty());
+
+        let reply = server
+            .add_authorized_key(Request::new(ssh::AddAuthorizedKeyRequest {
+                key: format!("{ED25519}\n"),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.keys.len(), 1);
+        assert_eq!(reply.keys[0].fingerprint, ED25519_FINGERPRINT);
+        assert_eq!(reply.keys[0].comment, "deck@steamdeck");
+        assert_eq!(
+            backend.calls(),
+            vec![Call::AddAuthorizedKey(
+                "deck".to_string(),
+                ED25519_FINGERPRINT.to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn removes_authorized_keys_of_the_configured_user() {
+        let key = AuthorizedKey::parse(ED25519).unwrap();
+        let (backend, server) = server_with_config(
+            FakeBackend::default().with_authorized_key("steam", key),
+            SshConfig {
+                user: "steam".to_string(),
+                ..SshConfig::default()
+            },
+        );
+
+        let listed = server
+            .list_authorized_keys(Request::new(ssh::ListAuthorizedKeysRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.keys.len(), 1);
+
+        let reply = server
+            .remove_authorized_key(Request::new(ssh::RemoveAuthorizedKeyRequest {
+                fingerprint: ED25519_FINGERPRINT.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(reply.keys.is_empty());
+
+        let status = server
+            .remove_authorized_key(Request::new(ssh::RemoveAuthorizedKeyRequest {
+                fingerprint: ED25519_FINGERPRINT.to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(
+            backend.calls()[..2],
+            [
+                Call::AuthorizedKeys("steam".to_string()),
+                Call::RemoveAuthorizedKey("steam".to_string(), ED25519_FINGERPRINT.to_string())
+            ]
+        );
+    }
+
+    fn set_options(options: ssh::SshdOptions) -> Request<ssh::SetOptionsRequest> {
+        Request::new(ssh::SetOptionsRequest {
+            options: Some(options),
+        })
+    }
+
+    #[tokio::test]
+    async fn set_options_reloads_sshd() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")));
+        let options = ssh::SshdOptions {
+            password_authentication: false,
+            port: 2222,
+            listen_addresses: vec!["192.168.1.10".to_string(), "[::1]:2222".to_string()],
+        };
+
+        let reply = server
+            .set_options(set_options(options.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.options.unwrap(), options);
+        assert_eq!(reply.state.unwrap().active_state, "active");
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::SetSshdOptions(Options {
+                    password_authentication: false,
+                    port: 2222,
+                    listen_addresses: options.listen_addresses,
+                }),
+                Call::ReloadUnit(SSH_UNIT.to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_options_rejects_invalid_options() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")));
+
+        for (port, address) in [
+            (0, ""),
+            (65536, ""),
+            (22, "example.com"),
+            (22, "0.0.0.0 -p"),
+        ] {
+            let options = ssh::SshdOptions {
+                password_authentication: true,
+                port,
+                listen_addresses: [address]
+                    .iter()
+                    .filter(|address| !address.is_empty())
+                    .map(|address| address.to_string())
+                    .collect(),
+            };
+            let status = server.set_options(set_options(options)).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{port} {address:?}");
+        }
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_options_are_not_reloaded() {
+        let (backend, server) =
+            server(FakeBackend::default().with_unit(SSH_UNIT, unit_state("active", "running")));
+        backend.fail(
+            Method::SetSshdOptions,
+            Error::InvalidArgument("sshd rejected the configuration".to_string()),
+        );
+
+        let status = server
+            .set_options(set_options(Options::default().into()))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(!backend
+            .calls()
+            .contains(&Call::ReloadUnit(SSH_UNIT.to_string())));
+
+        let reply = server
+            .get_options(Request::new(ssh::GetOptionsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.options.unwrap().port, 22);
+    }
+}
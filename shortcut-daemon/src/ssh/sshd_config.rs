@@ -0,0 +1,416 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::files;
+
+/// Where the stock `sshd_config` includes drop-ins from.
+pub const SSHD_CONFIG_DIR: &str = "/etc/ssh/sshd_config.d";
+
+/// The sshd binary, run with `-T` to check the configuration and read what it uses.
+pub const SSHD: &str = "/usr/bin/sshd";
+
+/// sshd keeps the first value it reads for most keywords, so the drop-in sorts early. `Port` and
+/// `ListenAddress` add up across files instead, see [`SshdConfig::set_options`].
+const DROP_IN: &str = "10-shortcut.conf";
+
+/// Addresses sshd listens on without `ListenAddress`, reported as every address.
+const WILDCARDS: [&str; 2] = ["0.0.0.0", "[::]"];
+
+#[derive(Debug)]
+pub enum Error {
+    /// `sshd -T` rejected the configuration, with what it printed.
+    Rejected(String),
+    /// sshd couldn't be run to check the configuration.
+    Check(io::Error),
+    /// sshd doesn't use the options of the drop-in, e.g. because `sshd_config` doesn't include
+    /// it or sets the same ports or addresses itself.
+    NotApplied {
+        written: Options,
+        effective: Options,
+    },
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rejected(output) => write!(f, "sshd rejected the configuration: {output}"),
+            Error::Check(err) => write!(f, "unable to run {SSHD} -T: {err}"),
+            Error::NotApplied { written, effective } => write!(
+                f,
+                "sshd uses {effective:?} instead of {written:?}, \
+                 check that sshd_config includes sshd_config.d/*.conf"
+            ),
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Rejected(_) => shortcut_core::Error::InvalidArgument(err.to_string()),
+            Error::Check(_) | Error::NotApplied { .. } => {
+                shortcut_core::Error::Unavailable(err.to_string())
+            }
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// The sshd options the daemon manages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub password_authentication: bool,
+    pub port: u16,
+    /// Addresses sshd listens on, every address when empty.
+    ///
+    /// Read back as sshd reports them, with the port, e.g. `192.168.1.10:22` or `[::1]:2222`.
+    pub listen_addresses: Vec<String>,
+}
+
+impl Default for Options {
+    /// What sshd does without the drop-in.
+    fn default() -> Self {
+        Self {
+            password_authentication: true,
+            port: 22,
+            listen_addresses: Vec::new(),
+        }
+    }
+}
+
+/// `address` the way `sshd -T` prints it, with brackets around IPv6 addresses and the port.
+fn with_port(address: &str, port: u16) -> String {
+    match address.matches(':').count() {
+        _ if address.starts_with('[') && address.contains("]:") => address.to_string(),
+        _ if address.starts_with('[') => format!("{address}:{port}"),
+        0 => format!("{address}:{port}"),
+        1 => address.to_string(),
+        _ => format!("[{address}]:{port}"),
+    }
+}
+
+impl Options {
+    /// Reads the options from the output of `sshd -T`, which has every keyword sshd uses in
+    /// lower case. Listening on every address is reported as no address.
+    fn parse(content: &str) -> Self {
+        let mut options = Options::default();
+        let mut ports = Vec::new();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let (keyword, value) = match (fields.next(), fields.next()) {
+                (Some(keyword), Some(value)) => (keyword, value),
+                _ => continue,
+            };
+            match keyword {
+                "passwordauthentication" => options.password_authentication = value == "yes",
+                "port" => ports.extend(value.parse::<u16>().ok()),
+                "listenaddress" => options.listen_addresses.push(value.to_string()),
+                _ => {}
+            }
+        }
+        // Every further port shows up as an address, so the options no longer match
+        if let Some(port) = ports.first() {
+            options.port = *port;
+        }
+        for port in ports.iter().skip(1) {
+            options.listen_addresses.push(format!("*:{port}"));
+        }
+        options.listen_addresses.retain(|address| {
+            !WILDCARDS
+                .iter()
+                .any(|wildcard| *address == with_port(wildcard, options.port))
+        });
+        options
+    }
+
+    /// The options as `sshd -T` reports them once sshd uses them.
+    fn effective(&self) -> Self {
+        Self {
+            password_authentication: self.password_authentication,
+            port: self.port,
+            listen_addresses: self
+                .listen_addresses
+                .iter()
+                .map(|address| with_port(address, self.port))
+                .collect(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut content = format!(
+            "# Managed by shortcut, changes are overwritten\n\
+             PasswordAuthentication {}\n\
+             Port {}\n",
+            if self.password_authentication {
+                "yes"
+            } else {
+                "no"
+            },
+            self.port
+        );
+        for address in &self.listen_addresses {
+            content.push_str(&format!("ListenAddress {address}\n"));
+        }
+        content
+    }
+}
+
+impl From<Options> for shortcut_core::ssh::SshdOptions {
+    fn from(options: Options) -> Self {
+        Self {
+            password_authentication: options.password_authentication,
+            port: u32::from(options.port),
+            listen_addresses: options.listen_addresses,
+        }
+    }
+}
+
+/// The drop-in of the daemon in a `sshd_config.d` directory, [`SSHD_CONFIG_DIR`] outside of
+/// tests.
+#[derive(Debug, Clone)]
+pub struct SshdConfig {
+    dir: PathBuf,
+    sshd: PathBuf,
+}
+
+impl SshdConfig {
+    /// Changes are checked with `sshd -T`, running the `sshd` binary.
+    pub fn new(dir: impl Into<PathBuf>, sshd: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            sshd: sshd.into(),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(DROP_IN)
+    }
+
+    fn read(path: &Path) -> Result<Option<String>, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::Io {
+                path: path.to_path_buf(),
+                err,
+            }),
+        }
+    }
+
+    /// Replaces the drop-in so sshd never reads a half written one, removes it for `None`.
+    fn write(path: &Path, content: Option<&str>) -> Result<(), Error> {
+        let io_error = |err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        };
+        let content = match content {
+            Some(content) => content,
+            None => {
+                return match fs::remove_file(path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => Err(io_error(err)),
+                    _ => Ok(()),
+                }
+            }
+        };
+
+        files::replace(path, content).map_err(io_error)
+    }
+
+    /// The options sshd uses, which also checks the configuration.
+    fn effective(&self) -> Result<Options, Error> {
+        let output = Command::new(&self.sshd)
+            .arg("-T")
+            .output()
+            .map_err(Error::Check)?;
+        if output.status.success() {
+            return Ok(Options::parse(&String::from_utf8_lossy(&output.stdout)));
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(Error::Rejected(if stderr.is_empty() {
+            output.status.to_string()
+        } else {
+            stderr
+        }))
+    }
+
+    /// The options sshd uses, wherever they are set.
+    pub fn options(&self) -> Result<Options, Error> {
+        self.effective()
+    }
+
+    /// Writes the options and reads back what sshd makes of them with `sshd -T`, putting the
+    /// previous drop-in back when sshd rejects them or doesn't use them.
+    ///
+    /// sshd silently skips a drop-in `sshd_config` doesn't include, and listens on the ports and
+    /// addresses of every file, so only what it reports tells the options apply.
+    pub fn set_options(&self, options: &Options) -> Result<Options, Error> {
+        let path = self.path();
+        let previous = Self::read(&path)?;
+        Self::write(&path, Some(&options.render()))?;
+
+        let applied = self.effective().and_then(|effective| {
+            if effective != options.effective() {
+                return Err(Error::NotApplied {
+                    written: options.clone(),
+                    effective,
+                });
+            }
+            Ok(effective)
+        });
+        if applied.is_err() {
+            if let Err(restore) = Self::write(&path, previous.as_deref()) {
+                tracing::error!("unable to restore {}: {restore}", path.display());
+            }
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    fn options() -> Options {
+        Options {
+            password_authentication: false,
+            port: 2222,
+            listen_addresses: vec!["192.168.1.10".to_string(), "[::1]:2222".to_string()],
+        }
+    }
+
+    /// An sshd whose `-T` prints `sshd-T` next to the drop-in directory.
+    fn fake_sshd(fake: &FakeSysfs, effective: &str) -> PathBuf {
+        let sshd = fake.root().join("sshd");
+        fake.write("sshd-T", effective);
+        fs::write(
+            &sshd,
+            format!("#!/bin/sh\ncat {}\n", fake.root().join("sshd-T").display()),
+        )
+        .unwrap();
+        fs::set_permissions(&sshd, fs::Permissions::from_mode(0o755)).unwrap();
+        sshd
+    }
+
+    const APPLIED: &str = "\
+port 2222
+passwordauthentication no
+listenaddress 192.168.1.10:2222
+listenaddress [::1]:2222
+";
+
+    #[test]
+    fn reads_what_sshd_uses() {
+        let fake = FakeSysfs::new("sshd-config-default");
+        let sshd = fake_sshd(
+            &fake,
+            "port 22\npasswordauthentication yes\nlistenaddress [::]:22\nlistenaddress 0.0.0.0:22\n",
+        );
+        let config = SshdConfig::new(fake.root().join("sshd_config.d"), sshd);
+
+        assert_eq!(config.options().unwrap(), Options::default());
+    }
+
+    #[test]
+    fn round_trips_through_the_drop_in() {
+        let fake = FakeSysfs::new("sshd-config-write");
+        let sshd = fake_sshd(&fake, APPLIED);
+        let config = SshdConfig::new(fake.root().join("sshd_config.d"), sshd);
+
+        assert_eq!(
+            config.set_options(&options()).unwrap(),
+            Options {
+                password_authentication: false,
+                port: 2222,
+                listen_addresses: vec!["192.168.1.10:2222".to_string(), "[::1]:2222".to_string()],
+            }
+        );
+        assert_eq!(
+            fake.read("sshd_config.d/10-shortcut.conf"),
+            "# Managed by shortcut, changes are overwritten\n\
+             PasswordAuthentication no\n\
+             Port 2222\n\
+             ListenAddress 192.168.1.10\n\
+             ListenAddress [::1]:2222"
+        );
+    }
+
+    #[test]
+    fn adds_ports_to_addresses_like_sshd() {
+        assert_eq!(with_port("192.168.1.10", 22), "192.168.1.10:22");
+        assert_eq!(with_port("192.168.1.10:2222", 22), "192.168.1.10:2222");
+        assert_eq!(with_port("::1", 22), "[::1]:22");
+        assert_eq!(with_port("[::1]", 22), "[::1]:22");
+        assert_eq!(with_port("[::1]:2222", 22), "[::1]:2222");
+    }
+
+    #[test]
+    fn rolls_back_a_drop_in_sshd_ignores() {
+        let fake = FakeSysfs::new("sshd-config-ignored");
+        fake.write("sshd_config.d/10-shortcut.conf", "Port 2022");
+        for effective in [
+            // Not included at all
+            "port 22\npasswordauthentication yes\n",
+            // sshd_config has a Port of its own
+            "port 22\nport 2222\npasswordauthentication no\n\
+             listenaddress 192.168.1.10:2222\nlistenaddress [::1]:2222\n",
+        ] {
+            let sshd = fake_sshd(&fake, effective);
+            let config = SshdConfig::new(fake.root().join("sshd_config.d"), sshd);
+
+            let err = config.set_options(&options()).unwrap_err();
+            assert!(matches!(err, Error::NotApplied { .. }), "{err}");
+            assert!(matches!(
+                shortcut_core::Error::from(err),
+                shortcut_core::Error::Unavailable(_)
+            ));
+            assert_eq!(fake.read("sshd_config.d/10-shortcut.conf"), "Port 2022");
+        }
+    }
+
+    #[test]
+    fn rolls_back_what_sshd_rejects() {
+        let fake = FakeSysfs::new("sshd-config-rollback");
+        fake.write("10-shortcut.conf", "Port 2022");
+        let config = SshdConfig::new(fake.root(), "/bin/false");
+
+        let err = config.set_options(&options()).unwrap_err();
+        assert!(matches!(err, Error::Rejected(_)));
+        assert_eq!(fake.read("10-shortcut.conf"), "Port 2022");
+
+        // Without a previous drop-in there is nothing left behind
+        fs::remove_file(fake.root().join("10-shortcut.conf")).unwrap();
+        config.set_options(&options()).unwrap_err();
+        assert!(!fake.root().join("10-shortcut.conf").exists());
+    }
+
+    #[test]
+    fn missing_sshd_is_unavailable() {
+        let fake = FakeSysfs::new("sshd-config-missing");
+        let config = SshdConfig::new(fake.root(), fake.root().join("sshd"));
+
+        let err = config.set_options(&options()).unwrap_err();
+        assert!(matches!(err, Error::Check(_)));
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::Unavailable(_)
+        ));
+        assert!(!fake.root().join("10-shortcut.conf").exists());
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use shortcut_core::futures::StreamExt;
+
+use crate::backend::{SystemBackend, SystemEvent};
+use crate::config::{Config, SharedConfig};
+use crate::files;
+use crate::wifi::auto_power_save::Policy;
+
+/// The settings callers asked for last, as opposed to what the system currently reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Desired {
+    /// Whether sshd was kept running by starting it after every boot, which systemd does now.
+    /// Only read to migrate, see [`migrate_ssh`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_enabled: Option<bool>,
+    /// Charge limit of the battery in percent.
+    pub charge_limit: Option<u32>,
+    /// Power save per WiFi device.
+    pub power_save: BTreeMap<String, bool>,
+    pub auto_power_save: Policy,
+}
+
+impl Desired {
+    /// The power save to restore on `device`, `None` while the automatic policy switches it.
+    pub fn power_save_of(&self, device: &str) -> Option<bool> {
+        let policy = &self.auto_power_save;
+        if policy.enabled && policy.device == device {
+            return None;
+        }
+        self.power_save.get(device).copied()
+    }
+}
+
+/// [`Desired`] settings, written to disk on every change.
+#[derive(Debug)]
+pub struct StateFile {
+    /// `None` keeps the state in memory only.
+    path: Option<PathBuf>,
+    desired: Mutex<Desired>,
+}
+
+impl StateFile {
+    /// Loads the state at `path`, starting over if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        let desired = match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|err| {
+                tracing::warn!("Ignoring invalid state file {}: {err}", path.display());
+                Desired::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Desired::default(),
+            Err(err) => {
+                tracing::warn!("Unable to read state file {}: {err}", path.display());
+                Desired::default()
+            }
+        };
+
+        Self {
+            path: Some(path.to_path_buf()),
+            desired: Mutex::new(desired),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            desired: Mutex::new(Desired::default()),
+        }
+    }
+
+    pub fn desired(&self) -> Desired {
+        self.desired.lock().unwrap().clone()
+    }
+
+    pub fn set_power_save(&self, device: &str, enabled: bool) {
+        self.update(|desired| {
+            desired.power_save.insert(device.to_string(), enabled);
+        });
+    }
+
+    /// Leaves power save on `device` to NetworkManager again.
+    pub fn clear_power_save(&self, device: &str) {
+        self.update(|desired| {
+            desired.power_save.remove(device);
+        });
+    }
+
+    pub fn set_auto_power_save(&self, policy: Policy) {
+        self.update(|desired| desired.auto_power_save = policy);
+    }
+
+    pub fn set_charge_limit(&self, percent: u32) {
+        self.update(|desired| desired.charge_limit = Some(percent));
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Desired)) {
+        let mut desired = self.desired.lock().unwrap();
+        let previous = desired.clone();
+        f(&mut desired);
+
+        if let Some(path) = &self.path {
+            if *desired != previous {
+                if let Err(err) = write(path, &desired) {
+                    tracing::warn!("unable to save state to {}: {err}", path.display());
+                }
+            }
+        }
+    }
+}
+
+/// Replaces the file at `path` so a crash never leaves a half written state behind.
+fn write(path: &Path, desired: &Desired) -> io::Result<()> {
+    let content = toml::to_string(desired).map_err(io::Error::other)?;
+    files::replace(path, &content)
+}
+
+/// Enables sshd at boot if an earlier version kept it running, once.
+///
+/// The old setting is kept until the unit could be enabled, or while SSH isn't managed.
+async fn migrate_ssh(backend: &dyn SystemBackend, config: &Config, state: &StateFile) {
+    match state.desired().ssh_enabled {
+        Some(true) if config.ssh.enabled => {}
+        Some(false) => {
+            state.update(|desired| desired.ssh_enabled = None);
+            return;
+        }
+        _ => return,
+    }
+
+    let unit = &config.ssh.unit;
+    tracing::info!("Enabling {unit} at boot, it was kept running before");
+    let migrated = async {
+        let unit_state = backend.set_unit_enabled(unit, true).await?;
+        if !unit_state.is_active() {
+            backend.start_unit(unit).await?;
+        }
+        Ok::<_, shortcut_core::Error>(())
+    };
+    match migrated.await {
+        Ok(()) => state.update(|desired| desired.ssh_enabled = None),
+        Err(err) => tracing::warn!("unable to enable {unit} at boot: {err}"),
+    }
+}
+
+async fn restore_power_save(
+    backend: &dyn SystemBackend,
+    config: &Config,
+    device: &str,
+    enabled: bool,
+) {
+    if !config.wifi.enabled || !config.wifi.allows(device) {
+        return;
+    }
+
+    match backend.power_save(device).await {
+        Ok(current) if current == enabled => {}
+        Ok(_) => {
+            tracing::info!("Restoring power save on {device} to {enabled}");
+            if let Err(err) = backend.set_power_save(device, enabled).await {
+                tracing::warn!("unable to restore power save on {device}: {err}");
+            }
+        }
+        Err(err) => tracing::warn!("unable to read power save on {device}: {err}"),
+    }
+}
+
+async fn restore_charge_limit(backend: &dyn SystemBackend, config: &Config, percent: u32) {
+    if !config.battery.enabled {
+        return;
+    }
+
+    match backend.battery().await {
+        Ok(battery) if battery.charge_limit.is_none() || battery.charge_limit == Some(percent) => {}
+        Ok(_) => {
+            tracing::info!("Restoring charge limit to {percent} %");
+            if let Err(err) = backend.set_charge_limit(percent).await {
+                tracing::warn!("unable to restore charge limit: {err}");
+            }
+        }
+        Err(err) => tracing::warn!("unable to read the battery: {err}"),
+    }
+}
+
+/// Re-applies every desired setting that the system no longer reports.
+///
+/// Devices that don't exist right now are skipped, they are restored once they show up.
+pub(crate) async fn restore_all(backend: &dyn SystemBackend, config: &Config, desired: &Desired) {
+    if !desired.power_save.is_empty() && config.wifi.enabled {
+        match backend.wifi_interfaces().await {
+            Ok(interfaces) => {
+                for iface in interfaces {
+                    if let Some(enabled) = desired.power_save_of(&iface.name) {
+                        restore_power_save(backend, config, &iface.name, enabled).await;
+                    }
+                }
+            }
+            Err(err) => tracing::warn!("unable to list wifi devices: {err}"),
+        }
+    }
+    if let Some(percent) = desired.charge_limit {
+        restore_charge_limit(backend, config, percent).await;
+    }
+}
+
+/// Restores the desired settings at startup, after resume and when an interface re-appears.
+pub async fn restore(backend: Arc<dyn SystemBackend>, config: SharedConfig, state: Arc<StateFile>) {
+    // Subscribe first so an interface showing up during the initial restore isn't missed
+    let events = backend.events().await;
+
+    let current = config.borrow().clone();
+    migrate_ssh(backend.as_ref(), &current, &state).await;
+    restore_all(backend.as_ref(), &current, &state.desired()).await;
+
+    let mut events = match events {
+        Ok(events) => events,
+        Err(err) => {
+            tracing::warn!(
+                "unable to watch for resume, settings are only restored at startup: {err}"
+            );
+            return;
+        }
+    };
+
+    while let Some(event) = events.next().await {
+        tracing::debug!("{:?}", event);
+        let current = config.borrow().clone();
+        let desired = state.desired();
+        match event {
+            SystemEvent::Resumed => {
+                restore_all(backend.as_ref(), &current, &desired).await;
+            }
+            SystemEvent::InterfaceAdded(device) => {
+                if let Some(enabled) = desired.power_save_of(&device) {
+                    restore_power_save(backend.as_ref(), &current, &device, enabled).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use shortcut_core::tokio;
+
+    use super::*;
+    use crate::backend::fake::{battery_state, interface, unit_state, Call, FakeBackend, Method};
+    use crate::config;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shortcutd-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("state.toml")
+    }
+
+    /// Waits for the restore task to make `call`.
+    async fn wait_for_call(backend: &FakeBackend, call: Call) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !backend.calls().contains(&call) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{call:?} never happened, got {:?}", backend.calls()));
+    }
+
+    #[test]
+    fn state_survives_a_restart() {
+        let path = temp_path("restart");
+        let state = StateFile::load(&path);
+        state.set_power_save("wlan0", false);
+        state.set_charge_limit(80);
+
+        let desired = StateFile::load(&path).desired();
+
+        assert_eq!(desired.power_save.get("wlan0"), Some(&false));
+        assert_eq!(desired.charge_limit, Some(80));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrates_a_running_sshd_to_enabled_at_boot() {
+        let path = temp_path("old");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // SSH is left to systemd since it can be enabled at boot
+        fs::write(&path, "ssh_enabled = true\n[power_save]\nwlan0 = false\n").unwrap();
+        let backend =
+            FakeBackend::default().with_unit("sshd.service", unit_state("inactive", "dead"));
+        let state = StateFile::load(&path);
+
+        backend.fail(
+            Method::SetUnitEnabled,
+            shortcut_core::Error::Internal("busy".to_string()),
+        );
+        migrate_ssh(&backend, &Config::default(), &state).await;
+        assert_eq!(StateFile::load(&path).desired().ssh_enabled, Some(true));
+
+        migrate_ssh(&backend, &Config::default(), &state).await;
+        migrate_ssh(&backend, &Config::default(), &state).await;
+
+        assert_eq!(
+            backend.calls(),
+            [
+                Call::SetUnitEnabled("sshd.service".to_string(), true),
+                Call::SetUnitEnabled("sshd.service".to_string(), true),
+                Call::StartUnit("sshd.service".to_string()),
+            ]
+        );
+        let desired = StateFile::load(&path).desired();
+        assert_eq!(desired.ssh_enabled, None);
+        assert_eq!(desired.power_save.get("wlan0"), Some(&false));
+        assert!(!fs::read_to_string(&path).unwrap().contains("ssh_enabled"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn invalid_state_file_starts_over() {
+        let path = temp_path("invalid");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "power_save = 3").unwrap();
+
+        assert_eq!(StateFile::load(&path).desired(), Desired::default());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restores_changed_settings_at_startup() {
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_interface(interface("wlan1", 4), false),
+        );
+        let state = Arc::new(StateFile::in_memory());
+        state.set_power_save("wlan0", false);
+        state.set_power_save("wlan1", false);
+        state.set_power_save("wlan2", false);
+
+        restore_all(backend.as_ref(), &Config::default(), &state.desired()).await;
+
+        let calls = backend.calls();
+        assert!(calls.contains(&Call::SetPowerSave("wlan0".to_string(), false)));
+        assert!(!calls.contains(&Call::SetPowerSave("wlan1".to_string(), false)));
+        assert!(!calls.contains(&Call::PowerSave("wlan2".to_string())));
+    }
+
+    #[tokio::test]
+    async fn leaves_devices_to_the_automatic_policy() {
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_interface(interface("wlan1", 4), true),
+        );
+        let state = Arc::new(StateFile::in_memory());
+        state.set_power_save("wlan0", false);
+        state.set_power_save("wlan1", false);
+        state.set_auto_power_save(Policy {
+            enabled: true,
+            device: "wlan0".to_string(),
+            ..Policy::default()
+        });
+
+        restore_all(backend.as_ref(), &Config::default(), &state.desired()).await;
+
+        let calls = backend.calls();
+        assert!(!calls.contains(&Call::PowerSave("wlan0".to_string())));
+        assert!(calls.contains(&Call::SetPowerSave("wlan1".to_string(), false)));
+    }
+
+    #[tokio::test]
+    async fn restores_the_charge_limit() {
+        let mut battery = battery_state();
+        battery.charge_limit = Some(100);
+        let backend = Arc::new(FakeBackend::default().with_battery(battery));
+        let state = Arc::new(StateFile::in_memory());
+        state.set_charge_limit(80);
+
+        restore_all(backend.as_ref(), &Config::default(), &state.desired()).await;
+        restore_all(backend.as_ref(), &Config::default(), &state.desired()).await;
+
+        assert_eq!(
+            backend.calls(),
+            [Call::Battery, Call::SetChargeLimit(80), Call::Battery]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_batteries_without_a_charge_limit() {
+        let mut battery = battery_state();
+        battery.charge_limit = None;
+        let backend = Arc::new(FakeBackend::default().with_battery(battery));
+        let mut config = Config::default();
+        let state = Arc::new(StateFile::in_memory());
+        state.set_charge_limit(80);
+
+        restore_all(backend.as_ref(), &config, &state.desired()).await;
+        config.battery.enabled = false;
+        restore_all(backend.as_ref(), &config, &state.desired()).await;
+
+        assert_eq!(backend.calls(), [Call::Battery]);
+    }
+
+    #[tokio::test]
+    async fn skips_disabled_services_and_devices() {
+        let backend = Arc::new(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+        let mut config = Config::default();
+        config.wifi.interfaces = vec!["wlan1".to_string()];
+        let state = Arc::new(StateFile::in_memory());
+        state.set_power_save("wlan0", false);
+
+        restore_all(backend.as_ref(), &config, &state.desired()).await;
+
+        assert_eq!(backend.calls(), vec![Call::WifiInterfaces]);
+    }
+
+    #[tokio::test]
+    async fn restores_after_resume_and_new_interfaces() {
+        let backend = Arc::new(FakeBackend::default().with_interface(interface("wlan0", 3), false));
+        let state = Arc::new(StateFile::in_memory());
+        state.set_power_save("wlan0", false);
+        tokio::spawn(restore(
+            backend.clone(),
+            config::fixed(Config::default()),
+            state,
+        ));
+        wait_for_call(&backend, Call::PowerSave("wlan0".to_string())).await;
+
+        backend.reset_power_save("wlan0", true);
+        backend.emit(SystemEvent::Resumed);
+        wait_for_call(&backend, Call::SetPowerSave("wlan0".to_string(), false)).await;
+
+        backend.reset_power_save("wlan0", true);
+        let calls = backend.calls().len();
+        backend.emit(SystemEvent::InterfaceAdded("wlan0".to_string()));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while backend.calls().len() < calls + 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            backend.calls()[calls..],
+            [
+                Call::PowerSave("wlan0".to_string()),
+                Call::SetPowerSave("wlan0".to_string(), false)
+            ]
+        );
+    }
+}
@@ -0,0 +1,608 @@
+use std::fmt;
+use std::time::Duration;
+
+use shortcut_core::futures::StreamExt;
+use shortcut_core::tokio;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+use zbus::{dbus_proxy, CacheProperties, Connection};
+
+/// How long to wait for systemd to finish a start/stop job.
+const JOB_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The kind, file and destination of every symlink systemd changed for a unit file.
+type UnitFileChanges = Vec<(String, String, String)>;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait Manager {
+    fn subscribe(&self) -> zbus::Result<()>;
+
+    fn load_unit(&self, name: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn start_unit(&self, name: &str, mode: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn stop_unit(&self, name: &str, mode: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn reload_or_try_restart_unit(&self, name: &str, mode: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn enable_unit_files(
+        &self,
+        files: &[&str],
+        runtime: bool,
+        force: bool,
+    ) -> zbus::Result<(bool, UnitFileChanges)>;
+
+    fn disable_unit_files(&self, files: &[&str], runtime: bool) -> zbus::Result<UnitFileChanges>;
+
+    fn reload(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn job_removed(
+        &self,
+        id: u32,
+        job: ObjectPath<'_>,
+        unit: &str,
+        result: &str,
+    ) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Unit",
+    default_service = "org.freedesktop.systemd1"
+)]
+trait Unit {
+    #[dbus_proxy(property)]
+    fn load_state(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn active_state(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn sub_state(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn unit_file_state(&self) -> zbus::Result<String>;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Bus(zbus::Error),
+    NoSuchUnit(String),
+    /// The job finished with a result other than `done`.
+    JobFailed {
+        unit: String,
+        result: String,
+    },
+    /// The job did not finish within [`JOB_TIMEOUT`].
+    Timeout {
+        unit: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bus(err) => write!(f, "D-Bus error: {err}"),
+            Error::NoSuchUnit(unit) => write!(f, "unit {unit} not found"),
+            Error::JobFailed { unit, result } => write!(f, "job for {unit} {result}"),
+            Error::Timeout { unit } => write!(f, "timed out waiting for job for {unit}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<zbus::Error> for Error {
+    fn from(err: zbus::Error) -> Self {
+        Error::Bus(err)
+    }
+}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoSuchUnit(unit) => shortcut_core::Error::not_found(format!("unit {unit}")),
+            Error::Bus(zbus::Error::MethodError(ref name, _, _))
+                if matches!(
+                    name.as_str(),
+                    "org.freedesktop.DBus.Error.AccessDenied"
+                        | "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired"
+                ) =>
+            {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            Error::Bus(_) => shortcut_core::Error::Unavailable(err.to_string()),
+            Error::JobFailed { .. } | Error::Timeout { .. } => {
+                shortcut_core::Error::Internal(err.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitState {
+    pub active_state: String,
+    pub sub_state: String,
+    pub unit_file_state: String,
+}
+
+impl UnitState {
+    /// Whether the unit is up, including while it reloads its configuration.
+    pub fn is_active(&self) -> bool {
+        matches!(self.active_state.as_str(), "active" | "reloading")
+    }
+
+    /// Whether the unit starts at boot.
+    pub fn is_enabled(&self) -> bool {
+        matches!(self.unit_file_state.as_str(), "enabled" | "enabled-runtime")
+    }
+}
+
+impl From<UnitState> for shortcut_core::ssh::UnitState {
+    fn from(state: UnitState) -> Self {
+        Self {
+            active_state: state.active_state,
+            sub_state: state.sub_state,
+            unit_file_state: state.unit_file_state,
+        }
+    }
+}
+
+/// The jobs the daemon queues for units.
+#[derive(Debug, Clone, Copy)]
+enum Job {
+    Start,
+    Stop,
+    /// Reloads a running unit, restarting it when it can't reload, and leaves it alone otherwise.
+    Reload,
+}
+
+/// Client for `org.freedesktop.systemd1` on a D-Bus connection.
+#[derive(Debug, Clone)]
+pub struct Systemd {
+    conn: Connection,
+}
+
+impl Systemd {
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    pub async fn unit_state(&self, unit: &str) -> Result<UnitState, Error> {
+        let manager = ManagerProxy::new(&self.conn).await?;
+        let path = manager.load_unit(unit).await.map_err(|err| match err {
+            zbus::Error::MethodError(ref name, _, _)
+                if name.as_str() == "org.freedesktop.systemd1.NoSuchUnit" =>
+            {
+                Error::NoSuchUnit(unit.to_string())
+            }
+            err => Error::Bus(err),
+        })?;
+
+        let proxy = UnitProxy::builder(&self.conn)
+            .path(path)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+
+        if proxy.load_state().await? == "not-found" {
+            return Err(Error::NoSuchUnit(unit.to_string()));
+        }
+
+        Ok(UnitState {
+            active_state: proxy.active_state().await?,
+            sub_state: proxy.sub_state().await?,
+            unit_file_state: proxy.unit_file_state().await?,
+        })
+    }
+
+    /// Starts `unit` and waits for the job to finish before returning the resulting state.
+    pub async fn start_unit(&self, unit: &str) -> Result<UnitState, Error> {
+        self.run_job(unit, Job::Start).await?;
+        self.unit_state(unit).await
+    }
+
+    /// Stops `unit` and waits for the job to finish before returning the resulting state.
+    pub async fn stop_unit(&self, unit: &str) -> Result<UnitState, Error> {
+        self.run_job(unit, Job::Stop).await?;
+        self.unit_state(unit).await
+    }
+
+    /// Makes a running `unit` pick up configuration changes and waits for the job to finish
+    /// before returning the resulting state, a stopped unit stays stopped.
+    pub async fn reload_unit(&self, unit: &str) -> Result<UnitState, Error> {
+        self.run_job(unit, Job::Reload).await?;
+        self.unit_state(unit).await
+    }
+
+    /// Enables or disables `unit` at boot like `systemctl enable/disable` and returns the
+    /// resulting state, whether it runs right now is left alone.
+    pub async fn set_unit_enabled(&self, unit: &str, enabled: bool) -> Result<UnitState, Error> {
+        // Fails early for unknown units, which systemd reports as a generic file error
+        self.unit_state(unit).await?;
+
+        let manager = ManagerProxy::new(&self.conn).await?;
+        let changes = if enabled {
+            manager.enable_unit_files(&[unit], false, false).await?.1
+        } else {
+            manager.disable_unit_files(&[unit], false).await?
+        };
+        for (change, file, target) in &changes {
+            tracing::debug!("{change} {file} -> {target}");
+        }
+        manager.reload().await?;
+
+        self.unit_state(unit).await
+    }
+
+    async fn run_job(&self, unit: &str, job: Job) -> Result<(), Error> {
+        let manager = ManagerProxy::new(&self.conn).await?;
+        manager.subscribe().await?;
+
+        // Listen before queueing the job so the JobRemoved signal can't be missed
+        let mut removed = manager.receive_job_removed().await?;
+
+        let job = match job {
+            Job::Start => manager.start_unit(unit, "replace").await?,
+            Job::Stop => manager.stop_unit(unit, "replace").await?,
+            Job::Reload => manager.reload_or_try_restart_unit(unit, "replace").await?,
+        };
+        tracing::debug!("Queued job {} for {unit}", job.as_str());
+
+        let result = tokio::time::timeout(JOB_TIMEOUT, async {
+            while let Some(signal) = removed.next().await {
+                let args = signal.args()?;
+                if args.job().as_str() == job.as_str() {
+                    return Ok(args.result().to_string());
+                }
+            }
+            Err(zbus::Error::InputOutput(
+                std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into(),
+            ))
+        })
+        .await
+        .map_err(|_| Error::Timeout {
+            unit: unit.to_string(),
+        })??;
+
+        if result == "done" {
+            Ok(())
+        } else {
+            Err(Error::JobFailed {
+                unit: unit.to_string(),
+                result,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use zbus::{dbus_interface, fdo, ConnectionBuilder, DBusError, SignalContext};
+
+    use super::*;
+    use crate::test_bus::{self, require_bus, Bus};
+
+    type Units = Arc<Mutex<HashMap<String, UnitState>>>;
+
+    fn unit_path(name: &str) -> String {
+        format!(
+            "/org/freedesktop/systemd1/unit/{}",
+            name.replace('.', "_2e").replace('-', "_2d")
+        )
+    }
+
+    #[derive(Debug, DBusError)]
+    #[dbus_error(prefix = "org.freedesktop.systemd1")]
+    enum StubError {
+        #[dbus_error(zbus_error)]
+        ZBus(zbus::Error),
+        NoSuchUnit(String),
+    }
+
+    struct StubManager {
+        units: Units,
+        job_result: String,
+        next_job: u32,
+    }
+
+    impl StubManager {
+        async fn queue(
+            &mut self,
+            ctxt: &SignalContext<'_>,
+            name: &str,
+            active: Option<bool>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            {
+                let mut units = self.units.lock().unwrap();
+                let state = units
+                    .get_mut(name)
+                    .ok_or_else(|| fdo::Error::Failed(format!("Unit {name} not found.")))?;
+                if let (Some(active), "done") = (active, self.job_result.as_str()) {
+                    state.active_state = if active { "active" } else { "inactive" }.to_string();
+                    state.sub_state = if active { "running" } else { "dead" }.to_string();
+                }
+            }
+
+            self.next_job += 1;
+            let job =
+                ObjectPath::try_from(format!("/org/freedesktop/systemd1/job/{}", self.next_job))
+                    .unwrap();
+            Self::job_removed(ctxt, self.next_job, job.clone(), name, &self.job_result).await?;
+
+            Ok(job.into())
+        }
+
+        fn set_unit_file_state(&self, files: &[String], unit_file_state: &str) -> UnitFileChanges {
+            let mut units = self.units.lock().unwrap();
+            files
+                .iter()
+                .filter_map(|file| {
+                    let state = units.get_mut(file)?;
+                    state.unit_file_state = unit_file_state.to_string();
+                    Some((
+                        "symlink".to_string(),
+                        format!("/etc/systemd/system/multi-user.target.wants/{file}"),
+                        format!("/usr/lib/systemd/system/{file}"),
+                    ))
+                })
+                .collect()
+        }
+    }
+
+    #[dbus_interface(name = "org.freedesktop.systemd1.Manager")]
+    impl StubManager {
+        fn subscribe(&self) {}
+
+        fn load_unit(&self, name: &str) -> Result<OwnedObjectPath, StubError> {
+            if !self.units.lock().unwrap().contains_key(name) {
+                return Err(StubError::NoSuchUnit(format!("Unit {name} not found.")));
+            }
+            Ok(ObjectPath::try_from(unit_path(name)).unwrap().into())
+        }
+
+        async fn start_unit(
+            &mut self,
+            name: &str,
+            _mode: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            self.queue(&ctxt, name, Some(true)).await
+        }
+
+        async fn stop_unit(
+            &mut self,
+            name: &str,
+            _mode: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            self.queue(&ctxt, name, Some(false)).await
+        }
+
+        async fn reload_or_try_restart_unit(
+            &mut self,
+            name: &str,
+            _mode: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            self.queue(&ctxt, name, None).await
+        }
+
+        fn enable_unit_files(
+            &self,
+            files: Vec<String>,
+            _runtime: bool,
+            _force: bool,
+        ) -> (bool, UnitFileChanges) {
+            (true, self.set_unit_file_state(&files, "enabled"))
+        }
+
+        fn disable_unit_files(&self, files: Vec<String>, _runtime: bool) -> UnitFileChanges {
+            self.set_unit_file_state(&files, "disabled")
+        }
+
+        fn reload(&self) {}
+
+        #[dbus_interface(signal)]
+        async fn job_removed(
+            ctxt: &SignalContext<'_>,
+            id: u32,
+            job: ObjectPath<'_>,
+            unit: &str,
+            result: &str,
+        ) -> zbus::Result<()>;
+    }
+
+    struct StubUnit {
+        name: String,
+        units: Units,
+    }
+
+    impl StubUnit {
+        fn state(&self) -> UnitState {
+            self.units.lock().unwrap()[&self.name].clone()
+        }
+    }
+
+    #[dbus_interface(name = "org.freedesktop.systemd1.Unit")]
+    impl StubUnit {
+        #[dbus_interface(property)]
+        fn load_state(&self) -> String {
+            "loaded".to_string()
+        }
+
+        #[dbus_interface(property)]
+        fn active_state(&self) -> String {
+            self.state().active_state
+        }
+
+        #[dbus_interface(property)]
+        fn sub_state(&self) -> String {
+            self.state().sub_state
+        }
+
+        #[dbus_interface(property)]
+        fn unit_file_state(&self) -> String {
+            self.state().unit_file_state
+        }
+    }
+
+    /// Serves a stub systemd with a single inactive `sshd.service` on a private bus.
+    async fn stub_systemd(bus: &Bus, job_result: &str) -> (Connection, Systemd) {
+        let units: Units = Arc::new(Mutex::new(HashMap::from([(
+            "sshd.service".to_string(),
+            UnitState {
+                active_state: "inactive".to_string(),
+                sub_state: "dead".to_string(),
+                unit_file_state: "disabled".to_string(),
+            },
+        )])));
+
+        let server = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.freedesktop.systemd1")
+            .unwrap()
+            .serve_at(
+                "/org/freedesktop/systemd1",
+                StubManager {
+                    units: units.clone(),
+                    job_result: job_result.to_string(),
+                    next_job: 0,
+                },
+            )
+            .unwrap()
+            .serve_at(
+                unit_path("sshd.service"),
+                StubUnit {
+                    name: "sshd.service".to_string(),
+                    units,
+                },
+            )
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        (server, Systemd::new(test_bus::connect(bus).await))
+    }
+
+    #[test]
+    fn reloading_units_are_active() {
+        for (active_state, active) in [
+            ("active", true),
+            ("reloading", true),
+            ("activating", false),
+            ("deactivating", false),
+            ("inactive", false),
+            ("failed", false),
+            // Unknown or garbled states never count as running
+            ("", false),
+            ("Active", false),
+            ("active ", false),
+        ] {
+            let state = UnitState {
+                active_state: active_state.to_string(),
+                sub_state: String::new(),
+                unit_file_state: String::new(),
+            };
+            assert_eq!(state.is_active(), active, "{active_state:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_unit_state() {
+        let bus = require_bus!();
+        let (_server, systemd) = stub_systemd(&bus, "done").await;
+
+        let state = systemd.unit_state("sshd.service").await.unwrap();
+        assert_eq!(state.active_state, "inactive");
+        assert_eq!(state.sub_state, "dead");
+        assert_eq!(state.unit_file_state, "disabled");
+        assert!(!state.is_active());
+    }
+
+    #[tokio::test]
+    async fn start_and_stop_wait_for_job() {
+        let bus = require_bus!();
+        let (_server, systemd) = stub_systemd(&bus, "done").await;
+
+        let state = systemd.start_unit("sshd.service").await.unwrap();
+        assert!(state.is_active());
+        assert_eq!(state.sub_state, "running");
+
+        let state = systemd.stop_unit("sshd.service").await.unwrap();
+        assert!(!state.is_active());
+        assert_eq!(state.sub_state, "dead");
+    }
+
+    #[tokio::test]
+    async fn enables_at_boot_without_starting() {
+        let bus = require_bus!();
+        let (_server, systemd) = stub_systemd(&bus, "done").await;
+
+        let state = systemd
+            .set_unit_enabled("sshd.service", true)
+            .await
+            .unwrap();
+        assert!(state.is_enabled());
+        assert!(!state.is_active());
+
+        let state = systemd
+            .set_unit_enabled("sshd.service", false)
+            .await
+            .unwrap();
+        assert!(!state.is_enabled());
+
+        let err = systemd
+            .set_unit_enabled("nope.service", true)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NoSuchUnit(_)));
+    }
+
+    #[tokio::test]
+    async fn reload_leaves_stopped_units_alone() {
+        let bus = require_bus!();
+        let (_server, systemd) = stub_systemd(&bus, "done").await;
+
+        let state = systemd.reload_unit("sshd.service").await.unwrap();
+        assert!(!state.is_active());
+
+        systemd.start_unit("sshd.service").await.unwrap();
+        let state = systemd.reload_unit("sshd.service").await.unwrap();
+        assert!(state.is_active());
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_an_error() {
+        let bus = require_bus!();
+        let (_server, systemd) = stub_systemd(&bus, "failed").await;
+
+        match systemd.start_unit("sshd.service").await {
+            Err(Error::JobFailed { unit, result }) => {
+                assert_eq!(unit, "sshd.service");
+                assert_eq!(result, "failed");
+            }
+            other => panic!("expected a failed job, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_unit_is_an_error() {
+        let bus = require_bus!();
+        let (_server, systemd) = stub_systemd(&bus, "done").await;
+
+        let err = systemd.unit_state("nope.service").await.unwrap_err();
+        assert!(matches!(err, Error::NoSuchUnit(ref unit) if unit == "nope.service"));
+        assert_eq!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::not_found("unit nope.service")
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Private D-Bus daemons for testing the D-Bus clients against stub services.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+use zbus::{Connection, ConnectionBuilder};
+
+/// Set in CI so a missing `dbus-daemon` fails the D-Bus tests instead of skipping them.
+pub const REQUIRE_DBUS_ENV: &str = "SHORTCUT_REQUIRE_DBUS";
+
+/// A private `dbus-daemon` that is killed when dropped.
+pub struct Bus {
+    child: Child,
+    pub address: String,
+}
+
+impl Bus {
+    pub fn spawn() -> Option<Self> {
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        BufReader::new(child.stdout.as_mut()?)
+            .read_line(&mut address)
+            .ok()?;
+
+        Some(Self {
+            child,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Connects a client to `bus`.
+pub async fn connect(bus: &Bus) -> Connection {
+    ConnectionBuilder::address(bus.address.as_str())
+        .unwrap()
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Spawns a [`Bus`], or skips the test when `dbus-daemon` is not installed and
+/// [`REQUIRE_DBUS_ENV`] is not set.
+macro_rules! require_bus {
+    () => {
+        match $crate::test_bus::Bus::spawn() {
+            Some(bus) => bus,
+            None if std::env::var_os($crate::test_bus::REQUIRE_DBUS_ENV).is_some() => {
+                panic!(
+                    "dbus-daemon not available and {} is set",
+                    $crate::test_bus::REQUIRE_DBUS_ENV
+                );
+            }
+            None => {
+                eprintln!("dbus-daemon not available, skipping");
+                return;
+            }
+        }
+    };
+}
+pub(crate) use require_bus;
@@ -0,0 +1,42 @@
+//! Throwaway directory trees standing in for `/sys` in tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A temporary directory that is removed when dropped.
+pub struct FakeSysfs {
+    root: PathBuf,
+}
+
+impl FakeSysfs {
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("shortcutd-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Writes `content` to the file at `path` relative to the root, creating its directories.
+    pub fn write(&self, path: &str, content: &str) {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("{content}\n")).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.root.join(path))
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+}
+
+impl Drop for FakeSysfs {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.root).ok();
+    }
+}
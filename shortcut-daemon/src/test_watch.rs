@@ -0,0 +1,18 @@
+//! Waiting on the status channels of background tasks in tests.
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::watch;
+
+/// Waits until the value of `rx` is `wanted`, failing the test after five seconds.
+pub async fn wait_for<T: Debug>(rx: &mut watch::Receiver<T>, wanted: impl Fn(&T) -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !wanted(&rx.borrow()) {
+            rx.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("stuck at {:?}", rx.borrow()));
+}
@@ -0,0 +1,615 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use shortcut_core::tokio;
+use shortcut_core::tokio::sync::watch;
+use shortcut_core::tokio::time::Instant;
+use shortcut_core::wifi;
+use shortcut_core::Error;
+
+use crate::backend::SystemBackend;
+use crate::battery::power_supply::{self, Battery};
+use crate::config::SharedConfig;
+
+/// How often the gateway is pinged and the policy applied.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// A ping without a reply by then is lost, and counts as this much latency for the jitter.
+const PING_TIMEOUT: Duration = Duration::from_millis(500);
+/// Pings the latency and jitter are taken over.
+const WINDOW: usize = 10;
+/// Less traffic than this, e.g. background chatter, leaves the link idle.
+const IDLE_THROUGHPUT_KBPS: u32 = 64;
+const MAX_JITTER_THRESHOLD_MS: u32 = 1000;
+const MAX_IDLE_SECONDS: u32 = 3600;
+
+/// When the daemon may switch power save on a device by itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub enabled: bool,
+    pub device: String,
+    pub jitter_threshold_ms: u32,
+    /// 0 never turns power save back on for an idle link.
+    pub idle_seconds: u32,
+    /// 0 ignores the battery.
+    pub battery_percent: u32,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: String::new(),
+            jitter_threshold_ms: 20,
+            idle_seconds: 60,
+            battery_percent: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Jitter,
+    Idle,
+    LowBattery,
+}
+
+/// A switch of power save the daemon made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Counts up from 1 so watchers can tell a new change from one they have seen.
+    pub id: u64,
+    pub power_save: bool,
+    pub reason: Reason,
+    pub jitter_ms: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    /// Whether a policy is being followed.
+    pub active: bool,
+    pub latency_ms: f64,
+    pub jitter_ms: f64,
+    pub lost: u32,
+    pub throughput_kbps: u32,
+    /// Why the last measurement failed, the policy waits for the next one.
+    pub error: Option<String>,
+    pub last_change: Option<Change>,
+}
+
+/// Round trips to the gateway over the last [`WINDOW`] pings, `None` for lost ones.
+#[derive(Debug, Default)]
+struct Pings {
+    samples: VecDeque<Option<Duration>>,
+}
+
+impl Pings {
+    fn push(&mut self, sample: Option<Duration>) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    fn lost(&self) -> u32 {
+        self.samples
+            .iter()
+            .filter(|sample| sample.is_none())
+            .count() as u32
+    }
+
+    /// The mean round trip of the answered pings in milliseconds.
+    fn latency_ms(&self) -> f64 {
+        let answered: Vec<f64> = self.samples.iter().flatten().map(millis).collect();
+        if answered.is_empty() {
+            return 0.0;
+        }
+        answered.iter().sum::<f64>() / answered.len() as f64
+    }
+
+    /// The mean difference between consecutive round trips in milliseconds, like RFC 3550
+    /// without the smoothing.
+    fn jitter_ms(&self) -> f64 {
+        let rtts: Vec<f64> = self
+            .samples
+            .iter()
+            .map(|sample| millis(&sample.unwrap_or(PING_TIMEOUT)))
+            .collect();
+        if rtts.len() < 2 {
+            return 0.0;
+        }
+        let total: f64 = rtts.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum();
+        total / (rtts.len() - 1) as f64
+    }
+}
+
+fn millis(duration: &Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// What power save should be, `None` leaves it as it is.
+///
+/// The jitter only counts while the link is `busy`, power save itself makes the round trips of
+/// an idle link jittery and would otherwise turn itself off.
+fn decide(
+    policy: &Policy,
+    jitter_ms: f64,
+    busy: bool,
+    idle_for: Duration,
+    battery: Option<&Battery>,
+) -> Option<(bool, Reason)> {
+    let low_battery = matches!(battery, Some(battery)
+        if battery.status == power_supply::Status::Discharging
+            && battery.capacity < policy.battery_percent);
+    if low_battery {
+        Some((true, Reason::LowBattery))
+    } else if busy && jitter_ms > f64::from(policy.jitter_threshold_ms) {
+        Some((false, Reason::Jitter))
+    } else if policy.idle_seconds > 0 && idle_for >= Duration::from_secs(policy.idle_seconds.into())
+    {
+        Some((true, Reason::Idle))
+    } else {
+        None
+    }
+}
+
+struct Controller {
+    backend: Arc<dyn SystemBackend>,
+    config: SharedConfig,
+    pings: Pings,
+    /// The bytes the link carried so far and when they were read, for the throughput.
+    traffic: Option<(u64, Instant)>,
+    /// When the link last carried more than idle traffic.
+    last_busy: Instant,
+    /// What the policy asked for last. Power save is only touched when that changes so a
+    /// SetPowerSave in between sticks until the next change.
+    decided: Option<bool>,
+    last_change: Option<Change>,
+}
+
+impl Controller {
+    /// Starts measuring over, e.g. for a new policy or after the device reconnected.
+    fn reset(&mut self) {
+        self.pings.clear();
+        self.traffic = None;
+        self.last_busy = Instant::now();
+        self.decided = None;
+    }
+
+    /// Measures the link and applies the policy to the result.
+    async fn step(&mut self, policy: &Policy) -> Result<Status, Error> {
+        let device = &policy.device;
+        let link = self
+            .backend
+            .wifi_link(device)
+            .await?
+            .ok_or_else(|| Error::Unavailable(format!("{device} is not connected")))?;
+        let gateway = self
+            .backend
+            .gateway(device)
+            .await?
+            .ok_or_else(|| Error::Unavailable(format!("no default route through {device}")))?;
+        self.pings
+            .push(self.backend.ping(gateway, PING_TIMEOUT).await?);
+
+        let now = Instant::now();
+        let bytes = link.rx_bytes + link.tx_bytes;
+        let throughput_kbps = match self.traffic.replace((bytes, now)) {
+            // The counters start over when the device reconnects
+            Some((before, at)) if bytes >= before => {
+                let seconds = now.duration_since(at).as_secs_f64().max(0.001);
+                ((bytes - before) as f64 * 8.0 / 1000.0 / seconds) as u32
+            }
+            _ => 0,
+        };
+        let busy = throughput_kbps >= IDLE_THROUGHPUT_KBPS;
+        if busy {
+            self.last_busy = now;
+        }
+
+        // Without a battery it never runs low
+        let battery = self.backend.battery().await.ok();
+        let jitter_ms = self.pings.jitter_ms();
+        let decision = decide(
+            policy,
+            jitter_ms,
+            busy,
+            now.duration_since(self.last_busy),
+            battery.as_ref(),
+        );
+        if let Some((power_save, reason)) = decision {
+            if self.decided != Some(power_save) {
+                self.apply(device, power_save, reason, jitter_ms).await?;
+                self.decided = Some(power_save);
+            }
+        }
+
+        Ok(Status {
+            active: true,
+            latency_ms: self.pings.latency_ms(),
+            jitter_ms,
+            lost: self.pings.lost(),
+            throughput_kbps,
+            error: None,
+            last_change: self.last_change.clone(),
+        })
+    }
+
+    async fn apply(
+        &mut self,
+        device: &str,
+        power_save: bool,
+        reason: Reason,
+        jitter_ms: f64,
+    ) -> Result<(), Error> {
+        if self.backend.power_save(device).await? == power_save {
+            return Ok(());
+        }
+
+        tracing::info!("Setting power save on {device} to {power_save} for {reason:?}");
+        self.backend.set_power_save(device, power_save).await?;
+        let id = self.last_change.as_ref().map_or(0, |change| change.id) + 1;
+        self.last_change = Some(Change {
+            id,
+            power_save,
+            reason,
+            jitter_ms,
+        });
+        Ok(())
+    }
+
+    async fn run(
+        mut self,
+        interval: Duration,
+        mut policy: watch::Receiver<Policy>,
+        status: watch::Sender<Status>,
+    ) {
+        loop {
+            let current = policy.borrow().clone();
+            let allowed = {
+                let config = self.config.borrow();
+                config.wifi.enabled && config.wifi.allows(&current.device)
+            };
+            let new_status = if current.enabled && allowed {
+                match self.step(&current).await {
+                    Ok(new_status) => new_status,
+                    Err(err) => {
+                        let err = err.to_string();
+                        if status.borrow().error.as_ref() != Some(&err) {
+                            tracing::warn!(
+                                "unable to measure the link of {}: {err}",
+                                current.device
+                            );
+                        }
+                        self.reset();
+                        Status {
+                            active: true,
+                            error: Some(err),
+                            last_change: self.last_change.clone(),
+                            ..Status::default()
+                        }
+                    }
+                }
+            } else {
+                Status {
+                    last_change: self.last_change.clone(),
+                    ..Status::default()
+                }
+            };
+            if *status.borrow() != new_status {
+                status.send_replace(new_status);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                result = policy.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                    self.reset();
+                }
+            }
+        }
+    }
+}
+
+/// Follows the power save policy in the background until every handle is dropped.
+#[derive(Clone)]
+pub struct AutoPowerSave {
+    policy: Arc<watch::Sender<Policy>>,
+    status: watch::Receiver<Status>,
+}
+
+impl AutoPowerSave {
+    pub fn spawn(
+        backend: Arc<dyn SystemBackend>,
+        config: SharedConfig,
+        policy: Policy,
+        interval: Duration,
+    ) -> Self {
+        let (policy, policy_rx) = watch::channel(policy);
+        let (status_tx, status) = watch::channel(Status::default());
+        let controller = Controller {
+            backend,
+            config,
+            pings: Pings::default(),
+            traffic: None,
+            last_busy: Instant::now(),
+            decided: None,
+            last_change: None,
+        };
+        tokio::spawn(controller.run(interval, policy_rx, status_tx));
+
+        Self {
+            policy: Arc::new(policy),
+            status,
+        }
+    }
+
+    pub fn set_policy(&self, policy: Policy) {
+        self.policy.send_replace(policy);
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy.borrow().clone()
+    }
+
+    pub fn status(&self) -> watch::Receiver<Status> {
+        self.status.clone()
+    }
+}
+
+impl TryFrom<wifi::AutoPowerSavePolicy> for Policy {
+    type Error = Error;
+
+    /// Checks the thresholds, whether the device may be used is up to the caller.
+    fn try_from(policy: wifi::AutoPowerSavePolicy) -> Result<Self, Error> {
+        if !(1..=MAX_JITTER_THRESHOLD_MS).contains(&policy.jitter_threshold_ms) {
+            return Err(Error::InvalidArgument(format!(
+                "the jitter threshold must be between 1 and {MAX_JITTER_THRESHOLD_MS} ms"
+            )));
+        }
+        if policy.idle_seconds > MAX_IDLE_SECONDS {
+            return Err(Error::InvalidArgument(format!(
+                "the idle time must be at most {MAX_IDLE_SECONDS} seconds"
+            )));
+        }
+        if policy.battery_percent > 100 {
+            return Err(Error::InvalidArgument(format!(
+                "battery level {} % is not a percentage",
+                policy.battery_percent
+            )));
+        }
+
+        Ok(Self {
+            enabled: policy.enabled,
+            device: policy.device,
+            jitter_threshold_ms: policy.jitter_threshold_ms,
+            idle_seconds: policy.idle_seconds,
+            battery_percent: policy.battery_percent,
+        })
+    }
+}
+
+impl From<&Policy> for wifi::AutoPowerSavePolicy {
+    fn from(policy: &Policy) -> Self {
+        Self {
+            enabled: policy.enabled,
+            device: policy.device.clone(),
+            jitter_threshold_ms: policy.jitter_threshold_ms,
+            idle_seconds: policy.idle_seconds,
+            battery_percent: policy.battery_percent,
+        }
+    }
+}
+
+impl From<Reason> for wifi::AutoPowerSaveReason {
+    fn from(reason: Reason) -> Self {
+        match reason {
+            Reason::Jitter => Self::Jitter,
+            Reason::Idle => Self::Idle,
+            Reason::LowBattery => Self::LowBattery,
+        }
+    }
+}
+
+impl From<&Status> for wifi::AutoPowerSaveStatus {
+    fn from(status: &Status) -> Self {
+        Self {
+            active: status.active,
+            latency_ms: status.latency_ms,
+            jitter_ms: status.jitter_ms,
+            lost: status.lost,
+            throughput_kbps: status.throughput_kbps,
+            error: status.error.clone().unwrap_or_default(),
+            last_change: status
+                .last_change
+                .as_ref()
+                .map(|change| wifi::AutoPowerSaveChange {
+                    id: change.id,
+                    power_save: change.power_save,
+                    reason: wifi::AutoPowerSaveReason::from(change.reason) as i32,
+                    jitter_ms: change.jitter_ms,
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::backend::fake::{battery_state, interface, link, Call, FakeBackend};
+    use crate::config::{self, Config};
+    use crate::test_watch::wait_for;
+
+    fn policy() -> Policy {
+        Policy {
+            enabled: true,
+            device: "wlan0".to_string(),
+            jitter_threshold_ms: 20,
+            idle_seconds: 1,
+            battery_percent: 0,
+        }
+    }
+
+    /// A connected wlan0 with power save on, streaming with jittery pings.
+    fn backend() -> FakeBackend {
+        let backend = FakeBackend::default()
+            .with_interface(interface("wlan0", 3), true)
+            .with_link("wlan0", link("Home 5G"))
+            .with_gateway("wlan0", Ipv4Addr::new(192, 168, 1, 1));
+        backend.set_latencies(&[Some(5), Some(60)]);
+        backend.set_traffic(1_000_000);
+        backend
+    }
+
+    fn spawn(backend: &Arc<FakeBackend>, policy: Policy) -> AutoPowerSave {
+        AutoPowerSave::spawn(
+            backend.clone(),
+            config::fixed(Config::default()),
+            policy,
+            Duration::from_millis(10),
+        )
+    }
+
+    fn pings(samples: &[Option<u64>]) -> Pings {
+        let mut pings = Pings::default();
+        for sample in samples {
+            pings.push(sample.map(Duration::from_millis));
+        }
+        pings
+    }
+
+    #[test]
+    fn measures_latency_and_jitter() {
+        let steady = pings(&[Some(4), Some(4), Some(4)]);
+        assert_eq!((steady.latency_ms(), steady.jitter_ms()), (4.0, 0.0));
+
+        let jittery = pings(&[Some(4), Some(10), Some(4), None]);
+        assert_eq!(jittery.latency_ms(), 6.0);
+        assert_eq!(jittery.jitter_ms(), (6.0 + 6.0 + 496.0) / 3.0);
+        assert_eq!(jittery.lost(), 1);
+    }
+
+    #[test]
+    fn keeps_only_the_last_pings() {
+        let mut pings = pings(&[None; WINDOW]);
+        pings.push(Some(Duration::from_millis(4)));
+
+        assert_eq!(pings.lost() as usize, WINDOW - 1);
+    }
+
+    #[test]
+    fn decides_by_battery_then_jitter_then_idle() {
+        let policy = Policy {
+            battery_percent: 30,
+            ..policy()
+        };
+        let low = Battery {
+            capacity: 25,
+            ..battery_state()
+        };
+        let charging = Battery {
+            status: power_supply::Status::Charging,
+            ..low.clone()
+        };
+        let busy = Duration::ZERO;
+        let idle = Duration::from_secs(5);
+
+        assert_eq!(
+            decide(&policy, 50.0, true, busy, Some(&low)),
+            Some((true, Reason::LowBattery))
+        );
+        assert_eq!(
+            decide(&policy, 50.0, true, busy, Some(&charging)),
+            Some((false, Reason::Jitter))
+        );
+        // Jittery because power save is on while nothing is going on
+        assert_eq!(
+            decide(&policy, 50.0, false, idle, None),
+            Some((true, Reason::Idle))
+        );
+        assert_eq!(decide(&policy, 5.0, true, busy, None), None);
+        let never_idle = Policy {
+            idle_seconds: 0,
+            ..policy
+        };
+        assert_eq!(decide(&never_idle, 5.0, false, idle, None), None);
+    }
+
+    #[tokio::test]
+    async fn turns_power_save_off_when_jittery_and_back_on_when_idle() {
+        let backend = Arc::new(backend());
+        let control = spawn(&backend, policy());
+        let mut status = control.status();
+
+        wait_for(&mut status, |status| status.last_change.is_some()).await;
+        let change = status.borrow().last_change.clone().unwrap();
+        assert_eq!((change.id, change.power_save), (1, false));
+        assert_eq!(change.reason, Reason::Jitter);
+        assert!(backend
+            .calls()
+            .contains(&Call::SetPowerSave("wlan0".to_string(), false)));
+
+        backend.set_traffic(0);
+        wait_for(
+            &mut status,
+            |status| matches!(&status.last_change, Some(change) if change.id == 2),
+        )
+        .await;
+        let change = status.borrow().last_change.clone().unwrap();
+        assert_eq!((change.power_save, change.reason), (true, Reason::Idle));
+    }
+
+    #[tokio::test]
+    async fn low_battery_keeps_power_save_on() {
+        let backend = Arc::new(backend().with_battery(Battery {
+            capacity: 15,
+            ..battery_state()
+        }));
+        let control = spawn(
+            &backend,
+            Policy {
+                battery_percent: 20,
+                ..policy()
+            },
+        );
+        let mut status = control.status();
+
+        wait_for(&mut status, |status| status.jitter_ms > 20.0).await;
+
+        assert!(!backend
+            .calls()
+            .contains(&Call::SetPowerSave("wlan0".to_string(), false)));
+        assert_eq!(status.borrow().last_change, None);
+    }
+
+    #[tokio::test]
+    async fn reports_why_the_link_cannot_be_measured() {
+        let backend = Arc::new(backend());
+        backend.set_link("wlan0", None);
+        let control = spawn(&backend, policy());
+        let mut status = control.status();
+
+        wait_for(&mut status, |status| status.error.is_some()).await;
+        assert_eq!(
+            status.borrow().error.as_deref(),
+            Some("wlan0 is not connected")
+        );
+
+        control.set_policy(Policy::default());
+        wait_for(&mut status, |status| !status.active).await;
+        assert!(!backend
+            .calls()
+            .iter()
+            .any(|call| matches!(call, Call::SetPowerSave(..))));
+    }
+}
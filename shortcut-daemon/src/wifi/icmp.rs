@@ -0,0 +1,232 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+/// The kernel's IPv4 routing table.
+pub const PROC_NET_ROUTE: &str = "/proc/net/route";
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+/// `RTF_UP | RTF_GATEWAY`.
+const ROUTE_GATEWAY: u32 = 0x0003;
+/// Sent along so the reply isn't just a header.
+const PAYLOAD: &[u8] = b"shortcut";
+
+/// Tells a late reply to an earlier request from the one waited for.
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Debug)]
+pub enum Error {
+    Read { path: PathBuf, err: io::Error },
+    Socket(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read { path, err } => write!(f, "unable to read {}: {err}", path.display()),
+            Error::Socket(err) => write!(f, "ping failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Socket(ref io) if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// Reads the gateway of the default route through `device` out of [`PROC_NET_ROUTE`].
+pub fn gateway(device: &str) -> Result<Option<Ipv4Addr>, Error> {
+    let content = fs::read_to_string(PROC_NET_ROUTE).map_err(|err| Error::Read {
+        path: PathBuf::from(PROC_NET_ROUTE),
+        err,
+    })?;
+    Ok(parse_gateway(&content, device))
+}
+
+/// Picks the default route through `device` with the lowest metric.
+fn parse_gateway(content: &str, device: &str) -> Option<Ipv4Addr> {
+    let hex = |field: &str| u32::from_str_radix(field, 16).ok();
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 || fields[0] != device {
+                return None;
+            }
+            let (destination, gateway, flags) = (hex(fields[1])?, hex(fields[2])?, hex(fields[3])?);
+            let (metric, mask) = (fields[6].parse::<u32>().ok()?, hex(fields[7])?);
+            if destination != 0 || mask != 0 || flags & ROUTE_GATEWAY != ROUTE_GATEWAY {
+                return None;
+            }
+            // Printed as the in-memory network order value
+            Some((metric, Ipv4Addr::from(gateway.to_ne_bytes())))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, gateway)| gateway)
+}
+
+/// The internet checksum of RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn echo_request(id: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![ECHO_REQUEST, 0, 0, 0];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(PAYLOAD);
+    let sum = checksum(&packet);
+    packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+/// Whether `packet` answers the request with `sequence`. Raw sockets see every reply to the
+/// host with the IP header in front, ping sockets only their own without one.
+fn is_reply(packet: &[u8], raw: bool, id: u16, sequence: u16) -> bool {
+    let icmp = if raw {
+        let header = usize::from(packet.first().map_or(0, |b| b & 0x0f)) * 4;
+        packet.get(header..).unwrap_or_default()
+    } else {
+        packet
+    };
+    if icmp.len() < 8 || icmp[0] != ECHO_REPLY {
+        return false;
+    }
+    let matches_id = !raw || icmp[4..6] == id.to_be_bytes();
+    matches_id && icmp[6..8] == sequence.to_be_bytes()
+}
+
+fn icmp_socket(kind: libc::c_int) -> io::Result<UdpSocket> {
+    // SAFETY: plain socket(2), the descriptor is checked before use
+    let fd = unsafe { libc::socket(libc::AF_INET, kind | libc::SOCK_CLOEXEC, libc::IPPROTO_ICMP) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just created and nothing else owns it. The datagram calls of
+    // UdpSocket work on any AF_INET socket.
+    Ok(unsafe { UdpSocket::from_raw_fd(fd) })
+}
+
+/// Opens a ping socket, or a raw one where `net.ipv4.ping_group_range` leaves root out.
+fn open() -> io::Result<(UdpSocket, bool)> {
+    match icmp_socket(libc::SOCK_DGRAM) {
+        Ok(socket) => Ok((socket, false)),
+        Err(err) if matches!(err.raw_os_error(), Some(libc::EACCES | libc::EPERM)) => {
+            Ok((icmp_socket(libc::SOCK_RAW)?, true))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Sends one echo request to `addr` and waits for the reply, `None` if none came in time.
+pub fn ping(addr: Ipv4Addr, timeout: Duration) -> Result<Option<Duration>, Error> {
+    let (socket, raw) = open().map_err(Error::Socket)?;
+    let id = std::process::id() as u16;
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let target = SocketAddr::V4(SocketAddrV4::new(addr, 0));
+
+    let sent = Instant::now();
+    socket
+        .send_to(&echo_request(id, sequence), target)
+        .map_err(Error::Socket)?;
+
+    let mut buf = [0; 128];
+    loop {
+        let left = match timeout.checked_sub(sent.elapsed()) {
+            Some(left) if !left.is_zero() => left,
+            _ => return Ok(None),
+        };
+        socket.set_read_timeout(Some(left)).map_err(Error::Socket)?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) if from.ip() == addr && is_reply(&buf[..len], raw, id, sequence) => {
+                return Ok(Some(sent.elapsed()));
+            }
+            Ok(_) => {}
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(err) => return Err(Error::Socket(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTES: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+
+    #[test]
+    fn finds_the_default_gateway_of_the_device() {
+        assert_eq!(
+            parse_gateway(ROUTES, "wlan0"),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(parse_gateway(ROUTES, "wlan1"), None);
+    }
+
+    #[test]
+    fn prefers_the_lowest_metric() {
+        let routes =
+            format!("{ROUTES}wlan0\t00000000\t0201A8C0\t0003\t0\t0\t50\t00000000\t0\t0\t0\n");
+
+        assert_eq!(
+            parse_gateway(&routes, "wlan0"),
+            Some(Ipv4Addr::new(192, 168, 1, 2))
+        );
+    }
+
+    #[test]
+    fn echo_requests_carry_a_valid_checksum() {
+        let request = echo_request(0x1234, 7);
+
+        assert_eq!(request[..2], [ECHO_REQUEST, 0]);
+        assert_eq!(checksum(&request), 0);
+    }
+
+    #[test]
+    fn matches_replies_to_their_request() {
+        let mut reply = echo_request(0x1234, 7);
+        reply[0] = ECHO_REPLY;
+        let mut raw = vec![0x45];
+        raw.extend_from_slice(&[0; 19]);
+        raw.extend_from_slice(&reply);
+
+        assert!(is_reply(&reply, false, 0x9999, 7));
+        assert!(!is_reply(&reply, false, 0x1234, 8));
+        assert!(is_reply(&raw, true, 0x1234, 7));
+        assert!(!is_reply(&raw, true, 0x4321, 7));
+        assert!(!is_reply(&echo_request(0x1234, 7), false, 0x1234, 7));
+    }
+}
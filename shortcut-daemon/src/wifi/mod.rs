@@ -0,0 +1,1744 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use shortcut_core::futures::stream;
+use shortcut_core::tonic::{self, Request, Response, Status};
+use shortcut_core::Error;
+
+use shortcut_core::wifi;
+use shortcut_core::wifi::wifi_service_server;
+
+use crate::auth::Caller;
+use crate::backend::SystemBackend;
+use crate::config::SharedConfig;
+use crate::poll::{Service, WatchStream};
+use crate::state::StateFile;
+
+use auto_power_save::{AutoPowerSave, Policy};
+use network_manager::{AccessPoint, SavedNetwork};
+use nl80211::{Interface, Link};
+
+pub(crate) mod auto_power_save;
+pub(crate) mod icmp;
+pub(crate) mod network_manager;
+pub(crate) mod nl80211;
+pub(crate) mod nm_config;
+
+/// The range of NetworkManager's `connection.autoconnect-priority`.
+const MIN_PRIORITY: i32 = -999;
+const MAX_PRIORITY: i32 = 999;
+
+/// How often the link statistics are re-read, they change with every frame.
+const LINK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The wireless interfaces callers may see, in a stable order.
+async fn allowed_interfaces(
+    backend: &dyn SystemBackend,
+    config: &SharedConfig,
+) -> Result<Vec<Interface>, Error> {
+    let mut interfaces = backend.wifi_interfaces().await?;
+
+    // Interfaces can be renamed or recreated while the dump is running
+    let config = config.borrow().clone();
+    interfaces.retain(|iface| !iface.name.is_empty() && config.wifi.allows(&iface.name));
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces.dedup_by(|a, b| a.name == b.name);
+
+    Ok(interfaces)
+}
+
+fn to_devices(interfaces: &[Interface]) -> Vec<wifi::Device> {
+    interfaces
+        .iter()
+        .map(|iface| wifi::Device {
+            name: iface.name.clone(),
+            ifindex: iface.ifindex,
+            mac: iface.mac_string(),
+            iftype: iface.iftype as i32,
+            phy: iface.phy,
+        })
+        .collect()
+}
+
+fn to_access_points(access_points: Vec<AccessPoint>) -> Vec<wifi::AccessPoint> {
+    access_points
+        .into_iter()
+        .map(wifi::AccessPoint::from)
+        .collect()
+}
+
+fn to_saved_networks(networks: Vec<SavedNetwork>) -> Vec<wifi::SavedNetwork> {
+    networks.into_iter().map(wifi::SavedNetwork::from).collect()
+}
+
+fn to_link_info(link: Link) -> wifi::LinkInfo {
+    wifi::LinkInfo {
+        bssid: link.bssid_string(),
+        channel: link.channel(),
+        ssid: link.ssid,
+        frequency: link.frequency,
+        signal: i32::from(link.signal),
+        tx_bitrate: link.tx_bitrate,
+        rx_bitrate: link.rx_bitrate,
+        tx_retries: link.tx_retries,
+        tx_failed: link.tx_failed,
+        rx_bytes: link.rx_bytes,
+        tx_bytes: link.tx_bytes,
+    }
+}
+
+/// The layers NetworkManager keeps power save in, nothing when they can't be read. They only
+/// explain the state of the device, so e.g. an unreadable drop-in must not fail reading it.
+async fn power_save_layers(
+    backend: &dyn SystemBackend,
+    device: &str,
+) -> (Option<bool>, Option<bool>) {
+    let layers = async {
+        let connection = backend.connection_power_save(device).await?;
+        Ok::<_, Error>((connection, backend.global_power_save().await?))
+    };
+    match layers.await {
+        Ok(layers) => layers,
+        Err(Error::Unavailable(err)) => {
+            tracing::debug!("no power save layers of {device}: {err}");
+            (None, None)
+        }
+        Err(err) => {
+            tracing::warn!("unable to read the power save layers of {device}: {err}");
+            (None, None)
+        }
+    }
+}
+
+/// The most lasting layer that agrees with the power save state of the device, runtime when
+/// reconnecting would change it. The connection overrides the global default.
+async fn power_save_source(
+    backend: &dyn SystemBackend,
+    device: &str,
+    enabled: bool,
+) -> wifi::PowerSaveScope {
+    match power_save_layers(backend, device).await {
+        (Some(connection), _) if connection == enabled => wifi::PowerSaveScope::Connection,
+        (None, Some(global)) if global == enabled => wifi::PowerSaveScope::Global,
+        _ => wifi::PowerSaveScope::Runtime,
+    }
+}
+
+fn validate_uuid(uuid: &str) -> Result<(), Error> {
+    if uuid.is_empty() {
+        return Err(Error::InvalidArgument("no saved network given".to_string()));
+    }
+    Ok(())
+}
+
+pub struct WifiServer {
+    backend: Arc<dyn SystemBackend>,
+    config: SharedConfig,
+    state: Arc<StateFile>,
+    auto_power_save: AutoPowerSave,
+    service: Service,
+}
+
+impl WifiServer {
+    pub fn new(
+        backend: Arc<dyn SystemBackend>,
+        config: SharedConfig,
+        state: Arc<StateFile>,
+        auto_power_save: AutoPowerSave,
+    ) -> Self {
+        Self {
+            backend,
+            service: Service::new("wifi", config.clone(), |config| config.wifi.enabled),
+            config,
+            state,
+            auto_power_save,
+        }
+    }
+
+    fn validate_device(&self, device: &str) -> Result<(), Error> {
+        self.service.check_enabled()?;
+        if device.is_empty() {
+            return Err(Error::InvalidArgument("no device given".to_string()));
+        }
+        if !self.config.borrow().wifi.allows(device) {
+            return Err(Error::PermissionDenied(format!(
+                "wifi device {device} is not allowed"
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl wifi_service_server::WifiService for WifiServer {
+    async fn set_power_save(
+        &self,
+        request: Request<wifi::SetPowerSaveRequest>,
+    ) -> Result<Response<wifi::SetPowerSaveResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let scope = wifi::PowerSaveScope::from_i32(inner.scope).ok_or_else(|| {
+            Error::InvalidArgument(format!("unknown power save scope {}", inner.scope))
+        })?;
+        tracing::info!(
+            "Setting power save on {} to {} ({scope:?}) for {caller}",
+            inner.device,
+            inner.enabled
+        );
+        let saved = match scope {
+            wifi::PowerSaveScope::Connection => {
+                self.backend
+                    .set_connection_power_save(&inner.device, inner.enabled)
+                    .await
+            }
+            wifi::PowerSaveScope::Global => self.backend.set_global_power_save(inner.enabled).await,
+            wifi::PowerSaveScope::Unspecified | wifi::PowerSaveScope::Runtime => Ok(()),
+        };
+        saved.map_err(|err| {
+            tracing::error!("error when saving power save: {err}");
+            err
+        })?;
+        // NetworkManager only applies what it keeps on the next activation
+        let enabled = self
+            .backend
+            .set_power_save(&inner.device, inner.enabled)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_power_save: {err}");
+                err
+            })?;
+        // A value kept by NetworkManager follows the network, forcing it onto the device after
+        // every resume would override the one of the next network
+        match scope {
+            wifi::PowerSaveScope::Unspecified | wifi::PowerSaveScope::Runtime => {
+                self.state.set_power_save(&inner.device, inner.enabled)
+            }
+            wifi::PowerSaveScope::Connection | wifi::PowerSaveScope::Global => {
+                self.state.clear_power_save(&inner.device)
+            }
+        }
+        self.service.notify();
+
+        if enabled != inner.enabled {
+            tracing::warn!(
+                "power save on {} is {enabled} after setting it to {}",
+                inner.device,
+                inner.enabled
+            );
+        }
+
+        let source = power_save_source(self.backend.as_ref(), &inner.device, enabled).await;
+
+        let reply = wifi::SetPowerSaveResponse {
+            enabled,
+            source: source as i32,
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_power_save(
+        &self,
+        request: Request<wifi::GetPowerSaveRequest>,
+    ) -> Result<Response<wifi::GetPowerSaveResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let enabled = self
+            .backend
+            .power_save(&inner.device)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when get_power_save: {err}");
+                err
+            })?;
+        let source = power_save_source(self.backend.as_ref(), &inner.device, enabled).await;
+
+        let reply = wifi::GetPowerSaveResponse {
+            enabled,
+            source: source as i32,
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn list_devices(
+        &self,
+        request: Request<wifi::ListDevicesRequest>,
+    ) -> Result<Response<wifi::ListDevicesResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let interfaces = allowed_interfaces(self.backend.as_ref(), &self.config)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when list_devices: {err}");
+                err
+            })?;
+
+        let reply = wifi::ListDevicesResponse {
+            devices: interfaces.iter().map(|iface| iface.name.clone()).collect(),
+            interfaces: to_devices(&interfaces),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchPowerSaveStream = WatchStream<wifi::WatchPowerSaveResponse>;
+
+    async fn watch_power_save(
+        &self,
+        request: Request<wifi::WatchPowerSaveRequest>,
+    ) -> Result<Response<Self::WatchPowerSaveStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            let device = inner.device.clone();
+            async move {
+                let enabled = backend.power_save(&device).await?;
+                let source = power_save_source(backend.as_ref(), &device, enabled).await;
+                Ok(wifi::WatchPowerSaveResponse {
+                    enabled,
+                    source: source as i32,
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    type WatchDevicesStream = WatchStream<wifi::WatchDevicesResponse>;
+
+    async fn watch_devices(
+        &self,
+        request: Request<wifi::WatchDevicesRequest>,
+    ) -> Result<Response<Self::WatchDevicesStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backend = self.backend.clone();
+        let config = self.config.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            let config = config.clone();
+            async move {
+                let interfaces = allowed_interfaces(backend.as_ref(), &config).await?;
+                Ok(wifi::WatchDevicesResponse {
+                    devices: interfaces.iter().map(|iface| iface.name.clone()).collect(),
+                    interfaces: to_devices(&interfaces),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn scan(
+        &self,
+        request: Request<wifi::ScanRequest>,
+    ) -> Result<Response<wifi::ScanResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let access_points = self.backend.scan_wifi(&inner.device).await.map_err(|err| {
+            tracing::error!("error when scan: {err}");
+            err
+        })?;
+        self.service.notify();
+
+        let reply = wifi::ScanResponse {
+            access_points: to_access_points(access_points),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchAccessPointsStream = WatchStream<wifi::WatchAccessPointsResponse>;
+
+    async fn watch_access_points(
+        &self,
+        request: Request<wifi::WatchAccessPointsRequest>,
+    ) -> Result<Response<Self::WatchAccessPointsStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            let device = inner.device.clone();
+            async move {
+                let access_points = backend.access_points(&device).await?;
+                Ok(wifi::WatchAccessPointsResponse {
+                    access_points: to_access_points(access_points),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn list_saved_networks(
+        &self,
+        request: Request<wifi::ListSavedNetworksRequest>,
+    ) -> Result<Response<wifi::ListSavedNetworksResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let networks = self.backend.saved_networks().await.map_err(|err| {
+            tracing::error!("error when list_saved_networks: {err}");
+            err
+        })?;
+
+        let reply = wifi::ListSavedNetworksResponse {
+            networks: to_saved_networks(networks),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchSavedNetworksStream = WatchStream<wifi::WatchSavedNetworksResponse>;
+
+    async fn watch_saved_networks(
+        &self,
+        request: Request<wifi::WatchSavedNetworksRequest>,
+    ) -> Result<Response<Self::WatchSavedNetworksStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let backend = self.backend.clone();
+        let stream = self.service.watch(move || {
+            let backend = backend.clone();
+            async move {
+                let networks = backend.saved_networks().await?;
+                Ok(wifi::WatchSavedNetworksResponse {
+                    networks: to_saved_networks(networks),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn forget_network(
+        &self,
+        request: Request<wifi::ForgetNetworkRequest>,
+    ) -> Result<Response<wifi::ForgetNetworkResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        validate_uuid(&inner.uuid)?;
+        tracing::info!("Forgetting network {} for {caller}", inner.uuid);
+        let networks = self
+            .backend
+            .forget_network(&inner.uuid)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when forget_network: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = wifi::ForgetNetworkResponse {
+            networks: to_saved_networks(networks),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_autoconnect(
+        &self,
+        request: Request<wifi::SetAutoconnectRequest>,
+    ) -> Result<Response<wifi::SetAutoconnectResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        validate_uuid(&inner.uuid)?;
+        tracing::info!(
+            "Setting autoconnect of network {} to {} for {caller}",
+            inner.uuid,
+            inner.enabled
+        );
+        let networks = self
+            .backend
+            .set_network_autoconnect(&inner.uuid, inner.enabled)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_autoconnect: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = wifi::SetAutoconnectResponse {
+            networks: to_saved_networks(networks),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_priority(
+        &self,
+        request: Request<wifi::SetPriorityRequest>,
+    ) -> Result<Response<wifi::SetPriorityResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        validate_uuid(&inner.uuid)?;
+        if !(MIN_PRIORITY..=MAX_PRIORITY).contains(&inner.priority) {
+            return Err(Error::InvalidArgument(format!(
+                "priority {} is not between {MIN_PRIORITY} and {MAX_PRIORITY}",
+                inner.priority
+            ))
+            .into());
+        }
+        tracing::info!(
+            "Setting priority of network {} to {} for {caller}",
+            inner.uuid,
+            inner.priority
+        );
+        let networks = self
+            .backend
+            .set_network_priority(&inner.uuid, inner.priority)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when set_priority: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = wifi::SetPriorityResponse {
+            networks: to_saved_networks(networks),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn connect_network(
+        &self,
+        request: Request<wifi::ConnectNetworkRequest>,
+    ) -> Result<Response<wifi::ConnectNetworkResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        // Without the passphrase
+        tracing::debug!(
+            "ConnectNetworkRequest {{ device: {:?}, ssid: {:?} }}",
+            inner.device,
+            inner.ssid
+        );
+
+        self.validate_device(&inner.device)?;
+        if inner.ssid.is_empty() {
+            return Err(Error::InvalidArgument("no network given".to_string()).into());
+        }
+        let passphrase = Some(inner.passphrase.as_str()).filter(|p| !p.is_empty());
+
+        tracing::info!("Connecting {} to {} for {caller}", inner.device, inner.ssid);
+        let access_points = self
+            .backend
+            .connect_wifi(&inner.device, &inner.ssid, passphrase)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when connect_network: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = wifi::ConnectNetworkResponse {
+            access_points: to_access_points(access_points),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn disconnect_network(
+        &self,
+        request: Request<wifi::DisconnectNetworkRequest>,
+    ) -> Result<Response<wifi::DisconnectNetworkResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        tracing::info!("Disconnecting {} for {caller}", inner.device);
+        let access_points = self
+            .backend
+            .disconnect_wifi(&inner.device)
+            .await
+            .map_err(|err| {
+                tracing::error!("error when disconnect_network: {err}");
+                err
+            })?;
+        self.service.notify();
+
+        let reply = wifi::DisconnectNetworkResponse {
+            access_points: to_access_points(access_points),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_link_info(
+        &self,
+        request: Request<wifi::GetLinkInfoRequest>,
+    ) -> Result<Response<wifi::GetLinkInfoResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let link = self.backend.wifi_link(&inner.device).await.map_err(|err| {
+            tracing::error!("error when get_link_info: {err}");
+            err
+        })?;
+
+        let reply = wifi::GetLinkInfoResponse {
+            link: link.map(to_link_info),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchLinkInfoStream = WatchStream<wifi::WatchLinkInfoResponse>;
+
+    async fn watch_link_info(
+        &self,
+        request: Request<wifi::WatchLinkInfoRequest>,
+    ) -> Result<Response<Self::WatchLinkInfoStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.validate_device(&inner.device)?;
+        let backend = self.backend.clone();
+        let interval = self.service.interval.min(LINK_POLL_INTERVAL);
+        let stream = self.service.watch_every(interval, move || {
+            let backend = backend.clone();
+            let device = inner.device.clone();
+            async move {
+                let link = backend.wifi_link(&device).await?;
+                Ok(wifi::WatchLinkInfoResponse {
+                    link: link.map(to_link_info),
+                })
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn get_auto_power_save(
+        &self,
+        request: Request<wifi::GetAutoPowerSaveRequest>,
+    ) -> Result<Response<wifi::GetAutoPowerSaveResponse>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        let reply = wifi::GetAutoPowerSaveResponse {
+            policy: Some(wifi::AutoPowerSavePolicy::from(
+                &self.auto_power_save.policy(),
+            )),
+            status: Some(wifi::AutoPowerSaveStatus::from(
+                &*self.auto_power_save.status().borrow(),
+            )),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_auto_power_save(
+        &self,
+        request: Request<wifi::SetAutoPowerSaveRequest>,
+    ) -> Result<Response<wifi::SetAutoPowerSaveResponse>, Status> {
+        let caller = Caller::describe(&request);
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        let policy = Policy::try_from(inner.policy.unwrap_or_default())?;
+        if policy.enabled {
+            self.validate_device(&policy.device)?;
+            tracing::info!(
+                "Managing power save on {} automatically for {caller}",
+                policy.device
+            );
+        } else {
+            self.service.check_enabled()?;
+            tracing::info!("Leaving power save to the callers for {caller}");
+        }
+        self.state.set_auto_power_save(policy.clone());
+        self.auto_power_save.set_policy(policy.clone());
+
+        let reply = wifi::SetAutoPowerSaveResponse {
+            policy: Some(wifi::AutoPowerSavePolicy::from(&policy)),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    type WatchAutoPowerSaveStream = WatchStream<wifi::WatchAutoPowerSaveResponse>;
+
+    async fn watch_auto_power_save(
+        &self,
+        request: Request<wifi::WatchAutoPowerSaveRequest>,
+    ) -> Result<Response<Self::WatchAutoPowerSaveStream>, Status> {
+        let inner = request.into_inner();
+        tracing::debug!("{:?}", inner);
+
+        self.service.check_enabled()?;
+        // The current status first, then every measurement and change
+        let stream = stream::unfold(
+            (self.auto_power_save.status(), true),
+            |(mut status, first)| async move {
+                if !first {
+                    status.changed().await.ok()?;
+                }
+                let reply = wifi::WatchAutoPowerSaveResponse {
+                    status: Some(wifi::AutoPowerSaveStatus::from(&*status.borrow())),
+                };
+                Some((Ok(reply), (status, false)))
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shortcut_core::futures::StreamExt;
+    use shortcut_core::tokio;
+    use shortcut_core::tonic::Code;
+    use shortcut_core::wifi::wifi_service_server::WifiService;
+
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::backend::fake::{
+        access_point, interface, link, saved_network, Call, FakeBackend, Method,
+    };
+    use crate::config::{self, Config, WifiConfig};
+    use crate::wifi::network_manager::Security;
+
+    fn server(backend: FakeBackend) -> (Arc<FakeBackend>, WifiServer) {
+        server_with_config(backend, WifiConfig::default())
+    }
+
+    fn server_with_state(backend: FakeBackend, state: Arc<StateFile>) -> WifiServer {
+        let backend = Arc::new(backend);
+        let config = config::fixed(Config::default());
+        let auto_power_save = auto_power_save(&backend, &config);
+        WifiServer::new(backend, config, state, auto_power_save)
+    }
+
+    fn auto_power_save(backend: &Arc<FakeBackend>, config: &SharedConfig) -> AutoPowerSave {
+        AutoPowerSave::spawn(
+            backend.clone(),
+            config.clone(),
+            Policy::default(),
+            Duration::from_millis(10),
+        )
+    }
+
+    fn server_with_config(
+        backend: FakeBackend,
+        wifi: WifiConfig,
+    ) -> (Arc<FakeBackend>, WifiServer) {
+        let backend = Arc::new(backend);
+        let config = config::fixed(Config {
+            wifi,
+            ..Config::default()
+        });
+        let state = Arc::new(StateFile::in_memory());
+        let auto_power_save = auto_power_save(&backend, &config);
+        (
+            backend.clone(),
+            WifiServer::new(backend, config, state, auto_power_save),
+        )
+    }
+
+    #[tokio::test]
+    async fn list_devices_returns_interfaces() {
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_interface(interface("wlan1", 4), true)
+                .with_interface(interface("wlan0", 3), false),
+        );
+
+        let reply = server
+            .list_devices(Request::new(wifi::ListDevicesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.devices, vec!["wlan0", "wlan1"]);
+        assert_eq!(reply.interfaces[0].ifindex, 3);
+        assert_eq!(reply.interfaces[0].mac, "02:00:00:00:00:03");
+        assert_eq!(
+            reply.interfaces[0].iftype,
+            wifi::InterfaceType::Station as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn list_devices_skips_unnamed_and_duplicate_interfaces() {
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_interface(interface("", 7), false)
+                .with_interface(interface("wlan0", 3), false)
+                .with_interface(interface("wlan0", 3), false),
+        );
+
+        let reply = server
+            .list_devices(Request::new(wifi::ListDevicesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.devices, vec!["wlan0"]);
+        assert_eq!(reply.interfaces.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn list_devices_reports_backend_errors() {
+        let (backend, server) = server(FakeBackend::default());
+        backend.fail(
+            Method::WifiInterfaces,
+            Error::Unavailable("nl80211 is not available".to_string()),
+        );
+
+        let status = server
+            .list_devices(Request::new(wifi::ListDevicesRequest {}))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn get_power_save_reads_device() {
+        let (backend, server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+
+        let reply = server
+            .get_power_save(Request::new(wifi::GetPowerSaveRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(reply.enabled);
+        assert_eq!(reply.source, wifi::PowerSaveScope::Runtime as i32);
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::PowerSave("wlan0".to_string()),
+                Call::ConnectionPowerSave("wlan0".to_string()),
+                Call::GlobalPowerSave,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_power_save_reports_the_layer_it_came_from() {
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_link("wlan0", link("Home 5G"))
+                .with_global_power_save(true),
+        );
+        let get = || async {
+            server
+                .get_power_save(Request::new(wifi::GetPowerSaveRequest {
+                    device: "wlan0".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .source
+        };
+
+        assert_eq!(get().await, wifi::PowerSaveScope::Global as i32);
+        server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Connection as i32,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(get().await, wifi::PowerSaveScope::Connection as i32);
+    }
+
+    #[tokio::test]
+    async fn get_power_save_works_without_its_layers() {
+        let (backend, server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+        let get = || {
+            server.get_power_save(Request::new(wifi::GetPowerSaveRequest {
+                device: "wlan0".to_string(),
+            }))
+        };
+
+        backend.fail(
+            Method::ConnectionPowerSave,
+            Error::Unavailable("NetworkManager is not running".to_string()),
+        );
+        let reply = get().await.unwrap().into_inner();
+        assert!(reply.enabled);
+        assert_eq!(reply.source, wifi::PowerSaveScope::Runtime as i32);
+
+        backend.fail(
+            Method::GlobalPowerSave,
+            Error::PermissionDenied("conf.d/zz-shortcut.conf".to_string()),
+        );
+        let reply = get().await.unwrap().into_inner();
+        assert!(reply.enabled);
+        assert_eq!(reply.source, wifi::PowerSaveScope::Runtime as i32);
+    }
+
+    #[tokio::test]
+    async fn get_power_save_unknown_device_is_not_found() {
+        let (_, server) = server(FakeBackend::default());
+
+        let status = server
+            .get_power_save(Request::new(wifi::GetPowerSaveRequest {
+                device: "wlan9".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(Error::from(status), Error::not_found("wifi device wlan9"));
+    }
+
+    #[tokio::test]
+    async fn get_power_save_rejects_empty_device() {
+        let (backend, server) = server(FakeBackend::default());
+
+        let status = server
+            .get_power_save(Request::new(wifi::GetPowerSaveRequest {
+                device: String::new(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_power_save_returns_resulting_state() {
+        let (backend, server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+
+        let reply = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Runtime as i32,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(!reply.enabled);
+        assert_eq!(reply.source, wifi::PowerSaveScope::Runtime as i32);
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::SetPowerSave("wlan0".to_string(), false),
+                Call::ConnectionPowerSave("wlan0".to_string()),
+                Call::GlobalPowerSave,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_power_save_keeps_it_in_the_connection() {
+        let (backend, server) = server(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_link("wlan0", link("Home 5G")),
+        );
+
+        let reply = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Connection as i32,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(!reply.enabled);
+        assert_eq!(reply.source, wifi::PowerSaveScope::Connection as i32);
+        assert_eq!(
+            backend.calls()[..2],
+            [
+                Call::SetConnectionPowerSave("wlan0".to_string(), false),
+                Call::SetPowerSave("wlan0".to_string(), false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_power_save_in_the_connection_needs_one() {
+        let (backend, server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+
+        let status = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Connection as i32,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            backend.calls(),
+            vec![Call::SetConnectionPowerSave("wlan0".to_string(), false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn global_power_save_loses_to_the_connection() {
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_link("wlan0", link("Home 5G"))
+                .with_connection_power_save("wlan0", false),
+        );
+        let set = |enabled| {
+            server.set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled,
+                scope: wifi::PowerSaveScope::Global as i32,
+            }))
+        };
+
+        let reply = set(false).await.unwrap().into_inner();
+        assert_eq!(reply.source, wifi::PowerSaveScope::Connection as i32);
+
+        // Applied until the connection is activated again
+        let reply = set(true).await.unwrap().into_inner();
+        assert!(reply.enabled);
+        assert_eq!(reply.source, wifi::PowerSaveScope::Runtime as i32);
+    }
+
+    #[tokio::test]
+    async fn set_power_save_rejects_unknown_scopes() {
+        let (backend, server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+
+        let status = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: 42,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_power_save_reports_what_the_device_kept() {
+        let (_, server) = server(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_sticky_power_save(true),
+        );
+
+        let reply = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Runtime as i32,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(reply.enabled);
+    }
+
+    #[tokio::test]
+    async fn set_power_save_reports_permission_errors() {
+        let (backend, server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+        backend.fail(
+            Method::SetPowerSave,
+            Error::PermissionDenied("Operation not permitted".to_string()),
+        );
+
+        let status = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Runtime as i32,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn only_allowed_interfaces_are_listed_and_changed() {
+        let (backend, server) = server_with_config(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_interface(interface("wlan1", 4), true),
+            WifiConfig {
+                interfaces: vec!["wlan1".to_string()],
+                ..WifiConfig::default()
+            },
+        );
+
+        let reply = server
+            .list_devices(Request::new(wifi::ListDevicesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let status = server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Runtime as i32,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(reply.devices, vec!["wlan1"]);
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(backend.calls(), vec![Call::WifiInterfaces]);
+    }
+
+    #[tokio::test]
+    async fn disabled_service_is_unavailable() {
+        let (backend, server) = server_with_config(
+            FakeBackend::default().with_interface(interface("wlan0", 3), true),
+            WifiConfig {
+                enabled: false,
+                ..WifiConfig::default()
+            },
+        );
+
+        let status = server
+            .get_power_save(Request::new(wifi::GetPowerSaveRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_power_save_remembers_the_requested_state() {
+        let state = Arc::new(StateFile::in_memory());
+        let server = server_with_state(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_sticky_power_save(true),
+            state.clone(),
+        );
+
+        server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: false,
+                scope: wifi::PowerSaveScope::Runtime as i32,
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(state.desired().power_save.get("wlan0"), Some(&false));
+    }
+
+    #[tokio::test]
+    async fn saved_power_save_is_left_to_network_manager() {
+        let backend = Arc::new(
+            FakeBackend::default()
+                .with_interface(interface("wlan0", 3), true)
+                .with_link("wlan0", link("Home 5G")),
+        );
+        let config = config::fixed(Config::default());
+        let state = Arc::new(StateFile::in_memory());
+        let auto_power_save = auto_power_save(&backend, &config);
+        let server = WifiServer::new(backend.clone(), config, state.clone(), auto_power_save);
+
+        for scope in [
+            wifi::PowerSaveScope::Runtime,
+            wifi::PowerSaveScope::Connection,
+        ] {
+            server
+                .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                    device: "wlan0".to_string(),
+                    enabled: false,
+                    scope: scope as i32,
+                }))
+                .await
+                .unwrap();
+        }
+        assert_eq!(state.desired().power_save.get("wlan0"), None);
+
+        // The next network NetworkManager joins turns it back on
+        backend.reset_power_save("wlan0", true);
+        let calls = backend.calls().len();
+        crate::state::restore_all(backend.as_ref(), &Config::default(), &state.desired()).await;
+
+        assert!(!backend.calls()[calls..]
+            .iter()
+            .any(|call| matches!(call, Call::SetPowerSave(..))));
+    }
+
+    #[tokio::test]
+    async fn watch_power_save_follows_changes() {
+        let (backend, mut server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+        server.service.interval = Duration::from_millis(10);
+
+        let mut stream = server
+            .watch_power_save(Request::new(wifi::WatchPowerSaveRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(stream.next().await.unwrap().unwrap().enabled);
+
+        // Changed behind the daemon's back, picked up by polling
+        backend.reset_power_save("wlan0", false);
+        assert!(!stream.next().await.unwrap().unwrap().enabled);
+
+        server
+            .set_power_save(Request::new(wifi::SetPowerSaveRequest {
+                device: "wlan0".to_string(),
+                enabled: true,
+                scope: wifi::PowerSaveScope::Runtime as i32,
+            }))
+            .await
+            .unwrap();
+        assert!(stream.next().await.unwrap().unwrap().enabled);
+    }
+
+    #[tokio::test]
+    async fn watch_power_save_ends_when_device_disappears() {
+        let (backend, mut server) =
+            server(FakeBackend::default().with_interface(interface("wlan0", 3), true));
+        server.service.interval = Duration::from_millis(10);
+        let mut stream = server
+            .watch_power_save(Request::new(wifi::WatchPowerSaveRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(stream.next().await.unwrap().is_ok());
+
+        backend.fail(Method::PowerSave, Error::not_found("wifi device wlan0"));
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap_err().code(),
+            Code::NotFound
+        );
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn watch_devices_emits_current_devices() {
+        let (_, server) = server_with_config(
+            FakeBackend::default()
+                .with_interface(interface("wlan1", 4), true)
+                .with_interface(interface("wlan0", 3), true),
+            WifiConfig {
+                interfaces: vec!["wlan0".to_string()],
+                ..WifiConfig::default()
+            },
+        );
+
+        let mut stream = server
+            .watch_devices(Request::new(wifi::WatchDevicesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let reply = stream.next().await.unwrap().unwrap();
+        assert_eq!(reply.devices, vec!["wlan0"]);
+        assert_eq!(reply.interfaces[0].ifindex, 3);
+    }
+
+    fn networks() -> FakeBackend {
+        FakeBackend::default()
+            .with_interface(interface("wlan0", 3), true)
+            .with_access_point(access_point("Home 5G", 80, Security::WpaPsk))
+            .with_access_point(access_point("Cafe", 60, Security::Open))
+            .with_access_point(access_point("Phone", 40, Security::Sae))
+            .with_saved_network(saved_network("Home", "Home 5G"))
+    }
+
+    #[tokio::test]
+    async fn scan_returns_access_points() {
+        let (backend, server) = server(networks());
+
+        let reply = server
+            .scan(Request::new(wifi::ScanRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let ssids: Vec<_> = reply.access_points.iter().map(|ap| &ap.ssid).collect();
+        assert_eq!(ssids, vec!["Home 5G", "Cafe", "Phone"]);
+        assert_eq!(reply.access_points[0].signal_percent, 80);
+        assert_eq!(
+            reply.access_points[0].security,
+            wifi::Security::WpaPsk as i32
+        );
+        assert!(reply.access_points[0].saved);
+        assert!(!reply.access_points[1].saved);
+        assert_eq!(backend.calls(), vec![Call::ScanWifi("wlan0".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn scan_checks_the_device() {
+        let (backend, server) = server_with_config(
+            networks(),
+            WifiConfig {
+                interfaces: vec!["wlan1".to_string()],
+                ..WifiConfig::default()
+            },
+        );
+
+        let status = server
+            .scan(Request::new(wifi::ScanRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn lists_saved_networks() {
+        let (_, server) = server(networks());
+
+        let reply = server
+            .list_saved_networks(Request::new(wifi::ListSavedNetworksRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.networks.len(), 1);
+        assert_eq!(reply.networks[0].id, "Home");
+        assert_eq!(reply.networks[0].ssid, "Home 5G");
+    }
+
+    #[tokio::test]
+    async fn connect_passes_the_passphrase_only_when_given() {
+        let (backend, server) = server(networks());
+
+        let reply = server
+            .connect_network(Request::new(wifi::ConnectNetworkRequest {
+                device: "wlan0".to_string(),
+                ssid: "Home 5G".to_string(),
+                passphrase: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(reply.access_points[0].active);
+
+        server
+            .connect_network(Request::new(wifi::ConnectNetworkRequest {
+                device: "wlan0".to_string(),
+                ssid: "Phone".to_string(),
+                passphrase: "hotspot-secret".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::ConnectWifi("wlan0".to_string(), "Home 5G".to_string(), None),
+                Call::ConnectWifi(
+                    "wlan0".to_string(),
+                    "Phone".to_string(),
+                    Some("hotspot-secret".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_needs_a_network() {
+        let (backend, server) = server(networks());
+
+        let status = server
+            .connect_network(Request::new(wifi::ConnectNetworkRequest {
+                device: "wlan0".to_string(),
+                ssid: String::new(),
+                passphrase: "secret".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn connect_reports_missing_passphrases() {
+        let (_, server) = server(networks());
+
+        let status = server
+            .connect_network(Request::new(wifi::ConnectNetworkRequest {
+                device: "wlan0".to_string(),
+                ssid: "Phone".to_string(),
+                passphrase: String::new(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn watch_access_points_follows_connections() {
+        let (_, mut server) = server(networks());
+        server.service.interval = Duration::from_secs(60);
+
+        let mut stream = server
+            .watch_access_points(Request::new(wifi::WatchAccessPointsRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let reply = stream.next().await.unwrap().unwrap();
+        assert!(reply.access_points.iter().all(|ap| !ap.active));
+
+        server
+            .connect_network(Request::new(wifi::ConnectNetworkRequest {
+                device: "wlan0".to_string(),
+                ssid: "Cafe".to_string(),
+                passphrase: String::new(),
+            }))
+            .await
+            .unwrap();
+        let reply = stream.next().await.unwrap().unwrap();
+        assert!(reply.access_points[1].active);
+
+        server
+            .disconnect_network(Request::new(wifi::DisconnectNetworkRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap();
+        let reply = stream.next().await.unwrap().unwrap();
+        assert!(reply.access_points.iter().all(|ap| !ap.active));
+    }
+
+    #[tokio::test]
+    async fn saved_networks_can_be_reordered_and_forgotten() {
+        let (backend, server) = server(
+            networks()
+                .with_access_point(access_point("Cafe", 60, Security::Open))
+                .with_saved_network(saved_network("Cafe", "Cafe")),
+        );
+
+        let reply = server
+            .set_priority(Request::new(wifi::SetPriorityRequest {
+                uuid: "uuid-Cafe".to_string(),
+                priority: 10,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let ids: Vec<_> = reply.networks.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["Cafe", "Home"]);
+        assert_eq!(reply.networks[0].priority, 10);
+
+        let reply = server
+            .set_autoconnect(Request::new(wifi::SetAutoconnectRequest {
+                uuid: "uuid-Home".to_string(),
+                enabled: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!reply.networks[1].autoconnect);
+
+        let reply = server
+            .forget_network(Request::new(wifi::ForgetNetworkRequest {
+                uuid: "uuid-Cafe".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.networks.len(), 1);
+
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::SetNetworkPriority("uuid-Cafe".to_string(), 10),
+                Call::SetNetworkAutoconnect("uuid-Home".to_string(), false),
+                Call::ForgetNetwork("uuid-Cafe".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn priorities_stay_in_network_managers_range() {
+        let (backend, server) = server(networks());
+
+        let status = server
+            .set_priority(Request::new(wifi::SetPriorityRequest {
+                uuid: "uuid-Home".to_string(),
+                priority: 1000,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let status = server
+            .forget_network(Request::new(wifi::ForgetNetworkRequest {
+                uuid: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgetting_unknown_networks_is_not_found() {
+        let (_, server) = server(networks());
+
+        let status = server
+            .forget_network(Request::new(wifi::ForgetNetworkRequest {
+                uuid: "uuid-Phone".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn get_link_info_reports_the_connection() {
+        let (backend, server) = server(networks().with_link("wlan0", link("Home 5G")));
+
+        let reply = server
+            .get_link_info(Request::new(wifi::GetLinkInfoRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(
+            reply.link,
+            Some(wifi::LinkInfo {
+                ssid: "Home 5G".to_string(),
+                bssid: "02:00:00:00:00:46".to_string(),
+                frequency: 5180,
+                channel: 36,
+                signal: -52,
+                tx_bitrate: 866_700,
+                rx_bitrate: 780_000,
+                tx_retries: 12,
+                tx_failed: 0,
+                rx_bytes: 52_428_800,
+                tx_bytes: 1_048_576,
+            })
+        );
+        assert_eq!(backend.calls(), vec![Call::WifiLink("wlan0".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn get_link_info_checks_the_device() {
+        let (backend, server) = server_with_config(
+            networks().with_interface(interface("wlan1", 4), true),
+            WifiConfig {
+                interfaces: vec!["wlan0".to_string()],
+                ..WifiConfig::default()
+            },
+        );
+
+        let status = server
+            .get_link_info(Request::new(wifi::GetLinkInfoRequest {
+                device: "wlan1".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let status = server
+            .get_link_info(Request::new(wifi::GetLinkInfoRequest {
+                device: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_link_info_follows_the_connection() {
+        let (backend, mut server) = server(networks().with_link("wlan0", link("Home 5G")));
+        server.service.interval = Duration::from_millis(10);
+
+        let mut stream = server
+            .watch_link_info(Request::new(wifi::WatchLinkInfoRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let link_info = stream.next().await.unwrap().unwrap().link.unwrap();
+        assert_eq!(link_info.signal, -52);
+
+        // Roamed to a weaker access point on 2.4 GHz
+        backend.set_link(
+            "wlan0",
+            Some(Link {
+                frequency: 2437,
+                signal: -71,
+                ..link("Home 5G")
+            }),
+        );
+        let link_info = stream.next().await.unwrap().unwrap().link.unwrap();
+        assert_eq!((link_info.channel, link_info.signal), (6, -71));
+
+        server
+            .disconnect_network(Request::new(wifi::DisconnectNetworkRequest {
+                device: "wlan0".to_string(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().link, None);
+    }
+
+    #[tokio::test]
+    async fn auto_power_save_policy_is_kept() {
+        let backend = networks()
+            .with_link("wlan0", link("Home 5G"))
+            .with_gateway("wlan0", Ipv4Addr::new(192, 168, 1, 1));
+        let state = Arc::new(StateFile::in_memory());
+        let server = server_with_state(backend, state.clone());
+        let policy = wifi::AutoPowerSavePolicy {
+            enabled: true,
+            device: "wlan0".to_string(),
+            jitter_threshold_ms: 30,
+            idle_seconds: 120,
+            battery_percent: 25,
+        };
+        let mut stream = server
+            .watch_auto_power_save(Request::new(wifi::WatchAutoPowerSaveRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!stream.next().await.unwrap().unwrap().status.unwrap().active);
+
+        let reply = server
+            .set_auto_power_save(Request::new(wifi::SetAutoPowerSaveRequest {
+                policy: Some(policy.clone()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(reply.policy, Some(policy.clone()));
+        assert!(stream.next().await.unwrap().unwrap().status.unwrap().active);
+        let reply = server
+            .get_auto_power_save(Request::new(wifi::GetAutoPowerSaveRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.policy, Some(policy));
+        let desired = state.desired().auto_power_save;
+        assert_eq!(
+            (desired.device.as_str(), desired.battery_percent),
+            ("wlan0", 25)
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_power_save_checks_the_policy() {
+        let (_, server) = server_with_config(
+            networks().with_interface(interface("wlan1", 4), true),
+            WifiConfig {
+                interfaces: vec!["wlan0".to_string()],
+                ..WifiConfig::default()
+            },
+        );
+        let policy = wifi::AutoPowerSavePolicy {
+            enabled: true,
+            device: "wlan0".to_string(),
+            jitter_threshold_ms: 20,
+            idle_seconds: 60,
+            battery_percent: 20,
+        };
+
+        for (policy, code) in [
+            (
+                wifi::AutoPowerSavePolicy {
+                    device: "wlan1".to_string(),
+                    ..policy.clone()
+                },
+                Code::PermissionDenied,
+            ),
+            (
+                wifi::AutoPowerSavePolicy {
+                    jitter_threshold_ms: 0,
+                    ..policy.clone()
+                },
+                Code::InvalidArgument,
+            ),
+            (
+                wifi::AutoPowerSavePolicy {
+                    battery_percent: 101,
+                    ..policy.clone()
+                },
+                Code::InvalidArgument,
+            ),
+        ] {
+            let status = server
+                .set_auto_power_save(Request::new(wifi::SetAutoPowerSaveRequest {
+                    policy: Some(policy),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), code);
+        }
+    }
+}
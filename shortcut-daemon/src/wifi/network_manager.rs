@@ -0,0 +1,1715 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+
+use shortcut_core::tokio;
+use zbus::fdo::PropertiesProxy;
+use zbus::names::InterfaceName;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::{dbus_proxy, CacheProperties, Connection, ProxyBuilder, ProxyDefault};
+
+/// How long a scan may take before the results found so far are returned.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long NetworkManager gets to associate, authenticate and get an address.
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// How often the progress of a scan or an activation is checked.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+const NETWORK_MANAGER: &str = "org.freedesktop.NetworkManager";
+const MANAGER_PATH: &str = "/org/freedesktop/NetworkManager";
+const SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+const ACCESS_POINT_INTERFACE: &str = "org.freedesktop.NetworkManager.AccessPoint";
+
+/// `NM_DEVICE_TYPE_WIFI`
+const DEVICE_TYPE_WIFI: u32 = 2;
+
+/// `NM_ACTIVE_CONNECTION_STATE_*`
+const STATE_ACTIVATED: u32 = 2;
+const STATE_DEACTIVATING: u32 = 3;
+const STATE_DEACTIVATED: u32 = 4;
+
+/// `NM_SETTING_WIRELESS_POWERSAVE_*`, the others leave it to the global default or the driver.
+const POWERSAVE_DISABLE: u32 = 2;
+const POWERSAVE_ENABLE: u32 = 3;
+
+/// `NM_MANAGER_RELOAD_FLAG_CONF`
+const RELOAD_FLAG_CONF: u32 = 0x1;
+
+/// `NM_802_11_AP_FLAGS_PRIVACY`, set for every network that isn't open.
+const AP_FLAGS_PRIVACY: u32 = 0x1;
+
+/// `NM_802_11_AP_SEC_KEY_MGMT_*` of the WPA and RSN flags.
+const KEY_MGMT_PSK: u32 = 0x100;
+const KEY_MGMT_802_1X: u32 = 0x200;
+const KEY_MGMT_SAE: u32 = 0x400;
+const KEY_MGMT_EAP_SUITE_B_192: u32 = 0x2000;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+trait Manager {
+    fn get_device_by_ip_iface(&self, iface: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn activate_connection(
+        &self,
+        connection: &ObjectPath<'_>,
+        device: &ObjectPath<'_>,
+        specific_object: &ObjectPath<'_>,
+    ) -> zbus::Result<OwnedObjectPath>;
+
+    fn add_and_activate_connection(
+        &self,
+        connection: ConnectionSettings,
+        device: &ObjectPath<'_>,
+        specific_object: &ObjectPath<'_>,
+    ) -> zbus::Result<(OwnedObjectPath, OwnedObjectPath)>;
+
+    fn reload(&self, flags: u32) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.Device",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait Device {
+    fn disconnect(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(property)]
+    fn device_type(&self) -> zbus::Result<u32>;
+
+    /// `/` while disconnected.
+    #[dbus_proxy(property)]
+    fn active_connection(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.Device.Wireless",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait Wireless {
+    fn request_scan(&self, options: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    fn get_all_access_points(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[dbus_proxy(property)]
+    fn active_access_point(&self) -> zbus::Result<OwnedObjectPath>;
+
+    /// When the last scan finished, in `CLOCK_BOOTTIME` milliseconds.
+    #[dbus_proxy(property)]
+    fn last_scan(&self) -> zbus::Result<i64>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.Settings",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager/Settings"
+)]
+trait Settings {
+    fn list_connections(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.Settings.Connection",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait SettingsConnection {
+    fn get_settings(&self) -> zbus::Result<ConnectionSettings>;
+
+    fn get_secrets(&self, setting_name: &str) -> zbus::Result<ConnectionSettings>;
+
+    fn update(&self, properties: ConnectionSettings) -> zbus::Result<()>;
+
+    fn delete(&self) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.Connection.Active",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait ActiveConnection {
+    #[dbus_proxy(property)]
+    fn state(&self) -> zbus::Result<u32>;
+
+    /// The saved connection that was activated.
+    #[dbus_proxy(property)]
+    fn connection(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+/// The settings of a connection, by setting name and property.
+type ConnectionSettings = HashMap<String, HashMap<String, OwnedValue>>;
+
+#[derive(Debug)]
+pub enum Error {
+    Bus(zbus::Error),
+    /// NetworkManager knows no device with the interface name.
+    NoSuchDevice(String),
+    /// The device exists but isn't a WiFi device.
+    NotWireless(String),
+    /// No access point or saved network has the SSID.
+    NoSuchNetwork(String),
+    /// No saved network has the UUID.
+    NoSuchConnection(String),
+    /// The network is secured and not saved yet.
+    PassphraseRequired(String),
+    /// The network needs credentials other than a passphrase.
+    Unsupported(String, Security),
+    /// NetworkManager gave up connecting, usually because of a wrong passphrase.
+    ActivationFailed(String),
+    /// The device has no active connection.
+    NotConnected(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bus(err) => write!(f, "D-Bus error: {err}"),
+            Error::NoSuchDevice(iface) => write!(f, "no such wifi device: {iface}"),
+            Error::NotWireless(iface) => write!(f, "{iface} is not a wifi device"),
+            Error::NoSuchNetwork(ssid) => write!(f, "no such wifi network: {ssid}"),
+            Error::NoSuchConnection(uuid) => write!(f, "no such saved network: {uuid}"),
+            Error::PassphraseRequired(ssid) => write!(f, "{ssid} needs a passphrase"),
+            Error::Unsupported(ssid, security) => {
+                write!(f, "{ssid} uses {security} security, which is not supported")
+            }
+            Error::ActivationFailed(ssid) => write!(f, "unable to connect to {ssid}"),
+            Error::NotConnected(iface) => write!(f, "{iface} is not connected"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<zbus::Error> for Error {
+    fn from(err: zbus::Error) -> Self {
+        Error::Bus(err)
+    }
+}
+
+impl From<zbus::fdo::Error> for Error {
+    fn from(err: zbus::fdo::Error) -> Self {
+        Error::Bus(err.into())
+    }
+}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoSuchDevice(iface) => {
+                shortcut_core::Error::not_found(format!("wifi device {iface}"))
+            }
+            Error::NoSuchNetwork(ssid) => {
+                shortcut_core::Error::not_found(format!("wifi network {ssid}"))
+            }
+            Error::NoSuchConnection(uuid) => {
+                shortcut_core::Error::not_found(format!("saved network {uuid}"))
+            }
+            Error::NotWireless(_) | Error::PassphraseRequired(_) | Error::Unsupported(..) => {
+                shortcut_core::Error::InvalidArgument(err.to_string())
+            }
+            Error::ActivationFailed(_) | Error::NotConnected(_) => {
+                shortcut_core::Error::Unavailable(err.to_string())
+            }
+            Error::Bus(zbus::Error::MethodError(ref name, _, _)) => match name.as_str() {
+                "org.freedesktop.DBus.Error.AccessDenied"
+                | "org.freedesktop.NetworkManager.PermissionDenied" => {
+                    shortcut_core::Error::PermissionDenied(err.to_string())
+                }
+                "org.freedesktop.DBus.Error.ServiceUnknown" => {
+                    shortcut_core::Error::Unavailable(err.to_string())
+                }
+                _ => shortcut_core::Error::Internal(err.to_string()),
+            },
+            Error::Bus(_) => shortcut_core::Error::Unavailable(err.to_string()),
+        }
+    }
+}
+
+/// The kind of credentials a network asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    Open,
+    Wep,
+    /// WPA or WPA2 with a passphrase, including WPA2/WPA3 transition networks.
+    WpaPsk,
+    /// WPA3 with a passphrase only.
+    Sae,
+    /// 802.1X, which needs a username or certificate.
+    Enterprise,
+}
+
+impl Security {
+    fn from_flags(flags: u32, wpa_flags: u32, rsn_flags: u32) -> Self {
+        let key_mgmt = wpa_flags | rsn_flags;
+        if key_mgmt & (KEY_MGMT_802_1X | KEY_MGMT_EAP_SUITE_B_192) != 0 {
+            Security::Enterprise
+        } else if key_mgmt & KEY_MGMT_PSK != 0 {
+            Security::WpaPsk
+        } else if key_mgmt & KEY_MGMT_SAE != 0 {
+            Security::Sae
+        } else if key_mgmt == 0 && flags & AP_FLAGS_PRIVACY != 0 {
+            Security::Wep
+        } else {
+            // Including OWE, which encrypts without asking for anything
+            Security::Open
+        }
+    }
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Security::Open => write!(f, "no"),
+            Security::Wep => write!(f, "WEP"),
+            Security::WpaPsk => write!(f, "WPA"),
+            Security::Sae => write!(f, "WPA3"),
+            Security::Enterprise => write!(f, "enterprise"),
+        }
+    }
+}
+
+impl From<Security> for shortcut_core::wifi::Security {
+    fn from(security: Security) -> Self {
+        match security {
+            Security::Open => Self::Open,
+            Security::Wep => Self::Wep,
+            Security::WpaPsk => Self::WpaPsk,
+            Security::Sae => Self::Sae,
+            Security::Enterprise => Self::Enterprise,
+        }
+    }
+}
+
+/// An access point seen by the last scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPoint {
+    pub ssid: String,
+    pub bssid: String,
+    /// Signal quality in percent.
+    pub strength: u8,
+    /// In MHz.
+    pub frequency: u32,
+    pub security: Security,
+    /// Whether the device is connected to this access point.
+    pub active: bool,
+    /// Whether a saved network exists for the SSID.
+    pub saved: bool,
+}
+
+impl From<AccessPoint> for shortcut_core::wifi::AccessPoint {
+    fn from(ap: AccessPoint) -> Self {
+        Self {
+            ssid: ap.ssid,
+            bssid: ap.bssid,
+            signal_percent: u32::from(ap.strength),
+            frequency: ap.frequency,
+            security: shortcut_core::wifi::Security::from(ap.security) as i32,
+            active: ap.active,
+            saved: ap.saved,
+        }
+    }
+}
+
+/// A WiFi connection profile NetworkManager keeps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedNetwork {
+    pub id: String,
+    pub uuid: String,
+    pub ssid: String,
+    /// Whether NetworkManager joins the network on its own when in range.
+    pub autoconnect: bool,
+    /// Networks with a higher priority are joined first.
+    pub priority: i32,
+}
+
+impl SavedNetwork {
+    /// `None` for anything but WiFi connections.
+    fn from_settings(settings: &ConnectionSettings) -> Option<Self> {
+        if setting::<&str>(settings, "connection", "type")? != "802-11-wireless" {
+            return None;
+        }
+        Some(Self {
+            id: setting::<&str>(settings, "connection", "id")?.to_string(),
+            uuid: setting::<&str>(settings, "connection", "uuid")?.to_string(),
+            ssid: ssid(settings.get("802-11-wireless")?.get("ssid")?)?,
+            // Properties at their default are left out
+            autoconnect: setting(settings, "connection", "autoconnect").unwrap_or(true),
+            priority: setting(settings, "connection", "autoconnect-priority").unwrap_or(0),
+        })
+    }
+}
+
+impl From<SavedNetwork> for shortcut_core::wifi::SavedNetwork {
+    fn from(network: SavedNetwork) -> Self {
+        Self {
+            id: network.id,
+            uuid: network.uuid,
+            ssid: network.ssid,
+            autoconnect: network.autoconnect,
+            priority: network.priority,
+        }
+    }
+}
+
+fn setting<'a, T>(settings: &'a ConnectionSettings, name: &str, property: &str) -> Option<T>
+where
+    T: TryFrom<&'a OwnedValue>,
+{
+    settings
+        .get(name)
+        .and_then(|properties| properties.get(property))
+        .and_then(|value| T::try_from(value).ok())
+}
+
+/// SSIDs are raw bytes, they are shown like NetworkManager does.
+fn ssid(value: &OwnedValue) -> Option<String> {
+    let bytes = Vec::<u8>::try_from(value.clone()).ok()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn access_point(
+    properties: &HashMap<String, OwnedValue>,
+    active: bool,
+    saved: &HashSet<String>,
+) -> Option<AccessPoint> {
+    let flag = |name: &str| {
+        properties
+            .get(name)
+            .and_then(|value| u32::try_from(value).ok())
+            .unwrap_or(0)
+    };
+    let ssid = ssid(properties.get("Ssid")?)?;
+    // Hidden networks can only be joined by name
+    if ssid.is_empty() {
+        return None;
+    }
+
+    Some(AccessPoint {
+        saved: saved.contains(&ssid),
+        bssid: <&str>::try_from(properties.get("HwAddress")?)
+            .ok()?
+            .to_string(),
+        strength: u8::try_from(properties.get("Strength")?).ok()?,
+        frequency: flag("Frequency"),
+        security: Security::from_flags(flag("Flags"), flag("WpaFlags"), flag("RsnFlags")),
+        active,
+        ssid,
+    })
+}
+
+fn value(value: impl Into<Value<'static>>) -> OwnedValue {
+    OwnedValue::from(value.into())
+}
+
+/// Settings for a new connection to `ap`, NetworkManager fills in the rest from the access point.
+fn new_connection(ap: &AccessPoint, passphrase: Option<&str>) -> Result<ConnectionSettings, Error> {
+    let key_mgmt = match (ap.security, passphrase) {
+        (Security::Open, _) => None,
+        (Security::WpaPsk, Some(_)) => Some("wpa-psk"),
+        (Security::Sae, Some(_)) => Some("sae"),
+        (Security::WpaPsk | Security::Sae, None) => {
+            return Err(Error::PassphraseRequired(ap.ssid.clone()))
+        }
+        (Security::Wep | Security::Enterprise, _) => {
+            return Err(Error::Unsupported(ap.ssid.clone(), ap.security))
+        }
+    };
+
+    let mut settings = ConnectionSettings::new();
+    settings.insert(
+        "connection".to_string(),
+        HashMap::from([
+            ("id".to_string(), value(ap.ssid.clone())),
+            ("type".to_string(), value("802-11-wireless")),
+        ]),
+    );
+    settings.insert(
+        "802-11-wireless".to_string(),
+        HashMap::from([
+            ("ssid".to_string(), value(ap.ssid.as_bytes().to_vec())),
+            ("mode".to_string(), value("infrastructure")),
+        ]),
+    );
+    if let (Some(key_mgmt), Some(passphrase)) = (key_mgmt, passphrase) {
+        settings.insert(
+            "802-11-wireless-security".to_string(),
+            HashMap::from([
+                ("key-mgmt".to_string(), value(key_mgmt)),
+                ("psk".to_string(), value(passphrase.to_string())),
+            ]),
+        );
+    }
+    Ok(settings)
+}
+
+/// What NetworkManager's `powersave` value turns power save to, `None` when it leaves it alone.
+pub(crate) fn power_save_from(value: u32) -> Option<bool> {
+    match value {
+        POWERSAVE_DISABLE => Some(false),
+        POWERSAVE_ENABLE => Some(true),
+        _ => None,
+    }
+}
+
+pub(crate) fn power_save_value(enabled: bool) -> u32 {
+    if enabled {
+        POWERSAVE_ENABLE
+    } else {
+        POWERSAVE_DISABLE
+    }
+}
+
+fn is_method_error(err: &zbus::Error, error_name: &str) -> bool {
+    matches!(err, zbus::Error::MethodError(name, _, _) if name.as_str() == error_name)
+}
+
+/// Client for `org.freedesktop.NetworkManager` on a D-Bus connection.
+#[derive(Debug, Clone)]
+pub struct NetworkManager {
+    conn: Connection,
+}
+
+impl NetworkManager {
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    /// A proxy that reads properties when asked, NetworkManager changes them all the time.
+    async fn proxy<T>(&self, path: impl Into<ObjectPath<'static>>) -> Result<T, Error>
+    where
+        T: From<zbus::Proxy<'static>> + ProxyDefault,
+    {
+        Ok(ProxyBuilder::new(&self.conn)
+            .destination(NETWORK_MANAGER)?
+            .path(path.into())?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?)
+    }
+
+    /// The object path of the WiFi device with the interface name `iface`.
+    async fn wireless_device(&self, iface: &str) -> Result<OwnedObjectPath, Error> {
+        let manager: ManagerProxy<'_> = self
+            .proxy(ObjectPath::from_static_str_unchecked(MANAGER_PATH))
+            .await?;
+        let path = match manager.get_device_by_ip_iface(iface).await {
+            Ok(path) => path,
+            Err(err) if is_method_error(&err, "org.freedesktop.NetworkManager.UnknownDevice") => {
+                return Err(Error::NoSuchDevice(iface.to_string()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let device: DeviceProxy<'_> = self.proxy(path.clone()).await?;
+        if device.device_type().await? != DEVICE_TYPE_WIFI {
+            return Err(Error::NotWireless(iface.to_string()));
+        }
+        Ok(path)
+    }
+
+    /// WiFi connections with their object paths, highest priority first.
+    async fn saved(&self) -> Result<Vec<(OwnedObjectPath, SavedNetwork)>, Error> {
+        let settings: SettingsProxy<'_> = self
+            .proxy(ObjectPath::from_static_str_unchecked(SETTINGS_PATH))
+            .await?;
+
+        let mut networks = Vec::new();
+        for path in settings.list_connections().await? {
+            let connection: SettingsConnectionProxy<'_> = self.proxy(path.clone()).await?;
+            // Connections can be deleted while they are read
+            match connection.get_settings().await {
+                Ok(settings) => {
+                    if let Some(network) = SavedNetwork::from_settings(&settings) {
+                        networks.push((path, network));
+                    }
+                }
+                Err(err) => tracing::debug!("unable to read connection {}: {err}", path.as_str()),
+            }
+        }
+
+        networks.sort_by(|(_, a), (_, b)| {
+            b.priority
+                .cmp(&a.priority)
+                .then(a.id.cmp(&b.id))
+                .then(a.uuid.cmp(&b.uuid))
+        });
+        Ok(networks)
+    }
+
+    /// Access points of the device with their object paths, strongest first.
+    async fn access_points_of(
+        &self,
+        device: &OwnedObjectPath,
+    ) -> Result<Vec<(OwnedObjectPath, AccessPoint)>, Error> {
+        let wireless: WirelessProxy<'_> = self.proxy(device.clone()).await?;
+        let active = wireless.active_access_point().await?;
+        let saved: HashSet<_> = self
+            .saved()
+            .await?
+            .into_iter()
+            .map(|(_, network)| network.ssid)
+            .collect();
+
+        let mut access_points = Vec::new();
+        for path in wireless.get_all_access_points().await? {
+            let properties: PropertiesProxy<'_> = self.proxy(path.clone()).await?;
+            let interface = InterfaceName::from_static_str_unchecked(ACCESS_POINT_INTERFACE);
+            // Access points come and go while they are read
+            let properties = match properties.get_all(interface).await {
+                Ok(properties) => properties,
+                Err(_) => continue,
+            };
+            if let Some(ap) = access_point(&properties, path == active, &saved) {
+                access_points.push((path, ap));
+            }
+        }
+
+        access_points.sort_by(|(_, a), (_, b)| {
+            b.strength
+                .cmp(&a.strength)
+                .then(a.ssid.cmp(&b.ssid))
+                .then(a.bssid.cmp(&b.bssid))
+        });
+        Ok(access_points)
+    }
+
+    /// The access points the device saw last, strongest first.
+    pub async fn access_points(&self, iface: &str) -> Result<Vec<AccessPoint>, Error> {
+        let device = self.wireless_device(iface).await?;
+        Ok(self
+            .access_points_of(&device)
+            .await?
+            .into_iter()
+            .map(|(_, ap)| ap)
+            .collect())
+    }
+
+    /// Scans and returns the access points found, strongest first.
+    pub async fn scan(&self, iface: &str) -> Result<Vec<AccessPoint>, Error> {
+        let device = self.wireless_device(iface).await?;
+        let wireless: WirelessProxy<'_> = self.proxy(device.clone()).await?;
+        let last_scan = wireless.last_scan().await?;
+
+        match wireless.request_scan(HashMap::new()).await {
+            Ok(()) => {
+                let finished = tokio::time::timeout(SCAN_TIMEOUT, async {
+                    while wireless.last_scan().await? == last_scan {
+                        tokio::time::sleep(PROGRESS_INTERVAL).await;
+                    }
+                    Ok::<_, Error>(())
+                })
+                .await;
+                match finished {
+                    Ok(result) => result?,
+                    Err(_) => tracing::warn!("scanning on {iface} did not finish in time"),
+                }
+            }
+            // NetworkManager refuses to scan again right after a scan, those results will do
+            Err(err)
+                if is_method_error(&err, "org.freedesktop.NetworkManager.Device.NotAllowed") =>
+            {
+                tracing::debug!("not scanning on {iface}: {err}");
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        self.access_points(iface).await
+    }
+
+    /// WiFi connections NetworkManager keeps, highest priority first.
+    pub async fn saved_networks(&self) -> Result<Vec<SavedNetwork>, Error> {
+        Ok(self
+            .saved()
+            .await?
+            .into_iter()
+            .map(|(_, network)| network)
+            .collect())
+    }
+
+    /// Connects to `ssid` and returns the access points once the connection is up.
+    ///
+    /// A saved network is reused, with its passphrase replaced when one is given. Otherwise a
+    /// new connection is saved, and removed again when connecting fails.
+    pub async fn connect(
+        &self,
+        iface: &str,
+        ssid: &str,
+        passphrase: Option<&str>,
+    ) -> Result<Vec<AccessPoint>, Error> {
+        let device = self.wireless_device(iface).await?;
+        let manager: ManagerProxy<'_> = self
+            .proxy(ObjectPath::from_static_str_unchecked(MANAGER_PATH))
+            .await?;
+        let no_object = ObjectPath::from_static_str_unchecked("/");
+        let saved = self
+            .saved()
+            .await?
+            .into_iter()
+            .find(|(_, network)| network.ssid == ssid);
+
+        let mut added = None;
+        let mut replaced = None;
+        let active = match saved {
+            Some((path, _)) => {
+                if let Some(passphrase) = passphrase {
+                    let previous = self.set_passphrase(&path, Some(passphrase)).await?;
+                    replaced = Some((path.clone(), previous));
+                }
+                manager
+                    .activate_connection(&path, &device, &no_object)
+                    .await?
+            }
+            None => {
+                let (ap_path, ap) = self
+                    .access_points_of(&device)
+                    .await?
+                    .into_iter()
+                    .find(|(_, ap)| ap.ssid == ssid)
+                    .ok_or_else(|| Error::NoSuchNetwork(ssid.to_string()))?;
+                let settings = new_connection(&ap, passphrase)?;
+                let (path, active) = manager
+                    .add_and_activate_connection(settings, &device, &ap_path)
+                    .await?;
+                added = Some(path);
+                active
+            }
+        };
+
+        if let Err(err) = self.wait_for_activation(active, ssid).await {
+            if let Some(path) = added {
+                let removed = async {
+                    let connection: SettingsConnectionProxy<'_> = self.proxy(path).await?;
+                    Ok::<_, Error>(connection.delete().await?)
+                };
+                if let Err(err) = removed.await {
+                    tracing::warn!("unable to remove the connection to {ssid}: {err}");
+                }
+            }
+            // A mistyped passphrase must not lock out the one that worked
+            if let Some((path, previous)) = replaced {
+                if let Err(err) = self.set_passphrase(&path, previous.as_deref()).await {
+                    tracing::warn!("unable to restore the passphrase of {ssid}: {err}");
+                }
+            }
+            return Err(err);
+        }
+
+        self.access_points(iface).await
+    }
+
+    /// Replaces the passphrase of the saved network at `path`, returning the previous one. Open
+    /// networks have none.
+    async fn set_passphrase(
+        &self,
+        path: &OwnedObjectPath,
+        passphrase: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        let mut previous = None;
+        self.update(path, |settings| {
+            if let Some(security) = settings.get_mut("802-11-wireless-security") {
+                let replaced = match passphrase {
+                    Some(passphrase) => {
+                        security.insert("psk".to_string(), value(passphrase.to_string()))
+                    }
+                    None => security.remove("psk"),
+                };
+                previous = replaced
+                    .as_ref()
+                    .and_then(|psk| <&str>::try_from(psk).ok())
+                    .map(str::to_string);
+            }
+        })
+        .await?;
+        Ok(previous)
+    }
+
+    /// Changes the settings of the connection at `path`.
+    ///
+    /// `Update` replaces every setting, so the secrets `GetSettings` leaves out are sent along.
+    async fn update<F>(&self, path: &OwnedObjectPath, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut ConnectionSettings),
+    {
+        let connection: SettingsConnectionProxy<'_> = self.proxy(path.clone()).await?;
+        let mut settings = connection.get_settings().await?;
+        if settings.contains_key("802-11-wireless-security") {
+            match connection.get_secrets("802-11-wireless-security").await {
+                Ok(secrets) => {
+                    for (name, properties) in secrets {
+                        settings.entry(name).or_default().extend(properties);
+                    }
+                }
+                // Secrets kept by a user's agent aren't stored by NetworkManager anyway
+                Err(err) => tracing::debug!("unable to read secrets of {}: {err}", path.as_str()),
+            }
+        }
+
+        f(&mut settings);
+        Ok(connection.update(settings).await?)
+    }
+
+    async fn find_saved(&self, uuid: &str) -> Result<OwnedObjectPath, Error> {
+        self.saved()
+            .await?
+            .into_iter()
+            .find(|(_, network)| network.uuid == uuid)
+            .map(|(path, _)| path)
+            .ok_or_else(|| Error::NoSuchConnection(uuid.to_string()))
+    }
+
+    /// Deletes the saved network and returns the ones left.
+    pub async fn forget(&self, uuid: &str) -> Result<Vec<SavedNetwork>, Error> {
+        let path = self.find_saved(uuid).await?;
+        let connection: SettingsConnectionProxy<'_> = self.proxy(path).await?;
+        connection.delete().await?;
+        self.saved_networks().await
+    }
+
+    pub async fn set_autoconnect(
+        &self,
+        uuid: &str,
+        enabled: bool,
+    ) -> Result<Vec<SavedNetwork>, Error> {
+        let path = self.find_saved(uuid).await?;
+        self.update(&path, |settings| {
+            settings
+                .entry("connection".to_string())
+                .or_default()
+                .insert("autoconnect".to_string(), value(enabled));
+        })
+        .await?;
+        self.saved_networks().await
+    }
+
+    pub async fn set_priority(
+        &self,
+        uuid: &str,
+        priority: i32,
+    ) -> Result<Vec<SavedNetwork>, Error> {
+        let path = self.find_saved(uuid).await?;
+        self.update(&path, |settings| {
+            settings
+                .entry("connection".to_string())
+                .or_default()
+                .insert("autoconnect-priority".to_string(), value(priority));
+        })
+        .await?;
+        self.saved_networks().await
+    }
+
+    /// The saved connection the device is connected with.
+    async fn active_connection(&self, iface: &str) -> Result<Option<OwnedObjectPath>, Error> {
+        let path = self.wireless_device(iface).await?;
+        let device: DeviceProxy<'_> = self.proxy(path).await?;
+        let active = device.active_connection().await?;
+        if active.as_str() == "/" {
+            return Ok(None);
+        }
+
+        let active: ActiveConnectionProxy<'_> = self.proxy(active).await?;
+        Ok(Some(active.connection().await?))
+    }
+
+    /// The power save setting of the connection the device is connected with, `None` when it is
+    /// left to the global default or the device isn't connected.
+    pub async fn power_save(&self, iface: &str) -> Result<Option<bool>, Error> {
+        let path = match self.active_connection(iface).await {
+            Ok(Some(path)) => path,
+            // Devices NetworkManager doesn't manage have no connection to keep it in
+            Ok(None) | Err(Error::NoSuchDevice(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let connection: SettingsConnectionProxy<'_> = self.proxy(path).await?;
+        let settings = connection.get_settings().await?;
+        Ok(setting(&settings, "802-11-wireless", "powersave").and_then(power_save_from))
+    }
+
+    /// Saves power save in the connection the device is connected with. NetworkManager applies
+    /// it the next time the connection is activated.
+    pub async fn set_power_save(&self, iface: &str, enabled: bool) -> Result<(), Error> {
+        let path = self
+            .active_connection(iface)
+            .await?
+            .ok_or_else(|| Error::NotConnected(iface.to_string()))?;
+        self.update(&path, |settings| {
+            settings
+                .entry("802-11-wireless".to_string())
+                .or_default()
+                .insert("powersave".to_string(), value(power_save_value(enabled)));
+        })
+        .await
+    }
+
+    /// Has NetworkManager read its configuration files again.
+    pub async fn reload_config(&self) -> Result<(), Error> {
+        let manager: ManagerProxy<'_> = self
+            .proxy(ObjectPath::from_static_str_unchecked(MANAGER_PATH))
+            .await?;
+        Ok(manager.reload(RELOAD_FLAG_CONF).await?)
+    }
+
+    async fn wait_for_activation(&self, active: OwnedObjectPath, ssid: &str) -> Result<(), Error> {
+        let connection: ActiveConnectionProxy<'_> = self.proxy(active).await?;
+        let activated = tokio::time::timeout(ACTIVATION_TIMEOUT, async {
+            loop {
+                match connection.state().await {
+                    Ok(STATE_ACTIVATED) => return true,
+                    // The active connection goes away soon after it failed
+                    Ok(STATE_DEACTIVATING | STATE_DEACTIVATED) | Err(_) => return false,
+                    Ok(_) => tokio::time::sleep(PROGRESS_INTERVAL).await,
+                }
+            }
+        })
+        .await;
+
+        match activated {
+            Ok(true) => Ok(()),
+            _ => Err(Error::ActivationFailed(ssid.to_string())),
+        }
+    }
+
+    /// Disconnects the device until it is told to connect again.
+    pub async fn disconnect(&self, iface: &str) -> Result<Vec<AccessPoint>, Error> {
+        let path = self.wireless_device(iface).await?;
+        let device: DeviceProxy<'_> = self.proxy(path).await?;
+        match device.disconnect().await {
+            Err(err)
+                if !is_method_error(&err, "org.freedesktop.NetworkManager.Device.NotActive") =>
+            {
+                return Err(err.into())
+            }
+            _ => {}
+        }
+
+        self.access_points(iface).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use shortcut_core::tokio;
+    use zbus::{dbus_interface, fdo, ConnectionBuilder, DBusError, ObjectServer};
+
+    use super::*;
+    use crate::test_bus::{self, require_bus, Bus};
+
+    const WLAN0: &str = "/org/freedesktop/NetworkManager/Devices/3";
+    const ETH0: &str = "/org/freedesktop/NetworkManager/Devices/2";
+
+    #[derive(DBusError, Debug)]
+    #[dbus_error(prefix = "org.freedesktop.NetworkManager")]
+    enum StubError {
+        #[dbus_error(zbus_error)]
+        ZBus(zbus::Error),
+        UnknownDevice(String),
+    }
+
+    /// What the stub objects share, like NetworkManager's own state.
+    #[derive(Default)]
+    struct Network {
+        /// Object paths and SSIDs of the access points in range.
+        access_points: Vec<(OwnedObjectPath, String)>,
+        active: Option<OwnedObjectPath>,
+        /// The active connection of the WiFi device.
+        active_connection: Option<OwnedObjectPath>,
+        connections: Vec<(OwnedObjectPath, ConnectionSettings)>,
+        last_scan: i64,
+        next_id: u32,
+        /// The configuration reloads asked for.
+        reloads: Vec<u32>,
+    }
+
+    impl Network {
+        fn next_path(&mut self, prefix: &str) -> OwnedObjectPath {
+            self.next_id += 1;
+            OwnedObjectPath::try_from(format!("{prefix}/{}", self.next_id)).unwrap()
+        }
+
+        /// Connects unless the passphrase is "wrong", returning the active connection state.
+        fn activate(&mut self, connection: &OwnedObjectPath) -> u32 {
+            let settings = match self.connections.iter().find(|(path, _)| path == connection) {
+                Some((_, settings)) => settings,
+                None => return STATE_DEACTIVATED,
+            };
+            let passphrase: Option<&str> = setting(settings, "802-11-wireless-security", "psk");
+            if passphrase == Some("wrong") {
+                return STATE_DEACTIVATED;
+            }
+            let wanted = ssid(&settings["802-11-wireless"]["ssid"]).unwrap();
+            self.active = self
+                .access_points
+                .iter()
+                .find(|(_, ssid)| *ssid == wanted)
+                .map(|(path, _)| path.clone());
+            STATE_ACTIVATED
+        }
+    }
+
+    type Shared = Arc<Mutex<Network>>;
+
+    struct StubManager {
+        network: Shared,
+    }
+
+    impl StubManager {
+        async fn serve_active(
+            &self,
+            server: &ObjectServer,
+            connection: OwnedObjectPath,
+            state: u32,
+        ) -> OwnedObjectPath {
+            let path = {
+                let mut network = self.network.lock().unwrap();
+                let path = network.next_path("/org/freedesktop/NetworkManager/ActiveConnection");
+                if state == STATE_ACTIVATED {
+                    network.active_connection = Some(path.clone());
+                }
+                path
+            };
+            let active = StubActive { state, connection };
+            server.at(&path, active).await.unwrap();
+            path
+        }
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager")]
+    impl StubManager {
+        fn get_device_by_ip_iface(&self, iface: &str) -> Result<OwnedObjectPath, StubError> {
+            match iface {
+                "wlan0" => Ok(OwnedObjectPath::try_from(WLAN0).unwrap()),
+                "eth0" => Ok(OwnedObjectPath::try_from(ETH0).unwrap()),
+                _ => Err(StubError::UnknownDevice("No device found".to_string())),
+            }
+        }
+
+        async fn activate_connection(
+            &self,
+            connection: OwnedObjectPath,
+            _device: OwnedObjectPath,
+            _specific_object: OwnedObjectPath,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> OwnedObjectPath {
+            let state = self.network.lock().unwrap().activate(&connection);
+            self.serve_active(server, connection, state).await
+        }
+
+        async fn add_and_activate_connection(
+            &self,
+            mut settings: ConnectionSettings,
+            _device: OwnedObjectPath,
+            _specific_object: OwnedObjectPath,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> (OwnedObjectPath, OwnedObjectPath) {
+            let (path, state) = {
+                let mut network = self.network.lock().unwrap();
+                let path = network.next_path(SETTINGS_PATH);
+                // Completed like NetworkManager does
+                if let Some(connection) = settings.get_mut("connection") {
+                    let uuid = format!("uuid-{}", path.as_str());
+                    connection.insert("uuid".to_string(), value(uuid));
+                }
+                network.connections.push((path.clone(), settings));
+                let state = network.activate(&path);
+                (path, state)
+            };
+            let connection = StubConnection {
+                path: path.clone(),
+                network: self.network.clone(),
+            };
+            server.at(&path, connection).await.unwrap();
+            let active = self.serve_active(server, path.clone(), state).await;
+            (path, active)
+        }
+
+        fn reload(&self, flags: u32) {
+            self.network.lock().unwrap().reloads.push(flags);
+        }
+    }
+
+    struct StubDevice {
+        device_type: u32,
+        network: Shared,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager.Device")]
+    impl StubDevice {
+        fn disconnect(&self) {
+            let mut network = self.network.lock().unwrap();
+            network.active = None;
+            network.active_connection = None;
+        }
+
+        #[dbus_interface(property)]
+        fn device_type(&self) -> u32 {
+            self.device_type
+        }
+
+        #[dbus_interface(property)]
+        fn active_connection(&self) -> OwnedObjectPath {
+            let network = self.network.lock().unwrap();
+            match &network.active_connection {
+                Some(path) if self.device_type == DEVICE_TYPE_WIFI => path.clone(),
+                _ => OwnedObjectPath::try_from("/").unwrap(),
+            }
+        }
+    }
+
+    struct StubWireless {
+        network: Shared,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager.Device.Wireless")]
+    impl StubWireless {
+        /// Finds a network that was out of range before.
+        async fn request_scan(
+            &self,
+            _options: HashMap<String, OwnedValue>,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) {
+            let path = {
+                let mut network = self.network.lock().unwrap();
+                network.last_scan += 1;
+                let path = network.next_path("/org/freedesktop/NetworkManager/AccessPoint");
+                network
+                    .access_points
+                    .push((path.clone(), "Neighbour".to_string()));
+                path
+            };
+            let ap = stub_ap("Neighbour", "AA:BB:CC:DD:EE:04", 20, 0, 0);
+            server.at(&path, ap).await.unwrap();
+        }
+
+        fn get_all_access_points(&self) -> Vec<OwnedObjectPath> {
+            let network = self.network.lock().unwrap();
+            network
+                .access_points
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect()
+        }
+
+        #[dbus_interface(property)]
+        fn active_access_point(&self) -> OwnedObjectPath {
+            let network = self.network.lock().unwrap();
+            network
+                .active
+                .clone()
+                .unwrap_or_else(|| OwnedObjectPath::try_from("/").unwrap())
+        }
+
+        #[dbus_interface(property)]
+        fn last_scan(&self) -> i64 {
+            self.network.lock().unwrap().last_scan
+        }
+    }
+
+    struct StubAccessPoint {
+        ssid: Vec<u8>,
+        hw_address: String,
+        strength: u8,
+        wpa_flags: u32,
+        rsn_flags: u32,
+    }
+
+    fn stub_ap(
+        ssid: &str,
+        hw_address: &str,
+        strength: u8,
+        wpa_flags: u32,
+        rsn_flags: u32,
+    ) -> StubAccessPoint {
+        StubAccessPoint {
+            ssid: ssid.as_bytes().to_vec(),
+            hw_address: hw_address.to_string(),
+            strength,
+            wpa_flags,
+            rsn_flags,
+        }
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager.AccessPoint")]
+    impl StubAccessPoint {
+        #[dbus_interface(property)]
+        fn ssid(&self) -> Vec<u8> {
+            self.ssid.clone()
+        }
+
+        #[dbus_interface(property)]
+        fn hw_address(&self) -> String {
+            self.hw_address.clone()
+        }
+
+        #[dbus_interface(property)]
+        fn strength(&self) -> u8 {
+            self.strength
+        }
+
+        #[dbus_interface(property)]
+        fn frequency(&self) -> u32 {
+            5180
+        }
+
+        #[dbus_interface(property)]
+        fn flags(&self) -> u32 {
+            u32::from(self.wpa_flags | self.rsn_flags != 0)
+        }
+
+        #[dbus_interface(property)]
+        fn wpa_flags(&self) -> u32 {
+            self.wpa_flags
+        }
+
+        #[dbus_interface(property)]
+        fn rsn_flags(&self) -> u32 {
+            self.rsn_flags
+        }
+    }
+
+    struct StubSettings {
+        network: Shared,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager.Settings")]
+    impl StubSettings {
+        fn list_connections(&self) -> Vec<OwnedObjectPath> {
+            let network = self.network.lock().unwrap();
+            network
+                .connections
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect()
+        }
+    }
+
+    struct StubConnection {
+        path: OwnedObjectPath,
+        network: Shared,
+    }
+
+    impl StubConnection {
+        fn settings(&self) -> fdo::Result<ConnectionSettings> {
+            let network = self.network.lock().unwrap();
+            network
+                .connections
+                .iter()
+                .find(|(path, _)| *path == self.path)
+                .map(|(_, settings)| settings.clone())
+                .ok_or_else(|| fdo::Error::UnknownObject(self.path.to_string()))
+        }
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager.Settings.Connection")]
+    impl StubConnection {
+        /// Without secrets, like NetworkManager.
+        fn get_settings(&self) -> fdo::Result<ConnectionSettings> {
+            let mut settings = self.settings()?;
+            if let Some(security) = settings.get_mut("802-11-wireless-security") {
+                security.remove("psk");
+            }
+            Ok(settings)
+        }
+
+        fn get_secrets(&self, setting_name: &str) -> fdo::Result<ConnectionSettings> {
+            let settings = self.settings()?;
+            let psk = settings
+                .get(setting_name)
+                .and_then(|properties| properties.get("psk"))
+                .cloned();
+            Ok(psk
+                .map(|psk| {
+                    ConnectionSettings::from([(
+                        setting_name.to_string(),
+                        HashMap::from([("psk".to_string(), psk)]),
+                    )])
+                })
+                .unwrap_or_default())
+        }
+
+        fn update(&self, properties: ConnectionSettings) {
+            let mut network = self.network.lock().unwrap();
+            for (path, settings) in network.connections.iter_mut() {
+                if *path == self.path {
+                    *settings = properties.clone();
+                }
+            }
+        }
+
+        async fn delete(&self, #[zbus(object_server)] server: &ObjectServer) {
+            self.network
+                .lock()
+                .unwrap()
+                .connections
+                .retain(|(path, _)| *path != self.path);
+            server.remove::<Self, _>(&self.path).await.ok();
+        }
+    }
+
+    struct StubActive {
+        state: u32,
+        connection: OwnedObjectPath,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.NetworkManager.Connection.Active")]
+    impl StubActive {
+        #[dbus_interface(property)]
+        fn state(&self) -> u32 {
+            self.state
+        }
+
+        #[dbus_interface(property)]
+        fn connection(&self) -> OwnedObjectPath {
+            self.connection.clone()
+        }
+    }
+
+    fn wifi_settings(id: &str, ssid: &str, psk: Option<&str>) -> ConnectionSettings {
+        let mut settings = ConnectionSettings::from([
+            (
+                "connection".to_string(),
+                HashMap::from([
+                    ("id".to_string(), value(id.to_string())),
+                    ("uuid".to_string(), value(format!("uuid-{id}"))),
+                    ("type".to_string(), value("802-11-wireless")),
+                ]),
+            ),
+            (
+                "802-11-wireless".to_string(),
+                HashMap::from([("ssid".to_string(), value(ssid.as_bytes().to_vec()))]),
+            ),
+        ]);
+        if let Some(psk) = psk {
+            settings.insert(
+                "802-11-wireless-security".to_string(),
+                HashMap::from([
+                    ("key-mgmt".to_string(), value("wpa-psk")),
+                    ("psk".to_string(), value(psk.to_string())),
+                ]),
+            );
+        }
+        settings
+    }
+
+    /// Serves a stub NetworkManager with a WiFi and a wired device, three access points and the
+    /// home network saved next to a wired connection.
+    async fn stub_network_manager(bus: &Bus) -> (Connection, Shared, NetworkManager) {
+        let network = Shared::default();
+        let mut builder = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name(NETWORK_MANAGER)
+            .unwrap()
+            .serve_at(
+                MANAGER_PATH,
+                StubManager {
+                    network: network.clone(),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                WLAN0,
+                StubDevice {
+                    device_type: DEVICE_TYPE_WIFI,
+                    network: network.clone(),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                WLAN0,
+                StubWireless {
+                    network: network.clone(),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                ETH0,
+                StubDevice {
+                    device_type: 1,
+                    network: network.clone(),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                SETTINGS_PATH,
+                StubSettings {
+                    network: network.clone(),
+                },
+            )
+            .unwrap();
+
+        let access_points = [
+            stub_ap("Home 5G", "AA:BB:CC:DD:EE:01", 70, 0, KEY_MGMT_PSK),
+            stub_ap("Cafe", "AA:BB:CC:DD:EE:02", 90, 0, 0),
+            stub_ap("Office", "AA:BB:CC:DD:EE:03", 50, 0, KEY_MGMT_802_1X),
+            stub_ap("", "AA:BB:CC:DD:EE:05", 99, 0, KEY_MGMT_PSK),
+        ];
+        for ap in access_points {
+            let path = network
+                .lock()
+                .unwrap()
+                .next_path("/org/freedesktop/NetworkManager/AccessPoint");
+            let ssid = String::from_utf8(ap.ssid.clone()).unwrap();
+            network
+                .lock()
+                .unwrap()
+                .access_points
+                .push((path.clone(), ssid));
+            builder = builder.serve_at(path, ap).unwrap();
+        }
+
+        let mut wired = wifi_settings("Wired", "", None);
+        wired.remove("802-11-wireless");
+        wired
+            .get_mut("connection")
+            .unwrap()
+            .insert("type".to_string(), value("802-3-ethernet"));
+        for settings in [wifi_settings("Home", "Home 5G", Some("secret")), wired] {
+            let path = network.lock().unwrap().next_path(SETTINGS_PATH);
+            network
+                .lock()
+                .unwrap()
+                .connections
+                .push((path.clone(), settings));
+            let connection = StubConnection {
+                path: path.clone(),
+                network: network.clone(),
+            };
+            builder = builder.serve_at(path, connection).unwrap();
+        }
+
+        let server = builder.build().await.unwrap();
+        (
+            server,
+            network,
+            NetworkManager::new(test_bus::connect(bus).await),
+        )
+    }
+
+    #[test]
+    fn classifies_security_by_key_management() {
+        assert_eq!(Security::from_flags(0, 0, 0), Security::Open);
+        assert_eq!(Security::from_flags(AP_FLAGS_PRIVACY, 0, 0), Security::Wep);
+        assert_eq!(
+            Security::from_flags(AP_FLAGS_PRIVACY, KEY_MGMT_PSK, KEY_MGMT_PSK),
+            Security::WpaPsk
+        );
+        // WPA2/WPA3 transition networks take a WPA2 connection
+        assert_eq!(
+            Security::from_flags(AP_FLAGS_PRIVACY, 0, KEY_MGMT_PSK | KEY_MGMT_SAE),
+            Security::WpaPsk
+        );
+        assert_eq!(
+            Security::from_flags(AP_FLAGS_PRIVACY, 0, KEY_MGMT_SAE),
+            Security::Sae
+        );
+        assert_eq!(
+            Security::from_flags(AP_FLAGS_PRIVACY, 0, KEY_MGMT_802_1X | KEY_MGMT_PSK),
+            Security::Enterprise
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_access_points_by_signal() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        let access_points = nm.access_points("wlan0").await.unwrap();
+
+        assert_eq!(
+            access_points,
+            vec![
+                AccessPoint {
+                    ssid: "Cafe".to_string(),
+                    bssid: "AA:BB:CC:DD:EE:02".to_string(),
+                    strength: 90,
+                    frequency: 5180,
+                    security: Security::Open,
+                    active: false,
+                    saved: false,
+                },
+                AccessPoint {
+                    ssid: "Home 5G".to_string(),
+                    bssid: "AA:BB:CC:DD:EE:01".to_string(),
+                    strength: 70,
+                    frequency: 5180,
+                    security: Security::WpaPsk,
+                    active: false,
+                    saved: true,
+                },
+                AccessPoint {
+                    ssid: "Office".to_string(),
+                    bssid: "AA:BB:CC:DD:EE:03".to_string(),
+                    strength: 50,
+                    frequency: 5180,
+                    security: Security::Enterprise,
+                    active: false,
+                    saved: false,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_waits_for_new_results() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        let access_points = nm.scan("wlan0").await.unwrap();
+
+        assert_eq!(access_points.len(), 4);
+        assert_eq!(access_points[3].ssid, "Neighbour");
+    }
+
+    #[tokio::test]
+    async fn only_wifi_devices_are_used() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        let err = nm.access_points("wlan9").await.unwrap_err();
+        assert!(matches!(err, Error::NoSuchDevice(_)));
+        assert_eq!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::not_found("wifi device wlan9")
+        );
+
+        let err = nm.scan("eth0").await.unwrap_err();
+        assert!(matches!(err, Error::NotWireless(_)));
+    }
+
+    #[tokio::test]
+    async fn lists_saved_wifi_networks_only() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        assert_eq!(
+            nm.saved_networks().await.unwrap(),
+            vec![SavedNetwork {
+                id: "Home".to_string(),
+                uuid: "uuid-Home".to_string(),
+                ssid: "Home 5G".to_string(),
+                autoconnect: true,
+                priority: 0,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn orders_saved_networks_by_priority() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+        nm.connect("wlan0", "Cafe", None).await.unwrap();
+        let ids = |networks: Vec<SavedNetwork>| -> Vec<String> {
+            networks.into_iter().map(|network| network.id).collect()
+        };
+        assert_eq!(
+            ids(nm.saved_networks().await.unwrap()),
+            vec!["Cafe", "Home"]
+        );
+
+        let cafe = nm.saved_networks().await.unwrap()[0].uuid.clone();
+        let networks = nm.set_priority("uuid-Home", 10).await.unwrap();
+        assert_eq!(ids(networks.clone()), vec!["Home", "Cafe"]);
+        assert_eq!(networks[0].priority, 10);
+
+        let networks = nm.set_priority(&cafe, 20).await.unwrap();
+        assert_eq!(ids(networks), vec!["Cafe", "Home"]);
+    }
+
+    #[tokio::test]
+    async fn updates_keep_the_passphrase() {
+        let bus = require_bus!();
+        let (_server, network, nm) = stub_network_manager(&bus).await;
+
+        let networks = nm.set_autoconnect("uuid-Home", false).await.unwrap();
+
+        assert!(!networks[0].autoconnect);
+        let network = network.lock().unwrap();
+        let (_, settings) = &network.connections[0];
+        assert_eq!(
+            setting::<&str>(settings, "802-11-wireless-security", "psk"),
+            Some("secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn forget_deletes_the_saved_network() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        assert!(nm.forget("uuid-Home").await.unwrap().is_empty());
+
+        let err = nm.forget("uuid-Home").await.unwrap_err();
+        assert_eq!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::not_found("saved network uuid-Home")
+        );
+        let err = nm.set_autoconnect("uuid-Home", true).await.unwrap_err();
+        assert!(matches!(err, Error::NoSuchConnection(_)));
+    }
+
+    #[tokio::test]
+    async fn connects_to_a_saved_network_without_a_passphrase() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        let access_points = nm.connect("wlan0", "Home 5G", None).await.unwrap();
+
+        let active: Vec<_> = access_points.iter().filter(|ap| ap.active).collect();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].ssid, "Home 5G");
+        assert_eq!(nm.saved_networks().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replaces_the_passphrase_of_a_saved_network() {
+        let bus = require_bus!();
+        let (_server, network, nm) = stub_network_manager(&bus).await;
+
+        let err = nm
+            .connect("wlan0", "Home 5G", Some("wrong"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ActivationFailed(_)));
+        // A saved network stays saved with the passphrase that worked
+        assert_eq!(nm.saved_networks().await.unwrap().len(), 1);
+        {
+            let network = network.lock().unwrap();
+            let (_, settings) = &network.connections[0];
+            assert_eq!(
+                setting::<&str>(settings, "802-11-wireless-security", "psk"),
+                Some("secret")
+            );
+        }
+        nm.connect("wlan0", "Home 5G", None).await.unwrap();
+
+        nm.connect("wlan0", "Home 5G", Some("new secret"))
+            .await
+            .unwrap();
+        let network = network.lock().unwrap();
+        let (_, settings) = &network.connections[0];
+        assert_eq!(
+            setting::<&str>(settings, "802-11-wireless-security", "psk"),
+            Some("new secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_new_networks_once_connected() {
+        let bus = require_bus!();
+        let (_server, network, nm) = stub_network_manager(&bus).await;
+
+        let access_points = nm.connect("wlan0", "Cafe", None).await.unwrap();
+
+        assert!(access_points[0].active);
+        assert!(access_points[0].saved);
+        let network = network.lock().unwrap();
+        let (_, settings) = network.connections.last().unwrap();
+        assert_eq!(setting::<&str>(settings, "connection", "id"), Some("Cafe"));
+        assert!(!settings.contains_key("802-11-wireless-security"));
+    }
+
+    #[tokio::test]
+    async fn forgets_new_networks_that_fail_to_connect() {
+        let bus = require_bus!();
+        let (_server, network, nm) = stub_network_manager(&bus).await;
+        network.lock().unwrap().connections.remove(0);
+
+        let err = nm.connect("wlan0", "Home 5G", None).await.unwrap_err();
+        assert!(matches!(err, Error::PassphraseRequired(_)));
+
+        let err = nm
+            .connect("wlan0", "Home 5G", Some("wrong"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ActivationFailed(_)));
+        assert!(nm.saved_networks().await.unwrap().is_empty());
+        assert_eq!(network.lock().unwrap().connections.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn new_networks_need_supported_credentials() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        let err = nm.connect("wlan0", "Home", None).await.unwrap_err();
+        assert!(matches!(err, Error::NoSuchNetwork(_)));
+
+        let err = nm
+            .connect("wlan0", "Office", Some("secret"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_, Security::Enterprise)));
+        assert!(matches!(
+            shortcut_core::Error::from(err),
+            shortcut_core::Error::InvalidArgument(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn disconnect_leaves_no_active_access_point() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+        nm.connect("wlan0", "Cafe", None).await.unwrap();
+
+        let access_points = nm.disconnect("wlan0").await.unwrap();
+
+        assert!(access_points.iter().all(|ap| !ap.active));
+    }
+
+    #[tokio::test]
+    async fn keeps_power_save_in_the_active_connection() {
+        let bus = require_bus!();
+        let (_server, network, nm) = stub_network_manager(&bus).await;
+        nm.connect("wlan0", "Home 5G", None).await.unwrap();
+        assert_eq!(nm.power_save("wlan0").await.unwrap(), None);
+
+        nm.set_power_save("wlan0", false).await.unwrap();
+
+        assert_eq!(nm.power_save("wlan0").await.unwrap(), Some(false));
+        let network = network.lock().unwrap();
+        let (_, settings) = &network.connections[0];
+        assert_eq!(
+            setting::<u32>(settings, "802-11-wireless", "powersave"),
+            Some(POWERSAVE_DISABLE)
+        );
+        assert_eq!(
+            setting::<&str>(settings, "802-11-wireless-security", "psk"),
+            Some("secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn power_save_needs_a_connection() {
+        let bus = require_bus!();
+        let (_server, _, nm) = stub_network_manager(&bus).await;
+
+        assert_eq!(nm.power_save("wlan0").await.unwrap(), None);
+        assert_eq!(nm.power_save("wlan9").await.unwrap(), None);
+        let err = nm.set_power_save("wlan0", true).await.unwrap_err();
+        assert!(matches!(err, Error::NotConnected(_)));
+        let err = nm.set_power_save("wlan9", true).await.unwrap_err();
+        assert!(matches!(err, Error::NoSuchDevice(_)));
+    }
+
+    #[tokio::test]
+    async fn reloads_only_the_configuration() {
+        let bus = require_bus!();
+        let (_server, network, nm) = stub_network_manager(&bus).await;
+
+        nm.reload_config().await.unwrap();
+
+        assert_eq!(network.lock().unwrap().reloads, vec![RELOAD_FLAG_CONF]);
+    }
+
+    #[test]
+    fn reads_powersave_values() {
+        assert_eq!(power_save_from(power_save_value(true)), Some(true));
+        assert_eq!(power_save_from(power_save_value(false)), Some(false));
+        // Default and ignore
+        assert_eq!(power_save_from(0), None);
+        assert_eq!(power_save_from(1), None);
+    }
+}
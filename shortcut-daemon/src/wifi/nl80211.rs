@@ -0,0 +1,652 @@
+use std::fmt;
+
+use neli::consts::nl::{NlmF, NlmFFlags};
+use neli::consts::socket::NlFamily;
+use neli::err::{NlError, SerError};
+use neli::genl::{Genlmsghdr, Nlattr};
+use neli::nl::{NlPayload, Nlmsghdr};
+use neli::socket::NlSocketHandle;
+use neli::types::GenlBuffer;
+
+use shortcut_core::wifi::InterfaceType;
+
+const NL80211_FAMILY: &str = "nl80211";
+const NL80211_VERSION: u8 = 1;
+/// Multicast group for interface creation, removal and configuration changes.
+const NL80211_CONFIG_GROUP: &str = "config";
+
+#[neli::neli_enum(serialized_type = "u8")]
+pub enum Nl80211Cmd {
+    Unspecified = 0,
+    GetInterface = 5,
+    NewInterface = 7,
+    GetStation = 17,
+    NewStation = 19,
+    SetPowerSave = 61,
+    GetPowerSave = 62,
+}
+impl neli::consts::genl::Cmd for Nl80211Cmd {}
+
+#[neli::neli_enum(serialized_type = "u16")]
+pub enum Nl80211Attr {
+    Unspecified = 0,
+    Wiphy = 1,
+    Ifindex = 3,
+    Ifname = 4,
+    Iftype = 5,
+    Mac = 6,
+    StaInfo = 21,
+    WiphyFreq = 38,
+    Ssid = 52,
+    PsState = 93,
+}
+impl neli::consts::genl::NlAttrType for Nl80211Attr {}
+
+/// Attributes nested in `NL80211_ATTR_STA_INFO`.
+#[neli::neli_enum(serialized_type = "u16")]
+pub enum Nl80211StaInfo {
+    Unspecified = 0,
+    RxBytes = 2,
+    TxBytes = 3,
+    Signal = 7,
+    TxBitrate = 8,
+    TxRetries = 11,
+    TxFailed = 12,
+    SignalAvg = 13,
+    RxBitrate = 14,
+    RxBytes64 = 23,
+    TxBytes64 = 24,
+}
+impl neli::consts::genl::NlAttrType for Nl80211StaInfo {}
+
+/// Attributes nested in `NL80211_STA_INFO_TX_BITRATE` and `NL80211_STA_INFO_RX_BITRATE`.
+#[neli::neli_enum(serialized_type = "u16")]
+pub enum Nl80211RateInfo {
+    Unspecified = 0,
+    Bitrate = 1,
+    Bitrate32 = 5,
+}
+impl neli::consts::genl::NlAttrType for Nl80211RateInfo {}
+
+type Nl80211Msg = Genlmsghdr<Nl80211Cmd, Nl80211Attr>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The nl80211 family is not registered, i.e. no cfg80211 driver is loaded.
+    Unsupported,
+    /// No wireless interface with the given name exists.
+    NoSuchDevice(String),
+    /// The kernel rejected the request with the given errno.
+    Kernel(i32),
+    Netlink(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unsupported => write!(f, "nl80211 is not available"),
+            Error::NoSuchDevice(name) => write!(f, "no such wireless device: {name}"),
+            Error::Kernel(errno) => {
+                write!(f, "{}", std::io::Error::from_raw_os_error(*errno))
+            }
+            Error::Netlink(msg) => write!(f, "netlink error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoSuchDevice(name) => {
+                shortcut_core::Error::not_found(format!("wifi device {name}"))
+            }
+            Error::Unsupported => shortcut_core::Error::Unavailable(err.to_string()),
+            Error::Kernel(libc::EPERM | libc::EACCES) => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+impl<T: fmt::Debug, P: fmt::Debug> From<NlError<T, P>> for Error {
+    fn from(err: NlError<T, P>) -> Self {
+        match err {
+            NlError::Nlmsgerr(err) => Error::Kernel(-err.error),
+            err => Error::Netlink(err.to_string()),
+        }
+    }
+}
+
+impl From<SerError> for Error {
+    fn from(err: SerError) -> Self {
+        Error::Netlink(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Netlink(err.to_string())
+    }
+}
+
+/// A wireless interface as reported by `NL80211_CMD_GET_INTERFACE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub ifindex: u32,
+    pub name: String,
+    pub mac: [u8; 6],
+    pub iftype: InterfaceType,
+    pub phy: u32,
+}
+
+impl Interface {
+    pub fn mac_string(&self) -> String {
+        mac_string(&self.mac)
+    }
+}
+
+fn mac_string(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The connection of a station interface, from `NL80211_CMD_GET_INTERFACE` and
+/// `NL80211_CMD_GET_STATION`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub ssid: String,
+    pub bssid: [u8; 6],
+    /// MHz.
+    pub frequency: u32,
+    /// dBm.
+    pub signal: i8,
+    /// kbit/s of the last frame sent and received, 0 when the driver doesn't report them.
+    pub tx_bitrate: u32,
+    pub rx_bitrate: u32,
+    /// Counted since the connection was made.
+    pub tx_retries: u32,
+    pub tx_failed: u32,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+impl Link {
+    pub fn bssid_string(&self) -> String {
+        mac_string(&self.bssid)
+    }
+
+    /// The channel number of the frequency, 0 outside the 2.4, 5 and 6 GHz bands.
+    pub fn channel(&self) -> u32 {
+        match self.frequency {
+            2484 => 14,
+            // The only 6 GHz channel off the 20 MHz grid
+            5935 => 2,
+            2412..=2472 => (self.frequency - 2407) / 5,
+            5160..=5885 => (self.frequency - 5000) / 5,
+            5955..=7115 => (self.frequency - 5950) / 5,
+            _ => 0,
+        }
+    }
+}
+
+/// Maps `enum nl80211_iftype` onto the proto representation.
+fn iftype_from_raw(raw: u32) -> InterfaceType {
+    match raw {
+        2 => InterfaceType::Station,
+        3 => InterfaceType::Ap,
+        6 => InterfaceType::Monitor,
+        8 => InterfaceType::P2pClient,
+        9 => InterfaceType::P2pGo,
+        0 => InterfaceType::Unspecified,
+        _ => InterfaceType::Other,
+    }
+}
+
+/// Reads an interface out of a `NL80211_CMD_NEW_INTERFACE` message.
+fn parse_interface(msg: &Nl80211Msg) -> Option<Interface> {
+    let attrs = msg.get_attr_handle();
+    // Wireless devices without a netdev (e.g. P2P-device) have no name or index
+    let name = attrs
+        .get_attr_payload_as_with_len::<String>(Nl80211Attr::Ifname)
+        .ok()?;
+    let ifindex = attrs
+        .get_attr_payload_as::<u32>(Nl80211Attr::Ifindex)
+        .ok()?;
+    let mac = attrs
+        .get_attr_payload_as_with_len::<&[u8]>(Nl80211Attr::Mac)
+        .ok()
+        .and_then(|mac| mac.try_into().ok())
+        .unwrap_or_default();
+    let iftype = attrs
+        .get_attr_payload_as::<u32>(Nl80211Attr::Iftype)
+        .map(iftype_from_raw)
+        .unwrap_or(InterfaceType::Unspecified);
+    let phy = attrs
+        .get_attr_payload_as::<u32>(Nl80211Attr::Wiphy)
+        .unwrap_or_default();
+
+    Some(Interface {
+        ifindex,
+        name,
+        mac,
+        iftype,
+        phy,
+    })
+}
+
+/// Reads a bitrate out of a nested `enum nl80211_rate_info`, in kbit/s.
+fn parse_bitrate(
+    sta_info: &mut neli::attr::AttrHandle<
+        GenlBuffer<Nl80211StaInfo, neli::types::Buffer>,
+        Nlattr<Nl80211StaInfo, neli::types::Buffer>,
+    >,
+    attr: Nl80211StaInfo,
+) -> u32 {
+    let rate_info = match sta_info.get_nested_attributes::<Nl80211RateInfo>(attr) {
+        Ok(rate_info) => rate_info,
+        Err(_) => return 0,
+    };
+    // Both count 100 kbit/s, the 16 bit one is left out for rates it can't hold
+    let rate = rate_info
+        .get_attr_payload_as::<u32>(Nl80211RateInfo::Bitrate32)
+        .or_else(|_| {
+            rate_info
+                .get_attr_payload_as::<u16>(Nl80211RateInfo::Bitrate)
+                .map(u32::from)
+        })
+        .unwrap_or_default();
+    rate * 100
+}
+
+/// Reads a byte counter, preferring the 64 bit attribute older kernels don't send.
+fn parse_bytes(
+    sta_info: &neli::attr::AttrHandle<
+        GenlBuffer<Nl80211StaInfo, neli::types::Buffer>,
+        Nlattr<Nl80211StaInfo, neli::types::Buffer>,
+    >,
+    attr64: Nl80211StaInfo,
+    attr32: Nl80211StaInfo,
+) -> u64 {
+    sta_info
+        .get_attr_payload_as::<u64>(attr64)
+        .or_else(|_| sta_info.get_attr_payload_as::<u32>(attr32).map(u64::from))
+        .unwrap_or_default()
+}
+
+/// Reads the link out of the interface's SSID and frequency and the station it is associated
+/// with, the access point.
+fn parse_link(iface: &Nl80211Msg, station: &Nl80211Msg) -> Option<Link> {
+    let attrs = iface.get_attr_handle();
+    let ssid = attrs
+        .get_attr_payload_as_with_len::<&[u8]>(Nl80211Attr::Ssid)
+        .ok()?;
+    let frequency = attrs
+        .get_attr_payload_as::<u32>(Nl80211Attr::WiphyFreq)
+        .unwrap_or_default();
+
+    let mut attrs = station.get_attr_handle();
+    let bssid = attrs
+        .get_attr_payload_as_with_len::<&[u8]>(Nl80211Attr::Mac)
+        .ok()?
+        .try_into()
+        .ok()?;
+    let mut sta_info = attrs
+        .get_nested_attributes::<Nl80211StaInfo>(Nl80211Attr::StaInfo)
+        .ok()?;
+    let signal = sta_info
+        .get_attr_payload_as::<i8>(Nl80211StaInfo::Signal)
+        .or_else(|_| sta_info.get_attr_payload_as::<i8>(Nl80211StaInfo::SignalAvg))
+        .unwrap_or_default();
+    let tx_retries = sta_info
+        .get_attr_payload_as::<u32>(Nl80211StaInfo::TxRetries)
+        .unwrap_or_default();
+    let tx_failed = sta_info
+        .get_attr_payload_as::<u32>(Nl80211StaInfo::TxFailed)
+        .unwrap_or_default();
+    let rx_bytes = parse_bytes(
+        &sta_info,
+        Nl80211StaInfo::RxBytes64,
+        Nl80211StaInfo::RxBytes,
+    );
+    let tx_bytes = parse_bytes(
+        &sta_info,
+        Nl80211StaInfo::TxBytes64,
+        Nl80211StaInfo::TxBytes,
+    );
+    let tx_bitrate = parse_bitrate(&mut sta_info, Nl80211StaInfo::TxBitrate);
+    let rx_bitrate = parse_bitrate(&mut sta_info, Nl80211StaInfo::RxBitrate);
+
+    Some(Link {
+        ssid: String::from_utf8_lossy(ssid).into_owned(),
+        bssid,
+        frequency,
+        signal,
+        tx_bitrate,
+        rx_bitrate,
+        tx_retries,
+        tx_failed,
+        rx_bytes,
+        tx_bytes,
+    })
+}
+
+/// Blocking generic netlink connection to the nl80211 family.
+pub struct Nl80211 {
+    sock: NlSocketHandle,
+    family: u16,
+}
+
+impl Nl80211 {
+    pub fn connect() -> Result<Self, Error> {
+        let mut sock = NlSocketHandle::connect(NlFamily::Generic, None, &[])?;
+        let family = sock
+            .resolve_genl_family(NL80211_FAMILY)
+            .map_err(|_| Error::Unsupported)?;
+
+        Ok(Self { sock, family })
+    }
+
+    fn request(
+        &mut self,
+        cmd: Nl80211Cmd,
+        flags: &[NlmF],
+        attrs: GenlBuffer<Nl80211Attr, neli::types::Buffer>,
+    ) -> Result<Vec<Nl80211Msg>, Error> {
+        let msg = Nlmsghdr::new(
+            None,
+            self.family,
+            NlmFFlags::new(flags),
+            None,
+            None,
+            NlPayload::Payload(Genlmsghdr::new(cmd, NL80211_VERSION, attrs)),
+        );
+        self.sock.send(msg)?;
+
+        let mut replies = Vec::new();
+        for msg in self.sock.iter::<u16, Nl80211Msg>(false) {
+            if let NlPayload::Payload(payload) = msg?.nl_payload {
+                replies.push(payload);
+            }
+        }
+
+        Ok(replies)
+    }
+
+    /// Lists all wireless interfaces that are backed by a netdev.
+    pub fn interfaces(&mut self) -> Result<Vec<Interface>, Error> {
+        let replies = self.request(
+            Nl80211Cmd::GetInterface,
+            &[NlmF::Request, NlmF::Dump],
+            GenlBuffer::new(),
+        )?;
+
+        let mut interfaces: Vec<Interface> = replies.iter().filter_map(parse_interface).collect();
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(interfaces)
+    }
+
+    /// Blocks and calls `f` for every wireless interface the kernel creates until `f` returns
+    /// false.
+    pub fn watch_new_interfaces(mut f: impl FnMut(Interface) -> bool) -> Result<(), Error> {
+        let mut sock = NlSocketHandle::connect(NlFamily::Generic, None, &[])?;
+        let group = sock
+            .resolve_nl_mcast_group(NL80211_FAMILY, NL80211_CONFIG_GROUP)
+            .map_err(|_| Error::Unsupported)?;
+        sock.add_mcast_membership(&[group])?;
+
+        for msg in sock.iter::<u16, Nl80211Msg>(true) {
+            let payload = match msg?.nl_payload {
+                NlPayload::Payload(payload) => payload,
+                _ => continue,
+            };
+            if payload.cmd != Nl80211Cmd::NewInterface {
+                continue;
+            }
+            if let Some(iface) = parse_interface(&payload) {
+                if !f(iface) {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn interface(&mut self, name: &str) -> Result<Interface, Error> {
+        self.interfaces()?
+            .into_iter()
+            .find(|iface| iface.name == name)
+            .ok_or_else(|| Error::NoSuchDevice(name.to_string()))
+    }
+
+    /// The connection of the interface, `None` while it isn't associated with an access point.
+    pub fn link(&mut self, ifindex: u32) -> Result<Option<Link>, Error> {
+        let mut attrs = GenlBuffer::new();
+        attrs.push(Nlattr::new(false, false, Nl80211Attr::Ifindex, ifindex)?);
+        let ifaces = self.request(Nl80211Cmd::GetInterface, &[NlmF::Request, NlmF::Ack], attrs)?;
+        let iface = match ifaces.first() {
+            Some(iface) => iface,
+            None => return Ok(None),
+        };
+
+        // A station interface only knows the access point it is associated with
+        let mut attrs = GenlBuffer::new();
+        attrs.push(Nlattr::new(false, false, Nl80211Attr::Ifindex, ifindex)?);
+        let stations = self.request(Nl80211Cmd::GetStation, &[NlmF::Request, NlmF::Dump], attrs)?;
+
+        Ok(stations
+            .iter()
+            .find_map(|station| parse_link(iface, station)))
+    }
+
+    pub fn power_save(&mut self, ifindex: u32) -> Result<bool, Error> {
+        let mut attrs = GenlBuffer::new();
+        attrs.push(Nlattr::new(false, false, Nl80211Attr::Ifindex, ifindex)?);
+
+        let replies = self.request(Nl80211Cmd::GetPowerSave, &[NlmF::Request, NlmF::Ack], attrs)?;
+
+        replies
+            .iter()
+            .find_map(|msg| {
+                msg.get_attr_handle()
+                    .get_attr_payload_as::<u32>(Nl80211Attr::PsState)
+                    .ok()
+            })
+            .map(|state| state != 0)
+            .ok_or_else(|| Error::Netlink("reply did not contain a power save state".to_string()))
+    }
+
+    pub fn set_power_save(&mut self, ifindex: u32, enabled: bool) -> Result<(), Error> {
+        let mut attrs = GenlBuffer::new();
+        attrs.push(Nlattr::new(false, false, Nl80211Attr::Ifindex, ifindex)?);
+        attrs.push(Nlattr::new(
+            false,
+            false,
+            Nl80211Attr::PsState,
+            u32::from(enabled),
+        )?);
+
+        self.request(Nl80211Cmd::SetPowerSave, &[NlmF::Request, NlmF::Ack], attrs)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use neli::types::Buffer;
+
+    use super::*;
+
+    fn link(frequency: u32) -> Link {
+        Link {
+            ssid: "Home".to_string(),
+            bssid: [0; 6],
+            frequency,
+            signal: 0,
+            tx_bitrate: 0,
+            rx_bitrate: 0,
+            tx_retries: 0,
+            tx_failed: 0,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }
+    }
+
+    fn attr<T: neli::consts::genl::NlAttrType>(
+        kind: T,
+        payload: impl neli::Size + neli::ToBytes,
+    ) -> Nlattr<T, Buffer> {
+        Nlattr::new(false, false, kind, payload).unwrap()
+    }
+
+    fn nested<T, N>(kind: T, attrs: &[Nlattr<N, Buffer>]) -> Nlattr<T, Buffer>
+    where
+        T: neli::consts::genl::NlAttrType,
+        N: neli::consts::genl::NlAttrType,
+    {
+        let mut nested = Nlattr::new(true, false, kind, Buffer::new()).unwrap();
+        for attr in attrs {
+            nested.add_nested_attribute(attr).unwrap();
+        }
+        nested
+    }
+
+    fn message(cmd: Nl80211Cmd, attrs: Vec<Nlattr<Nl80211Attr, Buffer>>) -> Nl80211Msg {
+        Genlmsghdr::new(cmd, NL80211_VERSION, attrs.into_iter().collect())
+    }
+
+    fn interface(ssid: &str, frequency: u32) -> Nl80211Msg {
+        message(
+            Nl80211Cmd::NewInterface,
+            vec![
+                attr(Nl80211Attr::Ssid, Buffer::from(ssid.as_bytes())),
+                attr(Nl80211Attr::WiphyFreq, frequency),
+            ],
+        )
+    }
+
+    fn station(sta_info: &[Nlattr<Nl80211StaInfo, Buffer>]) -> Nl80211Msg {
+        message(
+            Nl80211Cmd::NewStation,
+            vec![
+                attr(
+                    Nl80211Attr::Mac,
+                    Buffer::from(&[0x02, 0, 0, 0, 0, 0x01][..]),
+                ),
+                nested(Nl80211Attr::StaInfo, sta_info),
+            ],
+        )
+    }
+
+    #[test]
+    fn maps_frequencies_to_channels() {
+        for (frequency, channel) in [
+            (2412, 1),
+            (2437, 6),
+            (2472, 13),
+            (2484, 14),
+            (5180, 36),
+            (5500, 100),
+            (5825, 165),
+            (5885, 177),
+            (5935, 2),
+            (5955, 1),
+            (6115, 33),
+            (7115, 233),
+            (0, 0),
+            (2400, 0),
+            (5000, 0),
+            (60480, 0),
+        ] {
+            assert_eq!(link(frequency).channel(), channel, "{frequency} MHz");
+        }
+    }
+
+    #[test]
+    fn parses_a_link() {
+        let station = station(&[
+            attr(Nl80211StaInfo::Signal, -52i8),
+            attr(Nl80211StaInfo::TxRetries, 7u32),
+            attr(Nl80211StaInfo::TxFailed, 1u32),
+            attr(Nl80211StaInfo::RxBytes, 1000u32),
+            attr(Nl80211StaInfo::RxBytes64, 5_000_000_000u64),
+            attr(Nl80211StaInfo::TxBytes, 2000u32),
+            nested(
+                Nl80211StaInfo::TxBitrate,
+                &[attr(Nl80211RateInfo::Bitrate32, 8667u32)],
+            ),
+            nested(
+                Nl80211StaInfo::RxBitrate,
+                &[attr(Nl80211RateInfo::Bitrate, 540u16)],
+            ),
+        ]);
+
+        let link = parse_link(&interface("Home", 5180), &station).unwrap();
+
+        assert_eq!(
+            link,
+            Link {
+                ssid: "Home".to_string(),
+                bssid: [0x02, 0, 0, 0, 0, 0x01],
+                frequency: 5180,
+                signal: -52,
+                tx_bitrate: 866_700,
+                rx_bitrate: 54_000,
+                tx_retries: 7,
+                tx_failed: 1,
+                rx_bytes: 5_000_000_000,
+                tx_bytes: 2000,
+            }
+        );
+        assert_eq!(link.bssid_string(), "02:00:00:00:00:01");
+    }
+
+    #[test]
+    fn parses_a_link_the_driver_says_little_about() {
+        let station = station(&[attr(Nl80211StaInfo::SignalAvg, -70i8)]);
+
+        let link = parse_link(&interface("Cafe", 2437), &station).unwrap();
+
+        assert_eq!(link.signal, -70);
+        assert_eq!((link.tx_bitrate, link.rx_bitrate), (0, 0));
+        assert_eq!((link.rx_bytes, link.tx_bytes), (0, 0));
+    }
+
+    #[test]
+    fn prefers_the_32_bit_bitrate() {
+        let station = station(&[nested(
+            Nl80211StaInfo::TxBitrate,
+            &[
+                attr(Nl80211RateInfo::Bitrate, 1u16),
+                attr(Nl80211RateInfo::Bitrate32, 24020u32),
+            ],
+        )]);
+        let mut attrs = station.get_attr_handle();
+        let mut sta_info = attrs
+            .get_nested_attributes::<Nl80211StaInfo>(Nl80211Attr::StaInfo)
+            .unwrap();
+
+        assert_eq!(
+            parse_bitrate(&mut sta_info, Nl80211StaInfo::TxBitrate),
+            2_402_000
+        );
+        assert_eq!(parse_bitrate(&mut sta_info, Nl80211StaInfo::RxBitrate), 0);
+    }
+
+    #[test]
+    fn a_disconnected_interface_has_no_link() {
+        let iface = message(
+            Nl80211Cmd::NewInterface,
+            vec![attr(Nl80211Attr::WiphyFreq, 2412u32)],
+        );
+
+        assert_eq!(parse_link(&iface, &station(&[])), None);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::network_manager::{power_save_from, power_save_value};
+use crate::files;
+
+/// What NetworkManager's configuration paths are relative to, `/` outside of tests.
+pub const NM_CONFIG_ROOT: &str = "/";
+
+/// Read before any drop-in.
+const MAIN_CONFIG: &str = "etc/NetworkManager/NetworkManager.conf";
+
+/// Where NetworkManager reads drop-ins from, a drop-in hides those of the same name in the
+/// directories before it. Ours goes into the last one.
+const DROP_IN_DIRS: [&str; 3] = [
+    "usr/lib/NetworkManager/conf.d",
+    "run/NetworkManager/conf.d",
+    "etc/NetworkManager/conf.d",
+];
+
+/// NetworkManager reads drop-ins by name and later ones win, so this one sorts after those of
+/// distributions like `default-wifi-powersave-on.conf`.
+const DROP_IN: &str = "zz-shortcut.conf";
+
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, err: io::Error },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, err } => write!(f, "{}: {err}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for shortcut_core::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io { err: ref io, .. } if io.kind() == io::ErrorKind::PermissionDenied => {
+                shortcut_core::Error::PermissionDenied(err.to_string())
+            }
+            err => shortcut_core::Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// The last `wifi.powersave` of the plain `[connection]` section, sections that only apply to
+/// some devices are left out.
+fn parse_power_save(content: &str) -> Option<u32> {
+    let mut section = "";
+    let mut value = None;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = name.trim();
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, found)) if section == "connection" && key.trim() == "wifi.powersave" => {
+                value = found.trim().parse().ok();
+            }
+            _ => {}
+        }
+    }
+    value
+}
+
+/// The configuration files of NetworkManager below a root, [`NM_CONFIG_ROOT`] outside of tests.
+#[derive(Debug, Clone)]
+pub struct NmConfig {
+    root: PathBuf,
+}
+
+impl NmConfig {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn io_error(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |err| Error::Io {
+            path: path.to_path_buf(),
+            err,
+        }
+    }
+
+    /// The drop-ins in the order NetworkManager reads them, by name across all directories.
+    fn drop_ins(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut drop_ins = BTreeMap::<OsString, PathBuf>::new();
+        for dir in DROP_IN_DIRS.map(|dir| self.root.join(dir)) {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(Self::io_error(&dir)(err)),
+            };
+            for entry in entries {
+                let path = entry.map_err(Self::io_error(&dir))?.path();
+                if let (Some(name), Some("conf")) = (
+                    path.file_name(),
+                    path.extension().and_then(|ext| ext.to_str()),
+                ) {
+                    drop_ins.insert(name.to_os_string(), path);
+                }
+            }
+        }
+        Ok(drop_ins.into_values().collect())
+    }
+
+    /// The power save default of connections that leave it unset, `None` when the
+    /// configuration leaves it to the driver.
+    pub fn power_save(&self) -> Result<Option<bool>, Error> {
+        let mut value = None;
+        let main = self.root.join(MAIN_CONFIG);
+        for path in std::iter::once(main).chain(self.drop_ins()?) {
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(Self::io_error(&path)(err)),
+            };
+            value = parse_power_save(&content).or(value);
+        }
+        Ok(value.and_then(power_save_from))
+    }
+
+    /// Replaces the drop-in so NetworkManager never reads a half written one. NetworkManager
+    /// has to reload its configuration to see it.
+    pub fn set_power_save(&self, enabled: bool) -> Result<Option<bool>, Error> {
+        let dir = self.root.join(DROP_IN_DIRS[DROP_IN_DIRS.len() - 1]);
+        let path = dir.join(DROP_IN);
+        let content = format!(
+            "# Managed by shortcut, changes are overwritten\n\
+             [connection]\n\
+             wifi.powersave = {}\n",
+            power_save_value(enabled)
+        );
+
+        // The temporary file doesn't end in .conf, NetworkManager skips it
+        files::replace(&path, &content).map_err(Self::io_error(&path))?;
+
+        self.power_save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_sysfs::FakeSysfs;
+
+    #[test]
+    fn leaves_it_to_the_driver_without_configuration() {
+        let fake = FakeSysfs::new("nm-config-none");
+        let config = NmConfig::new(fake.root());
+
+        assert_eq!(config.power_save().unwrap(), None);
+    }
+
+    #[test]
+    fn later_drop_ins_win() {
+        let fake = FakeSysfs::new("nm-config-order");
+        fake.write(
+            "etc/NetworkManager/conf.d/default-wifi-powersave-on.conf",
+            "[connection]\nwifi.powersave = 3\n",
+        );
+        let config = NmConfig::new(fake.root());
+        assert_eq!(config.power_save().unwrap(), Some(true));
+
+        fake.write(
+            "etc/NetworkManager/conf.d/zy-other.conf",
+            "[main]\nplugins=keyfile\n",
+        );
+        fake.write(
+            "etc/NetworkManager/conf.d/a-first.conf",
+            "[connection]\nwifi.powersave=2\n",
+        );
+        assert_eq!(config.power_save().unwrap(), Some(true));
+
+        assert_eq!(config.set_power_save(false).unwrap(), Some(false));
+        assert_eq!(
+            fake.read("etc/NetworkManager/conf.d/zz-shortcut.conf"),
+            "# Managed by shortcut, changes are overwritten\n\
+             [connection]\n\
+             wifi.powersave = 2"
+        );
+    }
+
+    #[test]
+    fn merges_every_configuration_directory() {
+        let fake = FakeSysfs::new("nm-config-dirs");
+        let config = NmConfig::new(fake.root());
+        fake.write(
+            "etc/NetworkManager/NetworkManager.conf",
+            "[connection]\nwifi.powersave = 2\n",
+        );
+        assert_eq!(config.power_save().unwrap(), Some(false));
+
+        // Shipped by the distribution
+        fake.write(
+            "usr/lib/NetworkManager/conf.d/default-wifi-powersave-on.conf",
+            "[connection]\nwifi.powersave = 3\n",
+        );
+        assert_eq!(config.power_save().unwrap(), Some(true));
+
+        // Hides the one of the same name, before other names in later directories
+        fake.write(
+            "run/NetworkManager/conf.d/default-wifi-powersave-on.conf",
+            "[connection]\nwifi.powersave = 2\n",
+        );
+        fake.write(
+            "etc/NetworkManager/conf.d/b-vendor.conf",
+            "[connection]\nwifi.powersave = 3\n",
+        );
+        fake.write(
+            "usr/lib/NetworkManager/conf.d/e-late.conf",
+            "[connection]\nwifi.powersave = 2\n",
+        );
+        assert_eq!(config.power_save().unwrap(), Some(false));
+
+        fake.write(
+            "etc/NetworkManager/conf.d/e-late.conf",
+            "[connection]\nwifi.powersave = 3\n",
+        );
+        assert_eq!(config.power_save().unwrap(), Some(true));
+    }
+
+    #[test]
+    fn reads_only_the_plain_connection_section() {
+        let content = "\
+# wifi.powersave = 3
+[connection-wlan1]
+match-device=interface-name:wlan1
+wifi.powersave = 3
+[connection]
+  wifi.powersave = 2
+[device]
+wifi.powersave = 3
+";
+
+        assert_eq!(parse_power_save(content), Some(2));
+        assert_eq!(parse_power_save("[main]\ndns=none\n"), None);
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use eframe::egui;
+use egui_toast::{Toast, ToastOptions};
+use poll_promise::Promise;
+use shortcut_core::battery::battery_service_client;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{battery, tokio, tonic, Error};
+
+use crate::health::Serving;
+use crate::watch::Watch;
+
+/// The lowest charge limit the daemon accepts.
+const MIN_CHARGE_LIMIT: u32 = 50;
+
+pub struct Shortcut {
+    rt: tokio::runtime::Handle,
+    battery: Option<battery::Battery>,
+    /// The slider position, only sent once the slider is let go.
+    charge_limit_percent: u32,
+
+    watch: Watch<battery::WatchBatteryResponse>,
+    charge_limit_promise: Option<Promise<Result<battery::Battery, Error>>>,
+
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
+    notifications_tx: mpsc::Sender<Toast>,
+}
+
+impl Shortcut {
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "battery",
+            notifications_tx.clone(),
+            watch_battery,
+        );
+        let serving = Serving::check(&rt, battery::SERVICE_NAME);
+
+        Self {
+            rt,
+            battery: None,
+            charge_limit_percent: 100,
+
+            watch,
+            charge_limit_promise: None,
+
+            serving,
+            notifications_tx,
+        }
+    }
+
+    fn show_battery(&mut self, battery: battery::Battery) {
+        self.charge_limit_percent = battery.charge_limit_percent;
+        self.battery = Some(battery);
+    }
+}
+
+fn status_text(battery: &battery::Battery) -> String {
+    match battery.status() {
+        battery::ChargeStatus::Charging if battery.seconds_to_full > 0 => {
+            format!(
+                "Charging, full in {}",
+                duration_text(battery.seconds_to_full)
+            )
+        }
+        battery::ChargeStatus::Charging => "Charging".to_string(),
+        battery::ChargeStatus::Discharging if battery.seconds_to_empty > 0 => {
+            format!("{} left", duration_text(battery.seconds_to_empty))
+        }
+        battery::ChargeStatus::Discharging => "Discharging".to_string(),
+        battery::ChargeStatus::NotCharging => "Not charging".to_string(),
+        battery::ChargeStatus::Full => "Full".to_string(),
+        battery::ChargeStatus::Unspecified => "Unknown".to_string(),
+    }
+}
+
+fn duration_text(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+impl crate::Shortcut for Shortcut {
+    fn name(&mut self) -> Option<&str> {
+        Some("Battery")
+    }
+
+    fn description(&mut self) -> Option<&str> {
+        Some("Battery health and charge limit")
+    }
+
+    fn draw(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("No battery found on this system");
+            return;
+        }
+
+        if let Some(update) = self.watch.latest() {
+            tracing::debug!("Watch update: {update:?}");
+            // A change in flight reports the outcome itself, and dragging shouldn't jump back
+            if let (Some(battery), None) = (update.battery, &self.charge_limit_promise) {
+                if ui.memory().is_anything_being_dragged() {
+                    let charge_limit_percent = self.charge_limit_percent;
+                    self.show_battery(battery);
+                    self.charge_limit_percent = charge_limit_percent;
+                } else {
+                    self.show_battery(battery);
+                }
+            }
+        }
+
+        let battery = match self.battery.clone() {
+            Some(battery) => battery,
+            None => return,
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} %", battery.capacity_percent));
+            ui.label(status_text(&battery));
+            if battery.power_watts > 0.0 {
+                ui.label(format!("{:.1} W", battery.power_watts));
+            }
+        });
+        ui.horizontal(|ui| {
+            if battery.health_percent >= 0.0 {
+                ui.label(format!("Health {:.0} %", battery.health_percent));
+            }
+            if battery.cycle_count >= 0 {
+                ui.label(format!("{} cycles", battery.cycle_count));
+            }
+        });
+
+        if !battery.charge_limit_supported {
+            return;
+        }
+
+        ui.add_enabled_ui(self.charge_limit_promise.is_none(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Charge limit");
+                let response = ui.add(
+                    egui::Slider::new(&mut self.charge_limit_percent, MIN_CHARGE_LIMIT..=100)
+                        .suffix(" %"),
+                );
+                // Dragging would otherwise send a request every frame
+                if response.drag_released() || (response.changed() && !response.dragged()) {
+                    let percent = self.charge_limit_percent;
+                    self.charge_limit_promise =
+                        Some(self.rt.block_on(async move {
+                            Promise::spawn_async(set_charge_limit(percent))
+                        }));
+                }
+            });
+        });
+
+        if let Some(promise) = &self.charge_limit_promise {
+            match promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notifications_tx
+                        .send(Toast {
+                            kind: egui_toast::ToastKind::Error,
+                            text: format!("Unable to set the charge limit: {err}").into(),
+                            options: ToastOptions::with_duration(Duration::from_secs(5)),
+                        })
+                        .ok();
+                    tracing::error!("unable to set the charge limit: {err}");
+                    // Back to what the daemon reported last
+                    self.show_battery(battery);
+                    self.charge_limit_promise = None;
+                }
+                Some(Ok(new_battery)) => {
+                    let new_battery = new_battery.clone();
+                    self.show_battery(new_battery);
+                    self.charge_limit_promise = None;
+                }
+            }
+        }
+    }
+}
+
+async fn get_client() -> Result<battery_service_client::BatteryServiceClient<Channel>, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+
+    Ok(battery_service_client::BatteryServiceClient::new(channel))
+}
+
+async fn watch_battery() -> Result<tonic::Streaming<battery::WatchBatteryResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(battery::WatchBatteryRequest {});
+    let response = client.watch_battery(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn set_charge_limit(percent: u32) -> Result<battery::Battery, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(battery::SetChargeLimitRequest { percent });
+    let response = client.set_charge_limit(request).await?;
+
+    response
+        .into_inner()
+        .battery
+        .ok_or_else(|| Error::Internal("the daemon sent no battery".to_string()))
+}
@@ -0,0 +1,548 @@
+use std::time::Duration;
+
+use eframe::egui;
+use egui_toast::{Toast, ToastOptions};
+use poll_promise::Promise;
+use shortcut_core::bluetooth::bluetooth_service_client;
+use shortcut_core::bluetooth::pair_response::Event;
+use shortcut_core::futures::StreamExt;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tokio_stream::wrappers::ReceiverStream;
+use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{bluetooth, tokio, tonic, Error};
+use std::sync::mpsc;
+
+use crate::health::Serving;
+use crate::watch::Watch;
+use crate::widgets;
+
+const SELECTED_DEVICE_KEY: &str = "selected_bluetooth_device";
+
+/// A device action in flight, named for error messages.
+struct Action {
+    name: &'static str,
+    promise: Promise<Result<(), Error>>,
+}
+
+/// An open pairing dialog, the daemon keeps discovering until it is dropped.
+struct Pairing {
+    requests: tokio::sync::mpsc::Sender<bluetooth::PairRequest>,
+    responses: mpsc::Receiver<Result<bluetooth::PairResponse, Error>>,
+    discovered: Vec<bluetooth::Device>,
+    /// Open prompts with the passkey or PIN code typed so far.
+    prompts: Vec<(bluetooth::Prompt, String)>,
+    /// The device being paired with.
+    pairing: Option<String>,
+}
+
+impl Pairing {
+    fn start(rt: &tokio::runtime::Handle, ctx: egui::Context) -> Self {
+        let (requests, requests_rx) = tokio::sync::mpsc::channel(4);
+        let (responses_tx, responses) = mpsc::channel();
+        rt.spawn(async move {
+            let mut stream = match pair(ReceiverStream::new(requests_rx)).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    responses_tx.send(Err(err)).ok();
+                    ctx.request_repaint();
+                    return;
+                }
+            };
+            while let Some(response) = stream.next().await {
+                let failed = response.is_err();
+                if responses_tx.send(response.map_err(Error::from)).is_err() || failed {
+                    break;
+                }
+                ctx.request_repaint();
+            }
+            ctx.request_repaint();
+        });
+
+        Self {
+            requests,
+            responses,
+            discovered: vec![],
+            prompts: vec![],
+            pairing: None,
+        }
+    }
+
+    fn send(&self, action: bluetooth::pair_request::Action) {
+        let request = bluetooth::PairRequest {
+            action: Some(action),
+        };
+        if let Err(err) = self.requests.try_send(request) {
+            tracing::error!("unable to send pairing request: {err}");
+        }
+    }
+
+    fn reply(&mut self, index: usize, accept: bool) {
+        let (prompt, typed) = self.prompts.remove(index);
+        let mut reply = bluetooth::PromptReply {
+            prompt_id: prompt.id,
+            accept,
+            ..Default::default()
+        };
+        match prompt.kind() {
+            bluetooth::PromptKind::EnterPinCode => reply.pin_code = typed.trim().to_string(),
+            _ => reply.passkey = typed.trim().parse().unwrap_or_default(),
+        }
+        self.send(bluetooth::pair_request::Action::Reply(reply));
+    }
+
+    /// Draws the prompts and discovered devices.
+    fn draw(&mut self, ui: &mut egui::Ui) {
+        let mut answered = None;
+        for (index, (prompt, passkey)) in self.prompts.iter_mut().enumerate() {
+            let address = &prompt.address;
+            ui.horizontal(|ui| match prompt.kind() {
+                bluetooth::PromptKind::ConfirmPasskey => {
+                    ui.label(format!("Does {address} show {:06}?", prompt.passkey));
+                    if ui.button("Confirm").clicked() {
+                        answered = Some((index, true));
+                    }
+                    if ui.button("Reject").clicked() {
+                        answered = Some((index, false));
+                    }
+                }
+                bluetooth::PromptKind::DisplayPasskey => {
+                    ui.label(format!("Type {:06} on {address}", prompt.passkey));
+                }
+                bluetooth::PromptKind::EnterPasskey => {
+                    ui.label(format!("Passkey shown on {address}"));
+                    ui.text_edit_singleline(passkey);
+                    if ui.button("OK").clicked() {
+                        answered = Some((index, true));
+                    }
+                    if ui.button("Reject").clicked() {
+                        answered = Some((index, false));
+                    }
+                }
+                bluetooth::PromptKind::DisplayPinCode => {
+                    ui.label(format!("Type {} on {address}", prompt.pin_code));
+                }
+                bluetooth::PromptKind::EnterPinCode => {
+                    ui.label(format!("PIN code of {address}"));
+                    ui.text_edit_singleline(passkey);
+                    if ui.button("OK").clicked() {
+                        answered = Some((index, true));
+                    }
+                    if ui.button("Reject").clicked() {
+                        answered = Some((index, false));
+                    }
+                }
+                _ => {
+                    ui.label(format!("Allow {address} to pair?"));
+                    if ui.button("Allow").clicked() {
+                        answered = Some((index, true));
+                    }
+                    if ui.button("Reject").clicked() {
+                        answered = Some((index, false));
+                    }
+                }
+            });
+        }
+        if let Some((index, accept)) = answered {
+            self.reply(index, accept);
+        }
+
+        if self.discovered.is_empty() {
+            ui.label("Searching for devices...");
+        }
+        let mut clicked = None;
+        for device in &self.discovered {
+            ui.horizontal(|ui| {
+                ui.label(&device.name);
+                ui.set_enabled(self.pairing.is_none());
+                if ui.button("Pair").clicked() {
+                    clicked = Some(device.address.clone());
+                }
+            });
+        }
+        if let Some(address) = clicked {
+            tracing::debug!("Pairing with {address}");
+            self.send(bluetooth::pair_request::Action::PairDevice(
+                bluetooth::PairDevice {
+                    address: address.clone(),
+                },
+            ));
+            self.pairing = Some(address);
+        }
+    }
+}
+
+#[derive()]
+pub struct Shortcut {
+    rt: tokio::runtime::Handle,
+    powered: bool,
+    devices: Vec<bluetooth::Device>,
+    selected_device: Option<String>,
+
+    watch: Watch<bluetooth::WatchStateResponse>,
+    powered_promise: Option<Promise<Result<bool, Error>>>,
+    action: Option<Action>,
+    pairing: Option<Pairing>,
+
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
+    notifications_tx: mpsc::Sender<Toast>,
+}
+
+impl Shortcut {
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let selected_device = cc
+            .storage
+            .and_then(|storage| storage.get_string(SELECTED_DEVICE_KEY));
+
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "bluetooth devices",
+            notifications_tx.clone(),
+            watch_state,
+        );
+        let serving = Serving::check(&rt, bluetooth::SERVICE_NAME);
+
+        Self {
+            rt,
+            powered: false,
+            devices: vec![],
+            selected_device,
+
+            watch,
+            powered_promise: None,
+            action: None,
+            pairing: None,
+
+            serving,
+            notifications_tx,
+        }
+    }
+
+    fn notify_error(&self, what: &str, err: &Error) {
+        self.notifications_tx
+            .send(Toast {
+                kind: egui_toast::ToastKind::Error,
+                text: format!("Unable to {what}: {err}").into(),
+                options: ToastOptions::with_duration(Duration::from_secs(5)),
+            })
+            .ok();
+        tracing::error!("unable to {what}: {err}");
+    }
+
+    /// Applies what the daemon sent since the last frame, closing the dialog if it failed.
+    fn update_pairing(&mut self) {
+        let pairing = match &mut self.pairing {
+            Some(pairing) => pairing,
+            None => return,
+        };
+
+        let mut failed = None;
+        let mut rejected = vec![];
+        for response in pairing.responses.try_iter() {
+            let event = match response {
+                Ok(bluetooth::PairResponse { event: Some(event) }) => event,
+                Ok(_) => continue,
+                Err(err) => {
+                    failed = Some(err);
+                    break;
+                }
+            };
+            match event {
+                Event::Discovered(device) => {
+                    match pairing
+                        .discovered
+                        .iter_mut()
+                        .find(|known| known.address == device.address)
+                    {
+                        Some(known) => *known = device,
+                        None => pairing.discovered.push(device),
+                    }
+                }
+                Event::Prompt(prompt) => pairing.prompts.push((prompt, String::new())),
+                Event::Cancelled(cancelled) => pairing
+                    .prompts
+                    .retain(|(prompt, _)| !cancelled.prompt_ids.contains(&prompt.id)),
+                Event::Result(result) => {
+                    pairing
+                        .prompts
+                        .retain(|(prompt, _)| prompt.address != result.address);
+                    pairing.pairing = None;
+                    if result.paired {
+                        pairing
+                            .discovered
+                            .retain(|device| device.address != result.address);
+                        let name = result.device.map(|device| device.name);
+                        self.notifications_tx
+                            .send(Toast {
+                                kind: egui_toast::ToastKind::Success,
+                                text: format!("Paired with {}", name.unwrap_or(result.address))
+                                    .into(),
+                                options: ToastOptions::with_duration(Duration::from_secs(5)),
+                            })
+                            .ok();
+                    } else {
+                        rejected.push(Error::Internal(format!(
+                            "{}: {}",
+                            result.address, result.message
+                        )));
+                    }
+                }
+            }
+        }
+
+        // A failed pairing leaves the dialog open to try again
+        for err in rejected {
+            self.notify_error("pair bluetooth device", &err);
+        }
+        if let Some(err) = failed {
+            self.notify_error("pair bluetooth devices", &err);
+            self.pairing = None;
+        }
+    }
+
+    fn start_action<F>(&mut self, name: &'static str, action: F)
+    where
+        F: std::future::Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.action = Some(Action {
+            name,
+            promise: self
+                .rt
+                .block_on(async move { Promise::spawn_async(action) }),
+        });
+    }
+}
+
+/// How a device shows up in the device list.
+fn label(device: &bluetooth::Device) -> String {
+    let mut label = device.name.clone();
+    if device.battery_percentage >= 0 {
+        label.push_str(&format!(" ({}%)", device.battery_percentage));
+    }
+    if device.connected {
+        label.push_str(" - connected");
+    }
+    label
+}
+
+impl crate::Shortcut for Shortcut {
+    fn name(&mut self) -> Option<&str> {
+        Some("Bluetooth")
+    }
+
+    fn description(&mut self) -> Option<&str> {
+        Some("Manage bluetooth devices")
+    }
+
+    fn draw(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("Bluetooth is not available on this system");
+            return;
+        }
+
+        if let Some(update) = self.watch.latest() {
+            tracing::debug!("Watch update: {update:?}");
+            // A toggle in flight reports the outcome itself
+            if self.powered_promise.is_none() {
+                self.powered = update.powered;
+            }
+            self.devices = update.devices;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Enable");
+            ui.set_enabled(self.powered_promise.is_none());
+
+            if widgets::toggle(ui, &mut self.powered).clicked() {
+                let powered = self.powered;
+                self.powered_promise
+                    .get_or_insert(self.rt.block_on(async move {
+                        tracing::debug!("Creating new powered promise");
+                        Promise::spawn_async(async move { set_powered(powered).await })
+                    }));
+            }
+        });
+
+        if let Some(promise) = &self.powered_promise {
+            match promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notify_error("update bluetooth setting", err);
+                    // The adapter kept its state, so undo the toggle
+                    self.powered = !self.powered;
+                    self.powered_promise = None;
+                }
+                Some(Ok(powered)) => {
+                    tracing::debug!("Promise ready with result: powered={powered}");
+                    self.powered = *powered;
+                    self.powered_promise = None;
+                }
+            }
+        }
+
+        let selected = self.selected_device.as_ref().and_then(|address| {
+            self.devices
+                .iter()
+                .find(|device| &device.address == address)
+                .cloned()
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Devices");
+            ui.set_enabled(self.powered);
+            let mut clicked = None;
+            egui::ComboBox::from_id_source("bluetooth_devices")
+                .selected_text(selected.as_ref().map(label).unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for device in &self.devices {
+                        let is_selected = self.selected_device.as_ref() == Some(&device.address);
+                        if ui.selectable_label(is_selected, label(device)).clicked() {
+                            clicked = Some(device.address.clone());
+                        }
+                    }
+                });
+            if let Some(address) = clicked {
+                if let Some(storage) = frame.storage_mut() {
+                    storage.set_string(SELECTED_DEVICE_KEY, address.clone());
+                }
+                tracing::debug!("Selected device: {address}");
+                self.selected_device = Some(address);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.set_enabled(self.powered && selected.is_some() && self.action.is_none());
+            let connected = matches!(&selected, Some(device) if device.connected);
+            let address = selected
+                .as_ref()
+                .map(|device| device.address.clone())
+                .unwrap_or_default();
+
+            if connected {
+                if ui.button("Disconnect").clicked() {
+                    let address = address.clone();
+                    self.start_action(
+                        "disconnect",
+                        async move { disconnect_device(address).await },
+                    );
+                }
+            } else if ui.button("Connect").clicked() {
+                let address = address.clone();
+                self.start_action("connect", async move { connect_device(address).await });
+            }
+            if ui.button("Forget").clicked() {
+                self.start_action("forget", async move { remove_device(address).await });
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.set_enabled(self.powered && self.pairing.is_none());
+            if ui.button("Pair new device").clicked() {
+                self.pairing = Some(Pairing::start(&self.rt, ctx.clone()));
+            }
+        });
+
+        self.update_pairing();
+        if let Some(pairing) = &mut self.pairing {
+            let mut open = true;
+            egui::Window::new("Pair bluetooth device")
+                .open(&mut open)
+                .show(ctx, |ui| pairing.draw(ui));
+            if !open {
+                // Ends the session on the daemon
+                self.pairing = None;
+            }
+        }
+
+        if let Some(action) = &self.action {
+            match action.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notify_error(&format!("{} bluetooth device", action.name), err);
+                    self.action = None;
+                }
+                Some(Ok(())) => {
+                    tracing::debug!("Promise ready for {}", action.name);
+                    self.action = None;
+                }
+            }
+        }
+    }
+}
+
+async fn get_client() -> Result<bluetooth_service_client::BluetoothServiceClient<Channel>, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+
+    Ok(bluetooth_service_client::BluetoothServiceClient::new(
+        channel,
+    ))
+}
+
+async fn watch_state() -> Result<tonic::Streaming<bluetooth::WatchStateResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(bluetooth::WatchStateRequest {});
+    let response = client.watch_state(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn set_powered(powered: bool) -> Result<bool, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(bluetooth::SetPoweredRequest { powered });
+    let response = client.set_powered(request).await?;
+
+    let inner = response.into_inner();
+    Ok(inner.powered)
+}
+
+async fn connect_device(address: String) -> Result<(), Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(bluetooth::ConnectDeviceRequest { address });
+    client.connect_device(request).await?;
+
+    Ok(())
+}
+
+async fn disconnect_device(address: String) -> Result<(), Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(bluetooth::DisconnectDeviceRequest { address });
+    client.disconnect_device(request).await?;
+
+    Ok(())
+}
+
+async fn remove_device(address: String) -> Result<(), Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(bluetooth::RemoveDeviceRequest { address });
+    client.remove_device(request).await?;
+
+    Ok(())
+}
+
+async fn pair(
+    requests: ReceiverStream<bluetooth::PairRequest>,
+) -> Result<tonic::Streaming<bluetooth::PairResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let response = client.pair(tonic::Request::new(requests)).await?;
+
+    Ok(response.into_inner())
+}
@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use eframe::egui;
+use egui_toast::{Toast, ToastOptions};
+use poll_promise::Promise;
+use shortcut_core::cpu::cpu_service_client;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{cpu, tokio, tonic, Error};
+use std::sync::mpsc;
+
+use crate::health::Serving;
+use crate::watch::Watch;
+use crate::widgets;
+
+/// A change in flight, named for error messages.
+struct Change {
+    name: &'static str,
+    promise: Promise<Result<cpu::CpuState, Error>>,
+}
+
+#[derive()]
+pub struct Shortcut {
+    rt: tokio::runtime::Handle,
+    state: Option<cpu::CpuState>,
+    /// The slider position, only sent once the slider is let go.
+    max_frequency_mhz: u32,
+
+    watch: Watch<cpu::WatchStateResponse>,
+    change: Option<Change>,
+
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
+    notifications_tx: mpsc::Sender<Toast>,
+}
+
+impl Shortcut {
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "CPU settings",
+            notifications_tx.clone(),
+            watch_state,
+        );
+        let serving = Serving::check(&rt, cpu::SERVICE_NAME);
+
+        Self {
+            rt,
+            state: None,
+            max_frequency_mhz: 0,
+
+            watch,
+            change: None,
+
+            serving,
+            notifications_tx,
+        }
+    }
+
+    fn start_change<F>(&mut self, name: &'static str, change: F)
+    where
+        F: std::future::Future<Output = Result<cpu::CpuState, Error>> + Send + 'static,
+    {
+        self.change = Some(Change {
+            name,
+            promise: self
+                .rt
+                .block_on(async move { Promise::spawn_async(change) }),
+        });
+    }
+
+    fn show_state(&mut self, state: cpu::CpuState) {
+        self.max_frequency_mhz = state.max_frequency_khz / 1000;
+        self.state = Some(state);
+    }
+}
+
+impl crate::Shortcut for Shortcut {
+    fn name(&mut self) -> Option<&str> {
+        Some("CPU")
+    }
+
+    fn description(&mut self) -> Option<&str> {
+        Some("Tune CPU performance and power usage")
+    }
+
+    fn draw(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("CPU settings are not available on this system");
+            return;
+        }
+
+        if let Some(update) = self.watch.latest() {
+            tracing::debug!("Watch update: {update:?}");
+            // A change in flight reports the outcome itself
+            if let (Some(state), None) = (update.state, &self.change) {
+                self.show_state(state);
+            }
+        }
+
+        let state = match self.state.clone() {
+            Some(state) => state,
+            None => return,
+        };
+        ui.set_enabled(self.change.is_none());
+
+        ui.horizontal(|ui| {
+            ui.label("Governor");
+            let mut selected = None;
+            egui::ComboBox::from_id_source("cpu_governor")
+                .selected_text(&state.governor)
+                .show_ui(ui, |ui| {
+                    for governor in &state.available_governors {
+                        let is_selected = *governor == state.governor;
+                        if ui.selectable_label(is_selected, governor).clicked() && !is_selected {
+                            selected = Some(governor.clone());
+                        }
+                    }
+                });
+            if let Some(governor) = selected {
+                tracing::debug!("Selected governor: {governor}");
+                self.start_change("set governor", async move { set_governor(governor).await });
+            }
+        });
+
+        if state.smt_supported {
+            ui.horizontal(|ui| {
+                ui.label("SMT");
+                let mut enabled = state.smt_enabled;
+                if widgets::toggle(ui, &mut enabled).clicked() {
+                    self.start_change("update SMT", async move { set_smt(enabled).await });
+                }
+            });
+        }
+
+        if state.boost_supported {
+            ui.horizontal(|ui| {
+                ui.label("Boost");
+                let mut enabled = state.boost_enabled;
+                if widgets::toggle(ui, &mut enabled).clicked() {
+                    self.start_change("update boost", async move { set_boost(enabled).await });
+                }
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Max frequency");
+            let range =
+                state.hardware_min_frequency_khz / 1000..=state.hardware_max_frequency_khz / 1000;
+            let response = ui.add(
+                egui::Slider::new(&mut self.max_frequency_mhz, range)
+                    .suffix(" MHz")
+                    .step_by(100.0),
+            );
+            // Dragging would otherwise send a request every frame
+            if response.drag_released() || (response.changed() && !response.dragged()) {
+                let khz = (self.max_frequency_mhz * 1000).clamp(
+                    state.hardware_min_frequency_khz,
+                    state.hardware_max_frequency_khz,
+                );
+                self.start_change(
+                    "set max frequency",
+                    async move { set_max_frequency(khz).await },
+                );
+            }
+        });
+
+        if let Some(change) = &self.change {
+            match change.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notifications_tx
+                        .send(Toast {
+                            kind: egui_toast::ToastKind::Error,
+                            text: format!("Unable to {}: {err}", change.name).into(),
+                            options: ToastOptions::with_duration(Duration::from_secs(5)),
+                        })
+                        .ok();
+                    tracing::error!("unable to {}: {err}", change.name);
+                    // Back to what the daemon reported last
+                    self.show_state(state);
+                    self.change = None;
+                }
+                Some(Ok(new_state)) => {
+                    tracing::debug!("Promise ready for {}", change.name);
+                    let new_state = new_state.clone();
+                    self.show_state(new_state);
+                    self.change = None;
+                }
+            }
+        }
+    }
+}
+
+async fn get_client() -> Result<cpu_service_client::CpuServiceClient<Channel>, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+
+    Ok(cpu_service_client::CpuServiceClient::new(channel))
+}
+
+async fn watch_state() -> Result<tonic::Streaming<cpu::WatchStateResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(cpu::WatchStateRequest {});
+    let response = client.watch_state(request).await?;
+
+    Ok(response.into_inner())
+}
+
+/// The state a change returned, which every response carries.
+fn state(state: Option<cpu::CpuState>) -> Result<cpu::CpuState, Error> {
+    state.ok_or_else(|| Error::Internal("the daemon sent no cpu state".to_string()))
+}
+
+async fn set_governor(governor: String) -> Result<cpu::CpuState, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(cpu::SetGovernorRequest { governor });
+    let response = client.set_governor(request).await?;
+
+    state(response.into_inner().state)
+}
+
+async fn set_smt(enabled: bool) -> Result<cpu::CpuState, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(cpu::SetSmtRequest { enabled });
+    let response = client.set_smt(request).await?;
+
+    state(response.into_inner().state)
+}
+
+async fn set_boost(enabled: bool) -> Result<cpu::CpuState, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(cpu::SetBoostRequest { enabled });
+    let response = client.set_boost(request).await?;
+
+    state(response.into_inner().state)
+}
+
+async fn set_max_frequency(frequency_khz: u32) -> Result<cpu::CpuState, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(cpu::SetMaxFrequencyRequest { frequency_khz });
+    let response = client.set_max_frequency(request).await?;
+
+    state(response.into_inner().state)
+}
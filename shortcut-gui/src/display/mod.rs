@@ -0,0 +1,182 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use eframe::egui;
+use egui_toast::{Toast, ToastOptions};
+use poll_promise::Promise;
+use shortcut_core::display::display_service_client;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{display, tokio, tonic, Error};
+
+use crate::health::Serving;
+use crate::watch::Watch;
+
+pub struct Shortcut {
+    rt: tokio::runtime::Handle,
+    /// The backlight the slider controls, the first one the daemon lists.
+    backlight: Option<display::Backlight>,
+    /// The slider position, only sent once the slider is let go.
+    brightness_percent: u32,
+
+    watch: Watch<display::WatchBacklightsResponse>,
+    brightness_promise: Option<Promise<Result<display::Backlight, Error>>>,
+
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
+    notifications_tx: mpsc::Sender<Toast>,
+}
+
+impl Shortcut {
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "display brightness",
+            notifications_tx.clone(),
+            watch_backlights,
+        );
+        let serving = Serving::check(&rt, display::SERVICE_NAME);
+
+        Self {
+            rt,
+            backlight: None,
+            brightness_percent: 0,
+
+            watch,
+            brightness_promise: None,
+
+            serving,
+            notifications_tx,
+        }
+    }
+
+    fn show_backlight(&mut self, backlight: display::Backlight) {
+        self.brightness_percent = backlight.brightness_percent;
+        self.backlight = Some(backlight);
+    }
+}
+
+impl crate::Shortcut for Shortcut {
+    fn name(&mut self) -> Option<&str> {
+        Some("Display")
+    }
+
+    fn description(&mut self) -> Option<&str> {
+        Some("Adjust the screen brightness")
+    }
+
+    fn draw(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("Brightness control is not available on this system");
+            return;
+        }
+
+        if let Some(update) = self.watch.latest() {
+            tracing::debug!("Watch update: {update:?}");
+            // A change in flight reports the outcome itself, and dragging shouldn't jump back
+            if let (Some(backlight), None) = (
+                update.backlights.into_iter().next(),
+                &self.brightness_promise,
+            ) {
+                if !ui.memory().is_anything_being_dragged() {
+                    self.show_backlight(backlight);
+                }
+            }
+        }
+
+        let backlight = match self.backlight.clone() {
+            Some(backlight) => backlight,
+            None => return,
+        };
+        ui.set_enabled(self.brightness_promise.is_none());
+
+        ui.horizontal(|ui| {
+            ui.label("Brightness");
+            let response = ui.add(
+                egui::Slider::new(
+                    &mut self.brightness_percent,
+                    backlight.min_brightness_percent..=100,
+                )
+                .suffix(" %"),
+            );
+            // Dragging would otherwise send a request every frame
+            if response.drag_released() || (response.changed() && !response.dragged()) {
+                let name = backlight.name.clone();
+                let brightness_percent = self.brightness_percent;
+                self.brightness_promise = Some(self.rt.block_on(async move {
+                    Promise::spawn_async(set_brightness(name, brightness_percent))
+                }));
+            }
+        });
+
+        if let Some(promise) = &self.brightness_promise {
+            match promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notifications_tx
+                        .send(Toast {
+                            kind: egui_toast::ToastKind::Error,
+                            text: format!("Unable to set brightness: {err}").into(),
+                            options: ToastOptions::with_duration(Duration::from_secs(5)),
+                        })
+                        .ok();
+                    tracing::error!("unable to set brightness: {err}");
+                    // Back to what the daemon reported last
+                    self.show_backlight(backlight);
+                    self.brightness_promise = None;
+                }
+                Some(Ok(new_backlight)) => {
+                    let new_backlight = new_backlight.clone();
+                    self.show_backlight(new_backlight);
+                    self.brightness_promise = None;
+                }
+            }
+        }
+    }
+}
+
+async fn get_client() -> Result<display_service_client::DisplayServiceClient<Channel>, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+
+    Ok(display_service_client::DisplayServiceClient::new(channel))
+}
+
+async fn watch_backlights() -> Result<tonic::Streaming<display::WatchBacklightsResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(display::WatchBacklightsRequest {});
+    let response = client.watch_backlights(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn set_brightness(
+    name: String,
+    brightness_percent: u32,
+) -> Result<display::Backlight, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(display::SetBrightnessRequest {
+        name,
+        brightness_percent,
+    });
+    let response = client.set_brightness(request).await?;
+
+    response
+        .into_inner()
+        .backlight
+        .ok_or_else(|| Error::Internal("the daemon sent no backlight".to_string()))
+}
@@ -0,0 +1,300 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui::emath::RectTransform;
+use egui_toast::{Toast, ToastOptions};
+use poll_promise::Promise;
+use shortcut_core::sensors::sensors_service_client;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{sensors, tokio, tonic, Error};
+
+use crate::health::Serving;
+use crate::watch::Watch;
+
+/// The temperatures the editor covers, in degrees Celsius.
+const MAX_TEMPERATURE: f32 = 105.0;
+/// Mirrors the daemon, which never runs the fan slower than this when hot.
+const HOT_TEMPERATURE: f32 = 80.0;
+const MIN_HOT_DUTY: f32 = 70.0;
+/// Offered when the daemon has no curve yet.
+const DEFAULT_CURVE: [(f32, f32); 4] = [(40.0, 20.0), (60.0, 35.0), (75.0, 60.0), (90.0, 100.0)];
+const POINT_RADIUS: f32 = 6.0;
+
+/// A point of the curve as (temperature, duty in percent).
+type Point = (f32, f32);
+
+pub struct Shortcut {
+    rt: tokio::runtime::Handle,
+    points: Vec<Point>,
+    status: Option<sensors::FanStatus>,
+
+    watch: Watch<sensors::WatchFanStatusResponse>,
+    load_promise: Option<Promise<Result<sensors::GetFanCurveResponse, Error>>>,
+    change_promise: Option<Promise<Result<sensors::FanCurve, Error>>>,
+
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
+    notifications_tx: mpsc::Sender<Toast>,
+}
+
+impl Shortcut {
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "fan status",
+            notifications_tx.clone(),
+            watch_fan_status,
+        );
+        let load_promise = rt.block_on(async { Promise::spawn_async(get_fan_curve()) });
+        let serving = Serving::check(&rt, sensors::SERVICE_NAME);
+
+        Self {
+            rt,
+            points: DEFAULT_CURVE.to_vec(),
+            status: None,
+
+            watch,
+            load_promise: Some(load_promise),
+            change_promise: None,
+
+            serving,
+            notifications_tx,
+        }
+    }
+
+    fn show_curve(&mut self, curve: Option<sensors::FanCurve>) {
+        let points = curve.map(|curve| curve.points).unwrap_or_default();
+        // Firmware control keeps the curve being edited
+        if !points.is_empty() {
+            self.points = points
+                .iter()
+                .map(|point| (point.temperature as f32, point.duty_percent as f32))
+                .collect();
+        }
+    }
+
+    fn set_curve(&mut self, points: Vec<Point>) {
+        let curve = sensors::FanCurve {
+            points: points
+                .into_iter()
+                .map(|(temperature, duty)| sensors::CurvePoint {
+                    temperature: f64::from(temperature.round()),
+                    duty_percent: duty.round() as u32,
+                })
+                .collect(),
+        };
+        self.change_promise = Some(
+            self.rt
+                .block_on(async { Promise::spawn_async(set_fan_curve(curve)) }),
+        );
+    }
+
+    fn notify_error(&self, what: &str, err: &Error) {
+        self.notifications_tx
+            .send(Toast {
+                kind: egui_toast::ToastKind::Error,
+                text: format!("Unable to {what}: {err}").into(),
+                options: ToastOptions::with_duration(Duration::from_secs(5)),
+            })
+            .ok();
+        tracing::error!("unable to {what}: {err}");
+    }
+
+    /// Draws the curve and lets its points be dragged, keeping it rising.
+    fn draw_curve(&mut self, ui: &mut egui::Ui) {
+        let size = egui::vec2(ui.available_width(), 160.0);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+        let area = response.rect.shrink(POINT_RADIUS);
+        // Temperature left to right, duty bottom to top
+        let to_screen = RectTransform::from_to(
+            egui::Rect::from_min_max(egui::pos2(0.0, 100.0), egui::pos2(MAX_TEMPERATURE, 0.0)),
+            area,
+        );
+        let stroke = ui.visuals().widgets.inactive.fg_stroke;
+
+        painter.rect_stroke(area, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+        // Where the daemon lifts the curve to keep the fan from running too slow
+        painter.rect_filled(
+            egui::Rect::from_two_pos(
+                to_screen * egui::pos2(HOT_TEMPERATURE, MIN_HOT_DUTY),
+                to_screen * egui::pos2(MAX_TEMPERATURE, 0.0),
+            ),
+            0.0,
+            Color32::from_rgba_unmultiplied(200, 80, 80, 30),
+        );
+
+        for i in 0..self.points.len() {
+            let center = to_screen * egui::pos2(self.points[i].0, self.points[i].1);
+            let rect = egui::Rect::from_center_size(center, egui::Vec2::splat(4.0 * POINT_RADIUS));
+            let point_response = ui.interact(rect, response.id.with(i), egui::Sense::drag());
+            if point_response.dragged() {
+                let moved = to_screen.inverse() * (center + point_response.drag_delta());
+                let (min_temperature, min_duty) = match i {
+                    0 => (0.0, 0.0),
+                    _ => (self.points[i - 1].0 + 1.0, self.points[i - 1].1),
+                };
+                let (max_temperature, max_duty) = match self.points.get(i + 1) {
+                    Some(next) => (next.0 - 1.0, next.1),
+                    None => (MAX_TEMPERATURE, 100.0),
+                };
+                self.points[i] = (
+                    moved.x.clamp(min_temperature, max_temperature),
+                    moved.y.clamp(min_duty, max_duty),
+                );
+            }
+            if point_response.hovered() || point_response.dragged() {
+                ui.output().cursor_icon = egui::CursorIcon::Grab;
+            }
+        }
+
+        let line: Vec<_> = self
+            .points
+            .iter()
+            .map(|(temperature, duty)| to_screen * egui::pos2(*temperature, *duty))
+            .collect();
+        painter.add(egui::Shape::line(line.clone(), stroke));
+        for center in line {
+            painter.circle_filled(center, POINT_RADIUS, Color32::from_rgb(220, 220, 220));
+        }
+
+        if let Some(status) = &self.status {
+            if status.control() == sensors::FanControl::Curve {
+                let current =
+                    to_screen * egui::pos2(status.temperature as f32, status.duty_percent as f32);
+                painter.circle_stroke(current, POINT_RADIUS, (2.0, Color32::LIGHT_BLUE));
+            }
+        }
+    }
+}
+
+impl crate::Shortcut for Shortcut {
+    fn name(&mut self) -> Option<&str> {
+        Some("Fan")
+    }
+
+    fn description(&mut self) -> Option<&str> {
+        Some("Drag the points to shape the fan curve, above 80°C the fan runs at least at 70%")
+    }
+
+    fn draw(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("Fan control is not available on this system");
+            return;
+        }
+
+        if let Some(update) = self.watch.latest() {
+            tracing::debug!("Watch update: {update:?}");
+            self.status = update.status;
+        }
+
+        if let Some(promise) = &self.load_promise {
+            match promise.ready() {
+                None => return,
+                Some(Err(err)) => {
+                    self.notify_error("load the fan curve", err);
+                    self.load_promise = None;
+                }
+                Some(Ok(reply)) => {
+                    let reply = reply.clone();
+                    self.show_curve(reply.curve);
+                    self.status = self.status.take().or(reply.status);
+                    self.load_promise = None;
+                }
+            }
+        }
+
+        if let Some(status) = &self.status {
+            let label = match status.control() {
+                sensors::FanControl::Curve => egui::RichText::new(format!(
+                    "Following the curve at {:.0}°C, {}%",
+                    status.temperature, status.duty_percent
+                )),
+                sensors::FanControl::Failed => {
+                    egui::RichText::new(format!("The firmware took over again: {}", status.error))
+                        .color(Color32::from_rgb(230, 150, 60))
+                }
+                sensors::FanControl::Firmware | sensors::FanControl::Unspecified => {
+                    egui::RichText::new("Controlled by the firmware")
+                }
+            };
+            ui.label(label);
+        }
+
+        ui.set_enabled(self.change_promise.is_none());
+        self.draw_curve(ui);
+
+        ui.horizontal(|ui| {
+            if ui.button("Apply curve").clicked() {
+                tracing::debug!("Applying fan curve {:?}", self.points);
+                self.set_curve(self.points.clone());
+            }
+            if ui.button("Firmware control").clicked() {
+                self.set_curve(vec![]);
+            }
+        });
+
+        if let Some(promise) = &self.change_promise {
+            match promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notify_error("set the fan curve", err);
+                    self.change_promise = None;
+                }
+                Some(Ok(curve)) => {
+                    let curve = curve.clone();
+                    self.show_curve(Some(curve));
+                    self.change_promise = None;
+                }
+            }
+        }
+    }
+}
+
+async fn get_client() -> Result<sensors_service_client::SensorsServiceClient<Channel>, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+
+    Ok(sensors_service_client::SensorsServiceClient::new(channel))
+}
+
+async fn watch_fan_status() -> Result<tonic::Streaming<sensors::WatchFanStatusResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(sensors::WatchFanStatusRequest {});
+    let response = client.watch_fan_status(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn get_fan_curve() -> Result<sensors::GetFanCurveResponse, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(sensors::GetFanCurveRequest {});
+    let response = client.get_fan_curve(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn set_fan_curve(curve: sensors::FanCurve) -> Result<sensors::FanCurve, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(sensors::SetFanCurveRequest { curve: Some(curve) });
+    let response = client.set_fan_curve(request).await?;
+
+    Ok(response.into_inner().curve.unwrap_or_default())
+}
@@ -0,0 +1,60 @@
+use poll_promise::Promise;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tonic::transport::{Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{tokio, tonic, Error};
+use tonic_health::proto::health_check_response::ServingStatus;
+use tonic_health::proto::health_client::HealthClient;
+use tonic_health::proto::HealthCheckRequest;
+
+/// Asks the daemon whether `service` can be used on this system.
+pub async fn is_serving(service: &str) -> Result<bool, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+    let mut client = HealthClient::new(channel);
+
+    let request = tonic::Request::new(HealthCheckRequest {
+        service: service.to_string(),
+    });
+    let response = client.check(request).await?;
+
+    let inner = response.into_inner();
+    Ok(inner.status == ServingStatus::Serving as i32)
+}
+
+/// Whether the daemon provides a service, asked again after a stream of it failed since a
+/// config reload or a daemon restart can change the answer.
+pub struct Serving {
+    rt: tokio::runtime::Handle,
+    service: &'static str,
+    promise: Promise<Result<bool, Error>>,
+}
+
+impl Serving {
+    pub fn check(rt: &tokio::runtime::Handle, service: &'static str) -> Self {
+        Self {
+            rt: rt.clone(),
+            service,
+            promise: Self::spawn(rt, service),
+        }
+    }
+
+    fn spawn(rt: &tokio::runtime::Handle, service: &'static str) -> Promise<Result<bool, Error>> {
+        rt.block_on(async { Promise::spawn_async(async move { is_serving(service).await }) })
+    }
+
+    /// Asks again unless a check is still running.
+    pub fn recheck(&mut self) {
+        if self.promise.ready().is_some() {
+            self.promise = Self::spawn(&self.rt, self.service);
+        }
+    }
+
+    /// The answer, `None` while the daemon is being asked.
+    pub fn ready(&self) -> Option<&Result<bool, Error>> {
+        self.promise.ready()
+    }
+}
@@ -1,19 +1,41 @@
+use clap::Parser;
 use eframe::egui;
 use eframe::epaint::Color32;
 use egui_toast::{Toast, Toasts};
 use shortcut_core::tokio;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use tracing_subscriber::fmt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
 mod audio;
+mod battery;
+mod bluetooth;
+mod cpu;
+mod display;
+mod fan;
+mod health;
+mod sensors;
 mod ssh;
 mod style;
+mod watch;
 mod widgets;
 mod wifi;
 
+#[derive(Debug, Parser)]
+#[clap(about, version)]
+struct Args {
+    /// Socket the daemon listens on
+    #[clap(long, env = shortcut_core::SOCKET_PATH_ENV, default_value = shortcut_core::SOCKET_PATH)]
+    socket: PathBuf,
+}
+
 fn main() {
+    let args = Args::parse();
+    // The clients look the socket up through shortcut_core::socket_path()
+    std::env::set_var(shortcut_core::SOCKET_PATH_ENV, &args.socket);
+
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
         let collector = tracing_subscriber::registry()
@@ -30,6 +52,7 @@ fn main() {
     }
 
     tracing::info!("Logging initialized");
+    tracing::info!("Using daemon socket {}", args.socket.display());
 
     let options = eframe::NativeOptions {
         decorated: false,
@@ -62,6 +85,7 @@ struct SteamDeckApp {
     rt: tokio::runtime::Runtime,
     notifications_rx: mpsc::Receiver<Toast>,
 
+    status: sensors::StatusRow,
     shortcuts: Vec<Box<dyn Shortcut>>,
 }
 
@@ -82,14 +106,25 @@ impl SteamDeckApp {
             .build()
             .unwrap();
 
+        let status = sensors::StatusRow::new(rt.handle().clone(), cc, tx.clone());
         let shortcuts: Vec<Box<dyn Shortcut>> = vec![
-            Box::new(ssh::Shortcut::new(rt.handle().clone(), tx.clone())),
+            Box::new(ssh::Shortcut::new(rt.handle().clone(), cc, tx.clone())),
             Box::new(wifi::Shortcut::new(rt.handle().clone(), cc, tx.clone())),
+            Box::new(bluetooth::Shortcut::new(
+                rt.handle().clone(),
+                cc,
+                tx.clone(),
+            )),
+            Box::new(cpu::Shortcut::new(rt.handle().clone(), cc, tx.clone())),
+            Box::new(fan::Shortcut::new(rt.handle().clone(), cc, tx.clone())),
+            Box::new(display::Shortcut::new(rt.handle().clone(), cc, tx.clone())),
+            Box::new(battery::Shortcut::new(rt.handle().clone(), cc, tx.clone())),
             Box::new(audio::Shortcut::new(rt.handle().clone(), cc, tx)),
         ];
 
         Self {
             rt,
+            status,
             shortcuts,
             notifications_rx,
         }
@@ -108,6 +143,7 @@ impl eframe::App for SteamDeckApp {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.add_space(16.0);
             ui.heading("SteamDeck Shortcuts");
+            self.status.draw(ui);
 
             for shortcut in self.shortcuts.iter_mut() {
                 if let Some(name) = shortcut.name() {
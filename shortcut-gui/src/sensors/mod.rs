@@ -0,0 +1,114 @@
+use std::sync::mpsc;
+
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui_toast::Toast;
+use shortcut_core::sensors::sensors_service_client;
+use shortcut_core::tokio::net::UnixStream;
+use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
+use shortcut_core::tower::service_fn;
+use shortcut_core::{sensors, tokio, tonic, Error};
+
+use crate::health::Serving;
+use crate::watch::Watch;
+
+/// How often the row is refreshed.
+const INTERVAL_MS: u32 = 1000;
+
+/// A single line of live temperatures, fan speeds and power draw.
+pub struct StatusRow {
+    sensors: Vec<sensors::Sensor>,
+    watch: Watch<sensors::WatchSensorsResponse>,
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
+}
+
+impl StatusRow {
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "sensors",
+            notifications_tx,
+            watch_sensors,
+        );
+        let serving = Serving::check(&rt, sensors::SERVICE_NAME);
+
+        Self {
+            sensors: vec![],
+            watch,
+            serving,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if !matches!(self.serving.ready(), Some(Ok(true))) {
+            return;
+        }
+        if let Some(update) = self.watch.latest() {
+            self.sensors = update.sensors;
+        }
+
+        ui.horizontal_wrapped(|ui| {
+            for sensor in &self.sensors {
+                ui.small(
+                    egui::RichText::new(format!("{} {}", name(sensor), value(sensor)))
+                        .color(Color32::from_rgb(150, 150, 150)),
+                );
+            }
+        });
+    }
+}
+
+/// The kernel label, or the device for channels without one like `fan1`.
+fn name(sensor: &sensors::Sensor) -> &str {
+    let unlabelled = ["temp", "fan", "power"].iter().any(|prefix| {
+        sensor
+            .label
+            .strip_prefix(prefix)
+            .map(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false)
+    });
+    if unlabelled {
+        &sensor.device
+    } else {
+        &sensor.label
+    }
+}
+
+fn value(sensor: &sensors::Sensor) -> String {
+    match sensor.kind() {
+        sensors::SensorKind::Temperature => format!("{:.0}°C", sensor.value),
+        sensors::SensorKind::Fan => format!("{:.0} RPM", sensor.value),
+        sensors::SensorKind::Power => format!("{:.1} W", sensor.value),
+        sensors::SensorKind::Unspecified => format!("{}", sensor.value),
+    }
+}
+
+async fn get_client() -> Result<sensors_service_client::SensorsServiceClient<Channel>, Error> {
+    let channel = Endpoint::try_from("http://127.0.0.1:50000")?
+        .connect_with_connector(service_fn(|_: Uri| {
+            UnixStream::connect(shortcut_core::socket_path())
+        }))
+        .await?;
+
+    Ok(sensors_service_client::SensorsServiceClient::new(channel))
+}
+
+async fn watch_sensors() -> Result<tonic::Streaming<sensors::WatchSensorsResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(sensors::WatchSensorsRequest {
+        interval_ms: INTERVAL_MS,
+    });
+    let response = client.watch_sensors(request).await?;
+
+    Ok(response.into_inner())
+}
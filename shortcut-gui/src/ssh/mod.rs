@@ -8,33 +8,228 @@ use shortcut_core::tokio::net::UnixStream;
 use shortcut_core::tonic;
 use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
 use shortcut_core::tower::service_fn;
-use shortcut_core::{ssh, tokio};
+use shortcut_core::{ssh, tokio, Error};
 use std::sync::mpsc;
 
+use crate::health::Serving;
+use crate::watch::Watch;
 use crate::widgets;
 
+/// A change to the authorized keys in flight, named for error messages.
+struct KeysAction {
+    name: &'static str,
+    promise: Promise<Result<Vec<ssh::AuthorizedKey>, Error>>,
+}
+
+/// Loading or applying the sshd options, named for error messages.
+struct OptionsAction {
+    name: &'static str,
+    promise: Promise<Result<ssh::SshdOptions, Error>>,
+}
+
 #[derive()]
 pub struct Shortcut {
     rt: tokio::runtime::Handle,
+    /// Whether sshd runs right now.
     enabled: bool,
-    promise: Option<Promise<Result<bool, tonic::transport::Error>>>,
+    enabled_at_boot: bool,
+    /// Resolves to `(enabled, enabled_at_boot)`.
+    promise: Option<Promise<Result<(bool, bool), Error>>>,
+    // Set while a toggle is in flight so a failure can reload the actual state
+    setting: bool,
+    watch: Watch<ssh::WatchEnabledResponse>,
+    keys: Vec<ssh::AuthorizedKey>,
+    keys_action: Option<KeysAction>,
+    /// The `.pub` file to import keys from.
+    import_path: String,
+    /// The sshd options as edited, sent with Apply.
+    options: ssh::SshdOptions,
+    /// The listen addresses as typed, separated by spaces or commas.
+    listen_addresses: String,
+    options_action: Option<OptionsAction>,
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
     notifications_tx: mpsc::Sender<Toast>,
 }
 
 impl Shortcut {
-    pub fn new(rt: tokio::runtime::Handle, notifications_tx: mpsc::Sender<Toast>) -> Self {
-        let promise = Some(rt.block_on(async move {
-            tracing::debug!("Creating new promise");
-            Promise::spawn_async(async { get_enabled().await })
-        }));
+    pub fn new(
+        rt: tokio::runtime::Handle,
+        cc: &eframe::CreationContext<'_>,
+        notifications_tx: mpsc::Sender<Toast>,
+    ) -> Self {
+        let watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "remote access setting",
+            notifications_tx.clone(),
+            watch_enabled,
+        );
+
+        let serving = Serving::check(&rt, ssh::SERVICE_NAME);
+
+        let keys_action = Some(KeysAction {
+            name: "load",
+            promise: rt.block_on(async { Promise::spawn_async(list_authorized_keys()) }),
+        });
+
+        let options_action = Some(OptionsAction {
+            name: "load",
+            promise: rt.block_on(async { Promise::spawn_async(get_options()) }),
+        });
 
         Self {
             rt,
             enabled: false,
-            promise,
+            enabled_at_boot: false,
+            promise: None,
+            setting: false,
+            watch,
+            keys: Vec::new(),
+            keys_action,
+            import_path: String::new(),
+            options: ssh::SshdOptions::default(),
+            listen_addresses: String::new(),
+            options_action,
+            serving,
             notifications_tx,
         }
     }
+
+    fn start_keys_action<F>(&mut self, name: &'static str, action: F)
+    where
+        F: std::future::Future<Output = Result<Vec<ssh::AuthorizedKey>, Error>> + Send + 'static,
+    {
+        self.keys_action = Some(KeysAction {
+            name,
+            promise: self
+                .rt
+                .block_on(async move { Promise::spawn_async(action) }),
+        });
+    }
+
+    fn show_options(&mut self, options: ssh::SshdOptions) {
+        self.listen_addresses = options.listen_addresses.join(" ");
+        self.options = options;
+    }
+
+    fn draw_options(&mut self, ui: &mut egui::Ui) {
+        ui.add_enabled_ui(self.options_action.is_none(), |ui| {
+            ui.checkbox(
+                &mut self.options.password_authentication,
+                "Allow logging in with a password",
+            );
+            ui.horizontal(|ui| {
+                ui.label("Port");
+                ui.add(egui::DragValue::new(&mut self.options.port).clamp_range(1..=65535));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Listen on");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.listen_addresses)
+                        .hint_text("All addresses"),
+                );
+            });
+            if ui.button("Apply").clicked() {
+                let mut options = self.options.clone();
+                options.listen_addresses = self
+                    .listen_addresses
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|address| !address.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                self.options_action = Some(OptionsAction {
+                    name: "apply",
+                    promise: self
+                        .rt
+                        .block_on(async move { Promise::spawn_async(set_options(options)) }),
+                });
+            }
+        });
+
+        if let Some(action) = &self.options_action {
+            match action.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notifications_tx
+                        .send(Toast {
+                            kind: egui_toast::ToastKind::Error,
+                            text: format!("Unable to {} SSH options: {err}", action.name).into(),
+                            options: ToastOptions::with_duration(Duration::from_secs(5)),
+                        })
+                        .ok();
+                    tracing::error!("unable to {} SSH options: {err}", action.name);
+                    self.options_action = None;
+                }
+                Some(Ok(options)) => {
+                    tracing::debug!("Promise ready for {}", action.name);
+                    let options = options.clone();
+                    self.show_options(options);
+                    self.options_action = None;
+                }
+            }
+        }
+    }
+
+    fn draw_keys(&mut self, ui: &mut egui::Ui) {
+        ui.label("Authorized keys");
+        ui.add_enabled_ui(self.keys_action.is_none(), |ui| {
+            let mut removed = None;
+            for key in &self.keys {
+                ui.horizontal(|ui| {
+                    ui.label(&key.key_type).on_hover_text(&key.options);
+                    ui.monospace(&key.fingerprint);
+                    ui.label(&key.comment);
+                    if ui.button("Delete").clicked() {
+                        removed = Some(key.fingerprint.clone());
+                    }
+                });
+            }
+            if self.keys.is_empty() {
+                ui.label("No keys, only passwords can be used to log in");
+            }
+            if let Some(fingerprint) = removed {
+                self.start_keys_action("remove", remove_authorized_key(fingerprint));
+            }
+
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.import_path)
+                        .hint_text("Path to a .pub file"),
+                );
+                if ui.button("Import").clicked() && !self.import_path.is_empty() {
+                    let path = self.import_path.clone();
+                    self.start_keys_action("import", import_authorized_keys(path));
+                }
+            });
+        });
+
+        if let Some(action) = &self.keys_action {
+            match action.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notifications_tx
+                        .send(Toast {
+                            kind: egui_toast::ToastKind::Error,
+                            text: format!("Unable to {} authorized keys: {err}", action.name)
+                                .into(),
+                            options: ToastOptions::with_duration(Duration::from_secs(5)),
+                        })
+                        .ok();
+                    tracing::error!("unable to {} authorized keys: {err}", action.name);
+                    self.keys_action = None;
+                }
+                Some(Ok(keys)) => {
+                    tracing::debug!("Promise ready for {}", action.name);
+                    if action.name == "import" {
+                        self.import_path.clear();
+                    }
+                    self.keys = keys.clone();
+                    self.keys_action = None;
+                }
+            }
+        }
+    }
 }
 
 impl crate::Shortcut for Shortcut {
@@ -47,10 +242,28 @@ impl crate::Shortcut for Shortcut {
     }
 
     fn draw(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("Remote access is not available on this system");
+            return;
+        }
+
+        if let Some(update) = self.watch.latest() {
+            tracing::debug!("Watch update: {update:?}");
+            // A toggle in flight reports the outcome itself
+            if self.promise.is_none() {
+                self.enabled = update.enabled;
+                self.enabled_at_boot = update.enabled_at_boot;
+            }
+        }
+
         ui.horizontal(|ui| {
-            ui.label("Enable");
+            ui.label("Running");
             if widgets::toggle(ui, &mut self.enabled).clicked() {
                 let enabled = self.enabled;
+                self.setting = true;
 
                 self.promise.get_or_insert(self.rt.block_on(async move {
                     tracing::debug!("Creating new promise");
@@ -58,66 +271,179 @@ impl crate::Shortcut for Shortcut {
                 }));
             }
         });
+        ui.horizontal(|ui| {
+            ui.label("Start at boot");
+            if widgets::toggle(ui, &mut self.enabled_at_boot).clicked() {
+                let enabled = self.enabled_at_boot;
+                self.setting = true;
+
+                self.promise.get_or_insert(self.rt.block_on(async move {
+                    tracing::debug!("Creating new promise");
+                    Promise::spawn_async(async move { set_enabled_at_boot(enabled).await })
+                }));
+            }
+        });
 
         if let Some(promise) = &self.promise {
             match promise.ready() {
                 None => {}
                 Some(Err(err)) => {
+                    let action = if self.setting { "update" } else { "load" };
                     self.notifications_tx
                         .send(Toast {
                             kind: egui_toast::ToastKind::Error,
-                            text: format!("Unable to load remote access setting: {err}").into(),
+                            text: format!("Unable to {action} remote access setting: {err}").into(),
                             options: ToastOptions::with_duration(Duration::from_secs(5)),
                         })
                         .ok();
-                    tracing::error!("unable to load remote access setting: {err}");
-                    self.promise = None;
+                    tracing::error!("unable to {action} remote access setting: {err}");
+
+                    self.promise = if self.setting {
+                        self.setting = false;
+                        Some(self.rt.block_on(async move {
+                            Promise::spawn_async(async { get_enabled().await })
+                        }))
+                    } else {
+                        None
+                    };
                 }
-                Some(Ok(enabled)) => {
-                    tracing::debug!("Promise ready with result: {enabled}");
+                Some(Ok((enabled, enabled_at_boot))) => {
+                    tracing::debug!(
+                        "Promise ready with result: {enabled}, at boot {enabled_at_boot}"
+                    );
                     self.enabled = *enabled;
+                    self.enabled_at_boot = *enabled_at_boot;
+                    self.setting = false;
                     self.promise = None;
                 }
             }
         }
+
+        ui.separator();
+        self.draw_keys(ui);
+
+        egui::CollapsingHeader::new("Advanced")
+            .id_source("ssh_advanced")
+            .show(ui, |ui| self.draw_options(ui));
     }
 }
 
-async fn get_client(
-) -> Result<ssh_service_client::SshServiceClient<Channel>, tonic::transport::Error> {
+async fn get_client() -> Result<ssh_service_client::SshServiceClient<Channel>, Error> {
     let channel = Endpoint::try_from("http://127.0.0.1:50000")?
         .connect_with_connector(service_fn(|_: Uri| {
-            UnixStream::connect(shortcut_core::SOCKET_PATH)
+            UnixStream::connect(shortcut_core::socket_path())
         }))
         .await?;
 
     Ok(ssh_service_client::SshServiceClient::new(channel))
 }
 
-async fn set_enabled(enabled: bool) -> Result<bool, tonic::transport::Error> {
+async fn set_enabled(enabled: bool) -> Result<(bool, bool), Error> {
     let mut client = get_client().await?;
 
     let request = tonic::Request::new(ssh::SetEnabledRequest { enabled });
-    let response = client.set_enabled(request).await;
+    let response = client.set_enabled(request).await?;
 
-    if response.is_err() {
-        return Ok(!enabled);
-    }
+    let inner = response.into_inner();
+    Ok((inner.enabled, inner.enabled_at_boot))
+}
+
+async fn set_enabled_at_boot(enabled: bool) -> Result<(bool, bool), Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(ssh::SetEnabledAtBootRequest { enabled });
+    let response = client.set_enabled_at_boot(request).await?;
+
+    let inner = response.into_inner();
+    Ok((inner.enabled, inner.enabled_at_boot))
+}
+
+async fn watch_enabled() -> Result<tonic::Streaming<ssh::WatchEnabledResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(ssh::WatchEnabledRequest {});
+    let response = client.watch_enabled(request).await?;
 
-    let inner = response.unwrap().into_inner();
-    Ok(inner.enabled)
+    Ok(response.into_inner())
 }
 
-async fn get_enabled() -> Result<bool, tonic::transport::Error> {
+async fn get_enabled() -> Result<(bool, bool), Error> {
     let mut client = get_client().await?;
 
     let request = tonic::Request::new(ssh::GetEnabledRequest {});
-    let response = client.get_enabled(request).await;
+    let response = client.get_enabled(request).await?;
 
-    if response.is_err() {
-        return Ok(true);
+    let inner = response.into_inner();
+    Ok((inner.enabled, inner.enabled_at_boot))
+}
+
+async fn list_authorized_keys() -> Result<Vec<ssh::AuthorizedKey>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(ssh::ListAuthorizedKeysRequest {});
+    let response = client.list_authorized_keys(request).await?;
+
+    Ok(response.into_inner().keys)
+}
+
+async fn remove_authorized_key(fingerprint: String) -> Result<Vec<ssh::AuthorizedKey>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(ssh::RemoveAuthorizedKeyRequest { fingerprint });
+    let response = client.remove_authorized_key(request).await?;
+
+    Ok(response.into_inner().keys)
+}
+
+/// Authorizes every key in the file at `path`, read as the GUI user so the daemon never opens
+/// files on behalf of it.
+async fn import_authorized_keys(path: String) -> Result<Vec<ssh::AuthorizedKey>, Error> {
+    let path = match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => std::path::Path::new(&home).join(rest),
+        _ => std::path::PathBuf::from(path),
+    };
+    // A key file is tiny, not worth a trip to the blocking pool
+    let content = std::fs::read_to_string(&path)
+        .map_err(|err| Error::InvalidArgument(format!("{}: {err}", path.display())))?;
+
+    let mut client = get_client().await?;
+    let mut keys = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let request = tonic::Request::new(ssh::AddAuthorizedKeyRequest {
+            key: line.to_string(),
+        });
+        keys = Some(client.add_authorized_key(request).await?.into_inner().keys);
     }
 
-    let inner = response.unwrap().into_inner();
-    Ok(inner.enabled)
+    keys.ok_or_else(|| Error::InvalidArgument(format!("no keys in {}", path.display())))
+}
+
+async fn get_options() -> Result<ssh::SshdOptions, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(ssh::GetOptionsRequest {});
+    let response = client.get_options(request).await?;
+
+    response
+        .into_inner()
+        .options
+        .ok_or_else(|| Error::Internal("the daemon sent no options".to_string()))
+}
+
+async fn set_options(options: ssh::SshdOptions) -> Result<ssh::SshdOptions, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(ssh::SetOptionsRequest {
+        options: Some(options),
+    });
+    let response = client.set_options(request).await?;
+
+    response
+        .into_inner()
+        .options
+        .ok_or_else(|| Error::Internal("the daemon sent no options".to_string()))
 }
@@ -0,0 +1,97 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use eframe::egui;
+use egui_toast::{Toast, ToastOptions};
+use shortcut_core::tokio::task::JoinHandle;
+use shortcut_core::{tokio, tonic, Error};
+
+/// How long to wait before re-opening a stream, e.g. while the daemon restarts.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Follows a watch stream of the daemon in the background.
+///
+/// The stream is re-opened whenever it ends or fails and is cancelled when the `Watch` is
+/// dropped.
+pub struct Watch<T> {
+    rx: mpsc::Receiver<T>,
+    /// Set whenever the stream fails.
+    failed: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl<T: Send + 'static> Watch<T> {
+    /// Calls `open` to start the stream, `what` names the value in error messages.
+    pub fn spawn<F, Fut>(
+        rt: &tokio::runtime::Handle,
+        ctx: egui::Context,
+        what: &'static str,
+        notifications_tx: mpsc::Sender<Toast>,
+        mut open: F,
+    ) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<tonic::Streaming<T>, Error>> + Send,
+    {
+        let (tx, rx) = mpsc::channel();
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let task = rt.spawn({
+            let failed = failed.clone();
+            async move {
+                // Only the first of a series of failures is shown
+                let mut failing = false;
+                loop {
+                    let result: Result<(), Error> = async {
+                        let mut stream = open().await?;
+                        while let Some(value) = stream.message().await? {
+                            failing = false;
+                            tx.send(value).ok();
+                            ctx.request_repaint();
+                        }
+                        Ok(())
+                    }
+                    .await;
+
+                    if let Err(err) = result {
+                        tracing::error!("unable to watch {what}: {err}");
+                        failed.store(true, Ordering::Relaxed);
+                        if !failing {
+                            notifications_tx
+                                .send(Toast {
+                                    kind: egui_toast::ToastKind::Error,
+                                    text: format!("Unable to load {what}: {err}").into(),
+                                    options: ToastOptions::with_duration(Duration::from_secs(5)),
+                                })
+                                .ok();
+                            ctx.request_repaint();
+                        }
+                        failing = true;
+                    }
+
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        });
+
+        Self { rx, failed, task }
+    }
+
+    /// The most recent value received since the last call.
+    pub fn latest(&self) -> Option<T> {
+        self.rx.try_iter().last()
+    }
+
+    /// Whether the stream failed since the last call.
+    pub fn failed(&self) -> bool {
+        self.failed.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Watch<T> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -34,3 +34,41 @@ pub(crate) fn toggle(ui: &mut egui::Ui, on: &mut bool) -> egui::Response {
 
     response
 }
+
+/// Four bars of rising height, as many filled as the signal of `percent` deserves.
+pub(crate) fn signal_bars(ui: &mut egui::Ui, percent: u32) -> egui::Response {
+    let size = ui.spacing().interact_size.y * 0.8;
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+    let filled = match percent {
+        0 => 0,
+        1..=24 => 1,
+        25..=49 => 2,
+        50..=74 => 3,
+        _ => 4,
+    };
+
+    if ui.is_rect_visible(rect) {
+        let visuals = ui.visuals().widgets.noninteractive;
+        // Bars and the gaps between them are equally wide
+        let width = rect.width() / 7.0;
+        for bar in 0..4 {
+            let left = rect.left() + 2.0 * width * bar as f32;
+            let top = rect.bottom() - rect.height() * (bar + 1) as f32 / 4.0;
+            let color = if bar < filled {
+                visuals.fg_stroke.color
+            } else {
+                visuals.bg_stroke.color
+            };
+            ui.painter().rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(left, top),
+                    egui::pos2(left + width, rect.bottom()),
+                ),
+                0.0,
+                color,
+            );
+        }
+    }
+
+    response.on_hover_text(format!("{percent} %"))
+}
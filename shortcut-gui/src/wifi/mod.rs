@@ -6,14 +6,34 @@ use shortcut_core::tonic::transport::{Channel, Endpoint, Uri};
 use shortcut_core::tower::service_fn;
 use shortcut_core::wifi;
 use shortcut_core::wifi::wifi_service_client;
-use shortcut_core::{tokio, tonic};
+use shortcut_core::{tokio, tonic, Error};
 use std::sync::mpsc;
 use std::time::Duration;
 
+use crate::health::Serving;
+use crate::watch::Watch;
 use crate::widgets;
 
 const SELECTED_DEVICE_KEY: &str = "selected_device";
 
+/// A network action in flight, named for error messages.
+struct NetworkAction {
+    name: &'static str,
+    promise: Promise<Result<Vec<wifi::AccessPoint>, Error>>,
+}
+
+/// A change to the saved networks in flight, named for error messages.
+struct SavedAction {
+    name: &'static str,
+    promise: Promise<Result<Vec<wifi::SavedNetwork>, Error>>,
+}
+
+/// A change to the automatic power save policy in flight, named for error messages.
+struct AutoPowerSaveAction {
+    name: &'static str,
+    promise: Promise<Result<wifi::AutoPowerSavePolicy, Error>>,
+}
+
 #[derive()]
 pub struct Shortcut {
     rt: tokio::runtime::Handle,
@@ -21,9 +41,45 @@ pub struct Shortcut {
     available_devices: Vec<String>,
     selected_device: Option<String>,
 
-    devices_promise: Option<Promise<Result<Vec<String>, tonic::transport::Error>>>,
-    power_save_promise: Option<Promise<Result<bool, tonic::transport::Error>>>,
-
+    devices_watch: Watch<wifi::WatchDevicesResponse>,
+    power_save_watch: Option<Watch<wifi::WatchPowerSaveResponse>>,
+    power_save_promise: Option<Promise<Result<(bool, wifi::PowerSaveScope), Error>>>,
+    // Set while a toggle is in flight so a failure can reload the actual state
+    setting_power_save: bool,
+    /// Where a toggle keeps the setting.
+    power_save_scope: wifi::PowerSaveScope,
+    /// Where the daemon found the current setting.
+    power_save_source: wifi::PowerSaveScope,
+
+    /// The policy as edited, `auto_power_save_applied` is the one the daemon follows.
+    auto_power_save: wifi::AutoPowerSavePolicy,
+    auto_power_save_applied: Option<wifi::AutoPowerSavePolicy>,
+    auto_power_save_status: Option<wifi::AutoPowerSaveStatus>,
+    auto_power_save_watch: Watch<wifi::WatchAutoPowerSaveResponse>,
+    auto_power_save_action: Option<AutoPowerSaveAction>,
+    /// The id of the last change the daemon reported, only newer ones are notified about.
+    seen_change: Option<u64>,
+
+    /// `None` while the device isn't connected.
+    link: Option<wifi::LinkInfo>,
+    link_watch: Option<Watch<wifi::WatchLinkInfoResponse>>,
+
+    /// One access point per network, strongest first.
+    networks: Vec<wifi::AccessPoint>,
+    networks_watch: Option<Watch<wifi::WatchAccessPointsResponse>>,
+    network_action: Option<NetworkAction>,
+    /// The network a passphrase is typed for.
+    passphrase_for: Option<String>,
+    passphrase: String,
+
+    /// Highest priority first.
+    saved_networks: Vec<wifi::SavedNetwork>,
+    saved_networks_watch: Watch<wifi::WatchSavedNetworksResponse>,
+    saved_action: Option<SavedAction>,
+
+    ctx: egui::Context,
+    /// Whether the daemon can provide the service on this system at all.
+    serving: Serving,
     notifications_tx: mpsc::Sender<Toast>,
 }
 
@@ -33,38 +89,571 @@ impl Shortcut {
         cc: &eframe::CreationContext<'_>,
         notifications_tx: mpsc::Sender<Toast>,
     ) -> Self {
-        let (selected_device, power_save_promise) = if let Some(storage) = cc.storage {
-            let selected_device = storage.get_string(SELECTED_DEVICE_KEY);
-            let promise = selected_device.clone().map(|dev| {
-                rt.block_on(
-                    async move { Promise::spawn_async(async { get_power_save(dev).await }) },
-                )
+        let selected_device = cc
+            .storage
+            .and_then(|storage| storage.get_string(SELECTED_DEVICE_KEY));
+
+        let devices_watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "WiFi devices",
+            notifications_tx.clone(),
+            watch_devices,
+        );
+
+        let saved_networks_watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "saved WiFi networks",
+            notifications_tx.clone(),
+            watch_saved_networks,
+        );
+
+        let auto_power_save_watch = Watch::spawn(
+            &rt,
+            cc.egui_ctx.clone(),
+            "automatic power save",
+            notifications_tx.clone(),
+            watch_auto_power_save,
+        );
+        let auto_power_save_action = rt.block_on(async {
+            AutoPowerSaveAction {
+                name: "load",
+                promise: Promise::spawn_async(get_auto_power_save()),
+            }
+        });
+
+        let serving = Serving::check(&rt, wifi::SERVICE_NAME);
+
+        let mut shortcut = Self {
+            rt,
+            power_save_enabled: false,
+            available_devices: vec![],
+            selected_device: None,
+
+            devices_watch,
+            power_save_watch: None,
+            power_save_promise: None,
+            setting_power_save: false,
+            power_save_scope: wifi::PowerSaveScope::Runtime,
+            power_save_source: wifi::PowerSaveScope::Runtime,
+
+            auto_power_save: wifi::AutoPowerSavePolicy::default(),
+            auto_power_save_applied: None,
+            auto_power_save_status: None,
+            auto_power_save_watch,
+            auto_power_save_action: Some(auto_power_save_action),
+            seen_change: None,
+
+            link: None,
+            link_watch: None,
+
+            networks: vec![],
+            networks_watch: None,
+            network_action: None,
+            passphrase_for: None,
+            passphrase: String::new(),
+
+            saved_networks: vec![],
+            saved_networks_watch,
+            saved_action: None,
+
+            ctx: cc.egui_ctx.clone(),
+            serving,
+            notifications_tx,
+        };
+        if let Some(dev) = selected_device {
+            shortcut.select_device(dev);
+        }
+
+        shortcut
+    }
+
+    /// Switches the power save toggle and the network list over to `dev`.
+    fn select_device(&mut self, dev: String) {
+        self.networks = vec![];
+        self.passphrase_for = None;
+        self.link = None;
+        self.link_watch = Some(Watch::spawn(
+            &self.rt,
+            self.ctx.clone(),
+            "WiFi link",
+            self.notifications_tx.clone(),
+            {
+                let dev = dev.clone();
+                move || watch_link_info(dev.clone())
+            },
+        ));
+        self.networks_watch = Some(Watch::spawn(
+            &self.rt,
+            self.ctx.clone(),
+            "WiFi networks",
+            self.notifications_tx.clone(),
+            {
+                let dev = dev.clone();
+                move || watch_access_points(dev.clone())
+            },
+        ));
+        self.power_save_watch = Some(Watch::spawn(
+            &self.rt,
+            self.ctx.clone(),
+            "power save setting",
+            self.notifications_tx.clone(),
+            {
+                let dev = dev.clone();
+                move || watch_power_save(dev.clone())
+            },
+        ));
+        self.selected_device = Some(dev);
+    }
+
+    fn notify_error(&self, what: &str, err: &Error) {
+        self.notifications_tx
+            .send(Toast {
+                kind: egui_toast::ToastKind::Error,
+                text: format!("Unable to {what}: {err}").into(),
+                options: ToastOptions::with_duration(Duration::from_secs(5)),
+            })
+            .ok();
+        tracing::error!("unable to {what}: {err}");
+    }
+
+    fn start_network_action<F>(&mut self, name: &'static str, action: F)
+    where
+        F: std::future::Future<Output = Result<Vec<wifi::AccessPoint>, Error>> + Send + 'static,
+    {
+        self.network_action = Some(NetworkAction {
+            name,
+            promise: self
+                .rt
+                .block_on(async move { Promise::spawn_async(action) }),
+        });
+    }
+
+    /// Edits the policy the daemon switches power save by and shows what it measures.
+    fn draw_auto_power_save(&mut self, ui: &mut egui::Ui) {
+        let dev = match self.selected_device.clone() {
+            Some(dev) => dev,
+            None => return,
+        };
+
+        egui::CollapsingHeader::new("Automatic power save")
+            .id_source("wifi_auto_power_save")
+            .show(ui, |ui| {
+                ui.add_enabled_ui(self.auto_power_save_action.is_none(), |ui| {
+                    let policy = &mut self.auto_power_save;
+                    ui.checkbox(
+                        &mut policy.enabled,
+                        "Turn power save off when the latency gets jittery",
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("Jitter threshold");
+                        ui.add(
+                            egui::DragValue::new(&mut policy.jitter_threshold_ms)
+                                .clamp_range(1..=1000)
+                                .suffix(" ms"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Back on when idle for");
+                        ui.add(
+                            egui::DragValue::new(&mut policy.idle_seconds)
+                                .clamp_range(0..=3600)
+                                .suffix(" s"),
+                        )
+                        .on_hover_text("0 never");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Or on battery below");
+                        ui.add(
+                            egui::DragValue::new(&mut policy.battery_percent)
+                                .clamp_range(0..=100)
+                                .suffix(" %"),
+                        )
+                        .on_hover_text("0 never");
+                    });
+
+                    let mut policy = policy.clone();
+                    policy.device = dev.clone();
+                    let modified = self.auto_power_save_applied.as_ref() != Some(&policy);
+                    if ui
+                        .add_enabled(modified, egui::Button::new("Apply"))
+                        .clicked()
+                    {
+                        self.auto_power_save_action = Some(AutoPowerSaveAction {
+                            name: "update",
+                            promise: self.rt.block_on(async move {
+                                Promise::spawn_async(set_auto_power_save(policy))
+                            }),
+                        });
+                    }
+                });
+
+                match &self.auto_power_save_status {
+                    Some(status) if status.active && status.error.is_empty() => {
+                        ui.label(format!(
+                            "Latency {:.1} ms, jitter {:.1} ms, {} lost, {} kbit/s",
+                            status.latency_ms,
+                            status.jitter_ms,
+                            status.lost,
+                            status.throughput_kbps
+                        ));
+                    }
+                    Some(status) if status.active => {
+                        ui.weak(&status.error);
+                    }
+                    _ => {}
+                }
+            });
+
+        if let Some(action) = &self.auto_power_save_action {
+            match action.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notify_error(
+                        &format!("{} the automatic power save policy", action.name),
+                        err,
+                    );
+                    self.auto_power_save_action = None;
+                }
+                Some(Ok(policy)) => {
+                    tracing::debug!("Promise ready for {}", action.name);
+                    self.auto_power_save = policy.clone();
+                    self.auto_power_save_applied = Some(policy.clone());
+                    self.auto_power_save_action = None;
+                }
+            }
+        }
+    }
+
+    /// Tells about power save changes the daemon made since the last status.
+    fn notify_change(&mut self, status: &wifi::AutoPowerSaveStatus) {
+        let id = status.last_change.as_ref().map_or(0, |change| change.id);
+        if let (Some(seen), Some(change)) = (self.seen_change, &status.last_change) {
+            if change.id > seen {
+                self.notifications_tx
+                    .send(Toast {
+                        kind: egui_toast::ToastKind::Info,
+                        text: change_text(change).into(),
+                        options: ToastOptions::with_duration(Duration::from_secs(5)),
+                    })
+                    .ok();
+            }
+        }
+        // Starts over from 0 when the daemon restarts
+        self.seen_change = Some(id);
+    }
+
+    /// Shows the connection's signal, channel and bitrates as the device reports them.
+    fn draw_link(&mut self, ui: &mut egui::Ui) {
+        if self.selected_device.is_none() {
+            return;
+        }
+
+        egui::CollapsingHeader::new("Link")
+            .id_source("wifi_link")
+            .show(ui, |ui| {
+                let link = match &self.link {
+                    Some(link) => link,
+                    None => {
+                        ui.weak("Not connected");
+                        return;
+                    }
+                };
+                egui::Grid::new("wifi_link_grid").show(ui, |ui| {
+                    ui.label("Network");
+                    ui.label(format!("{} ({})", link.ssid, link.bssid));
+                    ui.end_row();
+
+                    ui.label("Channel");
+                    ui.label(format!("{} ({} MHz)", link.channel, link.frequency));
+                    ui.end_row();
+
+                    ui.label("Signal");
+                    ui.label(format!("{} dBm", link.signal));
+                    ui.end_row();
+
+                    ui.label("Bitrate");
+                    ui.label(format!(
+                        "{} down, {} up",
+                        bitrate_text(link.rx_bitrate),
+                        bitrate_text(link.tx_bitrate)
+                    ));
+                    ui.end_row();
+
+                    ui.label("Retries");
+                    ui.label(format!("{} ({} failed)", link.tx_retries, link.tx_failed));
+                    ui.end_row();
+                });
             });
+    }
 
-            (selected_device, promise)
-        } else {
-            (None, None)
+    /// Lists the networks with their signal and a button to connect or disconnect.
+    fn draw_networks(&mut self, ui: &mut egui::Ui) {
+        let dev = match self.selected_device.clone() {
+            Some(dev) => dev,
+            None => return,
         };
 
-        let devices_promise = rt.block_on(async move {
-            tracing::debug!("Creating new devices promise");
-            Promise::spawn_async(async { list_devices().await })
+        ui.horizontal(|ui| {
+            ui.label("Networks");
+            ui.set_enabled(self.network_action.is_none());
+            if ui.button("Scan").clicked() {
+                let dev = dev.clone();
+                self.start_network_action("scan for networks", async move { scan(dev).await });
+            }
         });
 
-        Self {
-            rt,
-            power_save_enabled: false,
-            available_devices: vec![],
-            selected_device,
+        let mut connect = None;
+        let mut disconnect = false;
+        egui::ScrollArea::vertical()
+            .id_source("wifi_networks")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                ui.set_enabled(self.network_action.is_none());
+                for network in &self.networks {
+                    ui.horizontal(|ui| {
+                        widgets::signal_bars(ui, network.signal_percent);
+                        ui.label(&network.ssid);
+                        if let Some(security) = security_text(network.security()) {
+                            ui.weak(security);
+                        }
 
-            devices_promise: Some(devices_promise),
-            power_save_promise,
+                        if network.active {
+                            ui.label("Connected");
+                            if ui.button("Disconnect").clicked() {
+                                disconnect = true;
+                            }
+                            return;
+                        }
+                        let supported = !matches!(
+                            network.security(),
+                            wifi::Security::Wep | wifi::Security::Enterprise
+                        );
+                        let response = ui.add_enabled(supported, egui::Button::new("Connect"));
+                        if response.clicked() {
+                            connect = Some(network.clone());
+                        }
+                        if !supported {
+                            response.on_disabled_hover_text("Not supported, use the desktop");
+                        }
+                    });
+
+                    if self.passphrase_for.as_ref() == Some(&network.ssid) {
+                        ui.horizontal(|ui| {
+                            ui.label("Passphrase");
+                            let response = ui.add(
+                                egui::TextEdit::singleline(&mut self.passphrase).password(true),
+                            );
+                            let entered =
+                                response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+                            if ui.button("Join").clicked() || entered {
+                                connect = Some(network.clone());
+                            }
+                            if ui.button("Cancel").clicked() {
+                                self.passphrase_for = None;
+                                self.passphrase.clear();
+                            }
+                        });
+                    }
+                }
+            });
 
-            notifications_tx,
+        if disconnect {
+            self.start_network_action("disconnect", async move { disconnect_network(dev).await });
+        } else if let Some(network) = connect {
+            let open = network.security() == wifi::Security::Open;
+            let typed = self.passphrase_for.as_ref() == Some(&network.ssid);
+            if open || network.saved || typed {
+                let passphrase = std::mem::take(&mut self.passphrase);
+                self.passphrase_for = None;
+                self.start_network_action("connect to the network", async move {
+                    connect_network(dev, network.ssid, passphrase).await
+                });
+            } else {
+                // Asked for first, the network isn't saved yet
+                self.passphrase_for = Some(network.ssid);
+                self.passphrase.clear();
+            }
+        }
+
+        if let Some(action) = &self.network_action {
+            match action.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notify_error(action.name, err);
+                    self.network_action = None;
+                }
+                Some(Ok(access_points)) => {
+                    tracing::debug!("Promise ready for {}", action.name);
+                    self.networks = by_network(access_points.clone());
+                    self.network_action = None;
+                }
+            }
+        }
+    }
+
+    fn start_saved_action<F>(&mut self, name: &'static str, action: F)
+    where
+        F: std::future::Future<Output = Result<Vec<wifi::SavedNetwork>, Error>> + Send + 'static,
+    {
+        self.saved_action = Some(SavedAction {
+            name,
+            promise: self
+                .rt
+                .block_on(async move { Promise::spawn_async(action) }),
+        });
+    }
+
+    /// Lists the saved networks in the order they are joined, with buttons to
+    /// move them up or down, toggle autoconnect or forget them.
+    fn draw_saved_networks(&mut self, ui: &mut egui::Ui) {
+        let mut moved = None;
+        let mut autoconnect = None;
+        let mut forget = None;
+        egui::CollapsingHeader::new("Saved networks")
+            .id_source("wifi_saved_networks")
+            .show(ui, |ui| {
+                ui.set_enabled(self.saved_action.is_none());
+                if self.saved_networks.is_empty() {
+                    ui.weak("No saved networks");
+                }
+                let last = self.saved_networks.len().saturating_sub(1);
+                for (index, network) in self.saved_networks.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                            moved = Some((index, index - 1));
+                        }
+                        if ui
+                            .add_enabled(index < last, egui::Button::new("Down"))
+                            .clicked()
+                        {
+                            moved = Some((index, index + 1));
+                        }
+                        ui.label(&network.id);
+                        if network.ssid != network.id {
+                            ui.weak(&network.ssid);
+                        }
+
+                        let mut enabled = network.autoconnect;
+                        if ui.checkbox(&mut enabled, "Autoconnect").changed() {
+                            autoconnect = Some((network.uuid.clone(), enabled));
+                        }
+                        if ui.button("Forget").clicked() {
+                            forget = Some(network.uuid.clone());
+                        }
+                    });
+                }
+            });
+
+        if let Some((from, to)) = moved {
+            let mut networks = self.saved_networks.clone();
+            networks.swap(from, to);
+            let changes = priorities(&networks);
+            self.saved_networks = networks;
+            self.start_saved_action("reorder the saved networks", async move {
+                set_priorities(changes).await
+            });
+        } else if let Some((uuid, enabled)) = autoconnect {
+            self.start_saved_action("update autoconnect", async move {
+                set_autoconnect(uuid, enabled).await
+            });
+        } else if let Some(uuid) = forget {
+            self.start_saved_action(
+                "forget the network",
+                async move { forget_network(uuid).await },
+            );
+        }
+
+        if let Some(action) = &self.saved_action {
+            match action.promise.ready() {
+                None => {}
+                Some(Err(err)) => {
+                    self.notify_error(action.name, err);
+                    self.saved_action = None;
+                }
+                Some(Ok(networks)) => {
+                    tracing::debug!("Promise ready for {}", action.name);
+                    self.saved_networks = networks.clone();
+                    self.saved_action = None;
+                }
+            }
+        }
+    }
+}
+
+/// The priorities that make NetworkManager join `networks` in list order,
+/// leaving out the networks already at theirs.
+fn priorities(networks: &[wifi::SavedNetwork]) -> Vec<(String, i32)> {
+    let count = networks.len() as i32;
+    networks
+        .iter()
+        .enumerate()
+        .map(|(index, network)| (network, count - index as i32))
+        .filter(|(network, priority)| network.priority != *priority)
+        .map(|(network, priority)| (network.uuid.clone(), priority))
+        .collect()
+}
+
+/// One entry per SSID, the access point connected to or otherwise the strongest.
+fn by_network(access_points: Vec<wifi::AccessPoint>) -> Vec<wifi::AccessPoint> {
+    let mut networks: Vec<wifi::AccessPoint> = vec![];
+    for ap in access_points {
+        match networks.iter_mut().find(|network| network.ssid == ap.ssid) {
+            Some(network) => {
+                if ap.active && !network.active {
+                    *network = ap;
+                }
+            }
+            None => networks.push(ap),
+        }
+    }
+    networks
+}
+
+fn scope_text(scope: wifi::PowerSaveScope) -> &'static str {
+    match scope {
+        wifi::PowerSaveScope::Unspecified | wifi::PowerSaveScope::Runtime => "until reconnect",
+        wifi::PowerSaveScope::Connection => "for this network",
+        wifi::PowerSaveScope::Global => "for every network",
+    }
+}
+
+fn change_text(change: &wifi::AutoPowerSaveChange) -> String {
+    match change.reason() {
+        wifi::AutoPowerSaveReason::Jitter => format!(
+            "Power save turned off, the latency varied by {:.0} ms",
+            change.jitter_ms
+        ),
+        wifi::AutoPowerSaveReason::Idle => "Power save turned on, the link is idle".to_string(),
+        wifi::AutoPowerSaveReason::LowBattery => {
+            "Power save turned on, the battery is low".to_string()
+        }
+        wifi::AutoPowerSaveReason::Unspecified => {
+            let state = if change.power_save { "on" } else { "off" };
+            format!("Power save turned {state}")
         }
     }
 }
 
+/// Formats kbit/s in Mbit/s, the unit drivers' rate tables use.
+fn bitrate_text(kbits: u32) -> String {
+    if kbits == 0 {
+        return "unknown".to_string();
+    }
+    format!("{:.1} Mbit/s", f64::from(kbits) / 1000.0)
+}
+
+fn security_text(security: wifi::Security) -> Option<&'static str> {
+    match security {
+        wifi::Security::Wep => Some("WEP"),
+        wifi::Security::WpaPsk => Some("WPA"),
+        wifi::Security::Sae => Some("WPA3"),
+        wifi::Security::Enterprise => Some("Enterprise"),
+        wifi::Security::Open | wifi::Security::Unspecified => None,
+    }
+}
+
 impl crate::Shortcut for Shortcut {
     fn name(&mut self) -> Option<&str> {
         Some("WiFi")
@@ -75,36 +664,73 @@ impl crate::Shortcut for Shortcut {
     }
 
     fn draw(&mut self, _ctx: &egui::Context, frame: &mut eframe::Frame, ui: &mut egui::Ui) {
+        if self.devices_watch.failed() {
+            self.serving.recheck();
+        }
+        if let Some(Ok(false)) = self.serving.ready() {
+            ui.label("WiFi is not available on this system");
+            return;
+        }
+
+        if let Some(update) = self.devices_watch.latest() {
+            tracing::debug!("Watch update: devices={:?}", update.devices);
+            self.available_devices = update.devices;
+        }
+        if let Some(status) = self
+            .auto_power_save_watch
+            .latest()
+            .and_then(|update| update.status)
+        {
+            tracing::debug!("Watch update: auto power save active={}", status.active);
+            self.notify_change(&status);
+            self.auto_power_save_status = Some(status);
+        }
+        if let Some(update) = self.link_watch.as_ref().and_then(Watch::latest) {
+            tracing::debug!("Watch update: link={:?}", update.link);
+            self.link = update.link;
+        }
+        if let Some(update) = self.networks_watch.as_ref().and_then(Watch::latest) {
+            tracing::debug!("Watch update: {} access points", update.access_points.len());
+            // An action in flight reports the outcome itself
+            if self.network_action.is_none() {
+                self.networks = by_network(update.access_points);
+            }
+        }
+        if let Some(update) = self.saved_networks_watch.latest() {
+            tracing::debug!("Watch update: {} saved networks", update.networks.len());
+            // A change in flight reports the outcome itself
+            if self.saved_action.is_none() {
+                self.saved_networks = update.networks;
+            }
+        }
+        if let Some(update) = self.power_save_watch.as_ref().and_then(Watch::latest) {
+            tracing::debug!("Watch update: power_save={}", update.enabled);
+            // A toggle in flight reports the outcome itself
+            if self.power_save_promise.is_none() {
+                self.power_save_enabled = update.enabled;
+                self.power_save_source = update.source();
+            }
+        }
+
         ui.horizontal(|ui| {
             ui.label("Devices");
+            let mut selected = None;
             egui::ComboBox::from_id_source("wifi_devices")
                 .selected_text(self.selected_device.as_ref().unwrap_or(&"".to_string()))
                 .show_ui(ui, |ui| {
                     for ele in &self.available_devices {
-                        if ui
-                            .selectable_value(&mut self.selected_device, Some(ele.clone()), ele)
-                            .clicked()
-                        {
-                            if let Some(storage) = frame.storage_mut() {
-                                storage.set_string(SELECTED_DEVICE_KEY, ele.clone());
-                            }
-                            tracing::debug!("Selected device: {ele}");
+                        let is_selected = self.selected_device.as_ref() == Some(ele);
+                        if ui.selectable_label(is_selected, ele).clicked() && !is_selected {
+                            selected = Some(ele.clone());
                         }
                     }
                 });
-            if let Some(promise) = &self.devices_promise {
-                match promise.ready() {
-                    None => {}
-                    Some(Err(err)) => {
-                        tracing::error!("unable to load devices: {err}");
-                        self.devices_promise = None;
-                    }
-                    Some(Ok(devices)) => {
-                        tracing::debug!("Promise ready with result: devices={devices:?}");
-                        self.available_devices = devices.clone();
-                        self.devices_promise = None;
-                    }
+            if let Some(ele) = selected {
+                if let Some(storage) = frame.storage_mut() {
+                    storage.set_string(SELECTED_DEVICE_KEY, ele.clone());
                 }
+                tracing::debug!("Selected device: {ele}");
+                self.select_device(ele);
             }
         });
 
@@ -116,12 +742,14 @@ impl crate::Shortcut for Shortcut {
                 if let Some(dev) = &self.selected_device {
                     let dev = dev.clone();
                     let enabled = self.power_save_enabled;
+                    let scope = self.power_save_scope;
+                    self.setting_power_save = true;
 
                     self.power_save_promise
                         .get_or_insert(self.rt.block_on(async move {
                             Promise::spawn_async(async move {
                                 tracing::debug!("Creating new power_save promise");
-                                set_power_save(dev.clone(), enabled).await
+                                set_power_save(dev.clone(), enabled, scope).await
                             })
                         }));
                 }
@@ -131,73 +759,253 @@ impl crate::Shortcut for Shortcut {
                 match promise.ready() {
                     None => {}
                     Some(Err(err)) => {
+                        let action = if self.setting_power_save {
+                            "update"
+                        } else {
+                            "load"
+                        };
                         self.notifications_tx
                             .send(Toast {
                                 kind: egui_toast::ToastKind::Error,
-                                text: format!("Unable to load power save setting: {err}").into(),
+                                text: format!("Unable to {action} power save setting: {err}")
+                                    .into(),
                                 options: ToastOptions::with_duration(Duration::from_secs(5)),
                             })
                             .ok();
-                        tracing::error!("unable to load power save setting: {err}");
-                        self.power_save_promise = None;
+                        tracing::error!("unable to {action} power save setting: {err}");
+
+                        self.power_save_promise = match self.selected_device.clone() {
+                            Some(dev) if self.setting_power_save => {
+                                Some(self.rt.block_on(async move {
+                                    Promise::spawn_async(async { get_power_save(dev).await })
+                                }))
+                            }
+                            _ => None,
+                        };
+                        self.setting_power_save = false;
                     }
-                    Some(Ok(power_save)) => {
+                    Some(Ok((power_save, source))) => {
                         tracing::debug!("Promise ready with result: power_save={power_save}");
                         self.power_save_enabled = *power_save;
+                        self.power_save_source = *source;
+                        self.setting_power_save = false;
                         self.power_save_promise = None;
                     }
                 }
             }
+
+            ui.label(format!("Kept {}", scope_text(self.power_save_source)))
+                .on_hover_text("Where the current setting comes from");
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Keep changes");
+            ui.set_enabled(self.selected_device.is_some());
+            egui::ComboBox::from_id_source("wifi_power_save_scope")
+                .selected_text(scope_text(self.power_save_scope))
+                .show_ui(ui, |ui| {
+                    for scope in [
+                        wifi::PowerSaveScope::Runtime,
+                        wifi::PowerSaveScope::Connection,
+                        wifi::PowerSaveScope::Global,
+                    ] {
+                        ui.selectable_value(&mut self.power_save_scope, scope, scope_text(scope));
+                    }
+                });
         });
+
+        self.draw_auto_power_save(ui);
+        self.draw_link(ui);
+        self.draw_networks(ui);
+        self.draw_saved_networks(ui);
     }
 }
 
-async fn get_client(
-) -> Result<wifi_service_client::WifiServiceClient<Channel>, tonic::transport::Error> {
+async fn get_client() -> Result<wifi_service_client::WifiServiceClient<Channel>, Error> {
     let channel = Endpoint::try_from("http://127.0.0.1:50000")?
         .connect_with_connector(service_fn(|_: Uri| {
-            UnixStream::connect(shortcut_core::SOCKET_PATH)
+            UnixStream::connect(shortcut_core::socket_path())
         }))
         .await?;
 
     Ok(wifi_service_client::WifiServiceClient::new(channel))
 }
 
-async fn list_devices() -> Result<Vec<String>, tonic::transport::Error> {
+async fn watch_devices() -> Result<tonic::Streaming<wifi::WatchDevicesResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::WatchDevicesRequest {});
+    let response = client.watch_devices(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn watch_power_save(
+    device: String,
+) -> Result<tonic::Streaming<wifi::WatchPowerSaveResponse>, Error> {
     let mut client = get_client().await?;
 
-    let request = tonic::Request::new(wifi::ListDevicesRequest {});
-    let response = client.list_devices(request).await.unwrap();
+    let request = tonic::Request::new(wifi::WatchPowerSaveRequest { device });
+    let response = client.watch_power_save(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn set_power_save(
+    device: String,
+    enabled: bool,
+    scope: wifi::PowerSaveScope,
+) -> Result<(bool, wifi::PowerSaveScope), Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::SetPowerSaveRequest {
+        device,
+        enabled,
+        scope: scope as i32,
+    });
+    let response = client.set_power_save(request).await?;
 
     let inner = response.into_inner();
+    Ok((inner.enabled, inner.source()))
+}
 
-    Ok(inner.devices)
+async fn get_power_save(device: String) -> Result<(bool, wifi::PowerSaveScope), Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::GetPowerSaveRequest { device });
+    let response = client.get_power_save(request).await?;
+
+    let inner = response.into_inner();
+    Ok((inner.enabled, inner.source()))
 }
 
-async fn set_power_save(device: String, enabled: bool) -> Result<bool, tonic::transport::Error> {
+async fn watch_access_points(
+    device: String,
+) -> Result<tonic::Streaming<wifi::WatchAccessPointsResponse>, Error> {
     let mut client = get_client().await?;
 
-    let request = tonic::Request::new(wifi::SetPowerSaveRequest { device, enabled });
-    let response = client.set_power_save(request).await;
+    let request = tonic::Request::new(wifi::WatchAccessPointsRequest { device });
+    let response = client.watch_access_points(request).await?;
 
-    if response.is_err() {
-        return Ok(!enabled);
-    }
+    Ok(response.into_inner())
+}
 
-    let inner = response.unwrap().into_inner();
-    Ok(inner.enabled)
+async fn scan(device: String) -> Result<Vec<wifi::AccessPoint>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::ScanRequest { device });
+    let response = client.scan(request).await?;
+
+    Ok(response.into_inner().access_points)
 }
 
-async fn get_power_save(device: String) -> Result<bool, tonic::transport::Error> {
+async fn connect_network(
+    device: String,
+    ssid: String,
+    passphrase: String,
+) -> Result<Vec<wifi::AccessPoint>, Error> {
     let mut client = get_client().await?;
 
-    let request = tonic::Request::new(wifi::GetPowerSaveRequest { device });
-    let response = client.get_power_save(request).await;
+    let request = tonic::Request::new(wifi::ConnectNetworkRequest {
+        device,
+        ssid,
+        passphrase,
+    });
+    let response = client.connect_network(request).await?;
+
+    Ok(response.into_inner().access_points)
+}
+
+async fn disconnect_network(device: String) -> Result<Vec<wifi::AccessPoint>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::DisconnectNetworkRequest { device });
+    let response = client.disconnect_network(request).await?;
+
+    Ok(response.into_inner().access_points)
+}
+
+async fn watch_saved_networks() -> Result<tonic::Streaming<wifi::WatchSavedNetworksResponse>, Error>
+{
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::WatchSavedNetworksRequest {});
+    let response = client.watch_saved_networks(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn forget_network(uuid: String) -> Result<Vec<wifi::SavedNetwork>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::ForgetNetworkRequest { uuid });
+    let response = client.forget_network(request).await?;
+
+    Ok(response.into_inner().networks)
+}
 
-    if response.is_err() {
-        return Ok(true);
+async fn set_autoconnect(uuid: String, enabled: bool) -> Result<Vec<wifi::SavedNetwork>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::SetAutoconnectRequest { uuid, enabled });
+    let response = client.set_autoconnect(request).await?;
+
+    Ok(response.into_inner().networks)
+}
+
+/// Applies the priorities one network at a time, returning the list after the last.
+async fn set_priorities(priorities: Vec<(String, i32)>) -> Result<Vec<wifi::SavedNetwork>, Error> {
+    let mut client = get_client().await?;
+
+    let mut networks = vec![];
+    for (uuid, priority) in priorities {
+        let request = tonic::Request::new(wifi::SetPriorityRequest { uuid, priority });
+        let response = client.set_priority(request).await?;
+        networks = response.into_inner().networks;
     }
 
-    let inner = response.unwrap().into_inner();
-    Ok(inner.enabled)
+    Ok(networks)
+}
+
+async fn watch_link_info(
+    device: String,
+) -> Result<tonic::Streaming<wifi::WatchLinkInfoResponse>, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::WatchLinkInfoRequest { device });
+    let response = client.watch_link_info(request).await?;
+
+    Ok(response.into_inner())
+}
+
+async fn get_auto_power_save() -> Result<wifi::AutoPowerSavePolicy, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::GetAutoPowerSaveRequest {});
+    let response = client.get_auto_power_save(request).await?;
+
+    Ok(response.into_inner().policy.unwrap_or_default())
+}
+
+async fn set_auto_power_save(
+    policy: wifi::AutoPowerSavePolicy,
+) -> Result<wifi::AutoPowerSavePolicy, Error> {
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::SetAutoPowerSaveRequest {
+        policy: Some(policy),
+    });
+    let response = client.set_auto_power_save(request).await?;
+
+    Ok(response.into_inner().policy.unwrap_or_default())
+}
+
+async fn watch_auto_power_save() -> Result<tonic::Streaming<wifi::WatchAutoPowerSaveResponse>, Error>
+{
+    let mut client = get_client().await?;
+
+    let request = tonic::Request::new(wifi::WatchAutoPowerSaveRequest {});
+    let response = client.watch_auto_power_save(request).await?;
+
+    Ok(response.into_inner())
 }